@@ -1,3 +1,11 @@
+use std::env;
+
 fn main() {
-    cc::Build::new().file("src/pikchr.c").compile("pikchr");
+    println!("cargo:rustc-check-cfg=cfg(pikchr_c)");
+    // pikchr-sys only builds the C where there is a C library for it, and
+    // is left out without the vendored feature.  The pure-Rust layout
+    // backend is used wherever there is no C
+    if env::var_os("DEP_PIKCHR_C").is_some() {
+        println!("cargo:rustc-cfg=pikchr_c");
+    }
 }
@@ -0,0 +1,64 @@
+/*
+ * The C interface of the pikchr crate
+ *
+ * Build the shared library with
+ *
+ *   cargo rustc --release --lib --features capi --crate-type cdylib
+ *
+ * These declarations match `src/capi.rs`, which documents each of them.
+ */
+#ifndef PIKCHR_RS_H
+#define PIKCHR_RS_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* What pikchr_rs_render() returns */
+#define PIKCHR_RS_OK               0
+#define PIKCHR_RS_INVALID_ARGUMENT 1
+#define PIKCHR_RS_RENDER_ERROR     2
+#define PIKCHR_RS_TOO_LARGE        3
+#define PIKCHR_RS_PANIC            4
+
+/* Flags for PikchrRsOptions.flags */
+#define PIKCHR_RS_PLAIN_ERRORS 0x0001
+#define PIKCHR_RS_DARK_MODE    0x0002
+#define PIKCHR_RS_MONOCHROME   0x0004
+#define PIKCHR_RS_CACHE        0x0008
+
+/* Values for PikchrRsOptions.palette */
+#define PIKCHR_RS_PALETTE_NONE              0
+#define PIKCHR_RS_PALETTE_COLOR_BLIND_SAFE  1
+#define PIKCHR_RS_PALETTE_HIGH_CONTRAST     2
+
+typedef struct PikchrRsOptions {
+  size_t size;                /* sizeof(PikchrRsOptions) */
+  const char *class_name;     /* or NULL for no class */
+  unsigned int flags;         /* PIKCHR_RS_* flags */
+  unsigned int palette;       /* PIKCHR_RS_PALETTE_* */
+  size_t max_source_bytes;    /* or 0 for any length */
+} PikchrRsOptions;
+
+typedef struct PikchrRsResult {
+  char *svg;                  /* or NULL if the render failed */
+  char *error;                /* or NULL if it did not */
+  int width;                  /* or -1 if the render failed */
+  int height;                 /* or -1 if the render failed */
+} PikchrRsResult;
+
+/* Render source; options may be NULL.  The result is always filled in
+** and must be released with pikchr_rs_free_result(). */
+int pikchr_rs_render(const char *source, const PikchrRsOptions *options,
+                     PikchrRsResult *result);
+
+/* Release the strings of a result */
+void pikchr_rs_free_result(PikchrRsResult *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PIKCHR_RS_H */
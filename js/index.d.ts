@@ -0,0 +1,21 @@
+export interface RenderOptions {
+  /** A class to add to the `<svg>` element */
+  class?: string;
+  /** Use colours which suit a dark background */
+  darkMode?: boolean;
+  /** Format errors as HTML rather than plain text */
+  htmlErrors?: boolean;
+}
+
+export interface RenderResult {
+  svg: string | null;
+  width: number | null;
+  height: number | null;
+  error: string | null;
+}
+
+export function init(
+  input?: WebAssembly.Module | BufferSource | Response | Promise<Response> | URL | string,
+): Promise<void>;
+
+export function render(source: string, options?: RenderOptions): RenderResult;
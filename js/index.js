@@ -0,0 +1,79 @@
+// JavaScript wrapper for the pikchr crate built as WebAssembly
+//
+// `init` loads `pikchr.wasm`, which `npm run build` produces from the
+// crate's `js` feature, and `render` calls into it.  The functions used
+// here are exported by `src/js.rs`.
+
+const PLAIN_ERRORS = 0x0001;
+const DARK_MODE = 0x0002;
+
+let wasm = null;
+
+/**
+ * Load the WebAssembly module
+ *
+ * `input` may be a `WebAssembly.Module`, the bytes of the module, a
+ * `Response`, or a URL to fetch.  By default `pikchr.wasm` is fetched from
+ * beside this file.
+ */
+export async function init(input) {
+  if (wasm !== null) {
+    return;
+  }
+  if (input === undefined) {
+    input = new URL('./pikchr.wasm', import.meta.url);
+  }
+  if (typeof input === 'string' || input instanceof URL) {
+    input = fetch(input);
+  }
+  input = await input;
+  let instance;
+  if (input instanceof WebAssembly.Module) {
+    instance = await WebAssembly.instantiate(input, {});
+  } else {
+    if (typeof Response !== 'undefined' && input instanceof Response) {
+      input = await input.arrayBuffer();
+    }
+    ({ instance } = await WebAssembly.instantiate(input, {}));
+  }
+  wasm = instance.exports;
+}
+
+function pass(text) {
+  const bytes = new TextEncoder().encode(text);
+  const ptr = wasm.pikchr_js_alloc(bytes.length);
+  new Uint8Array(wasm.memory.buffer, ptr, bytes.length).set(bytes);
+  return [ptr, bytes.length];
+}
+
+/**
+ * Render pikchr source as SVG
+ *
+ * Returns `{ svg, width, height, error }`.  On success `error` is `null`;
+ * on failure it holds the error message and the others are `null`.
+ *
+ * Options:
+ * - `class`: a class to add to the `<svg>` element
+ * - `darkMode`: use colours which suit a dark background
+ * - `htmlErrors`: format errors as HTML rather than plain text
+ */
+export function render(source, options = {}) {
+  if (wasm === null) {
+    throw new Error('pikchr: call init() before render()');
+  }
+  let flags = options.htmlErrors ? 0 : PLAIN_ERRORS;
+  if (options.darkMode) {
+    flags |= DARK_MODE;
+  }
+  const [sourcePtr, sourceLen] = pass(source);
+  const [classPtr, classLen] = options.class == null ? [0, 0] : pass(options.class);
+  const result = wasm.pikchr_js_render(sourcePtr, sourceLen, classPtr, classLen, flags);
+  wasm.pikchr_js_free(sourcePtr, sourceLen);
+  if (options.class != null) {
+    wasm.pikchr_js_free(classPtr, classLen);
+  }
+  const len = new DataView(wasm.memory.buffer).getUint32(result, true);
+  const json = new TextDecoder().decode(new Uint8Array(wasm.memory.buffer, result + 4, len));
+  wasm.pikchr_js_free(result, len + 4);
+  return JSON.parse(json);
+}
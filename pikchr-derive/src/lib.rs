@@ -0,0 +1,608 @@
+//! Derive macros for the pikchr crate
+//!
+//! These macros are re-exported by the `pikchr` crate when its `derive`
+//! feature is enabled, and that is how they are expected to be used.  The
+//! generated code refers to items in `::pikchr`.
+//!
+//! This crate deliberately has no dependencies, so the derive input is
+//! parsed directly from the token stream.  Only the shapes of input which
+//! the macros support are understood; anything else produces a compile
+//! error pointing at the offending tokens.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::fmt::Write;
+use std::iter::Peekable;
+
+/// Derive `pikchr::state::StateDiagram` for an enum
+///
+/// Each variant of the enum is a state.  Transitions are declared on the
+/// variant they leave from with `#[transition(to = Variant)]`, optionally
+/// with a label: `#[transition(to = Variant, label = "text")]`.  A variant
+/// may have any number of transitions.  The initial state is marked with
+/// `#[pikchr(initial)]`, and a state can be drawn with a different name
+/// using `#[pikchr(label = "text")]`.
+///
+/// Since transition targets are variant names, renaming or removing a
+/// variant without updating the transitions is a compile error.
+///
+/// ```
+/// use pikchr::PikchrStateDiagram;
+/// use pikchr::state::StateDiagram;
+///
+/// #[derive(PikchrStateDiagram)]
+/// enum Door {
+///     #[pikchr(initial)]
+///     #[transition(to = Open, label = "open")]
+///     #[transition(to = Locked, label = "lock")]
+///     Closed,
+///     #[transition(to = Closed, label = "close")]
+///     Open,
+///     #[pikchr(label = "Locked shut")]
+///     #[transition(to = Closed, label = "unlock")]
+///     Locked,
+/// }
+///
+/// let machine = Door::state_machine();
+/// assert_eq!(machine.states(), ["Closed", "Open", "Locked shut"]);
+/// assert!(Door::pikchr_source().contains("\"unlock\""));
+/// ```
+#[proc_macro_derive(PikchrStateDiagram, attributes(pikchr, transition))]
+pub fn derive_state_diagram(input: TokenStream) -> TokenStream {
+    match state_diagram(input) {
+        Ok(output) => output,
+        Err(err) => err.into_compile_error(),
+    }
+}
+
+/// Derive `pikchr::er::ErEntity` for a struct
+///
+/// The struct becomes an entity named after the struct, or after the
+/// `#[pikchr(name = "text")]` attribute if present.  Each named field is a
+/// column, labelled with its type.  Fields can be annotated with:
+///
+/// - `#[pikchr(key)]` to mark a key column
+/// - `#[pikchr(skip)]` to leave the field out of the diagram
+/// - `#[pikchr(references = Type)]` to add a many-to-one relation to the
+///   entity described by `Type`, which must also implement `ErEntity`
+///
+/// Referenced entities are not added automatically; add them to the
+/// diagram with `ErDiagram::add` as well.
+///
+/// ```
+/// use pikchr::PikchrEntity;
+/// use pikchr::er::ErDiagram;
+///
+/// #[derive(PikchrEntity)]
+/// #[pikchr(name = "users")]
+/// struct User {
+///     #[pikchr(key)]
+///     id: u64,
+///     name: String,
+/// }
+///
+/// #[derive(PikchrEntity)]
+/// struct Post {
+///     #[pikchr(key)]
+///     id: u64,
+///     #[pikchr(references = User)]
+///     author: u64,
+///     tags: Vec<String>,
+///     #[pikchr(skip)]
+///     cached_html: Option<String>,
+/// }
+///
+/// let mut er = ErDiagram::new();
+/// er.add::<User>().add::<Post>();
+/// assert_eq!(er.entities()[0].name(), "users");
+/// let source = er.to_source();
+/// assert!(source.contains("\"tags: Vec<String>\""));
+/// assert!(!source.contains("cached_html"));
+/// ```
+#[proc_macro_derive(PikchrEntity, attributes(pikchr))]
+pub fn derive_entity(input: TokenStream) -> TokenStream {
+    match entity(input) {
+        Ok(output) => output,
+        Err(err) => err.into_compile_error(),
+    }
+}
+
+/// An error to be reported as a `compile_error!()` at a given location
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: &str) -> Self {
+        Self {
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    fn into_compile_error(self) -> TokenStream {
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+        let mut tokens: Vec<TokenTree> = vec![
+            Ident::new("compile_error", self.span).into(),
+            Punct::new('!', Spacing::Alone).into(),
+            Group::new(Delimiter::Parenthesis, TokenTree::from(message).into()).into(),
+            Punct::new(';', Spacing::Alone).into(),
+        ];
+        for token in &mut tokens {
+            token.set_span(self.span);
+        }
+        tokens.into_iter().collect()
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// An attribute such as `#[transition(to = Open)]`
+struct Attribute {
+    name: Ident,
+    args: Option<Group>,
+}
+
+/// A `key` or `key = value` argument to an attribute
+struct Argument {
+    key: Ident,
+    value: Option<TokenTree>,
+}
+
+struct Variant {
+    name: Ident,
+    label: String,
+    initial: bool,
+    transitions: Vec<(Ident, Option<String>)>,
+}
+
+fn is_punct(tree: &TokenTree, ch: char) -> bool {
+    matches!(tree, TokenTree::Punct(p) if p.as_char() == ch)
+}
+
+/// Parse the contents of a `#[...]` group
+fn parse_attribute(group: &Group) -> Option<Attribute> {
+    let mut tokens = group.stream().into_iter();
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(name)) => name,
+        _ => return None,
+    };
+    let args = match tokens.next() {
+        Some(TokenTree::Group(args)) if args.delimiter() == Delimiter::Parenthesis => Some(args),
+        _ => None,
+    };
+    Some(Attribute { name, args })
+}
+
+/// Parse the arguments of an attribute, e.g. `(to = Open, label = "open")`
+fn parse_arguments(attr: &Attribute) -> Result<Vec<Argument>> {
+    let args = match &attr.args {
+        Some(args) => args,
+        None => return Err(Error::new(attr.name.span(), "expected arguments")),
+    };
+    let mut ret = Vec::new();
+    let mut tokens = args.stream().into_iter().peekable();
+    while let Some(tree) = tokens.next() {
+        let key = match tree {
+            TokenTree::Ident(key) => key,
+            other => return Err(Error::new(other.span(), "expected an argument name")),
+        };
+        let mut value = None;
+        if matches!(tokens.peek(), Some(t) if is_punct(t, '=')) {
+            let eq = tokens.next().unwrap();
+            value = Some(
+                tokens
+                    .next()
+                    .ok_or_else(|| Error::new(eq.span(), "expected a value after `=`"))?,
+            );
+        }
+        ret.push(Argument { key, value });
+        match tokens.next() {
+            None => break,
+            Some(ref t) if is_punct(t, ',') => {}
+            Some(other) => return Err(Error::new(other.span(), "expected `,`")),
+        }
+    }
+    Ok(ret)
+}
+
+/// Decode the value of a string literal token
+fn string_value(tree: &TokenTree) -> Result<String> {
+    let err = || Error::new(tree.span(), "expected a string literal");
+    let text = match tree {
+        TokenTree::Literal(lit) => lit.to_string(),
+        _ => return Err(err()),
+    };
+    if let Some(raw) = text.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return raw[hashes..raw.len() - hashes]
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .map(str::to_string)
+            .ok_or_else(err);
+    }
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(err)?;
+    let mut ret = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            Some('0') => ret.push('\0'),
+            Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => ret.push(c),
+            _ => {
+                return Err(Error::new(
+                    tree.span(),
+                    "unsupported escape sequence in string literal",
+                ))
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Parse one variant's worth of tokens, e.g. `#[pikchr(initial)] Idle`
+fn parse_variant(tokens: Vec<TokenTree>) -> Result<Variant> {
+    let mut iter = tokens.into_iter().peekable();
+    let attrs = take_attributes(&mut iter);
+    let name = match iter.next() {
+        Some(TokenTree::Ident(name)) => name,
+        Some(other) => return Err(Error::new(other.span(), "expected a variant name")),
+        None => return Err(Error::new(Span::call_site(), "expected a variant name")),
+    };
+    let mut variant = Variant {
+        label: name.to_string(),
+        name,
+        initial: false,
+        transitions: Vec::new(),
+    };
+    for attr in attrs {
+        match attr.name.to_string().as_str() {
+            "pikchr" => {
+                for arg in parse_arguments(&attr)? {
+                    match (arg.key.to_string().as_str(), &arg.value) {
+                        ("initial", None) => variant.initial = true,
+                        ("label", Some(value)) => variant.label = string_value(value)?,
+                        _ => {
+                            return Err(Error::new(
+                                arg.key.span(),
+                                "expected `initial` or `label = \"...\"`",
+                            ))
+                        }
+                    }
+                }
+            }
+            "transition" => {
+                let mut to = None;
+                let mut label = None;
+                for arg in parse_arguments(&attr)? {
+                    match (arg.key.to_string().as_str(), arg.value) {
+                        ("to", Some(TokenTree::Ident(target))) => to = Some(target),
+                        ("label", Some(value)) => label = Some(string_value(&value)?),
+                        _ => {
+                            return Err(Error::new(
+                                arg.key.span(),
+                                "expected `to = Variant` or `label = \"...\"`",
+                            ))
+                        }
+                    }
+                }
+                let to = to.ok_or_else(|| {
+                    Error::new(attr.name.span(), "transition requires `to = Variant`")
+                })?;
+                variant.transitions.push((to, label));
+            }
+            _ => {}
+        }
+    }
+    Ok(variant)
+}
+
+/// Consume any `#[...]` attributes from the front of a token iterator
+fn take_attributes<I: Iterator<Item = TokenTree>>(iter: &mut Peekable<I>) -> Vec<Attribute> {
+    let mut attrs = Vec::new();
+    while matches!(iter.peek(), Some(t) if is_punct(t, '#')) {
+        iter.next();
+        if let Some(TokenTree::Group(group)) = iter.next() {
+            if let Some(attr) = parse_attribute(&group) {
+                attrs.push(attr);
+            }
+        }
+    }
+    attrs
+}
+
+/// A derive input: the outer attributes, name and body of a type
+struct Item {
+    attrs: Vec<Attribute>,
+    name: Ident,
+    body: Group,
+}
+
+/// Parse a derive input which must be an item of the given `kind`
+/// (`enum` or `struct`) with a braced body and no generics
+fn parse_item(input: TokenStream, derive: &str, kind: &str) -> Result<Item> {
+    let wrong_kind = format!("{} can only be derived for {}s", derive, kind);
+    let mut tokens = input.into_iter().peekable();
+    let mut attrs = Vec::new();
+    let name = loop {
+        attrs.extend(take_attributes(&mut tokens));
+        match tokens.next() {
+            Some(TokenTree::Ident(ident)) if ident.to_string() == kind => match tokens.next() {
+                Some(TokenTree::Ident(name)) => break name,
+                _ => return Err(Error::new(ident.span(), "expected a type name")),
+            },
+            Some(TokenTree::Ident(ident))
+                if ["enum", "struct", "union"].contains(&ident.to_string().as_str()) =>
+            {
+                return Err(Error::new(ident.span(), &wrong_kind))
+            }
+            Some(_) => {}
+            None => return Err(Error::new(Span::call_site(), &wrong_kind)),
+        }
+    };
+    let body = match tokens.next() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => {
+            return Err(Error::new(
+                p.span(),
+                &format!("{} does not support generic types", derive),
+            ))
+        }
+        Some(other) => {
+            return Err(Error::new(
+                other.span(),
+                &format!("{} requires a {} with named fields", derive, kind),
+            ))
+        }
+        None => return Err(Error::new(name.span(), "expected a body")),
+    };
+    Ok(Item { attrs, name, body })
+}
+
+/// Split the contents of a body into its comma-separated entries
+///
+/// Commas inside of generic arguments (`HashMap<K, V>`) do not split.
+fn split_entries(body: &Group) -> Vec<Vec<TokenTree>> {
+    let mut entries = Vec::new();
+    let mut current = Vec::new();
+    let mut depth = 0usize;
+    let mut previous_dash = false;
+    for tree in body.stream() {
+        let dash = is_punct(&tree, '-');
+        if is_punct(&tree, '<') {
+            depth += 1;
+        } else if is_punct(&tree, '>') && !previous_dash {
+            depth = depth.saturating_sub(1);
+        } else if is_punct(&tree, ',') && depth == 0 {
+            entries.push(std::mem::take(&mut current));
+            previous_dash = false;
+            continue;
+        }
+        previous_dash = dash;
+        current.push(tree);
+    }
+    if !current.is_empty() {
+        entries.push(current);
+    }
+    entries
+}
+
+/// Render an optional string as Rust source for an `Option<&str>`
+fn option_str(value: Option<&String>) -> String {
+    match value {
+        Some(value) => format!("::core::option::Option::Some({:?})", value),
+        None => "::core::option::Option::None".to_string(),
+    }
+}
+
+fn state_diagram(input: TokenStream) -> Result<TokenStream> {
+    let item = parse_item(input, "PikchrStateDiagram", "enum")?;
+    let variants = split_entries(&item.body)
+        .into_iter()
+        .map(parse_variant)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut initial = None;
+    for variant in variants.iter().filter(|v| v.initial) {
+        if initial.replace(&variant.label).is_some() {
+            return Err(Error::new(
+                variant.name.span(),
+                "only one state may be marked `#[pikchr(initial)]`",
+            ));
+        }
+    }
+
+    let mut body = String::from("let mut machine = ::pikchr::state::StateMachine::new();\n");
+    for variant in &variants {
+        writeln!(body, "machine.state({:?});", variant.label).unwrap();
+    }
+    if let Some(initial) = initial {
+        writeln!(body, "machine.initial({:?});", initial).unwrap();
+    }
+    for variant in &variants {
+        for (to, label) in &variant.transitions {
+            let target = variants
+                .iter()
+                .find(|v| v.name.to_string() == to.to_string())
+                .ok_or_else(|| Error::new(to.span(), "transition to an unknown variant"))?;
+            writeln!(
+                body,
+                "machine.transition({:?}, {:?}, {});",
+                variant.label,
+                target.label,
+                option_str(label.as_ref())
+            )
+            .unwrap();
+        }
+    }
+
+    let output = format!(
+        "impl ::pikchr::state::StateDiagram for {} {{
+            fn state_machine() -> ::pikchr::state::StateMachine {{
+                {}
+                machine
+            }}
+        }}",
+        item.name, body
+    );
+    Ok(output.parse().expect("generated code should parse"))
+}
+
+struct Field {
+    name: String,
+    ty: String,
+    key: bool,
+    skip: bool,
+    references: Option<Ident>,
+}
+
+/// Render the tokens of a type as a compact string, e.g. `Vec<String>`
+fn type_string(tokens: &[TokenTree]) -> String {
+    let mut ret = String::new();
+    let mut previous_word = false;
+    for tree in tokens {
+        let word = matches!(tree, TokenTree::Ident(_) | TokenTree::Literal(_));
+        if word && previous_word {
+            ret.push(' ');
+        }
+        match tree {
+            TokenTree::Group(group) => {
+                let inner: Vec<_> = group.stream().into_iter().collect();
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::None => ("", ""),
+                };
+                ret.push_str(open);
+                ret.push_str(&type_string(&inner));
+                ret.push_str(close);
+            }
+            TokenTree::Punct(p) if p.as_char() == ',' || p.as_char() == ';' => {
+                ret.push(p.as_char());
+                ret.push(' ');
+            }
+            other => ret.push_str(&other.to_string()),
+        }
+        previous_word = word;
+    }
+    ret
+}
+
+/// Parse one field, e.g. `#[pikchr(key)] pub id: u64`
+fn parse_field(tokens: Vec<TokenTree>) -> Result<Field> {
+    let mut iter = tokens.into_iter().peekable();
+    let attrs = take_attributes(&mut iter);
+    if matches!(iter.peek(), Some(TokenTree::Ident(i)) if i.to_string() == "pub") {
+        iter.next();
+        if matches!(iter.peek(), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis)
+        {
+            iter.next();
+        }
+    }
+    let name = match iter.next() {
+        Some(TokenTree::Ident(name)) => name,
+        Some(other) => return Err(Error::new(other.span(), "expected a field name")),
+        None => return Err(Error::new(Span::call_site(), "expected a field name")),
+    };
+    match iter.next() {
+        Some(ref t) if is_punct(t, ':') => {}
+        _ => return Err(Error::new(name.span(), "expected `:` after the field name")),
+    }
+    let ty: Vec<_> = iter.collect();
+    let mut field = Field {
+        name: name.to_string().trim_start_matches("r#").to_string(),
+        ty: type_string(&ty),
+        key: false,
+        skip: false,
+        references: None,
+    };
+    for attr in attrs.iter().filter(|a| a.name.to_string() == "pikchr") {
+        for arg in parse_arguments(attr)? {
+            match (arg.key.to_string().as_str(), arg.value) {
+                ("key", None) => field.key = true,
+                ("skip", None) => field.skip = true,
+                ("references", Some(TokenTree::Ident(target))) => field.references = Some(target),
+                _ => {
+                    return Err(Error::new(
+                        arg.key.span(),
+                        "expected `key`, `skip` or `references = Type`",
+                    ))
+                }
+            }
+        }
+    }
+    Ok(field)
+}
+
+fn entity(input: TokenStream) -> Result<TokenStream> {
+    let item = parse_item(input, "PikchrEntity", "struct")?;
+    let mut entity_name = item.name.to_string();
+    for attr in item.attrs.iter().filter(|a| a.name.to_string() == "pikchr") {
+        for arg in parse_arguments(attr)? {
+            match (arg.key.to_string().as_str(), &arg.value) {
+                ("name", Some(value)) => entity_name = string_value(value)?,
+                _ => return Err(Error::new(arg.key.span(), "expected `name = \"...\"`")),
+            }
+        }
+    }
+    let fields = split_entries(&item.body)
+        .into_iter()
+        .map(parse_field)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut body = format!("let entity = diagram.entity({:?});\n", entity_name);
+    for field in fields.iter().filter(|f| !f.skip) {
+        writeln!(
+            body,
+            "entity.{}({:?}, {:?});",
+            if field.key { "key" } else { "column" },
+            field.name,
+            field.ty
+        )
+        .unwrap();
+    }
+    for field in &fields {
+        if let Some(target) = &field.references {
+            writeln!(
+                body,
+                "diagram.relation(
+                    {:?},
+                    &<{} as ::pikchr::er::ErEntity>::entity_name(),
+                    ::pikchr::er::Cardinality::ManyToOne,
+                    {},
+                );",
+                entity_name,
+                target,
+                option_str(Some(&field.name))
+            )
+            .unwrap();
+        }
+    }
+
+    let output = format!(
+        "impl ::pikchr::er::ErEntity for {} {{
+            fn entity_name() -> ::std::string::String {{
+                ::std::string::String::from({:?})
+            }}
+
+            fn describe(diagram: &mut ::pikchr::er::ErDiagram) {{
+                {}
+            }}
+        }}",
+        item.name, entity_name, body
+    );
+    Ok(output.parse().expect("generated code should parse"))
+}
@@ -0,0 +1,50 @@
+use pikchr::er::{ErDiagram, ErEntity};
+use pikchr::{PikchrEntity, PikchrFlags};
+use std::collections::HashMap;
+
+#[derive(PikchrEntity)]
+#[allow(dead_code)]
+struct Customer {
+    #[pikchr(key)]
+    pub id: u64,
+    pub(crate) name: String,
+    attributes: HashMap<String, Vec<u8>>,
+    callback: Option<fn(u32) -> bool>,
+}
+
+#[derive(PikchrEntity)]
+#[pikchr(name = "orders")]
+#[allow(dead_code)]
+struct Order {
+    #[pikchr(key)]
+    id: u64,
+    #[pikchr(references = Customer)]
+    customer: u64,
+    lines: [u32; 4],
+}
+
+#[test]
+fn entity_names_and_columns() {
+    assert_eq!(Customer::entity_name(), "Customer");
+    assert_eq!(Order::entity_name(), "orders");
+    let mut er = ErDiagram::new();
+    er.add::<Customer>();
+    assert_eq!(
+        er.entities()[0].column_names().collect::<Vec<_>>(),
+        ["id", "name", "attributes", "callback"]
+    );
+    let source = er.to_source();
+    assert!(source.contains("\"attributes: HashMap<String, Vec<u8>>\""));
+    assert!(source.contains("\"callback: Option<fn(u32)->bool>\""));
+}
+
+#[test]
+fn references_become_relations() {
+    let mut er = ErDiagram::new();
+    er.add::<Order>().add::<Customer>();
+    assert_eq!(er.entities().len(), 2);
+    let source = er.to_source();
+    assert!(source.contains("\"customer (N:1)\""));
+    assert!(source.contains("\"lines: [u32; 4]\""));
+    assert!(er.render(None, PikchrFlags::default()).is_ok());
+}
@@ -0,0 +1,35 @@
+use pikchr::state::StateDiagram;
+use pikchr::{PikchrFlags, PikchrStateDiagram};
+
+#[derive(PikchrStateDiagram)]
+#[allow(dead_code)]
+pub(crate) enum Connection {
+    #[pikchr(initial)]
+    #[transition(to = Connecting, label = "connect()")]
+    Disconnected,
+    #[transition(to = Connected)]
+    #[transition(to = Disconnected, label = "timeout")]
+    Connecting { attempt: u32 },
+    #[transition(to = Disconnected, label = r"close()")]
+    Connected(u64),
+}
+
+#[test]
+fn derived_machine_lists_every_variant() {
+    let machine = Connection::state_machine();
+    assert_eq!(
+        machine.states(),
+        ["Disconnected", "Connecting", "Connected"]
+    );
+}
+
+#[test]
+fn derived_machine_renders() {
+    let source = Connection::pikchr_source();
+    assert!(source.contains("\"connect()\""));
+    assert!(source.contains("\"close()\""));
+    let pic = Connection::state_machine()
+        .render(None, PikchrFlags::default())
+        .unwrap();
+    assert!(pic.contains("Connecting"));
+}
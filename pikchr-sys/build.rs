@@ -0,0 +1,23 @@
+use std::env;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/pikchr.c");
+    println!("cargo:rustc-check-cfg=cfg(pikchr_c)");
+    // wasm32-unknown-unknown has no C library for pikchr.c to use, so
+    // nothing is built there and the bindings are left out
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    if arch == "wasm32" && os == "unknown" {
+        return;
+    }
+    let mut build = cc::Build::new();
+    build.file("src/pikchr.c");
+    if env::var_os("CARGO_FEATURE_RUST_ALLOC").is_some() {
+        build.define("PIKCHR_RUST_ALLOC", None);
+    }
+    build.compile("pikchr");
+    println!("cargo:rustc-cfg=pikchr_c");
+    // Tells crates which depend on this one that the C is available, as
+    // DEP_PIKCHR_C
+    println!("cargo:c=1");
+}
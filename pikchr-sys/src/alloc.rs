@@ -0,0 +1,145 @@
+//! Counting the memory which pikchr allocates
+//!
+//! With the `rust-alloc` feature, `pikchr.c` calls the functions here
+//! rather than `malloc()`, `realloc()` and `free()`, and they allocate with
+//! the Rust global allocator.  Each thread keeps count of what it
+//! allocates, and [`track`] reports what was allocated while a closure ran.
+
+use core::ffi::c_void;
+use core::ptr::null_mut;
+use std::alloc::{alloc, dealloc, realloc, Layout};
+use std::cell::Cell;
+
+/// The size of the header at the start of each allocation, which holds
+/// its size and keeps the memory after it aligned as `malloc()` must
+const HEADER: usize = 16;
+
+/// The memory allocated by pikchr on a thread
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// The most memory which was allocated at any one time, in bytes
+    pub peak: usize,
+    /// All the memory which was allocated, in bytes, counting only the
+    /// growth of reallocations
+    pub total: usize,
+    /// The number of allocations and reallocations
+    pub allocations: usize,
+}
+
+/// The counts since the innermost call of [`track`] began
+#[derive(Copy, Clone, Default)]
+struct Counter {
+    /// The memory allocated and not yet freed, which is negative if more
+    /// was freed than allocated
+    live: isize,
+    stats: AllocStats,
+}
+
+thread_local! {
+    static COUNTER: Cell<Counter> = Cell::new(Counter::default());
+}
+
+/// Count an allocation growing or shrinking from one size to another
+fn count(old: usize, new: usize) {
+    COUNTER.with(|counter| {
+        let mut c = counter.get();
+        c.live += new as isize - old as isize;
+        c.stats.peak = c.stats.peak.max(c.live.max(0) as usize);
+        if new > old {
+            c.stats.total += new - old;
+            c.stats.allocations += 1;
+        }
+        counter.set(c);
+    });
+}
+
+/// Run a closure, reporting the memory which pikchr allocated on this
+/// thread while it ran
+///
+/// Calls may be nested, and the memory counted by an inner call is
+/// counted by the outer one too.
+pub fn track<R>(f: impl FnOnce() -> R) -> (R, AllocStats) {
+    let outer = COUNTER.with(|counter| counter.replace(Counter::default()));
+    let result = f();
+    let inner = COUNTER.with(|counter| counter.get());
+    COUNTER.with(|counter| {
+        counter.set(Counter {
+            live: outer.live + inner.live,
+            stats: AllocStats {
+                peak: outer
+                    .stats
+                    .peak
+                    .max((outer.live.max(0) as usize) + inner.stats.peak),
+                total: outer.stats.total + inner.stats.total,
+                allocations: outer.stats.allocations + inner.stats.allocations,
+            },
+        })
+    });
+    (result, inner.stats)
+}
+
+/// The layout of an allocation of some size with its header
+fn layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(HEADER)?, HEADER).ok()
+}
+
+/// `malloc()` for `pikchr.c`
+///
+/// # Safety
+///
+/// The memory must be freed with [`pikchr_rust_free`].
+#[no_mangle]
+pub unsafe extern "C" fn pikchr_rust_malloc(size: usize) -> *mut c_void {
+    let base = match layout(size) {
+        Some(layout) => alloc(layout),
+        None => return null_mut(),
+    };
+    if base.is_null() {
+        return null_mut();
+    }
+    (base as *mut usize).write(size);
+    count(0, size);
+    base.add(HEADER) as *mut c_void
+}
+
+/// `realloc()` for `pikchr.c`
+///
+/// # Safety
+///
+/// `ptr` must be null or come from [`pikchr_rust_malloc`] or
+/// `pikchr_rust_realloc`.
+#[no_mangle]
+pub unsafe extern "C" fn pikchr_rust_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return pikchr_rust_malloc(size);
+    }
+    let base = (ptr as *mut u8).sub(HEADER);
+    let old = (base as *mut usize).read();
+    if layout(size).is_none() {
+        return null_mut();
+    }
+    let base = realloc(base, layout(old).unwrap(), size + HEADER);
+    if base.is_null() {
+        return null_mut();
+    }
+    (base as *mut usize).write(size);
+    count(old, size);
+    base.add(HEADER) as *mut c_void
+}
+
+/// `free()` for `pikchr.c`, and for the buffer which `pikchr()` returns
+///
+/// # Safety
+///
+/// `ptr` must be null or come from [`pikchr_rust_malloc`] or
+/// [`pikchr_rust_realloc`], and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn pikchr_rust_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let base = (ptr as *mut u8).sub(HEADER);
+    let old = (base as *mut usize).read();
+    dealloc(base, layout(old).unwrap());
+    count(old, 0);
+}
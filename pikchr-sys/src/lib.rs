@@ -0,0 +1,101 @@
+//! Raw bindings to pikchr
+//!
+//! This crate builds the vendored copy of `pikchr.c` and declares the
+//! functions it provides.  The safe wrapper is the `pikchr` crate, which
+//! re-exports this one as `pikchr::raw`.
+//!
+//! On `wasm32-unknown-unknown` there is no C library to build pikchr
+//! against, so only the flag constants are available.
+//!
+//! With the `rust-alloc` feature, pikchr allocates its memory with the
+//! Rust allocator, and [`alloc::track`] counts how much a render uses.
+
+#![cfg_attr(not(feature = "rust-alloc"), no_std)]
+
+#[cfg(all(pikchr_c, feature = "rust-alloc"))]
+pub mod alloc;
+#[cfg(all(pikchr_c, feature = "rust-alloc"))]
+pub use alloc::pikchr_rust_free as free;
+
+use core::ffi::c_uint;
+#[cfg(pikchr_c)]
+use core::ffi::{c_char, c_int, c_void};
+
+#[cfg(pikchr_c)]
+extern "C" {
+    /// The main interface.  Invoke this routine to translate PIKCHR source
+    /// text into SVG. The SVG is returned in a buffer obtained from malloc().
+    /// The caller is responsible for freeing the buffer.
+    ///
+    /// If an error occurs, *pnWidth is filled with a negative number and
+    /// the return buffer contains error message text instead of SVG.  By
+    /// default, the error message is HTML encoded.  However, error messages
+    /// come out as plaintext if the PIKCHR_PLAINTEXT_ERRORS flag is included
+    /// as one of the bits in the mFlags parameter.
+    ///
+    /// - `zText`: Input PIKCHR source text.  zero-terminated
+    /// - `zClass`: Add class="%s" to <svg> markup
+    /// - `mFlags`: Flags used to influence rendering behavior
+    /// - `pnWidth`: OUT: Write width of <svg> here, if not NULL
+    /// - `pnHeight`: OUT: Write height here, if not NULL
+    #[allow(non_snake_case)]
+    pub fn pikchr(
+        zText: *const c_char,
+        zClass: *const c_char,
+        mFlags: c_uint,
+        pnWidth: *mut c_int,
+        pnHeight: *mut c_int,
+    ) -> *mut c_char;
+
+    /// The same as [`pikchr`], but measuring the width of text with
+    /// `xMeasure`, if it is not `None`, rather than with the built-in
+    /// estimate.  `pMeasureArg` is passed to each call of `xMeasure`.
+    ///
+    /// This is not part of upstream pikchr; it is added to the vendored
+    /// copy for the `pikchr` crate.
+    #[allow(non_snake_case)]
+    pub fn pikchr_measured(
+        zText: *const c_char,
+        zClass: *const c_char,
+        mFlags: c_uint,
+        pnWidth: *mut c_int,
+        pnHeight: *mut c_int,
+        xMeasure: PikchrMeasure,
+        pMeasureArg: *mut c_void,
+    ) -> *mut c_char;
+
+}
+
+#[cfg(all(pikchr_c, not(feature = "rust-alloc")))]
+extern "C" {
+    /// Release memory obtained from malloc(), such as the buffer which
+    /// [`pikchr`] returns.  This comes from the C library which pikchr
+    /// itself is linked against.
+    pub fn free(ptr: *mut c_void);
+}
+
+/// A callback which measures text for [`pikchr_measured`]
+///
+/// The arguments are `pMeasureArg`, then the text of a string literal
+/// without its quotes and its length in bytes, so it may still contain
+/// backslash escapes and HTML entities, then the width of an average
+/// character of the text in inches and the size of its font relative to
+/// normal text, both including `fontscale` and any `big` or `small`.  The
+/// result is the width of the text in inches, or a negative number to use
+/// the built-in estimate instead.
+#[cfg(pikchr_c)]
+pub type PikchrMeasure =
+    Option<unsafe extern "C" fn(*mut c_void, *const c_char, c_int, f64, f64) -> f64>;
+
+/// Include PIKCHR_PLAINTEXT_ERRORS among the bits of mFlags on the 3rd
+/// argument to pikchr() in order to cause error message text to come out
+/// as text/plain instead of as text/html
+pub const PIKCHR_PLAINTEXT_ERRORS: c_uint = 0x0001;
+
+/// Alter colour choices to make diagrams more suitable for rendering in
+/// a dark settings such as dark-mode web pages.
+pub const PIKCHR_DARK_MODE: c_uint = 0x0002;
+
+/// The date on which the vendored `pikchr.c` was taken from the pikchr
+/// website, as YYYY-MM-DD
+pub const PIKCHR_DATE: &str = "2021-05-08";
@@ -122,6 +122,17 @@
 #include <ctype.h>
 #include <math.h>
 #include <assert.h>
+#ifdef PIKCHR_RUST_ALLOC
+/* Allocate through the Rust allocator, so that the pikchr-sys crate can
+** count the memory each render uses.  This is not part of upstream
+** pikchr; it is added to the vendored copy for the pikchr crate. */
+void *pikchr_rust_malloc(size_t);
+void *pikchr_rust_realloc(void*, size_t);
+void pikchr_rust_free(void*);
+#define malloc pikchr_rust_malloc
+#define realloc pikchr_rust_realloc
+#define free pikchr_rust_free
+#endif
 #define count(X) (sizeof(X)/sizeof(X[0]))
 #ifndef M_PI
 # define M_PI 3.1415926535897932385
@@ -337,6 +348,25 @@ struct PMacro {
   int inUse;           /* Do not allow recursion */
 };
 
+/* A callback which measures text for pikchr_measured().  zText is the
+** text of a string literal without its quotes, so it may still contain
+** backslash escapes and HTML entities, and nText is its length in bytes.
+** rCharWidth is the width of an average character of the text, in
+** inches, and rFontScale is the size of its font relative to normal text,
+** both including "fontscale" and any "big" or "small".  The result is the
+** width of the text in inches, or a negative number to use the built-in
+** estimate instead.
+*/
+typedef double (*PikchrMeasure)(
+  void *pArg,
+  const char *zText,
+  int nText,
+  double rCharWidth,
+  double rFontScale
+);
+char *pikchr_measured(const char*, const char*, unsigned int, int*, int*,
+                      PikchrMeasure, void*);
+
 /* Each call to the pikchr() subroutine uses an instance of the following
 ** object to pass around context to all of its subroutines.
 */
@@ -375,6 +405,9 @@ struct Pik {
   /* Error contexts */
   unsigned int nCtx;       /* Number of error contexts */
   PToken aCtx[10];         /* Nested error contexts */
+  /* Text measurement, from pikchr_measured() */
+  PikchrMeasure xMeasure;  /* Measures text, or NULL for the estimate */
+  void *pMeasureArg;       /* First argument to xMeasure */
 };
 
 /* Include PIKCHR_PLAINTEXT_ERRORS among the bits of mFlags on the 3rd
@@ -4936,7 +4969,13 @@ static void pik_append_txt(Pik *p, PObj *pObj, PBox *pBox){
     if( pBox!=0 ){
       /* If pBox is not NULL, do not draw any <text>.  Instead, just expand
       ** pBox to include the text */
-      PNum cw = pik_text_length(t)*p->charWidth*xtraFontScale*0.01;
+      PNum cw = -1.0;
+      if( p->xMeasure ){
+        cw = p->xMeasure(p->pMeasureArg, t->z+1, t->n-2,
+                         p->charWidth*xtraFontScale,
+                         p->fontScale*xtraFontScale);
+      }
+      if( cw<0.0 ) cw = pik_text_length(t)*p->charWidth*xtraFontScale*0.01;
       PNum ch = p->charHeight*0.5*xtraFontScale;
       PNum x0, y0, x1, y1;  /* Boundary of text relative to pObj->ptAt */
       if( t->eCode & TP_BOLD ) cw *= 1.1;
@@ -7660,6 +7699,23 @@ char *pikchr(
   unsigned int mFlags,   /* Flags used to influence rendering behavior */
   int *pnWidth,          /* Write width of <svg> here, if not NULL */
   int *pnHeight          /* Write height here, if not NULL */
+){
+  return pikchr_measured(zText, zClass, mFlags, pnWidth, pnHeight, 0, 0);
+}
+
+/*
+** The same as pikchr(), but measuring the width of text with xMeasure,
+** if it is not NULL, rather than with the built-in estimate.  This is
+** not part of upstream pikchr; it was added for the Rust bindings.
+*/
+char *pikchr_measured(
+  const char *zText,     /* Input PIKCHR source text.  zero-terminated */
+  const char *zClass,    /* Add class="%s" to <svg> markup */
+  unsigned int mFlags,   /* Flags used to influence rendering behavior */
+  int *pnWidth,          /* Write width of <svg> here, if not NULL */
+  int *pnHeight,         /* Write height here, if not NULL */
+  PikchrMeasure xMeasure, /* Measures the width of text, or NULL */
+  void *pMeasureArg      /* First argument to xMeasure */
 ){
   Pik s;
   yyParser sParse;
@@ -7670,6 +7726,8 @@ char *pikchr(
   s.eDir = DIR_RIGHT;
   s.zClass = zClass;
   s.mFlags = mFlags;
+  s.xMeasure = xMeasure;
+  s.pMeasureArg = pMeasureArg;
   pik_parserInit(&sParse, &s);
 #if 0
   pik_parserTrace(stdout, "parser: ");
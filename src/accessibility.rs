@@ -0,0 +1,169 @@
+//! Describing diagrams to screen readers
+//!
+//! An SVG with no title is skipped by screen readers, or read out as a
+//! jumble of its labels.  [`Pikchr::with_accessibility`] gives a diagram
+//! `role="img"`, with a `<title>` and `<desc>` which it is labelled and
+//! described by, or hides a diagram which is only decoration.  The same
+//! can be done while rendering with
+//! [`RenderOptions::accessibility`](crate::RenderOptions::accessibility).
+//!
+//! ```
+//! use pikchr::{accessibility::AccessibilityOptions, Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box \"A\"; arrow; box \"B\"", None, PikchrFlags::default()).unwrap();
+//! let mut options = AccessibilityOptions::default();
+//! options.title("A leads to B").description("Two boxes joined by an arrow");
+//! let pic = pic.with_accessibility(&options).unwrap();
+//! let dom = pic.dom().unwrap();
+//! let svg = dom.svg().unwrap();
+//! assert_eq!(svg.attr("role"), Some("img"));
+//! let title = svg.elements().next().unwrap();
+//! assert_eq!(title.text(), "A leads to B");
+//! assert_eq!(svg.attr("aria-labelledby"), title.attr("id"));
+//! ```
+//!
+//! [`Pikchr::with_accessibility`]: crate::Pikchr::with_accessibility
+
+use crate::hash::sha256;
+use crate::svg::{escape, Dom, Element, Node};
+
+/// What to tell screen readers about a diagram
+///
+/// You can construct the default options using the [`std::default::Default`]
+/// trait, which give a diagram `role="img"` and nothing else.
+#[derive(Clone, Debug, Default)]
+pub struct AccessibilityOptions {
+    title: Option<String>,
+    description: Option<String>,
+    lang: Option<String>,
+    decorative: bool,
+}
+
+impl AccessibilityOptions {
+    /// Set a short name for the diagram, which it is labelled by
+    pub fn title(&mut self, title: &str) -> &mut AccessibilityOptions {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    /// Set a longer description of what the diagram shows
+    pub fn description(&mut self, description: &str) -> &mut AccessibilityOptions {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Set the language of the title, description and labels, such as
+    /// `en` or `fr-CA`
+    pub fn lang(&mut self, lang: &str) -> &mut AccessibilityOptions {
+        self.lang = Some(lang.to_string());
+        self
+    }
+
+    /// Set whether the diagram is only decoration, which screen readers
+    /// should skip, in which case it has no title or description
+    pub fn decorative(&mut self, decorative: bool) -> &mut AccessibilityOptions {
+        self.decorative = decorative;
+        self
+    }
+}
+
+/// An element with some text in it
+fn text_element(name: &str, id: &str, text: &str) -> Element {
+    let mut element = Element::new(name);
+    element.set_attr("id", id);
+    element.children.push(Node::Text(escape(text)));
+    element
+}
+
+/// Add the accessibility attributes and elements to a rendered diagram
+pub(crate) fn annotate(dom: &mut Dom, options: &AccessibilityOptions) {
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return,
+    };
+    // Any title and description from before are replaced
+    svg.children
+        .retain(|node| !matches!(node, Node::Element(e) if e.name == "title" || e.name == "desc"));
+    for name in &["role", "aria-hidden", "aria-labelledby", "aria-describedby"] {
+        svg.remove_attr(name);
+    }
+    if let Some(lang) = &options.lang {
+        svg.set_attr("lang", &escape(lang));
+    }
+    if options.decorative {
+        svg.set_attr("role", "presentation")
+            .set_attr("aria-hidden", "true");
+        return;
+    }
+    svg.set_attr("role", "img");
+    // The ids must be unique in the page the diagram is part of
+    let prefix = match svg.attr("id") {
+        Some(id) => id.to_string(),
+        None => {
+            let digest = sha256(svg.to_string().as_bytes()).to_string();
+            format!("pikchr-{}", &digest[..12])
+        }
+    };
+    let mut elements = Vec::new();
+    if let Some(title) = &options.title {
+        let id = format!("{}-title", prefix);
+        svg.set_attr("aria-labelledby", &id);
+        elements.push(Node::Element(text_element("title", &id, title)));
+    }
+    if let Some(description) = &options.description {
+        let id = format!("{}-desc", prefix);
+        svg.set_attr("aria-describedby", &id);
+        elements.push(Node::Element(text_element("desc", &id, description)));
+    }
+    // The title must be the first child of the SVG to name it
+    svg.children.splice(0..0, elements);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    fn annotated(options: &AccessibilityOptions) -> Dom {
+        let pic = Pikchr::render("box \"x\"", None, PikchrFlags::default()).unwrap();
+        let mut dom = pic.dom().unwrap();
+        annotate(&mut dom, options);
+        dom
+    }
+
+    #[test]
+    fn escapes_text() {
+        let mut options = AccessibilityOptions::default();
+        options.title("A < B & C").lang("en\"");
+        let dom = annotated(&options);
+        let svg = dom.svg().unwrap();
+        assert_eq!(svg.attr("lang"), Some("en&quot;"));
+        assert!(svg.to_string().contains(">A &lt; B &amp; C</title>"));
+        assert_eq!(svg.attr("aria-describedby"), None);
+    }
+
+    #[test]
+    fn hides_decorations() {
+        let mut options = AccessibilityOptions::default();
+        options.title("Unused").decorative(true);
+        let dom = annotated(&options);
+        let svg = dom.svg().unwrap();
+        assert_eq!(svg.attr("aria-hidden"), Some("true"));
+        assert!(svg.find_all("title").is_empty());
+    }
+
+    #[test]
+    fn replaces_earlier_titles() {
+        let mut options = AccessibilityOptions::default();
+        options.title("First").description("About it");
+        let mut dom = annotated(&options);
+        options.title("Second");
+        annotate(&mut dom, &options);
+        let svg = dom.svg().unwrap();
+        let names: Vec<_> = svg.elements().map(|e| e.name.as_str()).take(3).collect();
+        assert_eq!(names, ["title", "desc", "path"]);
+        assert_eq!(svg.elements().next().unwrap().text(), "Second");
+        let id = svg.attr("aria-describedby").unwrap();
+        assert!(id.starts_with("pikchr-") && id.ends_with("-desc"));
+    }
+}
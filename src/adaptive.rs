@@ -0,0 +1,152 @@
+//! Diagrams which follow the reader's color scheme
+//!
+//! pikchr draws a diagram either for a light background or, with
+//! [`PikchrFlags::use_dark_mode`](crate::PikchrFlags::use_dark_mode), for
+//! a dark one, but a page which follows the reader's color scheme needs
+//! both.  [`Pikchr::render_adaptive`] renders the diagram both ways and
+//! keeps the light one, with a `<style>` which gives each element its dark
+//! colors when the reader prefers a dark color scheme.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render_adaptive("box \"hi\"", None, PikchrFlags::default()).unwrap();
+//! assert!(pic.contains("@media (prefers-color-scheme: dark)"));
+//! assert!(pic.contains("stroke:rgb(0,0,0);"));
+//! assert!(pic.contains("{stroke:rgb(255,255,255) !important;}"));
+//! ```
+//!
+//! Each set of dark colors has a class named after a digest of them, so
+//! that several diagrams can share a page without their styles clashing.
+//!
+//! [`Pikchr::render_adaptive`]: crate::Pikchr::render_adaptive
+
+use crate::hash::sha256;
+use crate::svg::{Dom, Element, Node};
+use std::collections::BTreeMap;
+
+/// The `property:value` declarations of a `style` attribute
+fn declarations(style: &str) -> Vec<(&str, &str)> {
+    style
+        .split(';')
+        .filter_map(|d| d.split_once(':'))
+        .map(|(property, value)| (property.trim(), value.trim()))
+        .collect()
+}
+
+/// The CSS which gives an element the colors of its dark counterpart, if
+/// they differ
+fn dark_css(light: &Element, dark: &Element) -> Option<String> {
+    let mut css = String::new();
+    let light_style = declarations(light.attr("style").unwrap_or(""));
+    for (property, value) in declarations(dark.attr("style").unwrap_or("")) {
+        if !light_style.contains(&(property, value)) {
+            css.push_str(&format!("{}:{} !important;", property, value));
+        }
+    }
+    // Text is colored with presentation attributes, which are also CSS
+    // properties
+    for (name, value) in &dark.attributes {
+        if name != "style" && light.attr(name) != Some(value.as_str()) {
+            css.push_str(&format!("{}:{} !important;", name, value));
+        }
+    }
+    if css.is_empty() {
+        None
+    } else {
+        Some(css)
+    }
+}
+
+/// Add a class to an element
+fn add_class(element: &mut Element, class: &str) {
+    let classes = match element.attr("class") {
+        Some(classes) => format!("{} {}", classes, class),
+        None => class.to_string(),
+    };
+    element.set_attr("class", &classes);
+}
+
+/// Give the elements of a light diagram the classes for their dark
+/// colors, collecting the rules for each class
+fn mark(light: &mut Element, dark: &Element, rules: &mut BTreeMap<String, String>) {
+    if let Some(css) = dark_css(light, dark) {
+        let digest = sha256(css.as_bytes()).to_string();
+        let class = format!("pikchr-dark-{}", &digest[..12]);
+        add_class(light, &class);
+        rules.insert(class, css);
+    }
+    for (light, dark) in light.elements_mut().zip(dark.elements()) {
+        // Both come from the same source, so they only differ in color
+        if light.name == dark.name {
+            mark(light, dark, rules);
+        }
+    }
+}
+
+/// Add the colors of the dark rendering of a diagram to the light one
+pub(crate) fn combine(light: &mut Dom, dark: &Dom) {
+    let (light, dark) = match (light.svg_mut(), dark.svg()) {
+        (Some(light), Some(dark)) => (light, dark),
+        _ => return,
+    };
+    let mut rules = BTreeMap::new();
+    mark(light, dark, &mut rules);
+    if rules.is_empty() {
+        return;
+    }
+    let mut css = String::from("@media (prefers-color-scheme: dark) {");
+    for (class, declarations) in &rules {
+        css.push_str(&format!(".{}{{{}}}", class, declarations));
+    }
+    css.push('}');
+    let mut style = Element::new("style");
+    style.children.push(Node::Text(css));
+    light.children.insert(0, Node::Element(style));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn styles_only_what_changes() {
+        let light = Element {
+            name: "path".to_string(),
+            attributes: vec![(
+                "style".to_string(),
+                "fill:none;stroke-width:2.16;stroke:rgb(0,0,0);".to_string(),
+            )],
+            children: vec![],
+        };
+        let mut dark = light.clone();
+        dark.set_attr(
+            "style",
+            "fill:none;stroke-width:2.16;stroke:rgb(255,255,255);",
+        );
+        assert_eq!(
+            dark_css(&light, &dark).as_deref(),
+            Some("stroke:rgb(255,255,255) !important;")
+        );
+        assert_eq!(dark_css(&light, &light), None);
+    }
+
+    #[test]
+    fn shares_classes_between_elements() {
+        let mut flags = PikchrFlags::default();
+        // Dark mode asked for in the flags is ignored
+        flags.use_dark_mode();
+        let pic =
+            Pikchr::render_adaptive("box; box; text \"a\" color red", Some("d"), flags).unwrap();
+        let dom = pic.dom().unwrap();
+        let svg = dom.svg().unwrap();
+        assert_eq!(svg.attr("class"), Some("d"));
+        let classes: Vec<_> = svg.elements().filter_map(|e| e.attr("class")).collect();
+        assert_eq!(classes.len(), 3);
+        assert_eq!(classes[0], classes[1]);
+        assert_ne!(classes[1], classes[2]);
+        assert!(pic.contains("fill:rgb(255,127,127) !important;"));
+        assert!(pic.contains("stroke:rgb(0,0,0);"));
+    }
+}
@@ -0,0 +1,177 @@
+//! Animating the drawing of a diagram
+//!
+//! [`Pikchr::animated`](crate::Pikchr::animated) adds CSS animations to a
+//! rendered diagram so that it draws itself a piece at a time.  The pieces
+//! are the steps of a diagram from
+//! [`Pikchr::render_steps`](crate::Pikchr::render_steps), or else each
+//! element in turn.
+//!
+//! ```
+//! use pikchr::{animation::AnimationOptions, Pikchr, PikchrFlags};
+//! use std::time::Duration;
+//!
+//! let pic = Pikchr::render_steps("box; arrow; circle", None, PikchrFlags::default()).unwrap();
+//! let mut options = AnimationOptions::default();
+//! options.interval(Duration::from_millis(500));
+//! let animated = pic.animated(options).unwrap();
+//! assert!(animated.contains("<g data-step=\"3\" style=\"animation:pikchr-reveal 400ms 1000ms both;\">"));
+//! ```
+//!
+//! Each piece fades in, and lines which are not dashed or filled are also
+//! drawn from start to end.  Viewers who ask for reduced motion see the
+//! diagram without animation.
+
+use crate::svg::{Dom, Element, Node};
+use std::time::Duration;
+
+const KEYFRAMES: &str = "
+@keyframes pikchr-reveal { from { opacity: 0; } to { opacity: 1; } }
+@keyframes pikchr-draw { from { stroke-dashoffset: 1; } to { stroke-dashoffset: 0; } }
+@media (prefers-reduced-motion: reduce) { * { animation: none !important; } }
+";
+
+/// Options for animating a diagram
+///
+/// You can construct the default options using the [`std::default::Default`]
+/// trait, which reveals a piece every 300ms, taking 400ms for each.
+#[derive(Copy, Clone, Debug)]
+pub struct AnimationOptions {
+    duration: Duration,
+    interval: Duration,
+    draw_lines: bool,
+}
+
+impl AnimationOptions {
+    /// Set how long each piece takes to appear
+    pub fn duration(&mut self, duration: Duration) -> &mut AnimationOptions {
+        self.duration = duration;
+        self
+    }
+
+    /// Set the time between one piece starting to appear and the next
+    pub fn interval(&mut self, interval: Duration) -> &mut AnimationOptions {
+        self.interval = interval;
+        self
+    }
+
+    /// Set whether lines are drawn from start to end as they appear, rather
+    /// than only fading in
+    pub fn draw_lines(&mut self, draw_lines: bool) -> &mut AnimationOptions {
+        self.draw_lines = draw_lines;
+        self
+    }
+}
+
+impl std::default::Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_millis(400),
+            interval: Duration::from_millis(300),
+            draw_lines: true,
+        }
+    }
+}
+
+/// Add the animations to a rendered diagram
+pub(crate) fn animate(dom: &mut Dom, options: AnimationOptions) {
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return,
+    };
+    let duration = options.duration.as_millis();
+    let interval = options.interval.as_millis();
+    for (i, piece) in svg.elements_mut().enumerate() {
+        let delay = interval * i as u128;
+        let reveal = format!("pikchr-reveal {}ms {}ms both", duration, delay);
+        if options.draw_lines {
+            piece.for_each_mut(&mut |element| {
+                let style = element.attr("style").unwrap_or("");
+                if element.name == "path"
+                    && style.contains("fill:none;")
+                    && !style.contains("stroke-dasharray")
+                {
+                    element.set_attr("pathLength", "1");
+                    add_style(
+                        element,
+                        &format!(
+                            "stroke-dasharray:1;animation:{},pikchr-draw {}ms {}ms both;",
+                            reveal, duration, delay
+                        ),
+                    );
+                }
+            });
+        }
+        if piece.attr("pathLength").is_none() {
+            add_style(piece, &format!("animation:{};", reveal));
+        }
+    }
+    let mut style = Element::new("style");
+    style.children.push(Node::Text(KEYFRAMES.to_string()));
+    svg.children.insert(0, Node::Element(style));
+}
+
+/// Add CSS to the end of the `style` attribute of an element
+pub(crate) fn add_style(element: &mut Element, css: &str) {
+    let mut style = element.attr("style").unwrap_or("").to_string();
+    if !style.is_empty() && !style.ends_with(';') {
+        style.push(';');
+    }
+    style.push_str(css);
+    element.set_attr("style", &style);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn animates_each_element() {
+        let pic = Pikchr::render(
+            "line; line dashed; box fill red",
+            None,
+            PikchrFlags::default(),
+        )
+        .unwrap();
+        let mut options = AnimationOptions::default();
+        options.duration(Duration::from_secs(1));
+        let dom = pic.animated(options).unwrap().dom().unwrap();
+        let svg = dom.svg().unwrap();
+        assert_eq!(svg.find_all("style").len(), 1);
+        let styles: Vec<_> = svg
+            .elements()
+            .skip(1)
+            .map(|e| (e.attr("pathLength"), e.attr("style").unwrap()))
+            .collect();
+        assert_eq!(
+            styles,
+            [
+                (
+                    Some("1"),
+                    "fill:none;stroke-width:2.16;stroke:rgb(0,0,0);stroke-dasharray:1;\
+                     animation:pikchr-reveal 1000ms 0ms both,pikchr-draw 1000ms 0ms both;"
+                ),
+                (
+                    None,
+                    "fill:none;stroke-width:2.16;stroke:rgb(0,0,0);stroke-dasharray:7.2,7.2;\
+                     animation:pikchr-reveal 1000ms 300ms both;"
+                ),
+                (
+                    None,
+                    "fill:rgb(255,0,0);stroke-width:2.16;stroke:rgb(0,0,0);\
+                     animation:pikchr-reveal 1000ms 600ms both;"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_only_fade() {
+        let pic = Pikchr::render("line", None, PikchrFlags::default()).unwrap();
+        let mut options = AnimationOptions::default();
+        options.draw_lines(false);
+        let animated = pic.animated(options).unwrap();
+        assert!(!animated.contains("pikchr-draw 400ms"));
+        assert!(animated.contains("pikchr-reveal 400ms 0ms"));
+    }
+}
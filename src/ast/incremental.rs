@@ -0,0 +1,412 @@
+//! Updating a syntax tree as its source is edited
+
+use super::parser::Parser;
+use super::*;
+
+/// A syntax tree which is kept up to date as its source is edited
+///
+/// Each edit only reparses the top-level statements which it touches, and
+/// moves the spans of the statements after them, which keeps the cost of
+/// a keystroke low for large diagrams.  The result is always the same as
+/// calling [`parse_recovering`] on the edited source.
+///
+/// ```
+/// use pikchr::ast::{IncrementalParser, Span};
+///
+/// let mut parser = IncrementalParser::new("box\ncircle\narrow");
+/// let reparsed = parser.edit(Span::new(4, 10), "ellipse");
+/// assert_eq!(parser.source(), "box\nellipse\narrow");
+/// assert_eq!(reparsed, Span::new(3, 12));
+/// let last = &parser.document().statements[2];
+/// assert_eq!(last.span.text(parser.source()), "arrow");
+/// ```
+///
+/// Statements are parsed independently, except that macros can be used
+/// anywhere after they are defined, and an error may swallow the rest of
+/// a diagram.  Edits to source which defines macros or contains errors
+/// therefore fall back to parsing the whole source.
+#[derive(Clone, Debug)]
+pub struct IncrementalParser {
+    source: String,
+    document: Document,
+    errors: Vec<ParseError>,
+}
+
+impl IncrementalParser {
+    /// Parse the initial source
+    pub fn new(source: &str) -> Self {
+        let (document, errors) = parse_recovering(source);
+        IncrementalParser {
+            source: source.to_string(),
+            document,
+            errors,
+        }
+    }
+
+    /// The current source
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The syntax tree of the current source
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    /// The errors found in the current source
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Replace the source in `range` with `text`
+    ///
+    /// This returns the part of the new source which was reparsed.  Spans
+    /// in the tree which are outside it may have moved, but nothing else
+    /// about them has changed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds or does not fall on character
+    /// boundaries, like [`String::replace_range`].
+    pub fn edit(&mut self, range: Span, text: &str) -> Span {
+        self.source.replace_range(range.start..range.end, text);
+        let delta = text.len() as isize - range.len() as isize;
+        match self.reparse(range, delta) {
+            Some(reparsed) => reparsed,
+            None => {
+                let (document, errors) = parse_recovering(&self.source);
+                self.document = document;
+                self.errors = errors;
+                Span::new(0, self.source.len())
+            }
+        }
+    }
+
+    /// Reparse the statements touched by an edit, if that gives the same
+    /// result as parsing everything
+    fn reparse(&mut self, range: Span, delta: isize) -> Option<Span> {
+        let statements = &self.document.statements;
+        let defines = statements
+            .iter()
+            .any(|s| s.macro_call.is_some() || matches!(s.kind, StatementKind::Define { .. }));
+        if !self.errors.is_empty() || defines {
+            return None;
+        }
+        // Statements which end before the edit, or start after it, are
+        // kept, and the source between them is reparsed
+        let first = statements.partition_point(|s| s.span.end < range.start);
+        let last = statements.partition_point(|s| s.span.start <= range.end);
+        let start = match first {
+            0 => 0,
+            n => statements[n - 1].span.end,
+        };
+        let end = match statements.get(last) {
+            Some(next) => shift(next.span.start, delta),
+            None => self.source.len(),
+        };
+        let reparsed = Span::new(start, end);
+        if !self.separated(reparsed) {
+            return None;
+        }
+        let (document, errors) = Parser::new_range(&self.source, reparsed).parse();
+        let defines = document
+            .statements
+            .iter()
+            .any(|s| matches!(s.kind, StatementKind::Define { .. }));
+        if !errors.is_empty() || defines {
+            return None;
+        }
+        let mut after = self.document.statements.split_off(last);
+        for statement in &mut after {
+            shift_statement(statement, delta);
+        }
+        self.document.statements.truncate(first);
+        self.document.statements.extend(document.statements);
+        self.document.statements.append(&mut after);
+        Some(reparsed)
+    }
+
+    /// Whether a range of the source is separated from the statements on
+    /// either side of it, which an edit may have changed
+    fn separated(&self, range: Span) -> bool {
+        let tokens = tokenize(range.text(&self.source));
+        let first = tokens.iter().find(|t| !t.kind.is_trivia());
+        let last = tokens.iter().rev().find(|t| !t.kind.is_trivia());
+        let eol = |t: Option<&Token>| matches!(t, Some(t) if t.kind == TokenKind::Eol);
+        (range.start == 0 || eol(first)) && (range.end == self.source.len() || eol(last))
+    }
+}
+
+fn shift(offset: usize, delta: isize) -> usize {
+    (offset as isize + delta) as usize
+}
+
+fn shift_span(span: &mut Span, delta: isize) {
+    span.start = shift(span.start, delta);
+    span.end = shift(span.end, delta);
+}
+
+fn shift_name(name: &mut Name, delta: isize) {
+    shift_span(&mut name.span, delta);
+}
+
+fn shift_statement(statement: &mut Statement, delta: isize) {
+    shift_span(&mut statement.span, delta);
+    if let Some(label) = &mut statement.label {
+        shift_name(label, delta);
+    }
+    if let Some(call) = &mut statement.macro_call {
+        shift_span(call, delta);
+    }
+    match &mut statement.kind {
+        StatementKind::Direction(_) => {}
+        StatementKind::Assignment {
+            variable, value, ..
+        } => {
+            shift_name(variable, delta);
+            shift_expr(value, delta);
+        }
+        StatementKind::Object(object) => shift_object(object, delta),
+        StatementKind::Place(position) => shift_position(position, delta),
+        StatementKind::Print(items) => {
+            for item in items {
+                match item {
+                    PrintItem::Text(text) => shift_span(&mut text.span, delta),
+                    PrintItem::Value(expr) => shift_expr(expr, delta),
+                }
+            }
+        }
+        StatementKind::Assert(Assertion::Equal(lhs, rhs)) => {
+            shift_expr(lhs, delta);
+            shift_expr(rhs, delta);
+        }
+        StatementKind::Assert(Assertion::SamePosition(lhs, rhs)) => {
+            shift_position(lhs, delta);
+            shift_position(rhs, delta);
+        }
+        StatementKind::Define { name, body } => {
+            shift_name(name, delta);
+            shift_span(body, delta);
+        }
+    }
+}
+
+fn shift_object(object: &mut Object, delta: isize) {
+    shift_span(&mut object.span, delta);
+    match &mut object.kind {
+        ObjectKind::Class(name) => shift_name(name, delta),
+        ObjectKind::Text(text) => shift_span(&mut text.span, delta),
+        ObjectKind::Sublist(statements) => {
+            for statement in statements {
+                shift_statement(statement, delta);
+            }
+        }
+    }
+    for attribute in &mut object.attributes {
+        shift_span(&mut attribute.span, delta);
+        match &mut attribute.kind {
+            AttributeKind::Numeric { value, .. } => shift_rel(value, delta),
+            AttributeKind::Dash { spacing, .. } => {
+                if let Some(spacing) = spacing {
+                    shift_expr(spacing, delta);
+                }
+            }
+            AttributeKind::Color { value, .. } => shift_expr(value, delta),
+            AttributeKind::Go { distance, .. } | AttributeKind::EdgeHeading { distance, .. } => {
+                if let Some(distance) = distance {
+                    shift_rel(distance, delta);
+                }
+            }
+            AttributeKind::Heading {
+                distance, heading, ..
+            } => {
+                if let Some(distance) = distance {
+                    shift_rel(distance, delta);
+                }
+                shift_expr(heading, delta);
+            }
+            AttributeKind::Even { position, .. }
+            | AttributeKind::From(position)
+            | AttributeKind::To(position)
+            | AttributeKind::At(position)
+            | AttributeKind::With { position, .. } => shift_position(position, delta),
+            AttributeKind::Same(object) => {
+                if let Some(object) = object {
+                    shift_object_ref(object, delta);
+                }
+            }
+            AttributeKind::Behind(object) => shift_object_ref(object, delta),
+            AttributeKind::Text(text) => shift_span(&mut text.span, delta),
+            AttributeKind::Then
+            | AttributeKind::Close
+            | AttributeKind::Chop
+            | AttributeKind::Fit
+            | AttributeKind::Flag(_) => {}
+        }
+    }
+}
+
+fn shift_rel(rel: &mut RelExpr, delta: isize) {
+    shift_span(&mut rel.span, delta);
+    shift_expr(&mut rel.expr, delta);
+}
+
+fn shift_expr(expr: &mut Expr, delta: isize) {
+    shift_span(&mut expr.span, delta);
+    match &mut expr.kind {
+        ExprKind::Number(_) => {}
+        ExprKind::Variable(name) | ExprKind::Color(name) => shift_name(name, delta),
+        ExprKind::Unary { operand, .. } => shift_expr(operand, delta),
+        ExprKind::Binary { lhs, rhs, .. } => {
+            shift_expr(lhs, delta);
+            shift_expr(rhs, delta);
+        }
+        ExprKind::Call { args, .. } => {
+            for arg in args {
+                shift_expr(arg, delta);
+            }
+        }
+        ExprKind::Distance(from, to) => {
+            shift_position(from, delta);
+            shift_position(to, delta);
+        }
+        ExprKind::Coordinate { place, .. } => shift_place(place, delta),
+        ExprKind::Property { object, .. } => shift_object_ref(object, delta),
+    }
+}
+
+fn shift_position(position: &mut Position, delta: isize) {
+    shift_span(&mut position.span, delta);
+    match &mut position.kind {
+        PositionKind::Coordinates(x, y) => {
+            shift_expr(x, delta);
+            shift_expr(y, delta);
+        }
+        PositionKind::Place(place) => shift_place(place, delta),
+        PositionKind::Offset { place, x, y, .. } => {
+            shift_place(place, delta);
+            shift_expr(x, delta);
+            shift_expr(y, delta);
+        }
+        PositionKind::Combine(x, y) => {
+            shift_position(x, delta);
+            shift_position(y, delta);
+        }
+        PositionKind::Between { fraction, from, to } => {
+            shift_expr(fraction, delta);
+            shift_position(from, delta);
+            shift_position(to, delta);
+        }
+        PositionKind::Relative {
+            distance, position, ..
+        }
+        | PositionKind::EdgeHeading {
+            distance, position, ..
+        } => {
+            shift_expr(distance, delta);
+            shift_position(position, delta);
+        }
+        PositionKind::Heading {
+            distance,
+            heading,
+            position,
+        } => {
+            shift_expr(distance, delta);
+            shift_expr(heading, delta);
+            shift_position(position, delta);
+        }
+    }
+}
+
+fn shift_place(place: &mut Place, delta: isize) {
+    shift_span(&mut place.span, delta);
+    match &mut place.kind {
+        PlaceKind::Object(object)
+        | PlaceKind::Edge { object, .. }
+        | PlaceKind::Vertex { object, .. } => shift_object_ref(object, delta),
+    }
+}
+
+fn shift_object_ref(object: &mut ObjectRef, delta: isize) {
+    shift_span(&mut object.span, delta);
+    match &mut object.kind {
+        ObjectRefKind::This => {}
+        ObjectRefKind::Named(names) => {
+            for name in names {
+                shift_name(name, delta);
+            }
+        }
+        ObjectRefKind::Nth { nth, within } => {
+            if let NthClass::Class(name) = &mut nth.class {
+                shift_name(name, delta);
+            }
+            if let Some(within) = within {
+                shift_object_ref(within, delta);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply each edit in turn, checking the result against a full parse
+    fn check(source: &str, edits: &[(usize, usize, &str)]) -> Vec<Span> {
+        let mut parser = IncrementalParser::new(source);
+        let mut reparsed = Vec::new();
+        for &(start, end, text) in edits {
+            reparsed.push(parser.edit(Span::new(start, end), text));
+            let (document, errors) = parse_recovering(parser.source());
+            assert_eq!(parser.document(), &document, "{}", parser.source());
+            assert_eq!(parser.errors(), &errors[..], "{}", parser.source());
+        }
+        reparsed
+    }
+
+    #[test]
+    fn reparses_only_touched_statements() {
+        let source = "A: box \"one\"\nB: circle at A + (1, 0)\narrow from A to B chop\n";
+        let reparsed = check(
+            source,
+            &[
+                (16, 22, "ellipse"),
+                (0, 0, "down; "),
+                (source.len() + 7, source.len() + 7, "dot at B.s\n"),
+                (10, 10, "wid 2 "),
+            ],
+        );
+        assert_eq!(reparsed[0], Span::new(12, 38));
+        assert_eq!(reparsed[1], Span::new(0, 19));
+        assert_eq!(reparsed[2], Span::new(66, 78));
+    }
+
+    #[test]
+    fn joins_and_splits_statements() {
+        check(
+            "box\ncircle\nline\n",
+            &[
+                (3, 4, ""),
+                (3, 3, ";"),
+                (0, 3, ""),
+                (0, 1, "[ "),
+                (14, 14, " ]"),
+            ],
+        );
+    }
+
+    #[test]
+    fn falls_back_for_errors_and_macros() {
+        let mut parser = IncrementalParser::new("box\ncircle\nline");
+        assert_eq!(parser.edit(Span::new(10, 10), " at"), Span::new(0, 18));
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(parser.edit(Span::new(10, 13), ""), Span::new(0, 15));
+        assert!(parser.errors().is_empty());
+        assert_eq!(parser.edit(Span::new(4, 4), "\"a"), Span::new(0, 17));
+        assert_eq!(parser.edit(Span::new(4, 6), ""), Span::new(0, 15));
+        check(
+            "box\nm\ncircle",
+            &[(0, 0, "define m { line }\n"), (18, 21, "oval"), (0, 17, "")],
+        );
+    }
+}
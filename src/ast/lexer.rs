@@ -0,0 +1,699 @@
+//! Tokenizer for pikchr source
+//!
+//! This mirrors `pik_token_length()` in the C implementation so that the
+//! source is split up in exactly the same way, except that comments are
+//! reported separately from other whitespace.
+
+use super::{Edge, Span};
+
+/// The kind of a [`Token`]
+///
+/// These correspond to the terminal symbols of the pikchr grammar, plus
+/// [`TokenKind::Whitespace`], [`TokenKind::Comment`] and
+/// [`TokenKind::Error`] which the parser never sees.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// A lower-case identifier which is not a keyword, e.g. a variable
+    Id,
+    /// A compass point such as `n`, `se` or `c`
+    EdgePt,
+    /// `of`
+    Of,
+    /// `+`
+    Plus,
+    /// `-`
+    Minus,
+    /// `*`
+    Star,
+    /// `/`
+    Slash,
+    /// `%`
+    Percent,
+    /// A newline or `;`
+    Eol,
+    /// `=`, `+=`, `-=`, `*=` or `/=`
+    Assign,
+    /// An identifier starting with an upper-case letter
+    PlaceName,
+    /// `:`
+    Colon,
+    /// `assert`
+    Assert,
+    /// `(`
+    LParen,
+    /// `==`
+    Eq,
+    /// `)`
+    RParen,
+    /// `define`
+    Define,
+    /// A `{...}` macro body
+    CodeBlock,
+    /// `fill`
+    Fill,
+    /// `color`
+    Color,
+    /// `thickness`
+    Thickness,
+    /// `print`
+    Print,
+    /// A double-quoted string
+    String,
+    /// `,`
+    Comma,
+    /// The name of an object class, such as `box`
+    ClassName,
+    /// `[`
+    LBracket,
+    /// `]`
+    RBracket,
+    /// A numeric literal, possibly with units
+    Number,
+    /// `height` or `ht`
+    Height,
+    /// `width` or `wid`
+    Width,
+    /// `radius` or `rad`
+    Radius,
+    /// `diameter`
+    Diameter,
+    /// `dotted`
+    Dotted,
+    /// `dashed`
+    Dashed,
+    /// `cw`
+    Cw,
+    /// `ccw`
+    Ccw,
+    /// `<-`
+    LArrow,
+    /// `->`
+    RArrow,
+    /// `<->`
+    LRArrow,
+    /// `invis` or `invisible`
+    Invis,
+    /// `thick`
+    Thick,
+    /// `thin`
+    Thin,
+    /// `solid`
+    Solid,
+    /// `center`
+    Center,
+    /// `ljust`
+    LJust,
+    /// `rjust`
+    RJust,
+    /// `above`
+    Above,
+    /// `below`
+    Below,
+    /// `italic`
+    Italic,
+    /// `bold`
+    Bold,
+    /// `aligned`
+    Aligned,
+    /// `big`
+    Big,
+    /// `small`
+    Small,
+    /// `and`
+    And,
+    /// `<`
+    Lt,
+    /// `>`
+    Gt,
+    /// `way`
+    Way,
+    /// `between`
+    Between,
+    /// `the`
+    The,
+    /// An ordinal such as `first` or `3rd`
+    Nth,
+    /// `vertex`
+    Vertex,
+    /// `top` or `t`
+    Top,
+    /// `bottom`
+    Bottom,
+    /// `start`
+    Start,
+    /// `end`
+    End,
+    /// `in`
+    In,
+    /// `this`
+    This,
+    /// A `.` followed by a place name
+    DotU,
+    /// `last` or `previous`
+    Last,
+    /// A `.` followed by `x` or `y`
+    DotXY,
+    /// `x`
+    X,
+    /// `y`
+    Y,
+    /// A `.` followed by a property name such as `ht`
+    DotL,
+    /// A `.` followed by an edge name such as `n` or `start`
+    DotE,
+    /// A single-argument function: `abs`, `cos`, `int`, `sin` or `sqrt`
+    Func1,
+    /// A two-argument function: `max` or `min`
+    Func2,
+    /// `dist`
+    Dist,
+    /// `up`
+    Up,
+    /// `down`
+    Down,
+    /// `left`
+    Left,
+    /// `right`
+    Right,
+    /// `close`
+    Close,
+    /// `chop`
+    Chop,
+    /// `from`
+    From,
+    /// `to`
+    To,
+    /// `then`
+    Then,
+    /// `heading`
+    Heading,
+    /// `go`
+    Go,
+    /// `at`
+    At,
+    /// `with`
+    With,
+    /// `same`
+    Same,
+    /// `as`
+    As,
+    /// `fit`
+    Fit,
+    /// `behind`
+    Behind,
+    /// `until`
+    Until,
+    /// `even`
+    Even,
+    /// A macro parameter, `$1` to `$9`
+    Parameter,
+    /// Spaces, tabs and escaped newlines
+    Whitespace,
+    /// A `#`, `//` or `/* */` comment
+    Comment,
+    /// Text which is not a valid token
+    Error,
+}
+
+impl TokenKind {
+    /// Whether this kind of token is ignored by the parser
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment)
+    }
+}
+
+/// A single token of pikchr source
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    /// What kind of token this is
+    pub kind: TokenKind,
+    /// Where the token is in the source
+    pub span: Span,
+}
+
+impl Token {
+    /// The text of the token
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        self.span.text(source)
+    }
+}
+
+/// The keywords of the language, sorted by name
+static KEYWORDS: &[(&str, TokenKind)] = &[
+    ("above", TokenKind::Above),
+    ("abs", TokenKind::Func1),
+    ("aligned", TokenKind::Aligned),
+    ("and", TokenKind::And),
+    ("as", TokenKind::As),
+    ("assert", TokenKind::Assert),
+    ("at", TokenKind::At),
+    ("behind", TokenKind::Behind),
+    ("below", TokenKind::Below),
+    ("between", TokenKind::Between),
+    ("big", TokenKind::Big),
+    ("bold", TokenKind::Bold),
+    ("bot", TokenKind::EdgePt),
+    ("bottom", TokenKind::Bottom),
+    ("c", TokenKind::EdgePt),
+    ("ccw", TokenKind::Ccw),
+    ("center", TokenKind::Center),
+    ("chop", TokenKind::Chop),
+    ("close", TokenKind::Close),
+    ("color", TokenKind::Color),
+    ("cos", TokenKind::Func1),
+    ("cw", TokenKind::Cw),
+    ("dashed", TokenKind::Dashed),
+    ("define", TokenKind::Define),
+    ("diameter", TokenKind::Diameter),
+    ("dist", TokenKind::Dist),
+    ("dotted", TokenKind::Dotted),
+    ("down", TokenKind::Down),
+    ("e", TokenKind::EdgePt),
+    ("east", TokenKind::EdgePt),
+    ("end", TokenKind::End),
+    ("even", TokenKind::Even),
+    ("fill", TokenKind::Fill),
+    ("first", TokenKind::Nth),
+    ("fit", TokenKind::Fit),
+    ("from", TokenKind::From),
+    ("go", TokenKind::Go),
+    ("heading", TokenKind::Heading),
+    ("height", TokenKind::Height),
+    ("ht", TokenKind::Height),
+    ("in", TokenKind::In),
+    ("int", TokenKind::Func1),
+    ("invis", TokenKind::Invis),
+    ("invisible", TokenKind::Invis),
+    ("italic", TokenKind::Italic),
+    ("last", TokenKind::Last),
+    ("left", TokenKind::Left),
+    ("ljust", TokenKind::LJust),
+    ("max", TokenKind::Func2),
+    ("min", TokenKind::Func2),
+    ("n", TokenKind::EdgePt),
+    ("ne", TokenKind::EdgePt),
+    ("north", TokenKind::EdgePt),
+    ("nw", TokenKind::EdgePt),
+    ("of", TokenKind::Of),
+    ("previous", TokenKind::Last),
+    ("print", TokenKind::Print),
+    ("rad", TokenKind::Radius),
+    ("radius", TokenKind::Radius),
+    ("right", TokenKind::Right),
+    ("rjust", TokenKind::RJust),
+    ("s", TokenKind::EdgePt),
+    ("same", TokenKind::Same),
+    ("se", TokenKind::EdgePt),
+    ("sin", TokenKind::Func1),
+    ("small", TokenKind::Small),
+    ("solid", TokenKind::Solid),
+    ("south", TokenKind::EdgePt),
+    ("sqrt", TokenKind::Func1),
+    ("start", TokenKind::Start),
+    ("sw", TokenKind::EdgePt),
+    ("t", TokenKind::Top),
+    ("the", TokenKind::The),
+    ("then", TokenKind::Then),
+    ("thick", TokenKind::Thick),
+    ("thickness", TokenKind::Thickness),
+    ("thin", TokenKind::Thin),
+    ("this", TokenKind::This),
+    ("to", TokenKind::To),
+    ("top", TokenKind::Top),
+    ("until", TokenKind::Until),
+    ("up", TokenKind::Up),
+    ("vertex", TokenKind::Vertex),
+    ("w", TokenKind::EdgePt),
+    ("way", TokenKind::Way),
+    ("west", TokenKind::EdgePt),
+    ("wid", TokenKind::Width),
+    ("width", TokenKind::Width),
+    ("with", TokenKind::With),
+    ("x", TokenKind::X),
+    ("y", TokenKind::Y),
+];
+
+/// The names of the object classes, sorted by name
+pub(crate) static CLASSES: &[&str] = &[
+    "arc", "arrow", "box", "circle", "cylinder", "dot", "ellipse", "file", "line", "move", "oval",
+    "spline", "text",
+];
+
+/// Look up a keyword
+pub(crate) fn keyword(word: &str) -> Option<TokenKind> {
+    KEYWORDS
+        .binary_search_by_key(&word, |&(name, _)| name)
+        .ok()
+        .map(|idx| KEYWORDS[idx].1)
+}
+
+/// The edge named by a keyword, if it names one
+///
+/// This covers every word which may follow `.` to name a point on an
+/// object, such as `n`, `top` or `start`.
+pub(crate) fn edge_of(word: &str) -> Option<Edge> {
+    Some(match word {
+        "n" | "north" | "t" | "top" => Edge::North,
+        "ne" => Edge::NorthEast,
+        "e" | "east" | "right" => Edge::East,
+        "se" => Edge::SouthEast,
+        "s" | "south" | "bot" | "bottom" => Edge::South,
+        "sw" => Edge::SouthWest,
+        "w" | "west" | "left" => Edge::West,
+        "nw" => Edge::NorthWest,
+        "c" | "center" => Edge::Center,
+        "start" => Edge::Start,
+        "end" => Edge::End,
+        _ => return None,
+    })
+}
+
+/// Split pikchr source into tokens
+///
+/// Every byte of the source is covered by exactly one token, including
+/// whitespace and comments, so the source can be reconstructed from the
+/// result.  Macros are not expanded.
+///
+/// ```
+/// use pikchr::ast::{tokenize, TokenKind};
+///
+/// let kinds: Vec<_> = tokenize("box wid 2cm # wide")
+///     .into_iter()
+///     .map(|t| t.kind)
+///     .filter(|k| !k.is_trivia())
+///     .collect();
+/// assert_eq!(kinds, [TokenKind::ClassName, TokenKind::Width, TokenKind::Number]);
+/// ```
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < source.len() {
+        let (kind, len) = token_at(source, pos, true);
+        tokens.push(Token {
+            kind,
+            span: Span::new(pos, pos + len),
+        });
+        pos += len;
+    }
+    tokens
+}
+
+/// Whether `second` can follow the token `first` with no whitespace
+///
+/// This is the case if `first` is still a single token of the same kind
+/// when `second` is written straight after it.
+pub(crate) fn abuts(first: &str, kind: TokenKind, second: &str) -> bool {
+    let joined = format!("{}{}", first, second);
+    token_at(&joined, 0, true) == (kind, first.len())
+}
+
+/// Measure the token which starts at `start`
+///
+/// `start` must be less than the length of the source.  Code blocks are
+/// only recognised if `allow_code_block` is set, otherwise `{` is a token
+/// on its own.
+pub(crate) fn token_at(source: &str, start: usize, allow_code_block: bool) -> (TokenKind, usize) {
+    let z = &source.as_bytes()[start..];
+    let at = |i: usize| z.get(i).copied().unwrap_or(0);
+    let word_end = |from: usize| {
+        let mut i = from;
+        while at(i).is_ascii_alphanumeric() || at(i) == b'_' {
+            i += 1;
+        }
+        i
+    };
+    match z[0] {
+        b'\\' => {
+            let mut i = 1;
+            while matches!(at(i), b'\r' | b' ' | b'\t') {
+                i += 1;
+            }
+            if at(i) == b'\n' {
+                (TokenKind::Whitespace, i + 1)
+            } else {
+                (TokenKind::Error, 1)
+            }
+        }
+        b';' | b'\n' => (TokenKind::Eol, 1),
+        b'"' => {
+            let mut i = 1;
+            while i < z.len() {
+                match z[i] {
+                    b'\\' if i + 1 < z.len() => i += 2,
+                    b'\\' => break,
+                    b'"' => return (TokenKind::String, i + 1),
+                    _ => i += 1,
+                }
+            }
+            (TokenKind::Error, z.len())
+        }
+        b' ' | b'\t' | b'\x0c' | b'\r' => {
+            let mut i = 1;
+            while matches!(at(i), b' ' | b'\t' | b'\r') {
+                i += 1;
+            }
+            (TokenKind::Whitespace, i)
+        }
+        b'#' => {
+            let i = z.iter().position(|&c| c == b'\n').unwrap_or(z.len());
+            (TokenKind::Comment, i)
+        }
+        b'/' => match at(1) {
+            b'*' => {
+                let mut i = 2;
+                while i < z.len() && !(z[i] == b'*' && at(i + 1) == b'/') {
+                    i += 1;
+                }
+                if i < z.len() {
+                    (TokenKind::Comment, i + 2)
+                } else {
+                    (TokenKind::Error, i)
+                }
+            }
+            b'/' => {
+                let i = z.iter().position(|&c| c == b'\n').unwrap_or(z.len());
+                (TokenKind::Comment, i)
+            }
+            b'=' => (TokenKind::Assign, 2),
+            _ => (TokenKind::Slash, 1),
+        },
+        b'+' if at(1) == b'=' => (TokenKind::Assign, 2),
+        b'+' => (TokenKind::Plus, 1),
+        b'*' if at(1) == b'=' => (TokenKind::Assign, 2),
+        b'*' => (TokenKind::Star, 1),
+        b'%' => (TokenKind::Percent, 1),
+        b'(' => (TokenKind::LParen, 1),
+        b')' => (TokenKind::RParen, 1),
+        b'[' => (TokenKind::LBracket, 1),
+        b']' => (TokenKind::RBracket, 1),
+        b',' => (TokenKind::Comma, 1),
+        b':' => (TokenKind::Colon, 1),
+        b'>' => (TokenKind::Gt, 1),
+        b'=' if at(1) == b'=' => (TokenKind::Eq, 2),
+        b'=' => (TokenKind::Assign, 1),
+        b'-' => match at(1) {
+            b'>' => (TokenKind::RArrow, 2),
+            b'=' => (TokenKind::Assign, 2),
+            _ => (TokenKind::Minus, 1),
+        },
+        b'<' => match (at(1), at(2)) {
+            (b'-', b'>') => (TokenKind::LRArrow, 3),
+            (b'-', _) => (TokenKind::LArrow, 2),
+            _ => (TokenKind::Lt, 1),
+        },
+        b'{' => {
+            if !allow_code_block {
+                return (TokenKind::CodeBlock, 1);
+            }
+            let mut i = 1;
+            let mut depth = 1;
+            while i < z.len() && depth > 0 {
+                let (_, len) = token_at(source, start + i, false);
+                if len == 1 {
+                    match z[i] {
+                        b'{' => depth += 1,
+                        b'}' => depth -= 1,
+                        _ => {}
+                    }
+                }
+                i += len;
+            }
+            if depth > 0 {
+                (TokenKind::Error, 1)
+            } else {
+                (TokenKind::CodeBlock, i)
+            }
+        }
+        b'.' if at(1).is_ascii_lowercase() => {
+            let mut i = 2;
+            while at(i).is_ascii_lowercase() {
+                i += 1;
+            }
+            let word = &source[start + 1..start + i];
+            if edge_of(word).is_some() {
+                (TokenKind::DotE, 1)
+            } else if word == "x" || word == "y" {
+                (TokenKind::DotXY, 1)
+            } else {
+                (TokenKind::DotL, 1)
+            }
+        }
+        b'.' if at(1).is_ascii_uppercase() => (TokenKind::DotU, 1),
+        b'.' if !at(1).is_ascii_digit() => (TokenKind::Error, 1),
+        b'0'..=b'9' | b'.' => number_at(z),
+        b'a'..=b'z' => {
+            let i = word_end(1);
+            let word = &source[start..start + i];
+            if let Some(kind) = keyword(word) {
+                (kind, i)
+            } else if CLASSES.binary_search(&word).is_ok() {
+                (TokenKind::ClassName, i)
+            } else {
+                (TokenKind::Id, i)
+            }
+        }
+        b'A'..=b'Z' => (TokenKind::PlaceName, word_end(1)),
+        b'$' if matches!(at(1), b'1'..=b'9') && !at(2).is_ascii_digit() => {
+            (TokenKind::Parameter, 2)
+        }
+        b'_' | b'$' | b'@' => (TokenKind::Id, word_end(1)),
+        _ => {
+            // Consume the whole of any multi-byte character so that spans
+            // always fall on character boundaries.
+            let len = source[start..].chars().next().map_or(1, char::len_utf8);
+            (TokenKind::Error, len)
+        }
+    }
+}
+
+/// Measure a numeric literal, which might turn out to be an ordinal
+fn number_at(z: &[u8]) -> (TokenKind, usize) {
+    let at = |i: usize| z.get(i).copied().unwrap_or(0);
+    let mut is_int = true;
+    let mut digits = 0;
+    let mut i = 0;
+    if z[0] != b'.' {
+        while at(i).is_ascii_digit() {
+            i += 1;
+            digits += 1;
+        }
+        if i == 1 && matches!(at(1), b'x' | b'X') {
+            i = 2;
+            while at(i).is_ascii_hexdigit() {
+                i += 1;
+            }
+            return (TokenKind::Number, i);
+        }
+    }
+    if at(i) == b'.' {
+        is_int = false;
+        i += 1;
+        while at(i).is_ascii_digit() {
+            i += 1;
+            digits += 1;
+        }
+    }
+    if digits == 0 {
+        return (TokenKind::Error, i);
+    }
+    if matches!(at(i), b'e' | b'E') {
+        let mut j = i + 1;
+        if matches!(at(j), b'+' | b'-') {
+            j += 1;
+        }
+        if at(j).is_ascii_digit() {
+            is_int = false;
+            i = j;
+            while at(i).is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+    let suffix = (at(i), at(i + 1));
+    if is_int
+        && matches!(
+            suffix,
+            (b't', b'h') | (b'r', b'd') | (b'n', b'd') | (b's', b't')
+        )
+    {
+        return (TokenKind::Nth, i + 2);
+    }
+    if matches!(
+        suffix,
+        (b'i', b'n') | (b'c', b'm') | (b'm', b'm') | (b'p', b't') | (b'p', b'x') | (b'p', b'c')
+    ) {
+        i += 2;
+    }
+    (TokenKind::Number, i)
+}
+
+/// The value of a numeric literal, in inches
+pub(crate) fn number_value(text: &str) -> f64 {
+    if text.len() >= 3 && (text.starts_with("0x") || text.starts_with("0X")) {
+        return i64::from_str_radix(&text[2..], 16).map_or(0.0, |v| v as f64);
+    }
+    let (digits, scale) = match text.len().checked_sub(2).map(|n| text.split_at(n)) {
+        Some((digits, "in")) => (digits, 1.0),
+        Some((digits, "cm")) => (digits, 2.54),
+        Some((digits, "mm")) => (digits, 25.4),
+        Some((digits, "px")) => (digits, 96.0),
+        Some((digits, "pt")) => (digits, 72.0),
+        Some((digits, "pc")) => (digits, 6.0),
+        _ => (text, 1.0),
+    };
+    digits.parse::<f64>().unwrap_or(0.0) / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source).into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn tables_are_sorted() {
+        assert!(KEYWORDS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(CLASSES.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn dots_are_classified_by_what_follows() {
+        use TokenKind::*;
+        assert_eq!(kinds("A.n"), [PlaceName, DotE, EdgePt]);
+        assert_eq!(kinds("A.start"), [PlaceName, DotE, Start]);
+        assert_eq!(kinds("A.x"), [PlaceName, DotXY, X]);
+        assert_eq!(kinds("A.ht"), [PlaceName, DotL, Height]);
+        assert_eq!(kinds("A.B"), [PlaceName, DotU, PlaceName]);
+        assert_eq!(kinds(".5"), [Number]);
+    }
+
+    #[test]
+    fn numbers_ordinals_and_units() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("3rd 1st 2.5cm 0x1F 1e3 1.5th"),
+            [
+                Nth, Whitespace, Nth, Whitespace, Number, Whitespace, Number, Whitespace, Number,
+                Whitespace, Number, Id
+            ]
+        );
+        assert!((number_value("2.54cm") - 1.0).abs() < 1e-9);
+        assert!((number_value("72pt") - 1.0).abs() < 1e-9);
+        assert_eq!(number_value("0x10"), 16.0);
+        assert_eq!(number_value("1e2"), 100.0);
+    }
+
+    #[test]
+    fn trivia_and_errors() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("# c\n/* d */ // e\n\\\n"),
+            [Comment, Eol, Comment, Whitespace, Comment, Eol, Whitespace]
+        );
+        assert_eq!(kinds("\"open"), [Error]);
+        assert_eq!(kinds("{ a { b } \"}\" }"), [CodeBlock]);
+        assert_eq!(kinds("é"), [Error]);
+    }
+}
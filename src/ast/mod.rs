@@ -0,0 +1,1099 @@
+//! A pure-Rust parser for pikchr source
+//!
+//! The C implementation parses and renders in a single pass, so it can
+//! only tell us whether a diagram is valid and what it looks like.  This
+//! module parses pikchr source into a typed syntax tree which can be used
+//! to analyse, transform, or reformat diagrams without rendering them.
+//!
+//! ```
+//! use pikchr::ast::{self, ObjectKind, StatementKind};
+//!
+//! let doc = ast::parse("A: box \"hello\" fit\narrow from A.e right 1").unwrap();
+//! assert_eq!(doc.statements.len(), 2);
+//! let first = &doc.statements[0];
+//! assert_eq!(first.label.as_ref().unwrap().text, "A");
+//! match &first.kind {
+//!     StatementKind::Object(obj) => assert!(matches!(&obj.kind, ObjectKind::Class(c) if c.text == "box")),
+//!     _ => unreachable!(),
+//! }
+//! ```
+//!
+//! The grammar is the same as the one the C implementation uses, and
+//! macros are expanded in the same way, so any source which the C code
+//! accepts will parse.  The parser does not check things which the C
+//! code only discovers while laying out the diagram, such as references
+//! to objects which do not exist.
+//!
+//! Every node in the tree carries a [`Span`] giving its location in the
+//! source.  [`Name`] and [`Text`] spans always cover the text exactly;
+//! other nodes cover the source as written, so anything which came from
+//! expanding a macro is attributed to the macro invocation.
+
+mod incremental;
+mod lexer;
+mod parser;
+
+pub use incremental::IncrementalParser;
+pub(crate) use lexer::{abuts, CLASSES};
+pub use lexer::{tokenize, Token, TokenKind};
+
+use std::fmt;
+
+/// A range of bytes in the source text
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Span {
+    /// The offset of the first byte
+    pub start: usize,
+    /// The offset just past the last byte
+    pub end: usize,
+}
+
+impl Span {
+    /// Create a new span
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The length of the span in bytes
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the span is empty
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Whether the given offset falls within the span
+    ///
+    /// The end of the span counts as being within it, so that an editor
+    /// cursor placed just after a name is considered to be on the name.
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+
+    /// The smallest span which covers both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// The text which this span covers
+    ///
+    /// ```
+    /// # use pikchr::ast::Span;
+    /// assert_eq!(Span::new(4, 7).text("box wid 2"), "wid");
+    /// ```
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
+/// An error found while parsing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    message: String,
+    span: Span,
+}
+
+impl ParseError {
+    pub(crate) fn new(message: &str, span: Span) -> Self {
+        ParseError {
+            message: message.to_string(),
+            span,
+        }
+    }
+
+    /// A description of the error
+    ///
+    /// Where possible these match the messages of the C implementation.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The location of the error in the source
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at bytes {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse pikchr source, stopping at the first error
+///
+/// ```
+/// let err = pikchr::ast::parse("box wid").unwrap_err();
+/// assert_eq!(err.message(), "syntax error");
+/// assert_eq!(err.span().start, 7);
+/// ```
+pub fn parse(source: &str) -> Result<Document, ParseError> {
+    let (document, mut errors) = parse_recovering(source);
+    if errors.is_empty() {
+        Ok(document)
+    } else {
+        Err(errors.swap_remove(0))
+    }
+}
+
+/// Parse pikchr source, recovering from errors
+///
+/// When a statement cannot be parsed it is skipped, and parsing resumes
+/// with the next statement.  This is intended for editors and other tools
+/// which want to work with incomplete source.
+///
+/// ```
+/// let (doc, errors) = pikchr::ast::parse_recovering("box\ncircle at\nline");
+/// assert_eq!(doc.statements.len(), 2);
+/// assert_eq!(errors.len(), 1);
+/// ```
+pub fn parse_recovering(source: &str) -> (Document, Vec<ParseError>) {
+    parser::Parser::new(source).parse()
+}
+
+/// A parsed pikchr document
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Document {
+    /// The top-level statements, in order
+    pub statements: Vec<Statement>,
+}
+
+/// A name, such as a label, variable or object class
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Name {
+    /// The name as written
+    pub text: String,
+    /// Where the name is in the source
+    pub span: Span,
+}
+
+/// A string literal along with any text attributes which follow it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Text {
+    /// The text, without the surrounding quotes
+    ///
+    /// Backslash escapes are left as they were written.
+    pub text: String,
+    /// Where the string literal is in the source, including its quotes
+    pub span: Span,
+    /// The text attributes, such as `bold` or `above`, in order
+    pub positions: Vec<TextPosition>,
+}
+
+/// A single statement
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    /// The label of the statement, for example `A` in `A: box`
+    pub label: Option<Name>,
+    /// What the statement does
+    pub kind: StatementKind,
+    /// Where the statement is in the source
+    pub span: Span,
+    /// The macro invocation which produced this statement, if any
+    pub macro_call: Option<Span>,
+}
+
+/// The different kinds of [`Statement`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatementKind {
+    /// Change the layout direction, e.g. `down`
+    Direction(Direction),
+    /// Assign to a variable, e.g. `boxwid = 1`
+    Assignment {
+        /// The variable being assigned to
+        variable: Name,
+        /// The assignment operator
+        op: AssignOp,
+        /// The value being assigned
+        value: Expr,
+    },
+    /// Create an object
+    Object(Object),
+    /// Name a position, e.g. `P: A.ne + (0.5, 0)`
+    ///
+    /// The statement's label holds the name.
+    Place(Box<Position>),
+    /// Print values to the output, e.g. `print "width", boxwid`
+    Print(Vec<PrintItem>),
+    /// Check a condition, e.g. `assert( A.x == B.x )`
+    Assert(Assertion),
+    /// Define a macro
+    Define {
+        /// The name of the macro
+        name: Name,
+        /// The body of the macro, without the enclosing braces
+        body: Span,
+    },
+}
+
+/// A layout direction
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// `up`
+    Up,
+    /// `down`
+    Down,
+    /// `left`
+    Left,
+    /// `right`
+    Right,
+}
+
+/// A point on the boundary (or at the center) of an object
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Edge {
+    /// `n`, `north`, `t` or `top`
+    North,
+    /// `ne`
+    NorthEast,
+    /// `e`, `east` or `right`
+    East,
+    /// `se`
+    SouthEast,
+    /// `s`, `south`, `bot` or `bottom`
+    South,
+    /// `sw`
+    SouthWest,
+    /// `w`, `west` or `left`
+    West,
+    /// `nw`
+    NorthWest,
+    /// `c` or `center`
+    Center,
+    /// `start`
+    Start,
+    /// `end`
+    End,
+}
+
+/// An assignment operator
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AssignOp {
+    /// `=`
+    Assign,
+    /// `+=`
+    Add,
+    /// `-=`
+    Subtract,
+    /// `*=`
+    Multiply,
+    /// `/=`
+    Divide,
+}
+
+/// An item in a `print` statement
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrintItem {
+    /// A string literal
+    Text(Text),
+    /// A value
+    Value(Expr),
+}
+
+/// The condition of an `assert` statement
+#[derive(Clone, Debug, PartialEq)]
+pub enum Assertion {
+    /// Two expressions have the same value
+    Equal(Expr, Expr),
+    /// Two positions are the same
+    SamePosition(Box<Position>, Box<Position>),
+}
+
+/// An object, such as a box, some text, or a `[...]` sublist
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    /// What sort of object this is
+    pub kind: ObjectKind,
+    /// The attributes of the object, in order
+    pub attributes: Vec<Attribute>,
+    /// Where the object is in the source, excluding any label
+    pub span: Span,
+}
+
+impl Object {
+    /// The class of the object
+    ///
+    /// This is the class name for ordinary objects, `"text"` for an object
+    /// introduced by a string, and `"[]"` for a sublist.
+    pub fn class_name(&self) -> &str {
+        match &self.kind {
+            ObjectKind::Class(name) => &name.text,
+            ObjectKind::Text(_) => "text",
+            ObjectKind::Sublist(_) => "[]",
+        }
+    }
+
+    /// All of the text attached to the object, in order
+    pub fn texts(&self) -> impl Iterator<Item = &Text> {
+        let first = match &self.kind {
+            ObjectKind::Text(text) => Some(text),
+            _ => None,
+        };
+        first
+            .into_iter()
+            .chain(self.attributes.iter().filter_map(|a| match &a.kind {
+                AttributeKind::Text(text) => Some(text),
+                _ => None,
+            }))
+    }
+}
+
+/// The different kinds of [`Object`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectKind {
+    /// An object of a named class, such as `box`
+    Class(Name),
+    /// A text object introduced by a string, e.g. `"hello" bold`
+    Text(Text),
+    /// A `[...]` sublist of statements
+    Sublist(Vec<Statement>),
+}
+
+/// An attribute of an object
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    /// What the attribute is
+    pub kind: AttributeKind,
+    /// Where the attribute is in the source
+    pub span: Span,
+}
+
+/// The different kinds of [`Attribute`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeKind {
+    /// A numeric property, e.g. `wid 2` or `ht 150%`
+    Numeric {
+        /// The property being set
+        property: NumericProperty,
+        /// The new value
+        value: RelExpr,
+    },
+    /// `dashed` or `dotted`, with an optional spacing
+    Dash {
+        /// The style of the line
+        style: DashStyle,
+        /// The spacing of the dashes or dots
+        spacing: Option<Expr>,
+    },
+    /// A colour property, e.g. `fill red`
+    Color {
+        /// The property being set
+        property: ColorProperty,
+        /// The new colour
+        value: Expr,
+    },
+    /// Move in a direction, e.g. `right 2` or `go up`
+    ///
+    /// A bare distance at the start of the attributes, such as the `2` in
+    /// `line 2`, has no direction and moves in the layout direction.
+    Go {
+        /// The direction to move in
+        direction: Option<Direction>,
+        /// How far to move
+        distance: Option<RelExpr>,
+    },
+    /// Move until level with a position, e.g. `down until even with B`
+    Even {
+        /// The direction to move in
+        direction: Direction,
+        /// The position to move level with
+        position: Position,
+    },
+    /// Move at a compass heading, e.g. `go 1 heading 45`
+    Heading {
+        /// Whether the heading was introduced with `then` rather than `go`
+        then: bool,
+        /// How far to move
+        distance: Option<RelExpr>,
+        /// The heading, in degrees clockwise from north
+        heading: Expr,
+    },
+    /// Move towards a compass point, e.g. `then 1 ne`
+    EdgeHeading {
+        /// Whether the heading was introduced with `then` rather than `go`
+        then: bool,
+        /// How far to move
+        distance: Option<RelExpr>,
+        /// The compass point to head towards
+        edge: Edge,
+    },
+    /// `then`, starting a new segment of a line
+    Then,
+    /// `close`
+    Close,
+    /// `chop`
+    Chop,
+    /// `from` a position
+    From(Position),
+    /// `to` a position
+    To(Position),
+    /// `at` a position
+    At(Position),
+    /// `with` an edge `at` a position, e.g. `with .nw at A.ne`
+    With {
+        /// The edge of this object to place
+        edge: Edge,
+        /// Where to place it
+        position: Position,
+    },
+    /// `same`, or `same as` another object
+    Same(Option<ObjectRef>),
+    /// A string, and its text attributes
+    Text(Text),
+    /// `fit`
+    Fit,
+    /// `behind` another object
+    Behind(ObjectRef),
+    /// A flag such as `thick` or `->`
+    Flag(Flag),
+}
+
+/// A numeric object property
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NumericProperty {
+    /// `height` or `ht`
+    Height,
+    /// `width` or `wid`
+    Width,
+    /// `radius` or `rad`
+    Radius,
+    /// `diameter`
+    Diameter,
+    /// `thickness`
+    Thickness,
+}
+
+/// The style of a dashed or dotted line
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DashStyle {
+    /// `dotted`
+    Dotted,
+    /// `dashed`
+    Dashed,
+}
+
+/// A colour object property
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColorProperty {
+    /// `fill`
+    Fill,
+    /// `color`
+    Color,
+}
+
+/// A boolean object attribute
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// `cw`
+    Cw,
+    /// `ccw`
+    Ccw,
+    /// `<-`
+    LArrow,
+    /// `->`
+    RArrow,
+    /// `<->`
+    LRArrow,
+    /// `invis` or `invisible`
+    Invisible,
+    /// `thick`
+    Thick,
+    /// `thin`
+    Thin,
+    /// `solid`
+    Solid,
+}
+
+/// A text attribute, following a string
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextPosition {
+    /// `center`
+    Center,
+    /// `ljust`
+    LJust,
+    /// `rjust`
+    RJust,
+    /// `above`
+    Above,
+    /// `below`
+    Below,
+    /// `italic`
+    Italic,
+    /// `bold`
+    Bold,
+    /// `aligned`
+    Aligned,
+    /// `big`
+    Big,
+    /// `small`
+    Small,
+}
+
+/// An expression which may be a percentage, e.g. `150%`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelExpr {
+    /// The value
+    pub expr: Expr,
+    /// Whether the value is a percentage of the default
+    pub percent: bool,
+    /// Where the expression is in the source
+    pub span: Span,
+}
+
+/// A numeric expression
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    /// What the expression is
+    pub kind: ExprKind,
+    /// Where the expression is in the source
+    pub span: Span,
+}
+
+/// The different kinds of [`Expr`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind {
+    /// A number, converted to inches if it had units
+    Number(f64),
+    /// A variable, e.g. `boxwid` or `fill`
+    Variable(Name),
+    /// A colour name starting with a capital letter, e.g. `Red`
+    Color(Name),
+    /// `-x` or `+x`
+    Unary {
+        /// The operator
+        op: UnaryOp,
+        /// The operand
+        operand: Box<Expr>,
+    },
+    /// A binary arithmetic operation
+    Binary {
+        /// The operator
+        op: BinaryOp,
+        /// The left hand side
+        lhs: Box<Expr>,
+        /// The right hand side
+        rhs: Box<Expr>,
+    },
+    /// A call to a built-in function, e.g. `max(1, x)`
+    Call {
+        /// The function being called
+        function: Function,
+        /// The arguments
+        args: Vec<Expr>,
+    },
+    /// The distance between two positions, `dist(A, B)`
+    Distance(Box<Position>, Box<Position>),
+    /// One coordinate of a place, e.g. `A.n.x`
+    Coordinate {
+        /// The place
+        place: Box<Place>,
+        /// Which coordinate
+        axis: Axis,
+    },
+    /// A property of an object, e.g. `A.wid`
+    Property {
+        /// The object
+        object: Box<ObjectRef>,
+        /// The property
+        property: ObjectProperty,
+    },
+}
+
+/// A unary operator
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    /// `+`
+    Plus,
+    /// `-`
+    Minus,
+}
+
+/// A binary operator
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    /// `+`
+    Add,
+    /// `-`
+    Subtract,
+    /// `*`
+    Multiply,
+    /// `/`
+    Divide,
+}
+
+/// A built-in function
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Function {
+    /// `abs(x)`
+    Abs,
+    /// `cos(x)`, with `x` in degrees
+    Cos,
+    /// `int(x)`
+    Int,
+    /// `sin(x)`, with `x` in degrees
+    Sin,
+    /// `sqrt(x)`
+    Sqrt,
+    /// `max(x, y)`
+    Max,
+    /// `min(x, y)`
+    Min,
+}
+
+/// A coordinate axis
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    /// `.x`
+    X,
+    /// `.y`
+    Y,
+}
+
+/// A property which can be read from an object, e.g. `.ht`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectProperty {
+    /// A numeric property
+    Numeric(NumericProperty),
+    /// The dash spacing
+    Dash(DashStyle),
+    /// A colour
+    Color(ColorProperty),
+}
+
+/// A position on the diagram
+#[derive(Clone, Debug, PartialEq)]
+pub struct Position {
+    /// What the position is
+    pub kind: PositionKind,
+    /// Where the position is in the source
+    pub span: Span,
+}
+
+/// The different kinds of [`Position`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PositionKind {
+    /// Explicit coordinates, e.g. `1, 2`
+    Coordinates(Expr, Expr),
+    /// A place, e.g. `A.ne`
+    Place(Place),
+    /// A place plus or minus an offset, e.g. `A.ne + (0.5, 0)`
+    Offset {
+        /// The place
+        place: Place,
+        /// Whether the offset is subtracted rather than added
+        subtract: bool,
+        /// The horizontal offset
+        x: Expr,
+        /// The vertical offset
+        y: Expr,
+    },
+    /// The x coordinate of one position and the y of another, `(A, B)`
+    Combine(Box<Position>, Box<Position>),
+    /// A point part way between two others, e.g. `1/3 way between A and B`
+    Between {
+        /// How far along from `from` to `to`
+        fraction: Expr,
+        /// The starting point
+        from: Box<Position>,
+        /// The ending point
+        to: Box<Position>,
+    },
+    /// A distance above, below, left or right of another position
+    Relative {
+        /// How far away
+        distance: Expr,
+        /// Which way
+        direction: Direction,
+        /// The position to measure from
+        position: Box<Position>,
+    },
+    /// A distance towards a compass point, e.g. `1 ne of A`
+    EdgeHeading {
+        /// How far away
+        distance: Expr,
+        /// Which way
+        edge: Edge,
+        /// The position to measure from
+        position: Box<Position>,
+    },
+    /// A distance at a heading, e.g. `1 heading 30 from A`
+    Heading {
+        /// How far away
+        distance: Expr,
+        /// The heading, in degrees clockwise from north
+        heading: Expr,
+        /// The position to measure from
+        position: Box<Position>,
+    },
+}
+
+/// A point defined by an object
+#[derive(Clone, Debug, PartialEq)]
+pub struct Place {
+    /// What the place is
+    pub kind: PlaceKind,
+    /// Where the place is in the source
+    pub span: Span,
+}
+
+/// The different kinds of [`Place`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlaceKind {
+    /// The center of an object
+    Object(ObjectRef),
+    /// An edge of an object, e.g. `A.n` or `north of A`
+    Edge {
+        /// The object
+        object: ObjectRef,
+        /// The edge
+        edge: Edge,
+    },
+    /// A vertex of a line, e.g. `2nd vertex of L`
+    Vertex {
+        /// Which vertex, counting from one
+        nth: u32,
+        /// The line
+        object: ObjectRef,
+    },
+}
+
+/// A reference to an object
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectRef {
+    /// How the object is referred to
+    pub kind: ObjectRefKind,
+    /// Where the reference is in the source
+    pub span: Span,
+}
+
+/// The different kinds of [`ObjectRef`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectRefKind {
+    /// `this`, the object currently being defined
+    This,
+    /// A label, or a path of labels into sublists, e.g. `A.B`
+    Named(Vec<Name>),
+    /// An object found by counting, e.g. `2nd box` or `last [] in A`
+    Nth {
+        /// Which object to find
+        nth: Nth,
+        /// The sublist to search, instead of the current list
+        within: Option<Box<ObjectRef>>,
+    },
+}
+
+/// A counted object selector, e.g. `2nd last box`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Nth {
+    /// Which match, counting from one
+    pub count: u32,
+    /// Whether to count backwards from the most recent object
+    pub from_end: bool,
+    /// The kind of object to count
+    pub class: NthClass,
+}
+
+/// The kind of object counted by an [`Nth`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum NthClass {
+    /// Any object, as in a bare `last`
+    Any,
+    /// Objects of a particular class
+    Class(Name),
+    /// Sublists, `[]`
+    Sublist,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(source: &str) -> Object {
+        let doc = parse(source).unwrap_or_else(|e| panic!("{}: {}", source, e));
+        match doc.statements.into_iter().last().unwrap().kind {
+            StatementKind::Object(obj) => obj,
+            other => panic!("not an object: {:?}", other),
+        }
+    }
+
+    fn position(source: &str) -> PositionKind {
+        match object(&format!("box at {}", source))
+            .attributes
+            .remove(0)
+            .kind
+        {
+            AttributeKind::At(position) => position.kind,
+            other => panic!("not at: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_every_statement_kind() {
+        let source = r#"
+            down
+            boxwid *= 1.5
+            A: box "one" bold above "two"
+            P: A.ne + (0.5, 0)
+            print "width", boxwid, fill
+            assert( A.x == A.x )
+            assert( A.n == (A.x, A.n.y) )
+            define blob { circle rad $1 }
+            B: [ box; circle ] with .nw at A.se
+        "#;
+        let doc = parse(source).unwrap();
+        let kinds: Vec<_> = doc
+            .statements
+            .iter()
+            .map(|s| match &s.kind {
+                StatementKind::Direction(_) => "direction",
+                StatementKind::Assignment { .. } => "assignment",
+                StatementKind::Object(_) => "object",
+                StatementKind::Place(_) => "place",
+                StatementKind::Print(_) => "print",
+                StatementKind::Assert(Assertion::Equal(..)) => "assert",
+                StatementKind::Assert(Assertion::SamePosition(..)) => "assert position",
+                StatementKind::Define { .. } => "define",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "direction",
+                "assignment",
+                "object",
+                "place",
+                "print",
+                "assert",
+                "assert position",
+                "define",
+                "object"
+            ]
+        );
+        let last = &doc.statements[8];
+        assert_eq!(
+            last.span.text(source),
+            "B: [ box; circle ] with .nw at A.se"
+        );
+        match &last.kind {
+            StatementKind::Object(obj) => match &obj.kind {
+                ObjectKind::Sublist(inner) => assert_eq!(inner.len(), 2),
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_line_attributes() {
+        let obj = object(
+            "arrow from A.e right 1 then down until even with B then 0.5 ne <-> dashed 0.05 chop",
+        );
+        let kinds: Vec<_> = obj
+            .attributes
+            .iter()
+            .map(|a| match &a.kind {
+                AttributeKind::From(_) => "from",
+                AttributeKind::Go { .. } => "go",
+                AttributeKind::Then => "then",
+                AttributeKind::Even { .. } => "even",
+                AttributeKind::EdgeHeading { then: true, .. } => "then heading",
+                AttributeKind::Flag(Flag::LRArrow) => "<->",
+                AttributeKind::Dash {
+                    spacing: Some(_), ..
+                } => "dashed",
+                AttributeKind::Chop => "chop",
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "from",
+                "go",
+                "then",
+                "even",
+                "then heading",
+                "<->",
+                "dashed",
+                "chop"
+            ]
+        );
+        let first = object("line 150%");
+        assert!(matches!(
+            &first.attributes[0].kind,
+            AttributeKind::Go {
+                direction: None,
+                distance: Some(RelExpr { percent: true, .. })
+            }
+        ));
+    }
+
+    #[test]
+    fn disambiguates_positions() {
+        assert!(matches!(position("1, 2"), PositionKind::Coordinates(..)));
+        assert!(matches!(
+            position("A.x, B.y"),
+            PositionKind::Coordinates(..)
+        ));
+        assert!(matches!(position("(1, 2)"), PositionKind::Coordinates(..)));
+        assert!(matches!(
+            position("((1+2)*3, 4)"),
+            PositionKind::Coordinates(..)
+        ));
+        assert!(matches!(position("(A, B)"), PositionKind::Combine(..)));
+        assert!(matches!(
+            position("A.n"),
+            PositionKind::Place(Place {
+                kind: PlaceKind::Edge {
+                    edge: Edge::North,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(matches!(
+            position("top of last box"),
+            PositionKind::Place(Place {
+                kind: PlaceKind::Edge {
+                    edge: Edge::North,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(matches!(
+            position("A - (1, 2)"),
+            PositionKind::Offset { subtract: true, .. }
+        ));
+        assert!(matches!(
+            position("A + 1, 2"),
+            PositionKind::Offset {
+                subtract: false,
+                ..
+            }
+        ));
+        assert!(matches!(
+            position("1/3 way between A and B"),
+            PositionKind::Between { .. }
+        ));
+        assert!(matches!(
+            position("0.5 of the way between A and B"),
+            PositionKind::Between { .. }
+        ));
+        assert!(matches!(
+            position("0.5 <A, B>"),
+            PositionKind::Between { .. }
+        ));
+        assert!(matches!(
+            position("1 left of A"),
+            PositionKind::Relative {
+                direction: Direction::Left,
+                ..
+            }
+        ));
+        assert!(matches!(
+            position("1 heading 45 from A"),
+            PositionKind::Heading { .. }
+        ));
+        assert!(matches!(
+            position("1 ne of A"),
+            PositionKind::EdgeHeading { .. }
+        ));
+        assert!(matches!(
+            position("2nd vertex of last line"),
+            PositionKind::Place(Place {
+                kind: PlaceKind::Vertex { nth: 2, .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parses_expressions_with_precedence() {
+        let doc = parse("v = -1 + 2 * max(3, A.wid) / dist(A, B)").unwrap();
+        let value = match &doc.statements[0].kind {
+            StatementKind::Assignment { value, .. } => value,
+            other => panic!("{:?}", other),
+        };
+        match &value.kind {
+            ExprKind::Binary {
+                op: BinaryOp::Add,
+                lhs,
+                rhs,
+            } => {
+                assert!(matches!(
+                    lhs.kind,
+                    ExprKind::Unary {
+                        op: UnaryOp::Minus,
+                        ..
+                    }
+                ));
+                assert!(matches!(
+                    rhs.kind,
+                    ExprKind::Binary {
+                        op: BinaryOp::Divide,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+        let doc = parse("k = 2cm + 0x10 + (fill)").unwrap();
+        match &doc.statements[0].kind {
+            StatementKind::Assignment { value, .. } => match &value.kind {
+                ExprKind::Binary { lhs, rhs, .. } => {
+                    assert!(matches!(&rhs.kind, ExprKind::Variable(n) if n.text == "fill"));
+                    assert!(matches!(&lhs.kind, ExprKind::Binary { .. }));
+                }
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn expands_macros() {
+        let source = "define pair { box \"$1\"; box \"$2\" }\npair(a, b)\ncircle";
+        let doc = parse(source).unwrap();
+        assert_eq!(doc.statements.len(), 4);
+        let call = doc.statements[1].macro_call.unwrap();
+        assert_eq!(call.text(source), "pair(a, b)");
+        assert_eq!(doc.statements[1].span, call);
+        assert_eq!(doc.statements[3].macro_call, None);
+        let err = parse("define r { r }\nr").unwrap_err();
+        assert_eq!(err.message(), "recursive macro definition");
+    }
+
+    #[test]
+    fn accepts_the_examples_the_c_code_accepts() {
+        for source in &[
+            "arrow right 200% \"Markdown\" \"Source\"\nbox rad 10px \"Markdown\" \"Formatter\" fit\narrow right 200% \"HTML+SVG\" \"Output\"\narrow <-> down 70% from last box.s\nbox same \"Pikchr\" \"Formatter\" fit",
+            "C0: circle; arrow; circle \"C1\"; arrow; circle \"C2\"\nspline -> from C0.s down 0.5 then to 2nd circle.s",
+            "A: box; B: box at 1 heading 30 from A; line from A to B behind A",
+            "box; arc cw ->; oval \"x\" italic big big; file; cylinder \"db\" fill Red color 0x123456",
+            "line go 1 heading 90 then go 1 e close; dot at 1st line.start; move to (1,1)",
+            "X: [ A: box ]; box; box; box; arrow from X.A.e; text \"hi\" at 3rd previous box.n",
+        ] {
+            crate::Pikchr::render(source, None, crate::PikchrFlags::default())
+                .unwrap_or_else(|e| panic!("C rejected {}: {}", source, e));
+            if let Err(e) = parse(source) {
+                panic!("{}: {} ({:?})", source, e, e.span().text(source));
+            }
+        }
+    }
+
+    #[test]
+    fn recovers_from_errors_inside_sublists() {
+        let (doc, errors) = parse_recovering("[ box at; circle ]\nline");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(doc.statements.len(), 2);
+    }
+}
@@ -0,0 +1,1353 @@
+//! Recursive descent parser for pikchr
+//!
+//! The C implementation uses an LALR(1) parser generated by lemon.  A few
+//! places in that grammar need more than one token of lookahead to parse
+//! top-down, most notably telling the different forms of position apart,
+//! so the parser backtracks in those places.
+
+use super::lexer::{edge_of, number_value, token_at, TokenKind};
+use super::*;
+
+/// The deepest that macro expansions may be nested, as in the C code
+const MAX_DEPTH: usize = 10;
+
+type PResult<T> = Result<T, ParseError>;
+
+/// A token after macro expansion
+#[derive(Copy, Clone, Debug)]
+struct Lexeme {
+    kind: TokenKind,
+    /// Where the text of the token is
+    span: Span,
+    /// Where the token appears in the document, which is the macro
+    /// invocation for tokens which came from a macro
+    site: Span,
+}
+
+struct Macro {
+    name: String,
+    body: Span,
+    in_use: bool,
+}
+
+pub(super) struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Lexeme>,
+    pos: usize,
+    macros: Vec<Macro>,
+    errors: Vec<ParseError>,
+}
+
+impl<'a> Parser<'a> {
+    pub(super) fn new(source: &'a str) -> Self {
+        Parser::new_range(source, Span::new(0, source.len()))
+    }
+
+    /// Create a parser for part of the source, which must start and end
+    /// between statements
+    pub(super) fn new_range(source: &'a str, range: Span) -> Self {
+        let mut parser = Parser {
+            source,
+            tokens: Vec::new(),
+            pos: 0,
+            macros: Vec::new(),
+            errors: Vec::new(),
+        };
+        parser.expand(range, &[], None, 0);
+        parser
+    }
+
+    pub(super) fn parse(mut self) -> (Document, Vec<ParseError>) {
+        let statements = self.statement_list(false);
+        self.errors.sort_by_key(|e| e.span.start);
+        (Document { statements }, self.errors)
+    }
+
+    // Macro expansion, which mirrors pik_tokenize() in the C code
+
+    fn expand(&mut self, range: Span, args: &[Option<Span>], site: Option<Span>, depth: usize) {
+        let mut pos = range.start;
+        while pos < range.end {
+            let (kind, len) = token_at(self.source, pos, true);
+            let span = Span::new(pos, pos + len);
+            pos = span.end;
+            if span.end > range.end {
+                let span = Span::new(span.start, range.end);
+                self.errors.push(ParseError::new("syntax error", span));
+                return;
+            }
+            match kind {
+                TokenKind::Whitespace | TokenKind::Comment => {}
+                TokenKind::Error => {
+                    self.errors
+                        .push(ParseError::new("unrecognized token", span));
+                }
+                TokenKind::Parameter => {
+                    let idx = (self.source.as_bytes()[span.start + 1] - b'1') as usize;
+                    if let Some(Some(arg)) = args.get(idx) {
+                        if depth >= MAX_DEPTH {
+                            self.errors
+                                .push(ParseError::new("macros nested too deep", span));
+                        } else {
+                            self.expand(*arg, &[], Some(site.unwrap_or(span)), depth + 1);
+                        }
+                    }
+                }
+                TokenKind::Id if !self.after_define() => match self.find_macro(span) {
+                    Some(idx) => pos = self.expand_macro(idx, span, range.end, args, site, depth),
+                    None => self.push(kind, span, site),
+                },
+                _ => self.push(kind, span, site),
+            }
+        }
+    }
+
+    /// Expand a use of a macro, returning the offset just after the
+    /// invocation and its arguments
+    fn expand_macro(
+        &mut self,
+        idx: usize,
+        name: Span,
+        limit: usize,
+        outer: &[Option<Span>],
+        site: Option<Span>,
+        depth: usize,
+    ) -> usize {
+        if self.macros[idx].in_use {
+            self.errors
+                .push(ParseError::new("recursive macro definition", name));
+            return name.end;
+        }
+        let (args, len) = match self.macro_args(name.end, limit, outer) {
+            Ok(found) => found,
+            Err(e) => {
+                self.errors.push(e);
+                return name.end;
+            }
+        };
+        let call = Span::new(name.start, name.end + len);
+        if depth >= MAX_DEPTH {
+            self.errors
+                .push(ParseError::new("macros nested too deep", call));
+            return call.end;
+        }
+        let body = self.macros[idx].body;
+        self.macros[idx].in_use = true;
+        self.expand(body, &args, Some(site.unwrap_or(call)), depth + 1);
+        self.macros[idx].in_use = false;
+        call.end
+    }
+
+    /// Parse the arguments of a macro invocation, if there are any
+    ///
+    /// As in the C code, the argument list must start immediately after
+    /// the macro name, and ends at the first `)` which is not part of a
+    /// longer token.
+    fn macro_args(
+        &self,
+        start: usize,
+        limit: usize,
+        outer: &[Option<Span>],
+    ) -> PResult<(Vec<Option<Span>>, usize)> {
+        let z = self.source.as_bytes();
+        if start >= limit || z[start] != b'(' {
+            return Ok((Vec::new(), 0));
+        }
+        let open = Span::new(start, start + 1);
+        let mut args = Vec::new();
+        let mut arg_start = start + 1;
+        let mut depth = 0;
+        let mut i = start + 1;
+        while i < limit && z[i] != b')' {
+            let (_, len) = token_at(self.source, i, false);
+            if len == 1 {
+                match z[i] {
+                    b',' if depth <= 0 => {
+                        if args.len() == 8 {
+                            return Err(ParseError::new("too many macro arguments - max 9", open));
+                        }
+                        args.push(Span::new(arg_start, i));
+                        arg_start = i + 1;
+                        depth = 0;
+                    }
+                    b'(' | b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    _ => {}
+                }
+            }
+            i += len;
+        }
+        if i >= limit {
+            return Err(ParseError::new("unterminated macro argument list", open));
+        }
+        args.push(Span::new(arg_start, i));
+        let args = args
+            .into_iter()
+            .map(|arg| {
+                let arg = self.trim(arg);
+                match arg.text(self.source).as_bytes() {
+                    [b'$', n @ b'1'..=b'9'] => outer.get((n - b'1') as usize).copied().flatten(),
+                    [] => None,
+                    _ => Some(arg),
+                }
+            })
+            .collect();
+        Ok((args, i + 1 - start))
+    }
+
+    fn trim(&self, mut span: Span) -> Span {
+        let z = self.source.as_bytes();
+        while span.start < span.end && z[span.start].is_ascii_whitespace() {
+            span.start += 1;
+        }
+        while span.start < span.end && z[span.end - 1].is_ascii_whitespace() {
+            span.end -= 1;
+        }
+        span
+    }
+
+    fn after_define(&self) -> bool {
+        matches!(self.tokens.last(), Some(t) if t.kind == TokenKind::Define)
+    }
+
+    fn find_macro(&self, name: Span) -> Option<usize> {
+        let name = name.text(self.source);
+        self.macros.iter().position(|m| m.name == name)
+    }
+
+    fn push(&mut self, kind: TokenKind, span: Span, site: Option<Span>) {
+        self.tokens.push(Lexeme {
+            kind,
+            span,
+            site: site.unwrap_or(span),
+        });
+        if kind != TokenKind::CodeBlock {
+            return;
+        }
+        if let [.., define, name, _] = self.tokens.as_slice() {
+            if define.kind == TokenKind::Define && name.kind == TokenKind::Id {
+                let body = Span::new(span.start + 1, span.end - 1);
+                match self.find_macro(name.span) {
+                    Some(idx) => self.macros[idx].body = body,
+                    None => self.macros.push(Macro {
+                        name: name.span.text(self.source).to_string(),
+                        body,
+                        in_use: false,
+                    }),
+                }
+            }
+        }
+    }
+
+    // Helpers for working through the tokens
+
+    fn peek(&self) -> Option<TokenKind> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<TokenKind> {
+        self.tokens.get(self.pos + n).map(|t| t.kind)
+    }
+
+    fn at(&self, kind: TokenKind) -> bool {
+        self.peek() == Some(kind)
+    }
+
+    fn bump(&mut self) -> Lexeme {
+        let token = self.tokens[self.pos];
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, kind: TokenKind) -> Option<Lexeme> {
+        if self.at(kind) {
+            Some(self.bump())
+        } else {
+            None
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> PResult<Lexeme> {
+        match self.eat(kind) {
+            Some(token) => Ok(token),
+            None => self.fail(),
+        }
+    }
+
+    fn fail<T>(&self) -> PResult<T> {
+        let span = match self.tokens.get(self.pos) {
+            Some(token) => token.span,
+            None => Span::new(self.source.len(), self.source.len()),
+        };
+        Err(ParseError::new("syntax error", span))
+    }
+
+    /// Try to parse something, rewinding if it cannot be parsed
+    fn attempt<T>(&mut self, f: impl FnOnce(&mut Self) -> PResult<T>) -> Option<T> {
+        let start = self.pos;
+        match f(self) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    /// The span from the token at `start` up to the last token consumed
+    fn span_from(&self, start: usize) -> Span {
+        if self.pos > start {
+            self.tokens[start].site.to(self.tokens[self.pos - 1].site)
+        } else {
+            let at = self
+                .tokens
+                .get(start)
+                .map_or(self.source.len(), |t| t.site.start);
+            Span::new(at, at)
+        }
+    }
+
+    fn text(&self, token: Lexeme) -> &'a str {
+        token.span.text(self.source)
+    }
+
+    fn bump_name(&mut self) -> Name {
+        let token = self.bump();
+        self.name(token)
+    }
+
+    fn name(&self, token: Lexeme) -> Name {
+        Name {
+            text: self.text(token).to_string(),
+            span: token.span,
+        }
+    }
+
+    // Statements
+
+    fn statement_list(&mut self, in_sublist: bool) -> Vec<Statement> {
+        let mut statements = Vec::new();
+        loop {
+            match self.peek() {
+                None => break,
+                Some(TokenKind::RBracket) if in_sublist => break,
+                Some(TokenKind::Eol) => {
+                    self.pos += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            let result = self.statement().and_then(|statement| match self.peek() {
+                None | Some(TokenKind::Eol) => Ok(statement),
+                Some(TokenKind::RBracket) if in_sublist => Ok(statement),
+                _ => self.fail(),
+            });
+            match result {
+                Ok(statement) => statements.push(statement),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.recover(in_sublist);
+                }
+            }
+        }
+        statements
+    }
+
+    /// Skip to the end of a statement which could not be parsed
+    fn recover(&mut self, in_sublist: bool) {
+        let mut depth = 0;
+        while let Some(kind) = self.peek() {
+            match kind {
+                TokenKind::Eol if depth == 0 => return,
+                TokenKind::LBracket => depth += 1,
+                TokenKind::RBracket if depth > 0 => depth -= 1,
+                TokenKind::RBracket if in_sublist => return,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn statement(&mut self) -> PResult<Statement> {
+        let start = self.pos;
+        let first = self.tokens[start];
+        let macro_call = if first.site != first.span {
+            Some(first.site)
+        } else {
+            None
+        };
+        let mut label = None;
+        let kind = match first.kind {
+            TokenKind::PlaceName if self.peek_nth(1) == Some(TokenKind::Colon) => {
+                label = Some(self.bump_name());
+                self.bump();
+                if self.starts_object() {
+                    StatementKind::Object(self.object()?)
+                } else {
+                    StatementKind::Place(Box::new(self.position()?))
+                }
+            }
+            TokenKind::Up | TokenKind::Down | TokenKind::Left | TokenKind::Right => {
+                StatementKind::Direction(direction(self.bump().kind))
+            }
+            TokenKind::Id | TokenKind::Fill | TokenKind::Color | TokenKind::Thickness
+                if self.peek_nth(1) == Some(TokenKind::Assign) =>
+            {
+                let variable = self.bump_name();
+                let op = self.bump();
+                let op = match self.text(op) {
+                    "+=" => AssignOp::Add,
+                    "-=" => AssignOp::Subtract,
+                    "*=" => AssignOp::Multiply,
+                    "/=" => AssignOp::Divide,
+                    _ => AssignOp::Assign,
+                };
+                let value = self.rvalue()?;
+                StatementKind::Assignment {
+                    variable,
+                    op,
+                    value,
+                }
+            }
+            TokenKind::Print => {
+                self.bump();
+                let mut items = vec![self.print_item()?];
+                while self.eat(TokenKind::Comma).is_some() {
+                    items.push(self.print_item()?);
+                }
+                StatementKind::Print(items)
+            }
+            TokenKind::Assert => {
+                self.bump();
+                self.expect(TokenKind::LParen)?;
+                let values = self.attempt(|p| {
+                    let lhs = p.expr()?;
+                    p.expect(TokenKind::Eq)?;
+                    Ok((lhs, p.expr()?))
+                });
+                let assertion = match values {
+                    Some((lhs, rhs)) => Assertion::Equal(lhs, rhs),
+                    None => {
+                        let lhs = self.position()?;
+                        self.expect(TokenKind::Eq)?;
+                        Assertion::SamePosition(Box::new(lhs), Box::new(self.position()?))
+                    }
+                };
+                self.expect(TokenKind::RParen)?;
+                StatementKind::Assert(assertion)
+            }
+            TokenKind::Define => {
+                self.bump();
+                let name = self.expect(TokenKind::Id)?;
+                let body = self.expect(TokenKind::CodeBlock)?.span;
+                StatementKind::Define {
+                    name: self.name(name),
+                    body: Span::new(body.start + 1, body.end - 1),
+                }
+            }
+            _ if self.starts_object() => StatementKind::Object(self.object()?),
+            _ => return self.fail(),
+        };
+        Ok(Statement {
+            label,
+            kind,
+            span: self.span_from(start),
+            macro_call,
+        })
+    }
+
+    fn print_item(&mut self) -> PResult<PrintItem> {
+        let start = self.pos;
+        match self.peek() {
+            Some(TokenKind::String) => {
+                let token = self.bump();
+                Ok(PrintItem::Text(self.string(token, Vec::new())))
+            }
+            Some(TokenKind::Fill) | Some(TokenKind::Color) | Some(TokenKind::Thickness) => {
+                let name = self.bump_name();
+                Ok(PrintItem::Value(Expr {
+                    kind: ExprKind::Variable(name),
+                    span: self.span_from(start),
+                }))
+            }
+            _ => Ok(PrintItem::Value(self.rvalue()?)),
+        }
+    }
+
+    /// An expression, or a colour name such as `Red`
+    fn rvalue(&mut self) -> PResult<Expr> {
+        let is_color = self.at(TokenKind::PlaceName)
+            && !matches!(
+                self.peek_nth(1),
+                Some(TokenKind::DotE)
+                    | Some(TokenKind::DotL)
+                    | Some(TokenKind::DotXY)
+                    | Some(TokenKind::DotU)
+            );
+        if is_color {
+            let token = self.bump();
+            Ok(Expr {
+                kind: ExprKind::Color(self.name(token)),
+                span: token.site,
+            })
+        } else {
+            self.expr()
+        }
+    }
+
+    // Objects and their attributes
+
+    fn starts_object(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(TokenKind::ClassName) | Some(TokenKind::String) | Some(TokenKind::LBracket)
+        )
+    }
+
+    fn object(&mut self) -> PResult<Object> {
+        let start = self.pos;
+        let kind = match self.peek() {
+            Some(TokenKind::ClassName) => ObjectKind::Class(self.bump_name()),
+            Some(TokenKind::String) => ObjectKind::Text(self.text_with_positions()),
+            Some(TokenKind::LBracket) => {
+                self.bump();
+                let statements = self.statement_list(true);
+                self.expect(TokenKind::RBracket)?;
+                ObjectKind::Sublist(statements)
+            }
+            _ => return self.fail(),
+        };
+        let mut attributes = Vec::new();
+        if self.starts_expr() {
+            let start = self.pos;
+            let distance = self.relexpr()?;
+            attributes.push(Attribute {
+                kind: AttributeKind::Go {
+                    direction: None,
+                    distance: Some(distance),
+                },
+                span: self.span_from(start),
+            });
+        }
+        while !matches!(
+            self.peek(),
+            None | Some(TokenKind::Eol) | Some(TokenKind::RBracket)
+        ) {
+            attributes.push(self.attribute()?);
+        }
+        Ok(Object {
+            kind,
+            attributes,
+            span: self.span_from(start),
+        })
+    }
+
+    fn string(&self, token: Lexeme, positions: Vec<TextPosition>) -> Text {
+        let span = token.span;
+        Text {
+            text: self.source[span.start + 1..span.end - 1].to_string(),
+            span,
+            positions,
+        }
+    }
+
+    fn text_with_positions(&mut self) -> Text {
+        let token = self.bump();
+        let mut positions = Vec::new();
+        while let Some(position) = self.peek().and_then(text_position) {
+            self.bump();
+            positions.push(position);
+        }
+        self.string(token, positions)
+    }
+
+    fn attribute(&mut self) -> PResult<Attribute> {
+        let start = self.pos;
+        let kind = match self.peek() {
+            Some(
+                kind @ TokenKind::Height
+                | kind @ TokenKind::Width
+                | kind @ TokenKind::Radius
+                | kind @ TokenKind::Diameter
+                | kind @ TokenKind::Thickness,
+            ) => {
+                self.bump();
+                AttributeKind::Numeric {
+                    property: numeric_property(kind),
+                    value: self.relexpr()?,
+                }
+            }
+            Some(kind @ TokenKind::Dotted) | Some(kind @ TokenKind::Dashed) => {
+                self.bump();
+                let spacing = if self.starts_expr() {
+                    Some(self.expr()?)
+                } else {
+                    None
+                };
+                AttributeKind::Dash {
+                    style: dash_style(kind),
+                    spacing,
+                }
+            }
+            Some(kind @ TokenKind::Fill) | Some(kind @ TokenKind::Color) => {
+                self.bump();
+                AttributeKind::Color {
+                    property: color_property(kind),
+                    value: self.rvalue()?,
+                }
+            }
+            Some(TokenKind::Go) => {
+                self.bump();
+                if is_direction(self.peek()) {
+                    self.go_direction()?
+                } else {
+                    let distance = self.opt_relexpr()?;
+                    self.heading(false, distance)?
+                }
+            }
+            Some(TokenKind::Up)
+            | Some(TokenKind::Down)
+            | Some(TokenKind::Left)
+            | Some(TokenKind::Right) => self.go_direction()?,
+            Some(TokenKind::Then) => {
+                self.bump();
+                if self.starts_expr() {
+                    let distance = Some(self.relexpr()?);
+                    self.heading(true, distance)?
+                } else if matches!(
+                    self.peek(),
+                    Some(TokenKind::Heading) | Some(TokenKind::EdgePt)
+                ) {
+                    self.heading(true, None)?
+                } else {
+                    AttributeKind::Then
+                }
+            }
+            Some(TokenKind::Close) => {
+                self.bump();
+                AttributeKind::Close
+            }
+            Some(TokenKind::Chop) => {
+                self.bump();
+                AttributeKind::Chop
+            }
+            Some(TokenKind::From) => {
+                self.bump();
+                AttributeKind::From(self.position()?)
+            }
+            Some(TokenKind::To) => {
+                self.bump();
+                AttributeKind::To(self.position()?)
+            }
+            Some(TokenKind::At) => {
+                self.bump();
+                AttributeKind::At(self.position()?)
+            }
+            Some(TokenKind::Same) => {
+                self.bump();
+                if self.eat(TokenKind::As).is_some() {
+                    AttributeKind::Same(Some(self.object_ref()?))
+                } else {
+                    AttributeKind::Same(None)
+                }
+            }
+            Some(TokenKind::String) => AttributeKind::Text(self.text_with_positions()),
+            Some(TokenKind::Fit) => {
+                self.bump();
+                AttributeKind::Fit
+            }
+            Some(TokenKind::Behind) => {
+                self.bump();
+                AttributeKind::Behind(self.object_ref()?)
+            }
+            Some(TokenKind::With) => {
+                self.bump();
+                self.eat(TokenKind::DotE);
+                let edge = self.edge()?;
+                self.expect(TokenKind::At)?;
+                AttributeKind::With {
+                    edge,
+                    position: self.position()?,
+                }
+            }
+            Some(kind) => match flag(kind) {
+                Some(flag) => {
+                    self.bump();
+                    AttributeKind::Flag(flag)
+                }
+                None => return self.fail(),
+            },
+            None => return self.fail(),
+        };
+        Ok(Attribute {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// A direction, optionally followed by a distance or `even with`
+    fn go_direction(&mut self) -> PResult<AttributeKind> {
+        let direction = direction(self.bump().kind);
+        let even = if self.eat(TokenKind::Until).is_some() {
+            self.expect(TokenKind::Even)?;
+            true
+        } else {
+            self.eat(TokenKind::Even).is_some()
+        };
+        if even {
+            self.expect(TokenKind::With)?;
+            Ok(AttributeKind::Even {
+                direction,
+                position: self.position()?,
+            })
+        } else {
+            Ok(AttributeKind::Go {
+                direction: Some(direction),
+                distance: self.opt_relexpr()?,
+            })
+        }
+    }
+
+    /// The `heading ...` or compass point part of a movement
+    fn heading(&mut self, then: bool, distance: Option<RelExpr>) -> PResult<AttributeKind> {
+        if self.eat(TokenKind::Heading).is_some() {
+            Ok(AttributeKind::Heading {
+                then,
+                distance,
+                heading: self.expr()?,
+            })
+        } else if self.at(TokenKind::EdgePt) {
+            Ok(AttributeKind::EdgeHeading {
+                then,
+                distance,
+                edge: self.edge()?,
+            })
+        } else {
+            self.fail()
+        }
+    }
+
+    fn is_edge(&self, n: usize) -> bool {
+        matches!(
+            self.peek_nth(n),
+            Some(TokenKind::Center)
+                | Some(TokenKind::EdgePt)
+                | Some(TokenKind::Top)
+                | Some(TokenKind::Bottom)
+                | Some(TokenKind::Start)
+                | Some(TokenKind::End)
+                | Some(TokenKind::Left)
+                | Some(TokenKind::Right)
+        )
+    }
+
+    fn edge(&mut self) -> PResult<Edge> {
+        if !self.is_edge(0) {
+            return self.fail();
+        }
+        let token = self.bump();
+        Ok(edge_of(self.text(token)).expect("edge keyword"))
+    }
+
+    // Positions, places and objects
+
+    fn position(&mut self) -> PResult<Position> {
+        let start = self.pos;
+        if self.at(TokenKind::LParen) {
+            if let Some(position) = self.attempt(|p| p.paren_position(start)) {
+                return Ok(position);
+            }
+        }
+        if self.starts_place() {
+            if let Some(position) = self.attempt(|p| p.place_position(start)) {
+                return Ok(position);
+            }
+        }
+        let expr = self.expr()?;
+        let kind = match self.peek() {
+            Some(TokenKind::Comma) => {
+                self.bump();
+                PositionKind::Coordinates(expr, self.expr()?)
+            }
+            Some(TokenKind::Between) => {
+                self.bump();
+                self.between(expr)?
+            }
+            Some(TokenKind::Way) => {
+                self.bump();
+                self.expect(TokenKind::Between)?;
+                self.between(expr)?
+            }
+            Some(TokenKind::Of) => {
+                self.bump();
+                self.expect(TokenKind::The)?;
+                self.expect(TokenKind::Way)?;
+                self.expect(TokenKind::Between)?;
+                self.between(expr)?
+            }
+            Some(TokenKind::Lt) => {
+                self.bump();
+                let from = self.position()?;
+                self.expect(TokenKind::Comma)?;
+                let to = self.position()?;
+                self.expect(TokenKind::Gt)?;
+                PositionKind::Between {
+                    fraction: expr,
+                    from: Box::new(from),
+                    to: Box::new(to),
+                }
+            }
+            Some(kind @ TokenKind::Above) | Some(kind @ TokenKind::Below) => {
+                self.bump();
+                PositionKind::Relative {
+                    distance: expr,
+                    direction: if kind == TokenKind::Above {
+                        Direction::Up
+                    } else {
+                        Direction::Down
+                    },
+                    position: Box::new(self.position()?),
+                }
+            }
+            Some(kind @ TokenKind::Left) | Some(kind @ TokenKind::Right)
+                if self.peek_nth(1) == Some(TokenKind::Of) =>
+            {
+                self.bump();
+                self.bump();
+                PositionKind::Relative {
+                    distance: expr,
+                    direction: direction(kind),
+                    position: Box::new(self.position()?),
+                }
+            }
+            Some(TokenKind::Heading) => {
+                self.bump();
+                if self.at(TokenKind::EdgePt) && self.peek_nth(1) == Some(TokenKind::Of) {
+                    self.edge_heading(expr)?
+                } else {
+                    let heading = self.expr()?;
+                    self.expect(TokenKind::From)?;
+                    PositionKind::Heading {
+                        distance: expr,
+                        heading,
+                        position: Box::new(self.position()?),
+                    }
+                }
+            }
+            Some(TokenKind::EdgePt) if self.peek_nth(1) == Some(TokenKind::Of) => {
+                self.edge_heading(expr)?
+            }
+            _ => return self.fail(),
+        };
+        Ok(Position {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// `(position)` or `(position, position)`
+    fn paren_position(&mut self, start: usize) -> PResult<Position> {
+        self.bump();
+        let first = self.position()?;
+        let kind = if self.eat(TokenKind::Comma).is_some() {
+            PositionKind::Combine(Box::new(first), Box::new(self.position()?))
+        } else {
+            first.kind
+        };
+        self.expect(TokenKind::RParen)?;
+        Ok(Position {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// A place, optionally offset by some amount
+    fn place_position(&mut self, start: usize) -> PResult<Position> {
+        let place = self.place()?;
+        let kind = match self.peek() {
+            // This is really an expression such as `A.x`
+            Some(TokenKind::DotXY) | Some(TokenKind::DotL) => return self.fail(),
+            Some(op @ TokenKind::Plus) | Some(op @ TokenKind::Minus) => {
+                self.bump();
+                let parenthesised = self.attempt(|p| {
+                    p.expect(TokenKind::LParen)?;
+                    let x = p.expr()?;
+                    p.expect(TokenKind::Comma)?;
+                    let y = p.expr()?;
+                    p.expect(TokenKind::RParen)?;
+                    Ok((x, y))
+                });
+                let (x, y) = match parenthesised {
+                    Some(offset) => offset,
+                    None => {
+                        let x = self.expr()?;
+                        self.expect(TokenKind::Comma)?;
+                        (x, self.expr()?)
+                    }
+                };
+                PositionKind::Offset {
+                    place,
+                    subtract: op == TokenKind::Minus,
+                    x,
+                    y,
+                }
+            }
+            _ => PositionKind::Place(place),
+        };
+        Ok(Position {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    fn between(&mut self, fraction: Expr) -> PResult<PositionKind> {
+        let from = self.position()?;
+        self.expect(TokenKind::And)?;
+        let to = self.position()?;
+        Ok(PositionKind::Between {
+            fraction,
+            from: Box::new(from),
+            to: Box::new(to),
+        })
+    }
+
+    fn edge_heading(&mut self, distance: Expr) -> PResult<PositionKind> {
+        let edge = self.edge()?;
+        self.expect(TokenKind::Of)?;
+        Ok(PositionKind::EdgeHeading {
+            distance,
+            edge,
+            position: Box::new(self.position()?),
+        })
+    }
+
+    fn starts_place(&self) -> bool {
+        match self.peek() {
+            Some(TokenKind::PlaceName)
+            | Some(TokenKind::This)
+            | Some(TokenKind::Nth)
+            | Some(TokenKind::Last) => true,
+            _ => self.is_edge(0) && self.peek_nth(1) == Some(TokenKind::Of),
+        }
+    }
+
+    fn place(&mut self) -> PResult<Place> {
+        let start = self.pos;
+        if self.is_edge(0) && self.peek_nth(1) == Some(TokenKind::Of) {
+            let edge = self.edge()?;
+            self.bump();
+            let object = self.object_ref()?;
+            return Ok(Place {
+                kind: PlaceKind::Edge { object, edge },
+                span: self.span_from(start),
+            });
+        }
+        if self.at(TokenKind::Nth) && self.peek_nth(1) == Some(TokenKind::Vertex) {
+            return self.vertex();
+        }
+        let object = self.object_ref()?;
+        let kind = if self.eat(TokenKind::DotE).is_some() {
+            PlaceKind::Edge {
+                object,
+                edge: self.edge()?,
+            }
+        } else {
+            PlaceKind::Object(object)
+        };
+        Ok(Place {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// `nth vertex of object`
+    fn vertex(&mut self) -> PResult<Place> {
+        let start = self.pos;
+        let nth = self.bump();
+        let nth = self.ordinal(nth)?;
+        self.bump();
+        self.expect(TokenKind::Of)?;
+        let object = self.object_ref()?;
+        Ok(Place {
+            kind: PlaceKind::Vertex { nth, object },
+            span: self.span_from(start),
+        })
+    }
+
+    fn ordinal(&self, token: Lexeme) -> PResult<u32> {
+        let text = self.text(token);
+        if text == "first" {
+            return Ok(1);
+        }
+        let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        match digits.parse::<u32>() {
+            Ok(n) if n <= 1000 => Ok(n),
+            _ => Err(ParseError::new("value too big - max '1000th'", token.span)),
+        }
+    }
+
+    fn object_ref(&mut self) -> PResult<ObjectRef> {
+        let start = self.pos;
+        let kind = match self.peek() {
+            Some(TokenKind::This) => {
+                self.bump();
+                ObjectRefKind::This
+            }
+            Some(TokenKind::PlaceName) => {
+                let mut names = vec![self.bump_name()];
+                while self.at(TokenKind::DotU) && self.peek_nth(1) == Some(TokenKind::PlaceName) {
+                    self.bump();
+                    names.push(self.bump_name());
+                }
+                ObjectRefKind::Named(names)
+            }
+            Some(TokenKind::Nth) | Some(TokenKind::Last) => {
+                let nth = self.nth()?;
+                let within =
+                    if self.eat(TokenKind::Of).is_some() || self.eat(TokenKind::In).is_some() {
+                        Some(Box::new(self.object_ref()?))
+                    } else {
+                        None
+                    };
+                ObjectRefKind::Nth { nth, within }
+            }
+            _ => return self.fail(),
+        };
+        Ok(ObjectRef {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    fn nth(&mut self) -> PResult<Nth> {
+        let first = self.bump();
+        let (count, from_end) = if first.kind == TokenKind::Last {
+            (1, true)
+        } else {
+            let count = self.ordinal(first)?;
+            (count, self.eat(TokenKind::Last).is_some())
+        };
+        let class = match self.peek() {
+            Some(TokenKind::ClassName) => NthClass::Class(self.bump_name()),
+            Some(TokenKind::LBracket) if self.peek_nth(1) == Some(TokenKind::RBracket) => {
+                self.bump();
+                self.bump();
+                NthClass::Sublist
+            }
+            _ if first.kind == TokenKind::Last => NthClass::Any,
+            _ => return self.fail(),
+        };
+        Ok(Nth {
+            count,
+            from_end,
+            class,
+        })
+    }
+
+    // Expressions
+
+    fn starts_expr(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(TokenKind::Number)
+                | Some(TokenKind::Id)
+                | Some(TokenKind::LParen)
+                | Some(TokenKind::Minus)
+                | Some(TokenKind::Plus)
+                | Some(TokenKind::Func1)
+                | Some(TokenKind::Func2)
+                | Some(TokenKind::Dist)
+                | Some(TokenKind::PlaceName)
+                | Some(TokenKind::This)
+                | Some(TokenKind::Nth)
+                | Some(TokenKind::Last)
+        )
+    }
+
+    fn opt_relexpr(&mut self) -> PResult<Option<RelExpr>> {
+        if self.starts_expr() {
+            Ok(Some(self.relexpr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn relexpr(&mut self) -> PResult<RelExpr> {
+        let start = self.pos;
+        let expr = self.expr()?;
+        let percent = self.eat(TokenKind::Percent).is_some();
+        Ok(RelExpr {
+            expr,
+            percent,
+            span: self.span_from(start),
+        })
+    }
+
+    fn expr(&mut self) -> PResult<Expr> {
+        let start = self.pos;
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Plus) => BinaryOp::Add,
+                Some(TokenKind::Minus) => BinaryOp::Subtract,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+            let rhs = self.term()?;
+            lhs = self.binary(start, op, lhs, rhs);
+        }
+    }
+
+    fn term(&mut self) -> PResult<Expr> {
+        let start = self.pos;
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Star) => BinaryOp::Multiply,
+                Some(TokenKind::Slash) => BinaryOp::Divide,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+            let rhs = self.unary()?;
+            lhs = self.binary(start, op, lhs, rhs);
+        }
+    }
+
+    fn binary(&self, start: usize, op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr {
+            kind: ExprKind::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            },
+            span: self.span_from(start),
+        }
+    }
+
+    fn unary(&mut self) -> PResult<Expr> {
+        let start = self.pos;
+        let op = match self.peek() {
+            Some(TokenKind::Minus) => UnaryOp::Minus,
+            Some(TokenKind::Plus) => UnaryOp::Plus,
+            _ => return self.primary(),
+        };
+        self.bump();
+        let operand = self.unary()?;
+        Ok(Expr {
+            kind: ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+            span: self.span_from(start),
+        })
+    }
+
+    fn primary(&mut self) -> PResult<Expr> {
+        let start = self.pos;
+        let kind = match self.peek() {
+            Some(TokenKind::Number) => {
+                let token = self.bump();
+                ExprKind::Number(number_value(self.text(token)))
+            }
+            Some(TokenKind::Id) => ExprKind::Variable(self.bump_name()),
+            Some(TokenKind::LParen) => {
+                self.bump();
+                let builtin = matches!(
+                    self.peek(),
+                    Some(TokenKind::Fill) | Some(TokenKind::Color) | Some(TokenKind::Thickness)
+                ) && self.peek_nth(1) == Some(TokenKind::RParen);
+                let kind = if builtin {
+                    ExprKind::Variable(self.bump_name())
+                } else {
+                    self.expr()?.kind
+                };
+                self.expect(TokenKind::RParen)?;
+                kind
+            }
+            Some(kind @ TokenKind::Func1) | Some(kind @ TokenKind::Func2) => {
+                let token = self.bump();
+                let function = match self.text(token) {
+                    "abs" => Function::Abs,
+                    "cos" => Function::Cos,
+                    "int" => Function::Int,
+                    "sin" => Function::Sin,
+                    "sqrt" => Function::Sqrt,
+                    "max" => Function::Max,
+                    _ => Function::Min,
+                };
+                self.expect(TokenKind::LParen)?;
+                let mut args = vec![self.expr()?];
+                if kind == TokenKind::Func2 {
+                    self.expect(TokenKind::Comma)?;
+                    args.push(self.expr()?);
+                }
+                self.expect(TokenKind::RParen)?;
+                ExprKind::Call { function, args }
+            }
+            Some(TokenKind::Dist) => {
+                self.bump();
+                self.expect(TokenKind::LParen)?;
+                let from = self.position()?;
+                self.expect(TokenKind::Comma)?;
+                let to = self.position()?;
+                self.expect(TokenKind::RParen)?;
+                ExprKind::Distance(Box::new(from), Box::new(to))
+            }
+            Some(TokenKind::PlaceName)
+            | Some(TokenKind::This)
+            | Some(TokenKind::Nth)
+            | Some(TokenKind::Last) => return self.object_expr(),
+            _ => return self.fail(),
+        };
+        Ok(Expr {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// A coordinate of a place, or a property of an object
+    fn object_expr(&mut self) -> PResult<Expr> {
+        let start = self.pos;
+        if self.at(TokenKind::Nth) && self.peek_nth(1) == Some(TokenKind::Vertex) {
+            let place = self.vertex()?;
+            return self.coordinate(start, place);
+        }
+        let object = self.object_ref()?;
+        match self.peek() {
+            Some(TokenKind::DotL) => {
+                self.bump();
+                let property = match self.peek() {
+                    Some(
+                        kind @ TokenKind::Height
+                        | kind @ TokenKind::Width
+                        | kind @ TokenKind::Radius
+                        | kind @ TokenKind::Diameter
+                        | kind @ TokenKind::Thickness,
+                    ) => ObjectProperty::Numeric(numeric_property(kind)),
+                    Some(kind @ TokenKind::Dotted) | Some(kind @ TokenKind::Dashed) => {
+                        ObjectProperty::Dash(dash_style(kind))
+                    }
+                    Some(kind @ TokenKind::Fill) | Some(kind @ TokenKind::Color) => {
+                        ObjectProperty::Color(color_property(kind))
+                    }
+                    _ => return self.fail(),
+                };
+                self.bump();
+                Ok(Expr {
+                    kind: ExprKind::Property {
+                        object: Box::new(object),
+                        property,
+                    },
+                    span: self.span_from(start),
+                })
+            }
+            Some(TokenKind::DotE) => {
+                self.bump();
+                let edge = self.edge()?;
+                let place = Place {
+                    kind: PlaceKind::Edge { object, edge },
+                    span: self.span_from(start),
+                };
+                self.coordinate(start, place)
+            }
+            _ => {
+                let place = Place {
+                    kind: PlaceKind::Object(object),
+                    span: self.span_from(start),
+                };
+                self.coordinate(start, place)
+            }
+        }
+    }
+
+    fn coordinate(&mut self, start: usize, place: Place) -> PResult<Expr> {
+        self.expect(TokenKind::DotXY)?;
+        let axis = match self.peek() {
+            Some(TokenKind::X) => Axis::X,
+            Some(TokenKind::Y) => Axis::Y,
+            _ => return self.fail(),
+        };
+        self.bump();
+        Ok(Expr {
+            kind: ExprKind::Coordinate {
+                place: Box::new(place),
+                axis,
+            },
+            span: self.span_from(start),
+        })
+    }
+}
+
+fn is_direction(kind: Option<TokenKind>) -> bool {
+    matches!(
+        kind,
+        Some(TokenKind::Up)
+            | Some(TokenKind::Down)
+            | Some(TokenKind::Left)
+            | Some(TokenKind::Right)
+    )
+}
+
+fn direction(kind: TokenKind) -> Direction {
+    match kind {
+        TokenKind::Up => Direction::Up,
+        TokenKind::Down => Direction::Down,
+        TokenKind::Left => Direction::Left,
+        _ => Direction::Right,
+    }
+}
+
+fn numeric_property(kind: TokenKind) -> NumericProperty {
+    match kind {
+        TokenKind::Height => NumericProperty::Height,
+        TokenKind::Width => NumericProperty::Width,
+        TokenKind::Radius => NumericProperty::Radius,
+        TokenKind::Diameter => NumericProperty::Diameter,
+        _ => NumericProperty::Thickness,
+    }
+}
+
+fn dash_style(kind: TokenKind) -> DashStyle {
+    match kind {
+        TokenKind::Dotted => DashStyle::Dotted,
+        _ => DashStyle::Dashed,
+    }
+}
+
+fn color_property(kind: TokenKind) -> ColorProperty {
+    match kind {
+        TokenKind::Fill => ColorProperty::Fill,
+        _ => ColorProperty::Color,
+    }
+}
+
+fn text_position(kind: TokenKind) -> Option<TextPosition> {
+    Some(match kind {
+        TokenKind::Center => TextPosition::Center,
+        TokenKind::LJust => TextPosition::LJust,
+        TokenKind::RJust => TextPosition::RJust,
+        TokenKind::Above => TextPosition::Above,
+        TokenKind::Below => TextPosition::Below,
+        TokenKind::Italic => TextPosition::Italic,
+        TokenKind::Bold => TextPosition::Bold,
+        TokenKind::Aligned => TextPosition::Aligned,
+        TokenKind::Big => TextPosition::Big,
+        TokenKind::Small => TextPosition::Small,
+        _ => return None,
+    })
+}
+
+fn flag(kind: TokenKind) -> Option<Flag> {
+    Some(match kind {
+        TokenKind::Cw => Flag::Cw,
+        TokenKind::Ccw => Flag::Ccw,
+        TokenKind::LArrow => Flag::LArrow,
+        TokenKind::RArrow => Flag::RArrow,
+        TokenKind::LRArrow => Flag::LRArrow,
+        TokenKind::Invis => Flag::Invisible,
+        TokenKind::Thick => Flag::Thick,
+        TokenKind::Thin => Flag::Thin,
+        TokenKind::Solid => Flag::Solid,
+        _ => return None,
+    })
+}
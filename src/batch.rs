@@ -0,0 +1,118 @@
+//! Rendering many diagrams at once
+//!
+//! pikchr keeps no state between renders, so diagrams can be rendered on
+//! many threads at the same time, and a [`Pikchr`] owns its SVG so it can
+//! be sent back from the thread which rendered it.  [`render_batch`]
+//! spreads a list of sources over a thread for each core, which suits a
+//! static site build with hundreds of diagrams.
+//!
+//! ```
+//! use pikchr::{render_batch, PikchrFlags};
+//!
+//! let sources = ["box", "circle", "box wid"];
+//! let results = render_batch(&sources, Some("diagram"), PikchrFlags::default());
+//! assert!(results[0].as_ref().unwrap().contains("<path"));
+//! assert!(results[1].as_ref().unwrap().contains("<circle"));
+//! assert!(results[2].is_err());
+//! ```
+
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Render some sources on as many threads as there are cores
+///
+/// The results are in the same order as the sources, with an error in
+/// place of each diagram which fails to render.  A source which is slow
+/// to render holds up only its own thread, as each thread takes the next
+/// source as it finishes the last.  With one core, or one source, the
+/// sources are rendered on the calling thread.
+pub fn render_batch<I>(
+    sources: I,
+    class: Option<&str>,
+    flags: PikchrFlags,
+) -> Vec<Result<Pikchr, PikchrError>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str> + Sync,
+{
+    let sources: Vec<I::Item> = sources.into_iter().collect();
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(sources.len());
+    // Where threads cannot be spawned, such as wasm32-unknown-unknown,
+    // there is only the one
+    if threads <= 1 {
+        return sources
+            .iter()
+            .map(|source| Pikchr::render(source.as_ref(), class, flags))
+            .collect();
+    }
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<Result<Pikchr, PikchrError>>> = std::iter::repeat_with(|| None)
+        .take(sources.len())
+        .collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut rendered = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let source = match sources.get(i) {
+                            Some(source) => source.as_ref(),
+                            None => break,
+                        };
+                        rendered.push((i, Pikchr::render(source, class, flags)));
+                    }
+                    rendered
+                })
+            })
+            .collect();
+        for worker in workers {
+            // A panic in pikchr is passed on
+            let rendered = worker
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            for (i, result) in rendered {
+                results[i] = Some(result);
+            }
+        }
+    });
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pikchr_is_send_and_sync() {
+        fn shareable<T: Send + Sync>() {}
+        shareable::<Pikchr>();
+        shareable::<PikchrError>();
+    }
+
+    #[test]
+    fn keeps_the_order_of_the_sources() {
+        let sources: Vec<String> = (1..=50)
+            .map(|i| match i % 10 {
+                0 => format!("box wid {}", "x".repeat(i)),
+                _ => format!("box wid {}", i),
+            })
+            .collect();
+        let results = render_batch(&sources, None, PikchrFlags::default());
+        assert_eq!(results.len(), 50);
+        for (i, (source, result)) in sources.iter().zip(&results).enumerate() {
+            let expected = Pikchr::render(source, None, PikchrFlags::default());
+            match (result, expected) {
+                (Ok(pic), Ok(expected)) => assert_eq!(pic.rendered(), expected.rendered()),
+                (Err(error), Err(expected)) => assert_eq!(*error, expected),
+                _ => panic!("diagram {} rendered differently", i + 1),
+            }
+        }
+        assert!(render_batch(Vec::<&str>::new(), None, PikchrFlags::default()).is_empty());
+        let results = render_batch(["circle"], None, PikchrFlags::default());
+        assert!(results[0].as_ref().unwrap().contains("<circle"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Right-to-left text
+//!
+//! pikchr lays out every label as left-to-right text, so a label in Arabic
+//! or Hebrew can be drawn in the wrong order, and a diagram inside a
+//! right-to-left page inherits that direction and shifts its `ljust` and
+//! `rjust` labels.  [`Pikchr::with_text_direction`] gives each `<text>` an
+//! explicit `direction` and `unicode-bidi`, and swaps `start` and `end`
+//! anchors where the direction is right-to-left so that labels stay where
+//! pikchr placed them.
+//!
+//! ```
+//! use pikchr::{bidi::TextDirection, Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box \"שלום\" ljust; box \"hello\"", None, PikchrFlags::default())
+//!     .unwrap();
+//! let pic = pic.with_text_direction(TextDirection::Auto).unwrap();
+//! assert!(pic.contains(" text-anchor=\"end\" fill=\"rgb(0,0,0)\" dominant-baseline=\"central\" \
+//!     direction=\"rtl\" unicode-bidi=\"embed\">שלום</text>"));
+//! assert!(pic.contains(" direction=\"ltr\" unicode-bidi=\"embed\">hello</text>"));
+//! ```
+//!
+//! [`Pikchr::with_text_direction`]: crate::Pikchr::with_text_direction
+
+use crate::svg::Dom;
+
+/// The direction of the text in a diagram
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextDirection {
+    /// All text is left-to-right, whatever the direction of the page
+    Ltr,
+    /// All text is right-to-left
+    Rtl,
+    /// Text is right-to-left if its first letter with a direction is from
+    /// a right-to-left script, and left-to-right otherwise
+    Auto,
+}
+
+/// Whether a character is a letter of a right-to-left script
+fn is_rtl(c: char) -> bool {
+    matches!(c,
+        // Hebrew, Arabic, Syriac, Thaana, NKo, Samaritan, Mandaic and the
+        // Arabic supplements
+        '\u{0590}'..='\u{08FF}'
+        // Hebrew and Arabic presentation forms
+        | '\u{FB1D}'..='\u{FDFF}'
+        | '\u{FE70}'..='\u{FEFF}'
+        // Historic scripts and Arabic mathematical symbols
+        | '\u{10800}'..='\u{10FFF}'
+        | '\u{1E800}'..='\u{1EFFF}')
+}
+
+/// Whether text starts with a right-to-left letter, ignoring characters
+/// without a direction such as digits, spaces and punctuation
+fn starts_rtl(text: &str) -> bool {
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '&' {
+            // Skip over an entity
+            if let Some(end) = rest.find(';') {
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        if is_rtl(c) {
+            return true;
+        }
+        if c.is_alphabetic() {
+            return false;
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    false
+}
+
+/// Set the direction of each `<text>` in a rendered diagram
+pub(crate) fn set_direction(dom: &mut Dom, direction: TextDirection) {
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return,
+    };
+    svg.for_each_mut(&mut |element| {
+        if element.name != "text" {
+            return;
+        }
+        let rtl = match direction {
+            TextDirection::Ltr => false,
+            TextDirection::Rtl => true,
+            TextDirection::Auto => starts_rtl(&element.text()),
+        };
+        if rtl {
+            let anchor = match element.attr("text-anchor") {
+                Some("start") => Some("end"),
+                Some("end") => Some("start"),
+                _ => None,
+            };
+            if let Some(anchor) = anchor {
+                element.set_attr("text-anchor", anchor);
+            }
+        }
+        element.set_attr("direction", if rtl { "rtl" } else { "ltr" });
+        element.set_attr("unicode-bidi", "embed");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn finds_rtl_text() {
+        assert!(starts_rtl("مرحبا"));
+        assert!(starts_rtl("12 - שלום world"));
+        assert!(starts_rtl("&rarr; שלום"));
+        assert!(!starts_rtl("hello שלום"));
+        assert!(!starts_rtl("1234"));
+    }
+
+    #[test]
+    fn sets_directions_and_anchors() {
+        let pic = Pikchr::render(
+            "box \"a\" ljust \"b\" rjust \"c\"",
+            None,
+            PikchrFlags::default(),
+        )
+        .unwrap();
+        let texts = |direction| {
+            let mut dom = pic.dom().unwrap();
+            set_direction(&mut dom, direction);
+            dom.svg()
+                .unwrap()
+                .find_all("text")
+                .iter()
+                .map(|t| {
+                    (
+                        t.attr("text-anchor").unwrap().to_string(),
+                        t.attr("direction").unwrap().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = |anchors: [&str; 3], direction: &str| {
+            anchors
+                .iter()
+                .map(|a| (a.to_string(), direction.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(TextDirection::Ltr),
+            expected(["start", "end", "middle"], "ltr")
+        );
+        assert_eq!(
+            texts(TextDirection::Rtl),
+            expected(["end", "start", "middle"], "rtl")
+        );
+        assert_eq!(texts(TextDirection::Auto), texts(TextDirection::Ltr));
+    }
+}
@@ -0,0 +1,570 @@
+//! Caching rendered diagrams
+//!
+//! Services tend to render the same few diagrams over and over.  A
+//! [`PikchrLruCache`] keeps rendered diagrams in memory, keyed by the
+//! [`hash_source`] of what they were rendered from, and drops the least
+//! recently used once it holds too many entries or too many bytes of SVG.
+//! Entries can also expire after a time to live.
+//!
+//! ```
+//! use pikchr::cache::{CacheOptions, PikchrLruCache};
+//! use pikchr::PikchrFlags;
+//!
+//! let mut options = CacheOptions::default();
+//! options.max_entries(100).max_bytes(1 << 20);
+//! let cache = PikchrLruCache::new(options);
+//! let flags = PikchrFlags::default();
+//! let first = cache.render("box", None, flags).unwrap();
+//! let again = cache.render("box", None, flags).unwrap();
+//! assert!(std::sync::Arc::ptr_eq(&first, &again));
+//! let metrics = cache.metrics();
+//! assert_eq!((metrics.hits, metrics.misses, metrics.entries), (1, 1, 1));
+//! ```
+//!
+//! The cache can be shared between threads.  Sources which fail to render
+//! are not cached.
+//!
+//! A cache can also keep its diagrams in a directory, with
+//! [`PikchrLruCache::with_directory`], so that a static site build only
+//! renders the diagrams which changed since the last one.  Each diagram is
+//! a file named after its key, which includes the version of this crate,
+//! so an upgrade does not reuse diagrams which it might draw differently.
+//!
+//! [`hash_source`]: crate::hash::hash_source
+
+use crate::hash::{hash_source, Digest};
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The first word of a diagram's file in a cache directory, followed by
+/// a format version and the diagram's width and height
+const FILE_MAGIC: &str = "pikchr-cache";
+
+/// How many temporary files this process has written, to name each one
+/// differently
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The limits of a [`PikchrLruCache`]
+///
+/// You can construct the default options using the
+/// [`std::default::Default`] trait, which keep up to 1000 diagrams and up
+/// to 64MiB of SVG for as long as they are used.
+#[derive(Copy, Clone, Debug)]
+pub struct CacheOptions {
+    max_entries: usize,
+    max_bytes: usize,
+    ttl: Option<Duration>,
+}
+
+impl CacheOptions {
+    /// Set how many diagrams the cache may hold
+    pub fn max_entries(&mut self, max_entries: usize) -> &mut CacheOptions {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Set how many bytes of SVG the cache may hold
+    pub fn max_bytes(&mut self, max_bytes: usize) -> &mut CacheOptions {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Set how long a diagram stays in the cache after it was rendered, or
+    /// `None` to keep diagrams until they are pushed out
+    pub fn ttl(&mut self, ttl: Option<Duration>) -> &mut CacheOptions {
+        self.ttl = ttl;
+        self
+    }
+}
+
+impl std::default::Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            max_bytes: 64 << 20,
+            ttl: None,
+        }
+    }
+}
+
+/// Counts of how well a cache is doing
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    /// Lookups which found a diagram
+    pub hits: u64,
+    /// Hits which read the diagram back from the cache's directory, which
+    /// are also counted in `hits`
+    pub disk_hits: u64,
+    /// Lookups which did not, including those of expired diagrams
+    pub misses: u64,
+    /// Diagrams dropped to make room for others
+    pub evictions: u64,
+    /// Diagrams dropped because they expired
+    pub expirations: u64,
+    /// The diagrams in the cache now
+    pub entries: usize,
+    /// The bytes of SVG in the cache now
+    pub bytes: usize,
+}
+
+struct Entry {
+    pic: Arc<Pikchr>,
+    inserted: Instant,
+    /// When the entry was last used, as a count of uses of the cache
+    used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Digest, Entry>,
+    /// The keys by when they were last used, least recently first
+    order: BTreeMap<u64, Digest>,
+    clock: u64,
+    metrics: CacheMetrics,
+}
+
+impl Inner {
+    fn remove(&mut self, key: &Digest) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.used);
+        self.metrics.entries -= 1;
+        self.metrics.bytes -= entry.pic.rendered.len();
+        Some(entry)
+    }
+
+    /// Mark an entry as used now
+    fn touch(&mut self, key: &Digest) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.order.remove(&entry.used);
+            entry.used = self.clock;
+            self.order.insert(self.clock, *key);
+        }
+    }
+}
+
+/// An in-memory cache of rendered diagrams, which drops the least
+/// recently used
+pub struct PikchrLruCache {
+    options: CacheOptions,
+    directory: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+impl PikchrLruCache {
+    /// Create an empty cache with some limits
+    pub fn new(options: CacheOptions) -> Self {
+        PikchrLruCache {
+            options,
+            directory: None,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Keep diagrams in a directory as well as in memory
+    ///
+    /// A diagram which is not in memory is looked for in the directory
+    /// before it is rendered, and each diagram added to the cache is
+    /// written there, so that the next run of a program can use it.  The
+    /// directory is made if it does not exist.  It is not limited in size,
+    /// but diagrams in it expire as those in memory do, by the time their
+    /// files were written.  A diagram which cannot be read or written is
+    /// rendered, as if the directory were not there.
+    ///
+    /// ```
+    /// use pikchr::cache::{CacheOptions, PikchrLruCache};
+    /// use pikchr::PikchrFlags;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("pikchr-doc-cache-{}", std::process::id()));
+    /// let flags = PikchrFlags::default();
+    /// let cache = PikchrLruCache::new(CacheOptions::default()).with_directory(&dir);
+    /// let first = cache.render("box", None, flags).unwrap();
+    /// // As if the program were run again
+    /// let cache = PikchrLruCache::new(CacheOptions::default()).with_directory(&dir);
+    /// let again = cache.render("box", None, flags).unwrap();
+    /// assert_eq!(first.rendered(), again.rendered());
+    /// assert_eq!(cache.metrics().disk_hits, 1);
+    /// # std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn with_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// The file for a diagram in the cache's directory
+    fn file(&self, key: &Digest) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        Some(directory.join(format!("{}.cache", key)))
+    }
+
+    /// Read a diagram back from the cache's directory
+    fn load(&self, key: &Digest) -> Option<Pikchr> {
+        let path = self.file(key)?;
+        if let Some(ttl) = self.options.ttl {
+            let written = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            if written.elapsed().map_or(true, |age| age >= ttl) {
+                return None;
+            }
+        }
+        let contents = fs::read_to_string(&path).ok()?;
+        let (header, rendered) = contents.split_once('\n')?;
+        let mut words = header.split(' ');
+        if words.next() != Some(FILE_MAGIC) || words.next() != Some("2") {
+            return None;
+        }
+        let width = words.next()?.parse().ok()?;
+        let height = words.next()?.parse().ok()?;
+        // A file which was not written in full is rendered again
+        let length: usize = words.next()?.parse().ok()?;
+        if rendered.len() != length {
+            return None;
+        }
+        Some(Pikchr {
+            rendered: rendered.to_string(),
+            width,
+            height,
+        })
+    }
+
+    /// Write a diagram to the cache's directory
+    fn store(&self, key: &Digest, pic: &Pikchr) {
+        let path = match self.file(key) {
+            Some(path) => path,
+            None => return,
+        };
+        // Write to a temporary file first, so that another process never
+        // reads half a diagram, named so that no other writer, in this
+        // process or another, writes to it at the same time
+        let temporary = path.with_extension(format!(
+            "tmp-{}-{}",
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| write_file(&temporary, pic))
+            .and_then(|_| fs::rename(&temporary, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // Each change leaves the cache consistent, so a panic elsewhere
+        // cannot leave it broken
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Look up a diagram by its key
+    ///
+    /// A diagram which is only in the cache's directory is read back into
+    /// memory.
+    pub fn get(&self, key: &Digest) -> Option<Arc<Pikchr>> {
+        let mut inner = self.lock();
+        let expired = match (inner.entries.get(key), self.options.ttl) {
+            (None, _) => None,
+            (Some(entry), Some(ttl)) => Some(entry.inserted.elapsed() >= ttl),
+            (Some(_), None) => Some(false),
+        };
+        match expired {
+            Some(false) => {
+                inner.metrics.hits += 1;
+                inner.touch(key);
+                return inner.entries.get(key).map(|entry| Arc::clone(&entry.pic));
+            }
+            Some(true) => {
+                inner.remove(key);
+                inner.metrics.expirations += 1;
+            }
+            None => {}
+        }
+        drop(inner);
+        match self.load(key) {
+            Some(pic) => {
+                let pic = self.keep(*key, pic);
+                let mut inner = self.lock();
+                inner.metrics.hits += 1;
+                inner.metrics.disk_hits += 1;
+                Some(pic)
+            }
+            None => {
+                self.lock().metrics.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Add a diagram under a key, dropping the least recently used
+    /// diagrams if there is not room for it
+    ///
+    /// A diagram bigger than the cache is returned without being kept in
+    /// memory.
+    pub fn insert(&self, key: Digest, pic: Pikchr) -> Arc<Pikchr> {
+        self.store(&key, &pic);
+        self.keep(key, pic)
+    }
+
+    /// Add a diagram to the memory of the cache
+    fn keep(&self, key: Digest, pic: Pikchr) -> Arc<Pikchr> {
+        let pic = Arc::new(pic);
+        let size = pic.rendered.len();
+        if size > self.options.max_bytes || self.options.max_entries == 0 {
+            return pic;
+        }
+        let mut inner = self.lock();
+        inner.remove(&key);
+        while inner.metrics.entries >= self.options.max_entries
+            || inner.metrics.bytes + size > self.options.max_bytes
+        {
+            let oldest = match inner.order.values().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            inner.remove(&oldest);
+            inner.metrics.evictions += 1;
+        }
+        inner.entries.insert(
+            key,
+            Entry {
+                pic: Arc::clone(&pic),
+                inserted: Instant::now(),
+                used: 0,
+            },
+        );
+        inner.metrics.entries += 1;
+        inner.metrics.bytes += size;
+        inner.touch(&key);
+        pic
+    }
+
+    /// Render some pikchr source, or find it in the cache if it was
+    /// rendered before
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pikchr::render`].
+    pub fn render(
+        &self,
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Arc<Pikchr>, PikchrError> {
+        let key = hash_source(source, class, flags);
+        if let Some(pic) = self.get(&key) {
+            return Ok(pic);
+        }
+        Ok(self.insert(key, Pikchr::render(source, class, flags)?))
+    }
+
+    /// Render some diagrams ahead of time, so that the first requests for
+    /// them do not wait for them to render
+    ///
+    /// Diagrams already in the cache are left as they are, and looking for
+    /// them is not counted as a hit or a miss.  This returns how many
+    /// diagrams were rendered.
+    ///
+    /// ```
+    /// use pikchr::cache::{CacheOptions, PikchrLruCache};
+    /// use pikchr::PikchrFlags;
+    ///
+    /// let cache = PikchrLruCache::new(CacheOptions::default());
+    /// let flags = PikchrFlags::default();
+    /// let common = ["box \"start\"", "arrow; circle"];
+    /// assert_eq!(cache.precompile(common.iter().copied(), None, flags), Ok(2));
+    /// cache.render("arrow; circle", None, flags).unwrap();
+    /// assert_eq!(cache.metrics().hits, 1);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if one of the sources cannot be rendered, which is
+    /// the error for that source.  The diagrams before it are cached, and
+    /// those after it are not rendered.
+    pub fn precompile<'a>(
+        &self,
+        sources: impl IntoIterator<Item = &'a str>,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<usize, PikchrError> {
+        let mut rendered = 0;
+        for source in sources {
+            let key = hash_source(source, class, flags);
+            let cached = self.lock().entries.get(&key).is_some_and(|entry| {
+                self.options
+                    .ttl
+                    .is_none_or(|ttl| entry.inserted.elapsed() < ttl)
+            });
+            if cached {
+                continue;
+            }
+            if let Some(pic) = self.load(&key) {
+                self.keep(key, pic);
+                continue;
+            }
+            let pic = Pikchr::render(source, class, flags)?;
+            self.insert(key, pic);
+            rendered += 1;
+        }
+        Ok(rendered)
+    }
+
+    /// Drop every diagram from memory, keeping the counts of hits and
+    /// misses
+    ///
+    /// The cache's directory is left as it is.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.order.clear();
+        inner.metrics.entries = 0;
+        inner.metrics.bytes = 0;
+    }
+
+    /// The counts of hits and misses, and the size of the cache
+    pub fn metrics(&self) -> CacheMetrics {
+        self.lock().metrics
+    }
+}
+
+/// Write a diagram's file for a cache directory
+fn write_file(path: &Path, pic: &Pikchr) -> std::io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    writeln!(
+        file,
+        "{} 2 {} {} {}",
+        FILE_MAGIC,
+        pic.width,
+        pic.height,
+        pic.rendered.len()
+    )?;
+    file.write_all(pic.rendered.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(cache: &PikchrLruCache, source: &str) -> Arc<Pikchr> {
+        cache.render(source, None, PikchrFlags::default()).unwrap()
+    }
+
+    #[test]
+    fn drops_the_least_recently_used() {
+        let mut options = CacheOptions::default();
+        options.max_entries(2);
+        let cache = PikchrLruCache::new(options);
+        let a = render(&cache, "box");
+        render(&cache, "circle");
+        // Using the box makes the circle the least recently used
+        assert!(Arc::ptr_eq(&a, &render(&cache, "box")));
+        render(&cache, "oval");
+        let metrics = cache.metrics();
+        assert_eq!((metrics.entries, metrics.evictions), (2, 1));
+        assert!(Arc::ptr_eq(&a, &render(&cache, "box")));
+        render(&cache, "circle");
+        assert_eq!(cache.metrics().misses, 4);
+    }
+
+    #[test]
+    fn limits_bytes() {
+        let size = render(&PikchrLruCache::new(CacheOptions::default()), "box")
+            .rendered
+            .len();
+        let mut options = CacheOptions::default();
+        options.max_bytes(size * 2 + 1);
+        let cache = PikchrLruCache::new(options);
+        render(&cache, "box");
+        render(&cache, "box; box");
+        assert_eq!(cache.metrics().entries, 1);
+        // Too big to keep at all
+        render(&cache, "box; box; box; box");
+        assert_eq!(cache.metrics().entries, 1);
+        assert!(cache.metrics().bytes <= size * 2 + 1);
+        assert!(cache
+            .render("box wid", None, PikchrFlags::default())
+            .is_err());
+        cache.clear();
+        assert_eq!(cache.metrics().bytes, 0);
+    }
+
+    #[test]
+    fn precompiles() {
+        let cache = PikchrLruCache::new(CacheOptions::default());
+        let flags = PikchrFlags::default();
+        let a = render(&cache, "box");
+        assert_eq!(cache.precompile(vec!["box", "circle"], None, flags), Ok(1));
+        assert!(Arc::ptr_eq(&a, &render(&cache, "box")));
+        let err = cache
+            .precompile(vec!["oval", "box wid", "arrow"], None, flags)
+            .unwrap_err();
+        assert!(
+            matches!(err, PikchrError::Syntax { ref context_line, .. } if context_line == "box wid"),
+            "{}",
+            err
+        );
+        let metrics = cache.metrics();
+        assert_eq!((metrics.hits, metrics.misses, metrics.entries), (1, 1, 3));
+    }
+
+    #[test]
+    fn expires_entries() {
+        let mut options = CacheOptions::default();
+        options.ttl(Some(Duration::from_millis(1)));
+        let cache = PikchrLruCache::new(options);
+        let a = render(&cache, "box");
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(!Arc::ptr_eq(&a, &render(&cache, "box")));
+        let metrics = cache.metrics();
+        assert_eq!((metrics.expirations, metrics.entries), (1, 1));
+    }
+
+    #[test]
+    fn persists_to_a_directory() {
+        let dir = std::env::temp_dir().join(format!("pikchr-cache-{}", std::process::id()));
+        let flags = PikchrFlags::default();
+        let cache = || PikchrLruCache::new(CacheOptions::default()).with_directory(&dir);
+        let first = cache();
+        let a = render(&first, "box");
+        assert_eq!(first.metrics().disk_hits, 0);
+        // A directory which cannot be made only stops diagrams being kept
+        let file = dir.join(format!("{}.cache", hash_source("box", None, flags)));
+        let unusable = PikchrLruCache::new(CacheOptions::default()).with_directory(file.join("x"));
+        assert!(unusable.render("box", None, flags).is_ok());
+
+        let second = cache();
+        assert_eq!(second.precompile(vec!["box", "circle"], None, flags), Ok(1));
+        let b = render(&second, "box");
+        assert_eq!(
+            (a.rendered(), a.width(), a.height()),
+            (b.rendered(), b.width(), b.height())
+        );
+        let metrics = second.metrics();
+        assert_eq!((metrics.hits, metrics.disk_hits, metrics.misses), (1, 0, 0));
+
+        // A damaged file is rendered again, and replaced
+        let key = hash_source("box", None, flags);
+        fs::write(first.file(&key).unwrap(), "pikchr-cache 2 x\n<svg>").unwrap();
+        let third = cache();
+        assert_eq!(render(&third, "box").rendered(), a.rendered());
+        assert!(Arc::ptr_eq(
+            &render(&third, "circle"),
+            &render(&third, "circle")
+        ));
+        let metrics = third.metrics();
+        assert_eq!((metrics.hits, metrics.disk_hits, metrics.misses), (2, 1, 1));
+        assert_eq!(cache().load(&key).unwrap().rendered(), a.rendered());
+
+        // As is a file which was cut short
+        let contents = fs::read_to_string(first.file(&key).unwrap()).unwrap();
+        fs::write(first.file(&key).unwrap(), &contents[..contents.len() - 1]).unwrap();
+        assert!(cache().load(&key).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+//! A C interface to the safe wrapper
+//!
+//! With the `capi` feature, these functions are exported from the crate
+//! so that it can be built as a shared library, and `capi/pikchr_rs.h`
+//! declares them for C.  Bindings for other languages can then use the
+//! crate's caching, themes and limits rather than wrapping `pikchr.c`
+//! again.
+//!
+//! [`pikchr_rs_render`] fills in a [`PikchrRsResult`] whose strings must
+//! be released with [`pikchr_rs_free_result`].  The interface only grows:
+//! [`PikchrRsOptions`] starts with its own size, so that fields added
+//! later can be told apart from the end of an older caller's struct.
+
+use crate::cache::{CacheOptions, PikchrLruCache};
+use crate::palette::Palette;
+use crate::{Pikchr, PikchrFlags};
+use core::ffi::{c_char, c_int, c_uint};
+use std::ffi::{CStr, CString};
+use std::mem::{offset_of, size_of};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::OnceLock;
+
+/// The render succeeded
+pub const PIKCHR_RS_OK: c_int = 0;
+/// A pointer was null, a string was not UTF-8, or an option was unknown
+pub const PIKCHR_RS_INVALID_ARGUMENT: c_int = 1;
+/// The source could not be rendered, and the result has the error
+pub const PIKCHR_RS_RENDER_ERROR: c_int = 2;
+/// The source was longer than the options allow
+pub const PIKCHR_RS_TOO_LARGE: c_int = 3;
+/// The renderer panicked, which is a bug in the crate
+pub const PIKCHR_RS_PANIC: c_int = 4;
+
+/// Use plain text rather than HTML for errors
+pub const PIKCHR_RS_PLAIN_ERRORS: c_uint = 0x0001;
+/// Use colours which suit a dark background
+pub const PIKCHR_RS_DARK_MODE: c_uint = 0x0002;
+/// Draw in black and white; see [`Pikchr::monochrome`]
+pub const PIKCHR_RS_MONOCHROME: c_uint = 0x0004;
+/// Keep diagrams in, and find them in, a cache shared by the process
+pub const PIKCHR_RS_CACHE: c_uint = 0x0008;
+
+/// Keep the colors of the diagram
+pub const PIKCHR_RS_PALETTE_NONE: c_uint = 0;
+/// See [`Palette::ColorBlindSafe`]
+pub const PIKCHR_RS_PALETTE_COLOR_BLIND_SAFE: c_uint = 1;
+/// See [`Palette::HighContrast`]
+pub const PIKCHR_RS_PALETTE_HIGH_CONTRAST: c_uint = 2;
+
+/// How to render a diagram, as `PikchrRsOptions`
+#[repr(C)]
+pub struct PikchrRsOptions {
+    /// `sizeof(PikchrRsOptions)` as the caller was built
+    pub size: usize,
+    /// A class for the SVG, or null for none
+    pub class_name: *const c_char,
+    /// Some of the `PIKCHR_RS_*` flags
+    pub flags: c_uint,
+    /// One of the `PIKCHR_RS_PALETTE_*` values
+    pub palette: c_uint,
+    /// The longest source to render in bytes, or zero for any length
+    pub max_source_bytes: usize,
+}
+
+/// The outcome of a render, as `PikchrRsResult`
+#[repr(C)]
+pub struct PikchrRsResult {
+    /// The SVG, or null if the render failed
+    pub svg: *mut c_char,
+    /// Why the render failed, or null if it did not
+    pub error: *mut c_char,
+    /// The width of the diagram, or -1 if the render failed
+    pub width: c_int,
+    /// The height of the diagram, or -1 if the render failed
+    pub height: c_int,
+}
+
+/// The cache used with [`PIKCHR_RS_CACHE`]
+fn cache() -> &'static PikchrLruCache {
+    static CACHE: OnceLock<PikchrLruCache> = OnceLock::new();
+    CACHE.get_or_init(|| PikchrLruCache::new(CacheOptions::default()))
+}
+
+/// A string for C, dropping anything after a NUL which it cannot hold
+fn c_string(s: &str) -> *mut c_char {
+    let s = s.split('\0').next().unwrap_or("");
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+/// The options a caller passed, with the defaults for any fields its
+/// version of the struct does not have
+///
+/// # Safety
+///
+/// The pointer must be null or point to at least `size` bytes of options.
+unsafe fn read_options(options: *const PikchrRsOptions) -> Option<PikchrRsOptions> {
+    let mut read = PikchrRsOptions {
+        size: size_of::<PikchrRsOptions>(),
+        class_name: std::ptr::null(),
+        flags: PIKCHR_RS_PLAIN_ERRORS,
+        palette: PIKCHR_RS_PALETTE_NONE,
+        max_source_bytes: 0,
+    };
+    if options.is_null() {
+        return Some(read);
+    }
+    // Whether the caller's struct reaches the end of a field
+    let size = (*options).size;
+    let has = |offset: usize, len: usize| size >= offset + len;
+    if !has(
+        offset_of!(PikchrRsOptions, class_name),
+        size_of::<*const c_char>(),
+    ) {
+        return None;
+    }
+    read.class_name = (*options).class_name;
+    if has(offset_of!(PikchrRsOptions, palette), size_of::<c_uint>()) {
+        read.flags = (*options).flags;
+        read.palette = (*options).palette;
+    }
+    if has(
+        offset_of!(PikchrRsOptions, max_source_bytes),
+        size_of::<usize>(),
+    ) {
+        read.max_source_bytes = (*options).max_source_bytes;
+    }
+    Some(read)
+}
+
+/// Render a diagram as the options say
+fn render(source: &str, class: Option<&str>, options: &PikchrRsOptions) -> Result<Pikchr, String> {
+    let mut flags = PikchrFlags::default();
+    if options.flags & PIKCHR_RS_PLAIN_ERRORS == 0 {
+        flags.generate_html_errors();
+    }
+    if options.flags & PIKCHR_RS_DARK_MODE != 0 {
+        flags.use_dark_mode();
+    }
+    let mut pic = if options.flags & PIKCHR_RS_CACHE != 0 {
+        Pikchr::clone(&*cache().render(source, class, flags)?)
+    } else {
+        Pikchr::render(source, class, flags)?
+    };
+    match options.palette {
+        PIKCHR_RS_PALETTE_COLOR_BLIND_SAFE => pic = pic.with_palette(Palette::ColorBlindSafe)?,
+        PIKCHR_RS_PALETTE_HIGH_CONTRAST => pic = pic.with_palette(Palette::HighContrast)?,
+        _ => {}
+    }
+    if options.flags & PIKCHR_RS_MONOCHROME != 0 {
+        pic = pic.monochrome()?;
+    }
+    Ok(pic)
+}
+
+/// Render pikchr source
+///
+/// The options may be null for the defaults, which are plain text errors
+/// and no class, theme, cache or limit.  The result is always filled in,
+/// and must be released with [`pikchr_rs_free_result`] whatever this
+/// returns.  A panic while rendering is caught, and returns
+/// [`PIKCHR_RS_PANIC`] rather than unwinding into the caller.
+///
+/// # Safety
+///
+/// The source and any class must be NUL-terminated strings, the options
+/// must be null or valid for their `size`, and the result must point to
+/// writable memory for a [`PikchrRsResult`].
+#[no_mangle]
+pub unsafe extern "C" fn pikchr_rs_render(
+    source: *const c_char,
+    options: *const PikchrRsOptions,
+    result: *mut PikchrRsResult,
+) -> c_int {
+    if result.is_null() {
+        return PIKCHR_RS_INVALID_ARGUMENT;
+    }
+    catching_panics(result, || render_into(source, options, result))
+}
+
+/// Fill in a failed result, returning the code
+///
+/// # Safety
+///
+/// The result must point to writable memory for a [`PikchrRsResult`].
+unsafe fn fail(result: *mut PikchrRsResult, code: c_int, error: &str) -> c_int {
+    result.write(PikchrRsResult {
+        svg: std::ptr::null_mut(),
+        error: c_string(error),
+        width: -1,
+        height: -1,
+    });
+    code
+}
+
+/// Run a render, failing with [`PIKCHR_RS_PANIC`] rather than unwinding
+/// into C if it panics
+///
+/// # Safety
+///
+/// The result must point to writable memory for a [`PikchrRsResult`].
+unsafe fn catching_panics(result: *mut PikchrRsResult, render: impl FnOnce() -> c_int) -> c_int {
+    match catch_unwind(AssertUnwindSafe(render)) {
+        Ok(code) => code,
+        Err(_) => fail(result, PIKCHR_RS_PANIC, "pikchr panicked while rendering"),
+    }
+}
+
+/// The body of [`pikchr_rs_render`], for a result which is not null
+///
+/// # Safety
+///
+/// As for [`pikchr_rs_render`].
+unsafe fn render_into(
+    source: *const c_char,
+    options: *const PikchrRsOptions,
+    result: *mut PikchrRsResult,
+) -> c_int {
+    let fail = |code, error: &str| fail(result, code, error);
+    if source.is_null() {
+        return fail(PIKCHR_RS_INVALID_ARGUMENT, "the source is null");
+    }
+    let options = match read_options(options) {
+        Some(options) => options,
+        None => return fail(PIKCHR_RS_INVALID_ARGUMENT, "the options are too small"),
+    };
+    if options.palette > PIKCHR_RS_PALETTE_HIGH_CONTRAST {
+        return fail(PIKCHR_RS_INVALID_ARGUMENT, "the palette is unknown");
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(_) => return fail(PIKCHR_RS_INVALID_ARGUMENT, "the source is not UTF-8"),
+    };
+    let class = if options.class_name.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(options.class_name).to_str() {
+            Ok(class) => Some(class),
+            Err(_) => return fail(PIKCHR_RS_INVALID_ARGUMENT, "the class is not UTF-8"),
+        }
+    };
+    if options.max_source_bytes != 0 && source.len() > options.max_source_bytes {
+        return fail(
+            PIKCHR_RS_TOO_LARGE,
+            &format!(
+                "the source is {} bytes, more than the {} allowed",
+                source.len(),
+                options.max_source_bytes
+            ),
+        );
+    }
+    match render(source, class, &options) {
+        Ok(pic) => {
+            result.write(PikchrRsResult {
+                svg: c_string(&pic),
+                error: std::ptr::null_mut(),
+                width: pic.width() as c_int,
+                height: pic.height() as c_int,
+            });
+            PIKCHR_RS_OK
+        }
+        Err(error) => fail(PIKCHR_RS_RENDER_ERROR, &error),
+    }
+}
+
+/// Release the strings of a result, leaving them null
+///
+/// # Safety
+///
+/// The result must be null or have been filled in by
+/// [`pikchr_rs_render`], and not released since.
+#[no_mangle]
+pub unsafe extern "C" fn pikchr_rs_free_result(result: *mut PikchrRsResult) {
+    if result.is_null() {
+        return;
+    }
+    for string in [&mut (*result).svg, &mut (*result).error] {
+        if !string.is_null() {
+            drop(CString::from_raw(*string));
+            *string = std::ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::MaybeUninit;
+
+    fn call(
+        source: &[u8],
+        options: Option<&PikchrRsOptions>,
+    ) -> (c_int, Option<String>, Option<String>) {
+        let source = CString::new(source).unwrap();
+        let options = options.map_or(std::ptr::null(), |options| options as *const _);
+        unsafe {
+            let mut result = MaybeUninit::uninit();
+            let code = pikchr_rs_render(source.as_ptr(), options, result.as_mut_ptr());
+            let mut result = result.assume_init();
+            let string = |s: *mut c_char| {
+                (!s.is_null()).then(|| CStr::from_ptr(s).to_string_lossy().into_owned())
+            };
+            let out = (code, string(result.svg), string(result.error));
+            pikchr_rs_free_result(&mut result);
+            assert!(result.svg.is_null() && result.error.is_null());
+            out
+        }
+    }
+
+    fn options() -> PikchrRsOptions {
+        PikchrRsOptions {
+            size: size_of::<PikchrRsOptions>(),
+            class_name: std::ptr::null(),
+            flags: 0,
+            palette: PIKCHR_RS_PALETTE_NONE,
+            max_source_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn renders_with_options() {
+        let (code, svg, error) = call(b"box", None);
+        assert_eq!((code, error), (PIKCHR_RS_OK, None));
+        assert!(svg.unwrap().starts_with("<svg"));
+        let class = CString::new("diagram").unwrap();
+        let mut opts = options();
+        opts.class_name = class.as_ptr();
+        opts.flags = PIKCHR_RS_CACHE | PIKCHR_RS_MONOCHROME;
+        opts.palette = PIKCHR_RS_PALETTE_COLOR_BLIND_SAFE;
+        let (code, svg, _) = call(b"box fill red", Some(&opts));
+        assert_eq!(code, PIKCHR_RS_OK);
+        assert!(svg.unwrap().contains("class=\"diagram\""));
+    }
+
+    #[test]
+    fn reports_errors() {
+        let (code, svg, error) = call(b"box wid", None);
+        assert_eq!((code, svg), (PIKCHR_RS_RENDER_ERROR, None));
+        assert!(error.unwrap().contains("syntax error"));
+        let mut opts = options();
+        opts.max_source_bytes = 2;
+        assert_eq!(call(b"box", Some(&opts)).0, PIKCHR_RS_TOO_LARGE);
+        opts.max_source_bytes = 0;
+        opts.palette = 9;
+        assert_eq!(call(b"box", Some(&opts)).0, PIKCHR_RS_INVALID_ARGUMENT);
+        assert_eq!(call(b"\xff", None).0, PIKCHR_RS_INVALID_ARGUMENT);
+        opts.size = 4;
+        assert_eq!(call(b"box", Some(&opts)).0, PIKCHR_RS_INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn reads_older_options() {
+        // A caller from before `max_source_bytes` was added
+        let mut opts = options();
+        opts.size = offset_of!(PikchrRsOptions, max_source_bytes);
+        opts.max_source_bytes = 1;
+        assert_eq!(call(b"box", Some(&opts)).0, PIKCHR_RS_OK);
+    }
+
+    #[test]
+    fn catches_panics() {
+        unsafe {
+            let mut result = MaybeUninit::uninit();
+            let code = catching_panics(result.as_mut_ptr(), || panic!("a bug"));
+            let mut result = result.assume_init();
+            assert_eq!(code, PIKCHR_RS_PANIC);
+            assert!(result.svg.is_null());
+            assert!(CStr::from_ptr(result.error)
+                .to_bytes()
+                .ends_with(b"panicked while rendering"));
+            pikchr_rs_free_result(&mut result);
+        }
+    }
+}
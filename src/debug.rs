@@ -0,0 +1,241 @@
+//! Seeing how a diagram was laid out
+//!
+//! [`Pikchr::render_debug`] draws a diagram with an extra layer over it,
+//! which shows a grid of the diagram's coordinates in inches, the bounding
+//! box of each object and its `.n`, `.e`, `.s`, `.w` and `.c` anchors, or
+//! the `.start` and `.end` of each line.  Hovering over a bounding box
+//! shows the object's class, name, center and size.  It makes it much
+//! easier to see why objects ended up where they did.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render_debug("A: box; arrow", None, PikchrFlags::default()).unwrap();
+//! assert!(pic.contains("<g class=\"pikchr-debug\""));
+//! assert!(pic.contains("<title>box A at (0, 0) 0.75 × 0.5</title>"));
+//! ```
+//!
+//! The geometry comes from the pure-Rust [`layout`](crate::layout)
+//! backend, so this needs the `rust-backend` feature where the C library
+//! is available.
+//!
+//! [`Pikchr::render_debug`]: crate::Pikchr::render_debug
+
+use crate::ast::Edge;
+use crate::layout::{format_g, Bounds, Layout, Object, Point, SCALE};
+use crate::svg::{escape, Dom, Element, Node};
+
+/// The distance between grid lines, in inches
+const GRID: f64 = 0.25;
+
+const GRID_STYLE: &str = "fill:none;stroke:rgb(0,160,255);stroke-opacity:0.3;stroke-width:0.5;";
+const BOX_STYLE: &str = "fill:none;stroke:rgb(255,0,128);stroke-width:1;stroke-dasharray:3,2;";
+const ANCHOR_STYLE: &str = "fill:rgb(255,0,128);";
+const LABEL_FILL: &str = "rgb(255,0,128)";
+
+/// Converts the coordinates of a diagram to those of its SVG
+struct Frame {
+    bounds: Bounds,
+}
+
+impl Frame {
+    fn x(&self, x: f64) -> String {
+        format_g(SCALE * (x - self.bounds.sw.x), 6)
+    }
+
+    fn y(&self, y: f64) -> String {
+        format_g(SCALE * (self.bounds.ne.y - y), 6)
+    }
+}
+
+/// A small `<text>` for the overlay
+fn label(x: String, y: String, anchor: &str, text: &str) -> Node {
+    let mut element = Element::new("text");
+    element
+        .set_attr("x", &x)
+        .set_attr("y", &y)
+        .set_attr("text-anchor", anchor)
+        .set_attr("font-size", "8")
+        .set_attr("fill", LABEL_FILL);
+    element.children.push(Node::Text(escape(text)));
+    Node::Element(element)
+}
+
+/// The grid lines, with the coordinate of every whole inch
+fn grid(frame: &Frame, layer: &mut Element) {
+    let Bounds { sw, ne } = frame.bounds;
+    let mut d = String::new();
+    let mut labels = Vec::new();
+    let first = (sw.x / GRID).ceil() as i64;
+    for i in first..=(ne.x / GRID).floor() as i64 {
+        let x = i as f64 * GRID;
+        d.push_str(&format!(
+            "M{},{}L{},{}",
+            frame.x(x),
+            frame.y(sw.y),
+            frame.x(x),
+            frame.y(ne.y)
+        ));
+        if i % 4 == 0 {
+            labels.push(label(frame.x(x), frame.y(sw.y), "middle", &format_g(x, 6)));
+        }
+    }
+    let first = (sw.y / GRID).ceil() as i64;
+    for i in first..=(ne.y / GRID).floor() as i64 {
+        let y = i as f64 * GRID;
+        d.push_str(&format!(
+            "M{},{}L{},{}",
+            frame.x(sw.x),
+            frame.y(y),
+            frame.x(ne.x),
+            frame.y(y)
+        ));
+        if i % 4 == 0 {
+            labels.push(label(frame.x(sw.x), frame.y(y), "start", &format_g(y, 6)));
+        }
+    }
+    let mut path = Element::new("path");
+    path.set_attr("d", &d).set_attr("style", GRID_STYLE);
+    layer.children.push(Node::Element(path));
+    layer.children.extend(labels);
+}
+
+/// A point as it is written in a tooltip
+fn point(p: Point) -> String {
+    format!("({}, {})", format_g(p.x, 6), format_g(p.y, 6))
+}
+
+/// The bounding box and anchors of an object and of any objects within it
+fn object(frame: &Frame, object: &Object, layer: &mut Element) {
+    let anchors: &[(Edge, &str)] = if !object.path().is_empty() {
+        &[(Edge::Start, "start"), (Edge::End, "end")]
+    } else {
+        &[
+            (Edge::North, "n"),
+            (Edge::East, "e"),
+            (Edge::South, "s"),
+            (Edge::West, "w"),
+            (Edge::Center, "c"),
+        ]
+    };
+    if let Some(class) = object.class() {
+        let Bounds { sw, ne } = object.bounds();
+        let mut rect = Element::new("rect");
+        rect.set_attr("x", &frame.x(sw.x))
+            .set_attr("y", &frame.y(ne.y))
+            .set_attr("width", &format_g(SCALE * (ne.x - sw.x), 6))
+            .set_attr("height", &format_g(SCALE * (ne.y - sw.y), 6))
+            .set_attr("style", BOX_STYLE);
+        let mut description = class.to_string();
+        if let Some(name) = object.name() {
+            description.push(' ');
+            description.push_str(name);
+        }
+        description.push_str(&format!(
+            " at {} {} × {}",
+            point(object.center()),
+            format_g(object.width(), 6),
+            format_g(object.height(), 6)
+        ));
+        let mut title = Element::new("title");
+        title.children.push(Node::Text(escape(&description)));
+        rect.children.push(Node::Element(title));
+        layer.children.push(Node::Element(rect));
+    }
+    for &(edge, name) in anchors {
+        let p = object.anchor(edge);
+        let mut dot = Element::new("circle");
+        dot.set_attr("cx", &frame.x(p.x))
+            .set_attr("cy", &frame.y(p.y))
+            .set_attr("r", "2")
+            .set_attr("style", ANCHOR_STYLE);
+        let mut title = Element::new("title");
+        title
+            .children
+            .push(Node::Text(format!(".{} {}", name, point(p))));
+        dot.children.push(Node::Element(title));
+        layer.children.push(Node::Element(dot));
+        if edge != Edge::Center {
+            layer
+                .children
+                .push(label(frame.x(p.x), frame.y(p.y), "start", name));
+        }
+    }
+    for child in object.children() {
+        self::object(frame, child, layer);
+    }
+}
+
+/// Add the debugging layer to a diagram rendered from a layout
+pub(crate) fn overlay(dom: &mut Dom, diagram: &Layout) {
+    let (svg, bounds) = match (dom.svg_mut(), diagram.bounds()) {
+        (Some(svg), Some(bounds)) => (svg, bounds),
+        _ => return,
+    };
+    let frame = Frame { bounds };
+    let mut layer = Element::new("g");
+    layer.set_attr("class", "pikchr-debug");
+    grid(&frame, &mut layer);
+    for o in diagram.objects() {
+        object(&frame, o, &mut layer);
+    }
+    svg.children.push(Node::Element(layer));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::layout;
+
+    fn overlaid(source: &str) -> Element {
+        let diagram = layout(source).unwrap();
+        let mut dom = Dom::parse(&diagram.to_svg(None, Default::default())).unwrap();
+        overlay(&mut dom, &diagram);
+        let svg = dom.svg().unwrap();
+        svg.elements().last().unwrap().clone()
+    }
+
+    #[test]
+    fn marks_objects_and_anchors() {
+        let layer = overlaid("box; line; [circle]");
+        assert_eq!(layer.attr("class"), Some("pikchr-debug"));
+        // The box, the line, the sublist and the circle in it
+        assert_eq!(layer.find_all("rect").len(), 4);
+        // Five anchors for each of them and two for the line
+        assert_eq!(layer.find_all("circle").len(), 17);
+        let titles: Vec<_> = layer
+            .find_all("circle")
+            .iter()
+            .map(|c| c.elements().next().unwrap().text())
+            .collect();
+        assert_eq!(
+            titles[..5],
+            [
+                ".n (0, 0.25)",
+                ".e (0.375, 0)",
+                ".s (0, -0.25)",
+                ".w (-0.375, 0)",
+                ".c (0, 0)"
+            ]
+        );
+        assert_eq!(titles[5..7], [".start (0.375, 0)", ".end (0.875, 0)"]);
+    }
+
+    #[test]
+    fn lines_up_with_the_diagram() {
+        let layer = overlaid("box");
+        let rect = &layer.find_all("rect")[0];
+        // The same place as the box's own path, M2,74L110,74L110,2L2,2Z
+        assert_eq!(
+            ["x", "y", "width", "height"].map(|a| rect.attr(a).unwrap().to_string()),
+            ["2.16", "2.16", "108", "72"]
+        );
+        let labels: Vec<_> = layer
+            .find_all("text")
+            .iter()
+            .map(|t| t.text())
+            .filter(|t| t.parse::<f64>().is_ok())
+            .collect();
+        assert_eq!(labels, ["0", "0"]);
+    }
+}
@@ -0,0 +1,780 @@
+//! Building diagrams in code
+//!
+//! Programs which draw diagrams from data can describe them with typed
+//! values rather than by gluing strings together.  A [`Diagram`] holds a
+//! list of elements, such as [`Box`] and [`Arrow`], and generates pikchr
+//! source for them with all the quoting taken care of.
+//!
+//! ```
+//! use pikchr::diagram::{Arrow, Box, Cylinder, Diagram};
+//! use pikchr::PikchrFlags;
+//!
+//! let mut diagram = Diagram::new();
+//! diagram
+//!     .push(Box::new("Server").fit())
+//!     .push(Arrow::right().label("HTTP"))
+//!     .push(Cylinder::new("\"Data\"").name("Db"));
+//! assert_eq!(
+//!     diagram.to_source(),
+//!     "box \"Server\" fit\narrow \"HTTP\" right\nDb: cylinder \"\\\"Data\\\"\"\n"
+//! );
+//! let pic = diagram.render(None, PikchrFlags::default()).unwrap();
+//! assert!(pic.contains("Server"));
+//! ```
+//!
+//! Elements are built by value, so they can be constructed inline as an
+//! argument to [`Diagram::push`].  Anything which the typed methods do not
+//! cover can be added with `attribute`, or by implementing [`Element`].
+
+use crate::ast::{tokenize, Direction, Edge, TokenKind};
+use crate::semantic::{builtin, color};
+use crate::source::quote;
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::fmt::Write;
+
+/// Something which can be added to a [`Diagram`]
+pub trait Element {
+    /// The pikchr statement for this element, without a trailing newline
+    fn to_source(&self) -> String;
+}
+
+/// A diagram under construction
+#[derive(Clone, Debug, Default)]
+pub struct Diagram {
+    statements: Vec<String>,
+}
+
+impl Diagram {
+    /// Create a new, empty, diagram
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an element to the diagram
+    pub fn push<E: Element>(&mut self, element: E) -> &mut Diagram {
+        self.statements.push(element.to_source());
+        self
+    }
+
+    /// Change the direction in which subsequent elements are laid out
+    pub fn direction(&mut self, direction: Direction) -> &mut Diagram {
+        self.statements.push(direction_name(direction).to_string());
+        self
+    }
+
+    /// Set a variable, such as `boxwid`, for subsequent elements
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid variable name.
+    pub fn set(&mut self, name: &str, value: f64) -> &mut Diagram {
+        let tokens = tokenize(name);
+        let valid = builtin(name).is_some()
+            || matches!(&tokens[..], [token] if token.kind == TokenKind::Id);
+        assert!(valid, "{:?} is not a valid variable name", name);
+        self.statements
+            .push(format!("{} = {}", name, number(value)));
+        self
+    }
+
+    /// Generate the pikchr source for this diagram
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for statement in &self.statements {
+            writeln!(out, "{}", statement).unwrap();
+        }
+        out
+    }
+
+    /// Render the diagram
+    ///
+    /// This is a convenience wrapper which passes the result of
+    /// [`Diagram::to_source`] to [`Pikchr::render`].
+    pub fn render(&self, class: Option<&str>, flags: PikchrFlags) -> Result<Pikchr, PikchrError> {
+        Pikchr::render(&self.to_source(), class, flags)
+    }
+}
+
+/// A colour
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Color(u32);
+
+impl Color {
+    /// A colour from its red, green and blue components
+    pub fn rgb(red: u8, green: u8, blue: u8) -> Color {
+        Color(u32::from(red) << 16 | u32::from(green) << 8 | u32::from(blue))
+    }
+
+    /// One of pikchr's named colours, such as `LightBlue`, ignoring case
+    ///
+    /// ```
+    /// # use pikchr::diagram::Color;
+    /// assert_eq!(Color::named("lightblue"), Some(Color::rgb(0xad, 0xd8, 0xe6)));
+    /// assert_eq!(Color::named("Transparent"), None);
+    /// ```
+    pub fn named(name: &str) -> Option<Color> {
+        color(name)
+            .filter(|&(_, value)| value >= 0)
+            .map(|(_, value)| Color(value as u32))
+    }
+}
+
+impl From<u32> for Color {
+    /// A colour from a `0xRRGGBB` value
+    fn from(rgb: u32) -> Color {
+        Color(rgb & 0xff_ffff)
+    }
+}
+
+/// A position in the diagram
+#[derive(Clone, Debug, PartialEq)]
+pub struct Position(String);
+
+impl Position {
+    /// A point, in inches from the origin
+    pub fn point(x: f64, y: f64) -> Position {
+        Position(format!("({}, {})", number(x), number(y)))
+    }
+
+    /// The centre of a named element
+    ///
+    /// Elements inside a [`Group`] can be reached through the group's name,
+    /// as in `"Outer.Inner"`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid name.
+    pub fn of(name: &str) -> Position {
+        for part in name.split('.') {
+            check_name(part);
+        }
+        Position(name.to_string())
+    }
+
+    /// A point on the edge of a named element
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid name.
+    pub fn edge(name: &str, edge: Edge) -> Position {
+        let Position(name) = Position::of(name);
+        Position(format!("{}.{}", name, edge_name(edge)))
+    }
+
+    /// This position moved by some distance, in inches
+    ///
+    /// ```
+    /// # use pikchr::{ast::Edge, diagram::{Diagram, Dot, Position}};
+    /// let below = Position::edge("A", Edge::South).offset(0.0, -0.5);
+    /// let mut diagram = Diagram::new();
+    /// diagram.push(Dot::new("").at(below));
+    /// assert_eq!(diagram.to_source(), "dot at A.s + (0, -0.5)\n");
+    /// ```
+    pub fn offset(self, x: f64, y: f64) -> Position {
+        Position(format!("{} + ({}, {})", self.0, number(x), number(y)))
+    }
+}
+
+/// Which ends of a line have arrowheads
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Arrowheads {
+    /// Neither end
+    None,
+    /// The end of the line
+    End,
+    /// The start of the line
+    Start,
+    /// Both ends
+    Both,
+}
+
+/// The parts of an element common to every kind
+#[derive(Clone, Debug)]
+struct Object {
+    class: String,
+    name: Option<String>,
+    texts: Vec<String>,
+    /// Where a path starts, and its segments
+    from: Option<String>,
+    path: Vec<String>,
+    /// Attributes, keyed by the property they set so that setting a
+    /// property twice replaces it; flags have an empty key
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Object {
+    fn new(class: &str, text: &str) -> Object {
+        let mut object = Object {
+            class: class.to_string(),
+            name: None,
+            texts: Vec::new(),
+            from: None,
+            path: Vec::new(),
+            attributes: Vec::new(),
+        };
+        if !text.is_empty() {
+            object.texts.push(quote(text));
+        }
+        object
+    }
+
+    fn set(&mut self, key: &'static str, value: String) {
+        match self.attributes.iter_mut().find(|(k, _)| *k == key) {
+            Some(attribute) => attribute.1 = value,
+            None => self.attributes.push((key, value)),
+        }
+    }
+
+    fn flag(&mut self, flag: &str) {
+        self.attributes.push(("", flag.to_string()));
+    }
+
+    fn text_flag(&mut self, flag: &str) {
+        if let Some(text) = self.texts.last_mut() {
+            text.push(' ');
+            text.push_str(flag);
+        }
+    }
+
+    fn source(&self) -> String {
+        let mut out = String::new();
+        if let Some(name) = &self.name {
+            write!(out, "{}: ", name).unwrap();
+        }
+        out.push_str(&self.class);
+        for text in &self.texts {
+            write!(out, " {}", text).unwrap();
+        }
+        if let Some(from) = &self.from {
+            write!(out, " from {}", from).unwrap();
+        }
+        for segment in &self.path {
+            write!(out, " {}", segment).unwrap();
+        }
+        for (_, attribute) in &self.attributes {
+            write!(out, " {}", attribute).unwrap();
+        }
+        out
+    }
+}
+
+/// Format a number so that pikchr reads it back exactly
+fn number(value: f64) -> String {
+    assert!(value.is_finite(), "{} cannot be used in a diagram", value);
+    format!("{}", value)
+}
+
+fn check_name(name: &str) {
+    let tokens = tokenize(name);
+    assert!(
+        matches!(&tokens[..], [token] if token.kind == TokenKind::PlaceName),
+        "{:?} is not a valid name; names start with a capital letter",
+        name
+    );
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Up => "up",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Right => "right",
+    }
+}
+
+fn edge_name(edge: Edge) -> &'static str {
+    match edge {
+        Edge::North => "n",
+        Edge::NorthEast => "ne",
+        Edge::East => "e",
+        Edge::SouthEast => "se",
+        Edge::South => "s",
+        Edge::SouthWest => "sw",
+        Edge::West => "w",
+        Edge::NorthWest => "nw",
+        Edge::Center => "c",
+        Edge::Start => "start",
+        Edge::End => "end",
+    }
+}
+
+/// Methods shared by every kind of element
+macro_rules! element {
+    ($ty:ident) => {
+        impl $ty {
+            /// Name the element so that others can refer to it
+            ///
+            /// # Panics
+            ///
+            /// Panics if `name` is not a valid name.  Names start with a
+            /// capital letter, followed by letters, digits and underscores.
+            pub fn name(mut self, name: &str) -> Self {
+                check_name(name);
+                self.0.name = Some(name.to_string());
+                self
+            }
+
+            /// Add a line of text to the element
+            pub fn label(mut self, text: &str) -> Self {
+                self.0.texts.push(quote(text));
+                self
+            }
+
+            /// Make the most recent text bold
+            pub fn bold(mut self) -> Self {
+                self.0.text_flag("bold");
+                self
+            }
+
+            /// Make the most recent text italic
+            pub fn italic(mut self) -> Self {
+                self.0.text_flag("italic");
+                self
+            }
+
+            /// Make the most recent text bigger
+            pub fn big(mut self) -> Self {
+                self.0.text_flag("big");
+                self
+            }
+
+            /// Make the most recent text smaller
+            pub fn small(mut self) -> Self {
+                self.0.text_flag("small");
+                self
+            }
+
+            /// Place the most recent text above the centre of the element
+            pub fn above(mut self) -> Self {
+                self.0.text_flag("above");
+                self
+            }
+
+            /// Place the most recent text below the centre of the element
+            pub fn below(mut self) -> Self {
+                self.0.text_flag("below");
+                self
+            }
+
+            /// Left-justify the most recent text
+            pub fn ljust(mut self) -> Self {
+                self.0.text_flag("ljust");
+                self
+            }
+
+            /// Right-justify the most recent text
+            pub fn rjust(mut self) -> Self {
+                self.0.text_flag("rjust");
+                self
+            }
+
+            /// Set the colour of the outline and text
+            pub fn color<C: Into<Color>>(mut self, color: C) -> Self {
+                let Color(rgb) = color.into();
+                self.0.set("color", format!("color 0x{:06x}", rgb));
+                self
+            }
+
+            /// Set the width of the outline, in inches
+            pub fn thickness(mut self, thickness: f64) -> Self {
+                self.0
+                    .set("thickness", format!("thickness {}", number(thickness)));
+                self
+            }
+
+            /// Draw the outline with dashes
+            pub fn dashed(mut self) -> Self {
+                self.0.flag("dashed");
+                self
+            }
+
+            /// Draw the outline with dots
+            pub fn dotted(mut self) -> Self {
+                self.0.flag("dotted");
+                self
+            }
+
+            /// Draw the outline thicker than usual
+            pub fn thick(mut self) -> Self {
+                self.0.flag("thick");
+                self
+            }
+
+            /// Draw the outline thinner than usual
+            pub fn thin(mut self) -> Self {
+                self.0.flag("thin");
+                self
+            }
+
+            /// Do not draw the outline
+            pub fn invisible(mut self) -> Self {
+                self.0.flag("invis");
+                self
+            }
+
+            /// Add pikchr attributes which have no method of their own
+            ///
+            /// The text is added to the source as it is, so it is up to the
+            /// caller to make sure it is valid.
+            pub fn attribute(mut self, attribute: &str) -> Self {
+                self.0.flag(attribute);
+                self
+            }
+        }
+
+        impl Element for $ty {
+            fn to_source(&self) -> String {
+                self.0.source()
+            }
+        }
+    };
+}
+
+/// Methods shared by elements with an area
+macro_rules! shape {
+    ($(#[$doc:meta])* $ty:ident, $class:expr) => {
+        $(#[$doc])*
+        #[derive(Clone, Debug)]
+        pub struct $ty(Object);
+
+        impl $ty {
+            /// Create the element, with some text unless `text` is empty
+            pub fn new(text: &str) -> Self {
+                $ty(Object::new($class, text))
+            }
+
+            /// Size the element to fit its text
+            pub fn fit(mut self) -> Self {
+                self.0.set("fit", "fit".to_string());
+                self
+            }
+
+            /// Set the width, in inches
+            pub fn width(mut self, width: f64) -> Self {
+                self.0.set("wid", format!("wid {}", number(width)));
+                self
+            }
+
+            /// Set the height, in inches
+            pub fn height(mut self, height: f64) -> Self {
+                self.0.set("ht", format!("ht {}", number(height)));
+                self
+            }
+
+            /// Set the colour of the inside
+            pub fn fill<C: Into<Color>>(mut self, color: C) -> Self {
+                let Color(rgb) = color.into();
+                self.0.set("fill", format!("fill 0x{:06x}", rgb));
+                self
+            }
+
+            /// Place the centre of the element
+            pub fn at(mut self, position: Position) -> Self {
+                self.0.set("at", format!("at {}", position.0));
+                self
+            }
+
+            /// Place an edge of the element
+            pub fn with(mut self, edge: Edge, position: Position) -> Self {
+                self.0
+                    .set("at", format!("with .{} at {}", edge_name(edge), position.0));
+                self
+            }
+        }
+
+        element!($ty);
+    };
+}
+
+/// Methods for elements with a radius
+macro_rules! radius {
+    ($ty:ident, $what:expr) => {
+        impl $ty {
+            #[doc = $what]
+            pub fn radius(mut self, radius: f64) -> Self {
+                self.0.set("rad", format!("rad {}", number(radius)));
+                self
+            }
+        }
+    };
+}
+
+shape!(
+    /// A rectangle
+    Box,
+    "box"
+);
+radius!(Box, "Round the corners with some radius, in inches");
+shape!(
+    /// A circle
+    Circle,
+    "circle"
+);
+radius!(Circle, "Set the radius, in inches");
+shape!(
+    /// A cylinder, as used for databases
+    Cylinder,
+    "cylinder"
+);
+radius!(
+    Cylinder,
+    "Set the height of the ellipses at the ends, in inches"
+);
+shape!(
+    /// A dot, whose text is drawn beside it
+    Dot,
+    "dot"
+);
+radius!(Dot, "Set the radius, in inches");
+shape!(
+    /// An ellipse
+    Ellipse,
+    "ellipse"
+);
+shape!(
+    /// A page with a folded corner
+    File,
+    "file"
+);
+radius!(File, "Set the size of the folded corner, in inches");
+shape!(
+    /// A rectangle with semicircular ends
+    Oval,
+    "oval"
+);
+shape!(
+    /// Text with no outline
+    Text,
+    "text"
+);
+
+/// A group of elements, which can be placed and named as one
+///
+/// ```
+/// # use pikchr::{ast::Edge, diagram::{Box, Diagram, Group, Line, Position}};
+/// let mut inner = Diagram::new();
+/// inner.push(Box::new("a").name("A")).push(Box::new("b"));
+/// let mut outer = Diagram::new();
+/// outer
+///     .push(Group::new(&inner).name("G"))
+///     .push(Line::up().from(Position::edge("G.A", Edge::North)));
+/// assert_eq!(
+///     outer.to_source(),
+///     "G: [\n  A: box \"a\"\n  box \"b\"\n]\nline from G.A.n up\n"
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Group(Object);
+
+impl Group {
+    /// Create a group from the elements of a diagram
+    pub fn new(diagram: &Diagram) -> Self {
+        let mut body = String::from("[\n");
+        for statement in &diagram.statements {
+            for line in statement.lines() {
+                writeln!(body, "  {}", line).unwrap();
+            }
+        }
+        body.push(']');
+        Group(Object::new(&body, ""))
+    }
+
+    /// Place the centre of the group
+    pub fn at(mut self, position: Position) -> Self {
+        self.0.set("at", format!("at {}", position.0));
+        self
+    }
+
+    /// Place an edge of the group
+    pub fn with(mut self, edge: Edge, position: Position) -> Self {
+        self.0
+            .set("at", format!("with .{} at {}", edge_name(edge), position.0));
+        self
+    }
+}
+
+element!(Group);
+
+/// Methods shared by elements which are paths
+macro_rules! path {
+    ($(#[$doc:meta])* $ty:ident, $class:expr) => {
+        $(#[$doc])*
+        #[derive(Clone, Debug)]
+        pub struct $ty(Object);
+
+        impl $ty {
+            /// Create the element, going in the current direction
+            pub fn new() -> Self {
+                $ty(Object::new($class, ""))
+            }
+
+            fn segment(mut self, segment: String) -> Self {
+                let then = if self.0.path.is_empty() { "" } else { "then " };
+                self.0.path.push(format!("{}{}", then, segment));
+                self
+            }
+
+            /// Create the element, going right
+            pub fn right() -> Self {
+                Self::new().segment("right".to_string())
+            }
+
+            /// Create the element, going left
+            pub fn left() -> Self {
+                Self::new().segment("left".to_string())
+            }
+
+            /// Create the element, going up
+            pub fn up() -> Self {
+                Self::new().segment("up".to_string())
+            }
+
+            /// Create the element, going down
+            pub fn down() -> Self {
+                Self::new().segment("down".to_string())
+            }
+
+            /// Go some distance in a direction, in inches
+            ///
+            /// Each call adds a segment, so `go(Direction::Up, 1.0)`
+            /// followed by `go(Direction::Right, 2.0)` goes up and then
+            /// right.
+            pub fn go(self, direction: Direction, distance: f64) -> Self {
+                self.segment(format!("{} {}", direction_name(direction), number(distance)))
+            }
+
+            /// Go to a position
+            pub fn to(self, position: Position) -> Self {
+                self.segment(format!("to {}", position.0))
+            }
+
+            /// Start from a position
+            pub fn from(mut self, position: Position) -> Self {
+                self.0.from = Some(position.0);
+                self
+            }
+
+            /// Stop short of the objects at each end
+            pub fn chop(mut self) -> Self {
+                self.0.set("chop", "chop".to_string());
+                self
+            }
+
+            /// Choose which ends have arrowheads
+            pub fn arrowheads(mut self, arrowheads: Arrowheads) -> Self {
+                let value = match arrowheads {
+                    Arrowheads::None => "-",
+                    Arrowheads::End => "->",
+                    Arrowheads::Start => "<-",
+                    Arrowheads::Both => "<->",
+                };
+                self.0.set("arrows", value.to_string());
+                self
+            }
+        }
+
+        impl Default for $ty {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        element!($ty);
+    };
+}
+
+path!(
+    /// A line with an arrowhead at the end
+    Arrow,
+    "arrow"
+);
+path!(
+    /// A straight line, or a series of them
+    Line,
+    "line"
+);
+path!(
+    /// A smooth curve through a series of points
+    Spline,
+    "spline"
+);
+path!(
+    /// An invisible line, for leaving a gap between elements
+    Move,
+    "move"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_shapes() {
+        let mut diagram = Diagram::new();
+        diagram
+            .set("boxwid", 1.5)
+            .direction(Direction::Down)
+            .push(
+                Box::new("Hello")
+                    .name("Greeting")
+                    .bold()
+                    .label("world")
+                    .italic()
+                    .width(2.0)
+                    .width(1.25)
+                    .fill(Color::rgb(0xff, 0xee, 0xdd))
+                    .radius(0.1)
+                    .dashed(),
+            )
+            .push(Circle::new("").fill(Color::named("red").unwrap()))
+            .push(Text::new("x").at(Position::point(1.0, -0.5)));
+        assert_eq!(
+            diagram.to_source(),
+            "boxwid = 1.5\ndown\nGreeting: box \"Hello\" bold \"world\" italic wid 1.25 fill 0xffeedd rad 0.1 dashed\ncircle fill 0xff0000\ntext \"x\" at (1, -0.5)\n"
+        );
+        diagram.render(None, PikchrFlags::default()).unwrap();
+    }
+
+    #[test]
+    fn builds_paths() {
+        let mut diagram = Diagram::new();
+        diagram
+            .push(Box::new("a").name("A"))
+            .push(
+                Box::new("b")
+                    .name("B")
+                    .with(Edge::West, Position::edge("A", Edge::East).offset(1.0, 0.0)),
+            )
+            .push(
+                Arrow::new()
+                    .go(Direction::Down, 0.25)
+                    .go(Direction::Right, 1.0)
+                    .to(Position::edge("B", Edge::South))
+                    .from(Position::edge("A", Edge::South))
+                    .arrowheads(Arrowheads::Both)
+                    .label("both")
+                    .below(),
+            )
+            .push(Line::right().chop().to(Position::of("B")));
+        assert_eq!(
+            diagram.to_source(),
+            "A: box \"a\"\nB: box \"b\" with .w at A.e + (1, 0)\narrow \"both\" below from A.s down 0.25 then right 1 then to B.s <->\nline right then to B chop\n"
+        );
+        diagram.render(None, PikchrFlags::default()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "not a valid name")]
+    fn rejects_bad_names() {
+        Box::new("x").name("lower");
+    }
+
+    #[test]
+    fn quotes_text() {
+        let mut diagram = Diagram::new();
+        diagram.push(Text::new(r#"say "hi" \ bye"#));
+        assert_eq!(diagram.to_source(), "text \"say \\\"hi\\\" \\\\ bye\"\n");
+        let pic = diagram.render(None, PikchrFlags::default()).unwrap();
+        assert!(pic.contains("\"hi\"") && pic.contains("&#92;"));
+    }
+}
@@ -0,0 +1,332 @@
+//! Comparing rendered diagrams
+//!
+//! [`diff`] matches up the elements of two rendered diagrams and reports
+//! which were added, removed or moved, along with where they are, so that
+//! a review can point at what changed rather than showing two pictures.
+//!
+//! ```
+//! use pikchr::{diff::ChangeKind, Pikchr, PikchrFlags};
+//!
+//! let flags = PikchrFlags::default();
+//! let before = Pikchr::render("box \"A\"; arrow; box \"B\"", None, flags).unwrap();
+//! let after = Pikchr::render("box \"A\"; arrow; circle \"B\"", None, flags).unwrap();
+//! let diff = pikchr::diff(&before, &after).unwrap();
+//! let changes: Vec<_> = diff
+//!     .changes
+//!     .iter()
+//!     .map(|c| (&c.kind, c.element.name.as_str()))
+//!     .collect();
+//! assert_eq!(changes[0], (&ChangeKind::Removed, "path"));
+//! assert_eq!(changes[1], (&ChangeKind::Added, "circle"));
+//! // The label moved to the middle of the smaller circle
+//! assert!(matches!(changes[2], (ChangeKind::Moved { .. }, "text")));
+//! ```
+//!
+//! Elements match when they have the same tag, the same attributes other
+//! than their coordinates, the same text and, for paths, the same shape of
+//! path.  An element whose coordinates changed has moved, which includes
+//! being resized.  Bounds are those from
+//! [`Element::bounds`](crate::svg::Element::bounds).
+
+use crate::svg::{Dom, Element, Node, Rect};
+use crate::{Pikchr, PikchrError};
+
+/// Attributes which hold coordinates rather than style
+const GEOMETRY: &[&str] = &["d", "points", "x", "y", "cx", "cy", "r", "rx", "ry"];
+
+/// How an element changed
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeKind {
+    /// The element is only in the second diagram
+    Added,
+    /// The element is only in the first diagram
+    Removed,
+    /// The element is in both diagrams but its coordinates changed
+    Moved {
+        /// The bounds of the element in the first diagram
+        from: Rect,
+    },
+}
+
+/// A changed element
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// How the element changed
+    pub kind: ChangeKind,
+    /// The element, from the second diagram unless it was removed
+    pub element: Element,
+    /// The bounds of the element, in the diagram it is taken from
+    pub bounds: Rect,
+}
+
+/// The differences between two rendered diagrams
+#[derive(Clone, Debug)]
+pub struct Diff {
+    /// The changed elements, with removed elements in the order of the
+    /// first diagram, followed by the others in the order of the second
+    pub changes: Vec<Change>,
+    after: Dom,
+    view_box: Option<Rect>,
+}
+
+/// Compare two rendered diagrams
+///
+/// # Errors
+///
+/// It is an error if either diagram cannot be parsed; see
+/// [`Pikchr::dom`].
+pub fn diff(a: &Pikchr, b: &Pikchr) -> Result<Diff, PikchrError> {
+    let before = a.dom()?;
+    let after = b.dom()?;
+    let old = drawn(&before);
+    let new = drawn(&after);
+
+    let mut matched_old = vec![false; old.len()];
+    let mut matched_new: Vec<Option<usize>> = vec![None; new.len()];
+    // Unchanged elements are matched first, so that a moved element is
+    // not matched with one which stayed where it was
+    for exact in [true, false] {
+        for (j, (key, element)) in new.iter().enumerate() {
+            if matched_new[j].is_some() {
+                continue;
+            }
+            let found = old.iter().enumerate().position(|(i, (k, e))| {
+                !matched_old[i] && k == key && (!exact || same_geometry(e, element))
+            });
+            if let Some(i) = found {
+                matched_old[i] = true;
+                matched_new[j] = Some(i);
+            }
+        }
+    }
+
+    let mut changes = Vec::new();
+    for (i, (_, element)) in old.iter().enumerate() {
+        if !matched_old[i] {
+            changes.push(change(ChangeKind::Removed, element));
+        }
+    }
+    for (j, (_, element)) in new.iter().enumerate() {
+        match matched_new[j] {
+            None => changes.push(change(ChangeKind::Added, element)),
+            Some(i) if !same_geometry(old[i].1, element) => {
+                let from = old[i].1.bounds().unwrap_or_default();
+                changes.push(change(ChangeKind::Moved { from }, element));
+            }
+            Some(_) => {}
+        }
+    }
+
+    let view_box = match (before.view_box(), after.view_box()) {
+        (Some(a), Some(b)) => Some(a.union(b)),
+        (a, b) => a.or(b),
+    };
+    Ok(Diff {
+        changes,
+        after,
+        view_box,
+    })
+}
+
+impl Diff {
+    /// Whether the diagrams draw the same elements in the same places
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The second diagram with the changes marked on it
+    ///
+    /// Added elements are outlined in green and moved elements in blue,
+    /// with a dashed outline where they were.  Removed elements are drawn
+    /// faintly, outlined in red.  The marks are in a `<g>` with the class
+    /// `pikchr-diff`, after the rest of the diagram, and each has the class
+    /// `added`, `removed`, `moved` or `moved-from`.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// # let flags = PikchrFlags::default();
+    /// let before = Pikchr::render("box", None, flags).unwrap();
+    /// let after = Pikchr::render("box; circle", None, flags).unwrap();
+    /// let overlay = pikchr::diff(&before, &after).unwrap().overlay();
+    /// assert!(overlay.contains("<rect class=\"added\""));
+    /// ```
+    pub fn overlay(&self) -> String {
+        let mut dom = self.after.clone();
+        let mut marks = Element::new("g");
+        marks.set_attr("class", "pikchr-diff");
+        for change in &self.changes {
+            match &change.kind {
+                ChangeKind::Added => marks.children.push(mark("added", change.bounds)),
+                ChangeKind::Removed => {
+                    let mut ghost = change.element.clone();
+                    ghost.set_attr("opacity", "0.4");
+                    marks.children.push(Node::Element(ghost));
+                    marks.children.push(mark("removed", change.bounds));
+                }
+                ChangeKind::Moved { from } => {
+                    marks.children.push(mark("moved-from", *from));
+                    marks.children.push(mark("moved", change.bounds));
+                }
+            }
+        }
+        if let Some(svg) = dom.svg_mut() {
+            if let Some(view_box) = self.view_box {
+                svg.set_attr(
+                    "viewBox",
+                    &format!(
+                        "{} {} {} {}",
+                        view_box.x, view_box.y, view_box.width, view_box.height
+                    ),
+                );
+            }
+            svg.children.push(Node::Element(marks));
+            svg.children.push(Node::Text("\n".to_string()));
+        }
+        dom.to_string()
+    }
+}
+
+/// The elements which draw something, each with the key used to match it
+fn drawn(dom: &Dom) -> Vec<(String, &Element)> {
+    fn walk<'a>(element: &'a Element, out: &mut Vec<(String, &'a Element)>) {
+        if element.elements().next().is_none() {
+            out.push((key(element), element));
+        }
+        for child in element.elements() {
+            walk(child, out);
+        }
+    }
+    let mut out = Vec::new();
+    if let Some(svg) = dom.svg() {
+        for child in svg.elements() {
+            walk(child, &mut out);
+        }
+    }
+    out
+}
+
+fn key(element: &Element) -> String {
+    let mut key = element.name.clone();
+    for (name, value) in &element.attributes {
+        if !GEOMETRY.contains(&name.as_str()) {
+            key.push_str(&format!(" {}={:?}", name, value));
+        }
+    }
+    if let Some(d) = element.attr("d") {
+        key.push(' ');
+        key.extend(d.chars().filter(char::is_ascii_alphabetic));
+    }
+    if element.attr("points").is_some() {
+        key.push_str(&format!(" {}", element.points().len()));
+    }
+    key.push_str(&format!(" {:?}", element.text()));
+    key
+}
+
+fn same_geometry(a: &Element, b: &Element) -> bool {
+    GEOMETRY.iter().all(|name| a.attr(name) == b.attr(name))
+}
+
+fn change(kind: ChangeKind, element: &Element) -> Change {
+    Change {
+        kind,
+        element: element.clone(),
+        bounds: element.bounds().unwrap_or_default(),
+    }
+}
+
+fn mark(class: &str, bounds: Rect) -> Node {
+    let colour = match class {
+        "added" => "rgb(0,160,0)",
+        "removed" => "rgb(220,0,0)",
+        _ => "rgb(0,0,220)",
+    };
+    let mut style = format!("fill:none;stroke-width:2;stroke:{};", colour);
+    if class == "moved-from" {
+        style.push_str("stroke-dasharray:4,4;");
+    }
+    let pad = 4.0;
+    let mut rect = Element::new("rect");
+    rect.set_attr("class", class)
+        .set_attr("x", &(bounds.x - pad).to_string())
+        .set_attr("y", &(bounds.y - pad).to_string())
+        .set_attr("width", &(bounds.width + 2.0 * pad).to_string())
+        .set_attr("height", &(bounds.height + 2.0 * pad).to_string())
+        .set_attr("style", &style);
+    Node::Element(rect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PikchrFlags;
+
+    fn render(source: &str) -> Pikchr {
+        Pikchr::render(source, None, PikchrFlags::default()).unwrap()
+    }
+
+    #[test]
+    fn finds_no_changes_in_the_same_diagram() {
+        let pic = render("box \"a\"; arrow; circle");
+        assert!(diff(&pic, &pic).unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_moved_elements() {
+        let before = render("box \"a\"; move; circle");
+        let after = render("box \"a\"; circle");
+        let diff = diff(&before, &after).unwrap();
+        assert_eq!(diff.changes.len(), 1);
+        let change = &diff.changes[0];
+        assert_eq!(change.element.name, "circle");
+        match change.kind {
+            ChangeKind::Moved { from } => assert!(from.x > change.bounds.x),
+            ref kind => panic!("unexpected {:?}", kind),
+        }
+        assert_eq!(change.bounds.width, 72.0);
+        assert_eq!(change.bounds.width, change.bounds.height);
+    }
+
+    #[test]
+    fn finds_added_and_removed_text() {
+        let before = render("box \"a\"");
+        let after = render("box \"b\" dashed");
+        let kinds: Vec<_> = diff(&before, &after)
+            .unwrap()
+            .changes
+            .iter()
+            .map(|c| (c.kind.clone(), c.element.name.clone()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (ChangeKind::Removed, "path".to_string()),
+                (ChangeKind::Removed, "text".to_string()),
+                (ChangeKind::Added, "path".to_string()),
+                (ChangeKind::Added, "text".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlays_changes() {
+        let before = render("box; box");
+        let after = render("box wid 2");
+        let overlay = diff(&before, &after).unwrap().overlay();
+        let dom = Dom::parse(&overlay).unwrap();
+        let svg = dom.svg().unwrap();
+        // Wide enough for both diagrams
+        assert_eq!(svg.attr("viewBox"), Some("0 0 292.32 76.32"));
+        let marks = svg.elements().last().unwrap();
+        assert_eq!(marks.attr("class"), Some("pikchr-diff"));
+        let classes: Vec<_> = marks.elements().map(|e| e.attr("class")).collect();
+        assert_eq!(
+            classes,
+            [None, Some("removed"), Some("moved-from"), Some("moved")]
+        );
+        assert_eq!(
+            marks.elements().next().unwrap().attr("opacity"),
+            Some("0.4")
+        );
+    }
+}
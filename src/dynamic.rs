@@ -0,0 +1,205 @@
+//! Loading pikchr from a shared library at runtime
+//!
+//! With the `dlopen` feature, a [`Library`] loads a pikchr shared library
+//! while the program runs, so a host can offer pikchr support only where
+//! the library is installed rather than linking against it.  To leave
+//! the vendored copy of pikchr out of the build as well, turn off the
+//! default `vendored` feature:
+//!
+//! ```toml
+//! pikchr = { version = "0.1", default-features = false, features = ["dlopen"] }
+//! ```
+//!
+//! ```no_run
+//! use pikchr::{dynamic::Library, PikchrFlags};
+//!
+//! match Library::open_default() {
+//!     Ok(library) => {
+//!         let pic = library.render("box", None, PikchrFlags::default()).unwrap();
+//!         println!("{}", pic);
+//!     }
+//!     Err(err) => eprintln!("pikchr support disabled: {}", err),
+//! }
+//! ```
+
+use crate::{render_with, Pikchr, PikchrError, PikchrFlags, RenderFn};
+use core::ffi::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::path::Path;
+
+#[cfg_attr(target_os = "linux", link(name = "dl"))]
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *mut c_char;
+    fn free(ptr: *mut c_void);
+}
+
+/// Resolve every symbol when the library is loaded, so a broken library
+/// is found by [`Library::open`] rather than by a later render
+const RTLD_NOW: c_int = 2;
+
+/// The name of the pikchr shared library on this platform
+#[cfg(target_os = "macos")]
+pub const DEFAULT_NAME: &str = "libpikchr.dylib";
+
+/// The name of the pikchr shared library on this platform
+#[cfg(not(target_os = "macos"))]
+pub const DEFAULT_NAME: &str = "libpikchr.so";
+
+/// A pikchr shared library loaded at runtime
+///
+/// The library stays loaded until the `Library` is dropped.  Diagrams
+/// which it rendered do not depend on it.
+#[derive(Debug)]
+pub struct Library {
+    handle: *mut c_void,
+    pikchr: RenderFn,
+}
+
+// The library only exposes the `pikchr()` function, which does not share
+// state between calls
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+impl Library {
+    /// Load the pikchr shared library at a path
+    ///
+    /// A path without a `/` is searched for in the same way as the system
+    /// searches for other shared libraries.
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the library cannot be loaded or does not have a
+    /// `pikchr` function.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Library, String> {
+        let path = path.as_ref();
+        let name =
+            CString::new(path.to_string_lossy().into_owned()).map_err(|e| format!("{:?}", e))?;
+        unsafe {
+            let handle = dlopen(name.as_ptr(), RTLD_NOW);
+            if handle.is_null() {
+                return Err(last_error(&format!("cannot load {}", path.display())));
+            }
+            let symbol = dlsym(handle, b"pikchr\0".as_ptr() as *const c_char);
+            if symbol.is_null() {
+                let err = last_error(&format!("{} has no pikchr function", path.display()));
+                dlclose(handle);
+                return Err(err);
+            }
+            Ok(Library {
+                handle,
+                pikchr: std::mem::transmute::<*mut c_void, RenderFn>(symbol),
+            })
+        }
+    }
+
+    /// Load the pikchr shared library by its usual name, [`DEFAULT_NAME`]
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the library is not installed.
+    pub fn open_default() -> Result<Library, String> {
+        Library::open(DEFAULT_NAME)
+    }
+
+    /// Render some input pikchr source as an SVG
+    ///
+    /// This behaves in the same way as [`Pikchr::render`].
+    pub fn render(
+        &self,
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        // pikchr() allocates with the C library's malloc(), which is
+        // shared with the rest of the process
+        let pikchr = self.pikchr;
+        unsafe {
+            render_with(
+                |source, class, flags, width, height| pikchr(source, class, flags, width, height),
+                free,
+                source,
+                class,
+                flags,
+            )
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            dlclose(self.handle);
+        }
+    }
+}
+
+/// Whether the pikchr shared library can be loaded by its usual name
+pub fn is_available() -> bool {
+    Library::open_default().is_ok()
+}
+
+/// Describe the most recent dynamic loading error
+unsafe fn last_error(context: &str) -> String {
+    let err = dlerror();
+    if err.is_null() {
+        context.to_string()
+    } else {
+        format!(
+            "{}: {}",
+            context,
+            CStr::from_ptr(err).to_string_lossy().into_owned()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn reports_missing_libraries() {
+        let err = Library::open("/nonexistent/libpikchr.so").unwrap_err();
+        assert!(err.starts_with("cannot load /nonexistent/libpikchr.so: "));
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let err = Library::open(manifest).unwrap_err();
+        assert!(err.starts_with("cannot load"), "{}", err);
+        if cfg!(target_os = "linux") {
+            let err = Library::open("libm.so.6").unwrap_err();
+            assert!(
+                err.starts_with("libm.so.6 has no pikchr function: "),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn renders_with_a_loaded_library() {
+        let dir = std::env::temp_dir().join(format!("pikchr-dynamic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(DEFAULT_NAME);
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("pikchr-sys/src/pikchr.c");
+        let built = Command::new("cc")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&path)
+            .arg(&source)
+            .status();
+        assert!(
+            matches!(built, Ok(status) if status.success()),
+            "cannot build a pikchr shared library with cc"
+        );
+        let library = Library::open(&path).unwrap();
+        let flags = PikchrFlags::default();
+        let pic = library.render("box", Some("diagram"), flags).unwrap();
+        let expected = Pikchr::render("box", Some("diagram"), flags).unwrap();
+        assert_eq!(pic.rendered(), expected.rendered());
+        assert_eq!((pic.width(), pic.height()), (112, 76));
+        drop(library);
+        assert!(pic.starts_with("<svg xmlns='http://www.w3.org/2000/svg' class=\"diagram\""));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
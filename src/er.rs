@@ -0,0 +1,338 @@
+//! Entity-relationship diagrams
+//!
+//! An [`ErDiagram`] describes a set of entities (tables, structs, ...) with
+//! their columns, and the relationships between them.  It generates pikchr
+//! source drawing each entity as a table, with the entities laid out left
+//! to right and top-aligned.
+//!
+//! ```
+//! use pikchr::PikchrFlags;
+//! use pikchr::er::{Cardinality, ErDiagram};
+//!
+//! let mut er = ErDiagram::new();
+//! er.entity("User").key("id", "u64").column("name", "String");
+//! er.entity("Post").key("id", "u64").column("author", "u64");
+//! er.relation("Post", "User", Cardinality::ManyToOne, Some("author"));
+//!
+//! let pic = er.render(None, PikchrFlags::default()).unwrap();
+//! assert!(pic.contains("author"));
+//! ```
+//!
+//! If the `derive` feature is enabled then the `PikchrEntity` derive macro
+//! can implement [`ErEntity`] for a struct, so that the data model diagram
+//! is generated from the code itself.
+
+use crate::source::{quote, row_route};
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::fmt::{self, Write};
+
+/// Approximate width of a character, matching pikchr's `charwid`
+const CHAR_WIDTH: f64 = 0.08;
+/// Height of each line of text in an entity
+const LINE_HEIGHT: f64 = 0.2;
+
+/// The cardinality of a relationship between two entities
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cardinality {
+    /// Each `from` relates to exactly one `to`, and vice versa
+    OneToOne,
+    /// Each `from` relates to many `to`s
+    OneToMany,
+    /// Many `from`s relate to each `to`
+    ManyToOne,
+    /// Any number of `from`s relate to any number of `to`s
+    ManyToMany,
+}
+
+impl fmt::Display for Cardinality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cardinality::OneToOne => "1:1",
+            Cardinality::OneToMany => "1:N",
+            Cardinality::ManyToOne => "N:1",
+            Cardinality::ManyToMany => "N:M",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Column {
+    name: String,
+    ty: String,
+    key: bool,
+}
+
+/// An entity in an [`ErDiagram`]
+///
+/// Entities are created with [`ErDiagram::entity`].
+#[derive(Clone, Debug)]
+pub struct Entity {
+    name: String,
+    columns: Vec<Column>,
+}
+
+impl Entity {
+    /// The name of the entity
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn add_column(&mut self, name: &str, ty: &str, key: bool) -> &mut Entity {
+        self.columns.push(Column {
+            name: name.to_string(),
+            ty: ty.to_string(),
+            key,
+        });
+        self
+    }
+
+    /// Add a column to the entity
+    pub fn column(&mut self, name: &str, ty: &str) -> &mut Entity {
+        self.add_column(name, ty, false)
+    }
+
+    /// Add a key column to the entity
+    ///
+    /// Key columns are drawn in bold.
+    pub fn key(&mut self, name: &str, ty: &str) -> &mut Entity {
+        self.add_column(name, ty, true)
+    }
+
+    /// The column names of the entity, in order
+    ///
+    /// ```
+    /// # use pikchr::er::ErDiagram;
+    /// let mut er = ErDiagram::new();
+    /// er.entity("T").key("id", "u64").column("value", "f64");
+    /// assert_eq!(er.entities()[0].column_names().collect::<Vec<_>>(), ["id", "value"]);
+    /// ```
+    pub fn column_names(&self) -> impl Iterator<Item = &str> {
+        self.columns.iter().map(|c| c.name.as_str())
+    }
+
+    /// The lines of text which make up the body of the entity
+    fn lines(&self) -> impl Iterator<Item = (String, bool)> + '_ {
+        self.columns.iter().map(|c| {
+            if c.ty.is_empty() {
+                (c.name.clone(), c.key)
+            } else {
+                (format!("{}: {}", c.name, c.ty), c.key)
+            }
+        })
+    }
+
+    fn width(&self) -> f64 {
+        self.lines()
+            .map(|(l, _)| l)
+            .chain(std::iter::once(self.name.clone()))
+            .map(|l| l.chars().count() as f64 * CHAR_WIDTH + 0.3)
+            .fold(0.75, f64::max)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Relation {
+    from: usize,
+    to: usize,
+    cardinality: Cardinality,
+    label: Option<String>,
+}
+
+/// An entity-relationship diagram
+#[derive(Clone, Debug, Default)]
+pub struct ErDiagram {
+    entities: Vec<Entity>,
+    relations: Vec<Relation>,
+    described: Vec<String>,
+}
+
+impl ErDiagram {
+    /// Create a new, empty, entity-relationship diagram
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn index_of(&mut self, name: &str) -> usize {
+        match self.entities.iter().position(|e| e.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.entities.push(Entity {
+                    name: name.to_string(),
+                    columns: Vec::new(),
+                });
+                self.entities.len() - 1
+            }
+        }
+    }
+
+    /// Retrieve an entity so that columns can be added to it
+    ///
+    /// The entity is created if it does not already exist.
+    pub fn entity(&mut self, name: &str) -> &mut Entity {
+        let idx = self.index_of(name);
+        &mut self.entities[idx]
+    }
+
+    /// Add the entity described by an [`ErEntity`] implementation
+    ///
+    /// Adding the same entity more than once has no further effect.
+    pub fn add<T: ErEntity>(&mut self) -> &mut ErDiagram {
+        let name = T::entity_name();
+        if !self.described.contains(&name) {
+            self.described.push(name);
+            T::describe(self);
+        }
+        self
+    }
+
+    /// The entities in the diagram, in the order they will be drawn
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    /// Add a relationship between two entities
+    ///
+    /// Entities which do not yet exist are created with no columns.
+    pub fn relation(
+        &mut self,
+        from: &str,
+        to: &str,
+        cardinality: Cardinality,
+        label: Option<&str>,
+    ) -> &mut ErDiagram {
+        let from = self.index_of(from);
+        let to = self.index_of(to);
+        self.relations.push(Relation {
+            from,
+            to,
+            cardinality,
+            label: label.map(str::to_string),
+        });
+        self
+    }
+
+    /// Generate the pikchr source for this diagram
+    ///
+    /// Each entity is a `[...]` block named `E1`, `E2`, ... in order, so
+    /// the source can be extended by hand if necessary.
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for (idx, entity) in self.entities.iter().enumerate() {
+            let width = entity.width();
+            writeln!(out, "E{}: [", idx + 1).unwrap();
+            writeln!(
+                out,
+                "  H: box {} bold wid {:.3} ht {:.3}",
+                quote(&entity.name),
+                width,
+                LINE_HEIGHT + 0.1
+            )
+            .unwrap();
+            write!(out, "  box").unwrap();
+            for (line, key) in entity.lines() {
+                write!(out, " {}{}", quote(&line), if key { " bold" } else { "" }).unwrap();
+            }
+            writeln!(
+                out,
+                " wid {:.3} ht {:.3} with .n at H.s",
+                width,
+                entity.columns.len().max(1) as f64 * LINE_HEIGHT + 0.1
+            )
+            .unwrap();
+            out.push(']');
+            if idx > 0 {
+                write!(out, " with .nw at E{}.ne + (0.75, 0)", idx).unwrap();
+            }
+            out.push('\n');
+        }
+        for (nth, relation) in self.relations.iter().enumerate() {
+            let text = match &relation.label {
+                Some(label) => format!("{} ({})", label, relation.cardinality),
+                None => relation.cardinality.to_string(),
+            };
+            writeln!(
+                out,
+                "line {} {} above",
+                row_route("E", relation.from, relation.to, nth),
+                quote(&text)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Render the diagram
+    ///
+    /// This is a convenience wrapper which passes the result of
+    /// [`ErDiagram::to_source`] to [`Pikchr::render`].
+    pub fn render(&self, class: Option<&str>, flags: PikchrFlags) -> Result<Pikchr, PikchrError> {
+        Pikchr::render(&self.to_source(), class, flags)
+    }
+}
+
+/// Types which can describe themselves as an entity in an [`ErDiagram`]
+///
+/// This is normally implemented with the `PikchrEntity` derive macro
+/// (enabled by the `derive` feature) rather than by hand.
+pub trait ErEntity {
+    /// The name of the entity in the diagram
+    fn entity_name() -> String;
+
+    /// Add this entity, and any relations it has, to the diagram
+    fn describe(diagram: &mut ErDiagram);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_entities_and_relations() {
+        let mut er = ErDiagram::new();
+        er.entity("Author")
+            .key("id", "u64")
+            .column("name", "String");
+        er.entity("Book")
+            .key("isbn", "String")
+            .column("title", "String")
+            .column("author_id", "u64");
+        er.entity("Tag").key("name", "String");
+        er.relation("Book", "Author", Cardinality::ManyToOne, Some("author_id"))
+            .relation("Book", "Tag", Cardinality::ManyToMany, None)
+            .relation("Author", "Tag", Cardinality::OneToMany, None);
+        let source = er.to_source();
+        if let Err(e) = er.render(None, PikchrFlags::default()) {
+            panic!("{}\n{}", source, e);
+        }
+        assert!(source.contains("\"isbn: String\" bold"));
+        assert!(source.contains("\"author_id (N:1)\" above"));
+        assert!(source.contains("E3: [") && source.contains("with .nw at E2.ne"));
+    }
+
+    struct Account;
+
+    impl ErEntity for Account {
+        fn entity_name() -> String {
+            "accounts".to_string()
+        }
+
+        fn describe(diagram: &mut ErDiagram) {
+            diagram.entity("accounts").key("id", "u64");
+        }
+    }
+
+    #[test]
+    fn entities_are_only_described_once() {
+        let mut er = ErDiagram::new();
+        er.add::<Account>().add::<Account>();
+        assert_eq!(er.entities()[0].column_names().count(), 1);
+    }
+
+    #[test]
+    fn relations_create_missing_entities() {
+        let mut er = ErDiagram::new();
+        er.relation("A", "B", Cardinality::OneToOne, None);
+        assert_eq!(er.entities().len(), 2);
+        assert!(er.render(None, PikchrFlags::default()).is_ok());
+    }
+}
@@ -0,0 +1,301 @@
+//! Why a diagram could not be rendered
+//!
+//! pikchr reports an error as a block of text, or of HTML, which quotes
+//! the lines leading up to the error and points at it with carets.  A
+//! [`PikchrError`] picks the line, column and message out of that block,
+//! so that an editor can mark the error in place, and keeps the block
+//! itself for showing as it is.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrError, PikchrFlags};
+//!
+//! let error = Pikchr::render("box\ncircle rad xx", None, PikchrFlags::default()).err().unwrap();
+//! match &error {
+//!     PikchrError::Syntax {
+//!         line,
+//!         column,
+//!         message,
+//!         context_line,
+//!         ..
+//!     } => {
+//!         assert_eq!((*line, *column), (2, 12));
+//!         assert_eq!(message, "no such variable");
+//!         assert_eq!(context_line, "circle rad xx");
+//!     }
+//!     _ => panic!("expected a located error"),
+//! }
+//! assert!(error.to_string().contains("no such variable"));
+//! ```
+
+use std::fmt;
+
+/// What pikchr writes before the message of an error
+#[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
+const ERROR_PREFIX: &str = "ERROR: ";
+
+/// Why a diagram could not be rendered
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PikchrError {
+    /// An error at a place in the source
+    Syntax {
+        /// The line of the error, counting from 1
+        line: usize,
+        /// The column of the error, in characters counting from 1
+        column: usize,
+        /// A description of the error, such as `syntax error`
+        message: String,
+        /// The line of the source which the error is on
+        context_line: String,
+        /// The error as the renderer reported it, as plain text or HTML
+        /// according to the flags
+        raw: String,
+    },
+    /// An error which the renderer did not give a place for
+    Other {
+        /// A description of the error
+        message: String,
+        /// The error as the renderer reported it
+        raw: String,
+    },
+    /// The source or the class contained a NUL byte, which the C library
+    /// cannot be given
+    NulInInput,
+    /// The renderer ran out of memory
+    OutOfMemory,
+    /// A rendered diagram could not be changed as asked, because its SVG
+    /// could not be parsed or the change does not suit it
+    Svg {
+        /// A description of the error
+        message: String,
+    },
+}
+
+impl PikchrError {
+    /// Make sense of the error which the C library wrote for some source
+    #[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
+    pub(crate) fn from_output(source: &str, raw: String) -> PikchrError {
+        let lines: Vec<&str> = raw.lines().collect();
+        let error = match lines.iter().position(|l| l.starts_with(ERROR_PREFIX)) {
+            Some(error) => error,
+            None if raw.contains("Out of memory") => return PikchrError::OutOfMemory,
+            None => {
+                return PikchrError::Other {
+                    message: unescape(raw.trim()),
+                    raw,
+                }
+            }
+        };
+        let message = unescape(&lines[error][ERROR_PREFIX.len()..]);
+        let line = lines[..error].iter().rev().find_map(|l| context_number(l));
+        let caret = error
+            .checked_sub(1)
+            .map(|caret| lines[caret])
+            .filter(|caret| caret.trim_start().starts_with('^'));
+        match (line, caret) {
+            (Some(line), Some(caret)) => {
+                // The carets are under the quoted line, after a 12 byte
+                // prefix, except that pikchr puts them one byte too far left
+                // on the first line
+                let spaces = caret.len() - caret.trim_start().len();
+                let offset = (spaces + usize::from(line == 1)).saturating_sub(12);
+                PikchrError::located(source, line, offset, message, raw)
+            }
+            _ => PikchrError::Other { message, raw },
+        }
+    }
+
+    /// An error found by the pure-Rust backend
+    #[cfg(any(feature = "rust-backend", not(pikchr_c)))]
+    pub(crate) fn from_layout(source: &str, error: &crate::layout::LayoutError) -> PikchrError {
+        let start = error.span().start.min(source.len());
+        let before = &source.as_bytes()[..start];
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        let offset = start
+            - before
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |n| n + 1);
+        PikchrError::located(
+            source,
+            line,
+            offset,
+            error.message().to_string(),
+            error.to_string(),
+        )
+    }
+
+    /// An error in making changes to a rendered diagram
+    pub(crate) fn svg(message: impl Into<String>) -> PikchrError {
+        PikchrError::Svg {
+            message: message.into(),
+        }
+    }
+
+    /// An error at a byte offset into a line of the source
+    fn located(
+        source: &str,
+        line: usize,
+        offset: usize,
+        message: String,
+        raw: String,
+    ) -> PikchrError {
+        let context_line = source
+            .split('\n')
+            .nth(line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\r');
+        let mut offset = offset.min(context_line.len());
+        while !context_line.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        PikchrError::Syntax {
+            line,
+            column: context_line[..offset].chars().count() + 1,
+            message,
+            context_line: context_line.to_string(),
+            raw,
+        }
+    }
+
+    /// The error as the renderer reported it
+    ///
+    /// This is plain text or HTML according to the flags the diagram was
+    /// rendered with, and may begin with the output of any `print`
+    /// statements which ran before the error.  An error which the
+    /// renderer never saw is described in plain text.
+    pub fn raw(&self) -> &str {
+        match self {
+            PikchrError::Syntax { raw, .. } | PikchrError::Other { raw, .. } => raw,
+            PikchrError::NulInInput => "the source contains a NUL byte",
+            PikchrError::OutOfMemory => "pikchr could not allocate its output",
+            PikchrError::Svg { message } => message,
+        }
+    }
+
+    /// A description of the error, without the source it quotes
+    pub fn message(&self) -> &str {
+        match self {
+            PikchrError::Syntax { message, .. } | PikchrError::Other { message, .. } => message,
+            _ => self.raw(),
+        }
+    }
+}
+
+impl fmt::Display for PikchrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.raw())
+    }
+}
+
+impl std::error::Error for PikchrError {}
+
+impl From<PikchrError> for String {
+    fn from(error: PikchrError) -> String {
+        match error {
+            PikchrError::Syntax { raw, .. } | PikchrError::Other { raw, .. } => raw,
+            error => error.raw().to_string(),
+        }
+    }
+}
+
+/// The number of a line of source quoted as `/*   N */  text`
+#[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
+fn context_number(line: &str) -> Option<usize> {
+    let number = line.strip_prefix("/*")?.split("*/").next()?;
+    number.trim().parse().ok()
+}
+
+/// Undo the escaping of an error message written as HTML
+#[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    fn render_error(source: &str, flags: PikchrFlags) -> PikchrError {
+        Pikchr::render(source, None, flags).err().unwrap()
+    }
+
+    fn location(error: &PikchrError) -> (usize, usize, &str, &str) {
+        match error {
+            PikchrError::Syntax {
+                line,
+                column,
+                message,
+                context_line,
+                ..
+            } => (*line, *column, message, context_line),
+            _ => panic!("no location in {:?}", error),
+        }
+    }
+
+    #[test]
+    fn locates_errors() {
+        let flags = PikchrFlags::default();
+        let error = render_error("box\nbox; circle rad xx", flags);
+        assert_eq!(
+            location(&error),
+            (2, 17, "no such variable", "box; circle rad xx")
+        );
+        let error = render_error("circle rad xx", flags);
+        assert_eq!(
+            location(&error),
+            (1, 12, "no such variable", "circle rad xx")
+        );
+    }
+
+    #[test]
+    fn counts_columns_in_characters() {
+        let error = render_error("box \"héllo\"; circle rad xx", PikchrFlags::default());
+        assert_eq!(location(&error).1, 25);
+    }
+
+    #[test]
+    #[cfg(pikchr_c)]
+    fn reads_html_errors() {
+        let mut flags = PikchrFlags::default();
+        flags.generate_html_errors();
+        let error = render_error("print 1\nbox \"a<b\"; arrow from Foo", flags);
+        assert_eq!(
+            location(&error),
+            (2, 23, "no such object", "box \"a<b\"; arrow from Foo")
+        );
+        assert!(error.raw().starts_with("1<br>\n<div><pre>"));
+        assert!(error.to_string().contains("&lt;"));
+    }
+
+    #[test]
+    #[cfg(pikchr_c)]
+    fn reports_nul_bytes() {
+        let flags = PikchrFlags::default();
+        assert_eq!(render_error("box\0", flags), PikchrError::NulInInput);
+        let error = Pikchr::render("box", Some("a\0b"), flags).err().unwrap();
+        assert_eq!(error, PikchrError::NulInInput);
+    }
+
+    #[test]
+    fn reports_changes_which_fail() {
+        let pic = Pikchr::render("box", None, PikchrFlags::default()).unwrap();
+        let error = pic.thumbnail(0, false).err().unwrap();
+        assert!(matches!(error, PikchrError::Svg { .. }));
+        assert_eq!(
+            error.message(),
+            "a thumbnail must be at least a pixel in size"
+        );
+        assert_eq!(String::from(error.clone()), error.raw());
+    }
+
+    #[test]
+    fn converts_to_a_string() {
+        let error = render_error("box wid", PikchrFlags::default());
+        let raw = error.raw().to_string();
+        assert_eq!(String::from(error), raw);
+    }
+}
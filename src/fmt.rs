@@ -0,0 +1,529 @@
+//! Formatting pikchr source
+//!
+//! [`format()`] rewrites pikchr source into a canonical layout without
+//! changing its meaning.  Tokens on a line are separated by single spaces
+//! (with the usual exceptions such as `A.n`, `(1, 2)` and `50%`), the
+//! contents of `[...]` sublists and multi-line macro bodies are indented,
+//! runs of blank lines are collapsed, and needless escapes are removed from
+//! strings.  Line breaks and comments are kept where they were written.
+//!
+//! ```
+//! use pikchr::fmt::{format, FormatConfig};
+//!
+//! let source = "A:box \"one\"   wid 2cm\nLong: [\nbox;circle at A.n+(1,0)\n]\n";
+//! assert_eq!(
+//!     format(source, &FormatConfig::default()),
+//!     "A:    box \"one\" wid 2cm\nLong: [\n  box; circle at A.n + (1, 0)\n]\n"
+//! );
+//! ```
+//!
+//! This is the one formatting engine for the crate; anything which offers
+//! to format pikchr source should call it so that the results agree.
+
+use crate::ast::{abuts, tokenize, Token, TokenKind};
+
+/// Configuration for [`format()`]
+#[derive(Clone, Debug)]
+pub struct FormatConfig {
+    indent: usize,
+    max_blank_lines: usize,
+    align_labels: bool,
+    align_assignments: bool,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            indent: 2,
+            max_blank_lines: 1,
+            align_labels: true,
+            align_assignments: true,
+        }
+    }
+}
+
+impl FormatConfig {
+    /// Create the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of spaces used for each level of indentation
+    ///
+    /// The default is 2.
+    pub fn indent(&mut self, spaces: usize) -> &mut Self {
+        self.indent = spaces;
+        self
+    }
+
+    /// Set the number of consecutive blank lines to keep
+    ///
+    /// The default is 1.
+    pub fn max_blank_lines(&mut self, lines: usize) -> &mut Self {
+        self.max_blank_lines = lines;
+        self
+    }
+
+    /// Set whether consecutive labelled statements are aligned
+    ///
+    /// When set (the default), the statements following the labels on
+    /// consecutive lines start in the same column.
+    ///
+    /// ```
+    /// # use pikchr::fmt::{format, FormatConfig};
+    /// let source = "A: box\nLong: circle\n";
+    /// assert_eq!(format(source, FormatConfig::new().align_labels(false)), source);
+    /// assert_eq!(format(source, &FormatConfig::new()), "A:    box\nLong: circle\n");
+    /// ```
+    pub fn align_labels(&mut self, align: bool) -> &mut Self {
+        self.align_labels = align;
+        self
+    }
+
+    /// Set whether consecutive assignments are aligned
+    ///
+    /// When set (the default), the assignment operators on consecutive
+    /// lines which assign to variables are lined up.
+    ///
+    /// ```
+    /// # use pikchr::fmt::{format, FormatConfig};
+    /// let source = "boxwid = 1\nlinewid *= 2\n";
+    /// assert_eq!(format(source, &FormatConfig::new()), "boxwid  = 1\nlinewid *= 2\n");
+    /// ```
+    pub fn align_assignments(&mut self, align: bool) -> &mut Self {
+        self.align_assignments = align;
+        self
+    }
+}
+
+/// Format pikchr source
+///
+/// Formatting works on the tokens of the source rather than requiring it
+/// to parse, so source with syntax errors is still formatted.  Source
+/// which cannot even be split into tokens, such as an unterminated
+/// string, is returned unchanged.
+///
+/// ```
+/// use pikchr::fmt::{format, FormatConfig};
+///
+/// let config = FormatConfig::default();
+/// assert_eq!(format("box  \"hi\" ;arrow   # note", &config), "box \"hi\"; arrow # note\n");
+/// assert_eq!(format("box \"unterminated", &config), "box \"unterminated");
+/// ```
+pub fn format(source: &str, config: &FormatConfig) -> String {
+    let tokens = tokenize(source);
+    if tokens.iter().any(|t| t.kind == TokenKind::Error) {
+        return source.to_string();
+    }
+    let mut formatter = Formatter {
+        source,
+        config,
+        tokens: &tokens,
+        lines: Vec::new(),
+        line: Line::default(),
+        depth: 0,
+        continued: false,
+        prev: None,
+        prev2: None,
+        prev_unary: false,
+    };
+    formatter.run();
+    formatter.finish()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Align {
+    Label,
+    Assignment,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Line {
+    depth: usize,
+    text: String,
+    /// The number of significant tokens on the line so far
+    tokens: usize,
+    /// Where padding goes if this line is aligned with its neighbours
+    align: Option<(Align, usize)>,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    config: &'a FormatConfig,
+    tokens: &'a [Token],
+    /// The finished lines, with `None` for blank lines
+    lines: Vec<Option<Line>>,
+    line: Line,
+    depth: usize,
+    continued: bool,
+    prev: Option<Token>,
+    prev2: Option<Token>,
+    prev_unary: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn text(&self, token: &Token) -> &'a str {
+        token.text(self.source)
+    }
+
+    fn run(&mut self) {
+        for (idx, token) in self.tokens.iter().enumerate() {
+            match token.kind {
+                TokenKind::Whitespace => {
+                    if self.text(token).ends_with('\n') {
+                        self.line.text.push_str(" \\");
+                        self.end_line();
+                        self.continued = true;
+                    }
+                }
+                TokenKind::Eol if self.text(token) == "\n" => {
+                    if self.line.text.is_empty() {
+                        self.lines.push(None);
+                    } else {
+                        self.end_line();
+                    }
+                    self.continued = false;
+                }
+                TokenKind::Eol => {
+                    if !self.line.text.is_empty() && !self.ends_line(idx + 1) {
+                        self.line.text.push(';');
+                        self.prev = Some(*token);
+                        self.prev2 = None;
+                        self.prev_unary = false;
+                    }
+                }
+                TokenKind::Comment => {
+                    self.start_line(token.kind);
+                    if !self.line.text.is_empty() {
+                        self.line.text.push(' ');
+                    }
+                    self.line.text.push_str(self.text(token));
+                    self.prev = Some(*token);
+                    self.prev2 = None;
+                    self.prev_unary = false;
+                }
+                _ => self.push(token),
+            }
+        }
+        if !self.line.text.is_empty() {
+            self.end_line();
+        }
+    }
+
+    /// Whether the line ends at token `idx`, ignoring a trailing comment
+    fn ends_line(&self, idx: usize) -> bool {
+        for token in &self.tokens[idx..] {
+            match token.kind {
+                TokenKind::Whitespace => {
+                    if self.text(token).ends_with('\n') {
+                        return false;
+                    }
+                }
+                TokenKind::Comment => {
+                    if !self.text(token).starts_with("/*") {
+                        return true;
+                    }
+                }
+                TokenKind::Eol => return true,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn start_line(&mut self, first: TokenKind) {
+        if self.line.text.is_empty() {
+            self.line.depth = self.depth + usize::from(self.continued);
+            if first == TokenKind::RBracket {
+                self.line.depth = self.line.depth.saturating_sub(1);
+            }
+            self.prev = None;
+            self.prev2 = None;
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        self.lines.push(Some(line));
+    }
+
+    fn push(&mut self, token: &Token) {
+        self.start_line(token.kind);
+        let text = match token.kind {
+            TokenKind::String => normalize_string(self.text(token)),
+            TokenKind::CodeBlock => self.code_block(token),
+            _ => self.text(token).to_string(),
+        };
+        if let Some(prev) = self.prev {
+            if self.wants_space(&prev, token) || !self.glues(&prev, token) {
+                self.line.text.push(' ');
+            }
+        }
+        self.line.text.push_str(&text);
+        self.line.tokens += 1;
+        match (self.line.tokens, token.kind, self.prev.map(|p| p.kind)) {
+            (2, TokenKind::Colon, Some(TokenKind::PlaceName)) => {
+                self.line.align = Some((Align::Label, self.line.text.len()))
+            }
+            (2, TokenKind::Assign, _) => {
+                self.line.align = Some((Align::Assignment, self.line.text.len() - text.len() - 1))
+            }
+            _ => {}
+        }
+        match token.kind {
+            TokenKind::LBracket => self.depth += 1,
+            TokenKind::RBracket => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        self.prev_unary = matches!(token.kind, TokenKind::Minus | TokenKind::Plus)
+            && !ends_value(self.prev.map(|p| p.kind), self.prev2.map(|p| p.kind));
+        self.prev2 = self.prev;
+        self.prev = Some(*token);
+    }
+
+    /// Whether two adjacent tokens on a line are separated by a space
+    fn wants_space(&self, prev: &Token, next: &Token) -> bool {
+        use TokenKind::*;
+        if prev.kind == Comment || next.kind == Comment {
+            return true;
+        }
+        if matches!(
+            next.kind,
+            Comma | RParen | RBracket | Percent | Colon | Gt | Eol
+        ) {
+            return false;
+        }
+        if matches!(next.kind, DotE | DotL | DotU | DotXY) {
+            return !ends_value(Some(prev.kind), self.prev2.map(|p| p.kind));
+        }
+        if matches!(
+            prev.kind,
+            LParen | LBracket | Lt | DotE | DotL | DotU | DotXY
+        ) {
+            return false;
+        }
+        if self.prev_unary {
+            return false;
+        }
+        match (prev.kind, next.kind) {
+            (Func1 | Func2 | Dist | Assert, LParen) => false,
+            // Whether a macro is given arguments depends on the space
+            (Id, LParen) => prev.span.end != next.span.start,
+            _ => true,
+        }
+    }
+
+    /// Whether two tokens can be written with nothing between them
+    fn glues(&self, prev: &Token, next: &Token) -> bool {
+        prev.kind == TokenKind::Eol || abuts(self.text(prev), prev.kind, self.text(next))
+    }
+
+    fn code_block(&self, token: &Token) -> String {
+        let text = self.text(token);
+        let body = format(&text[1..text.len() - 1], self.config);
+        let body = body.trim_end_matches('\n');
+        if body.is_empty() {
+            "{}".to_string()
+        } else if !body.contains('\n') {
+            format!("{{ {} }}", body)
+        } else {
+            let indent = " ".repeat(self.line.depth * self.config.indent);
+            let mut out = "{\n".to_string();
+            for line in body.lines() {
+                if !line.is_empty() {
+                    out.push_str(&indent);
+                    out.push_str(&" ".repeat(self.config.indent));
+                    out.push_str(line);
+                }
+                out.push('\n');
+            }
+            out.push_str(&indent);
+            out.push('}');
+            out
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.align();
+        let mut out = String::new();
+        let mut blanks = 0;
+        for line in &self.lines {
+            match line {
+                None => blanks += 1,
+                Some(line) => {
+                    if !out.is_empty() {
+                        for _ in 0..blanks.min(self.config.max_blank_lines) {
+                            out.push('\n');
+                        }
+                    }
+                    blanks = 0;
+                    out.push_str(&" ".repeat(line.depth * self.config.indent));
+                    out.push_str(&line.text);
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+
+    fn align(&mut self) {
+        let mut start = 0;
+        while start < self.lines.len() {
+            let key = |line: &Option<Line>| {
+                let line = line.as_ref()?;
+                let (align, at) = line.align?;
+                let wanted = match align {
+                    Align::Label => self.config.align_labels,
+                    Align::Assignment => self.config.align_assignments,
+                };
+                if wanted {
+                    Some((align, line.depth, at))
+                } else {
+                    None
+                }
+            };
+            let (align, depth) = match key(&self.lines[start]) {
+                Some((align, depth, _)) => (align, depth),
+                None => {
+                    start += 1;
+                    continue;
+                }
+            };
+            let mut end = start;
+            let mut width = 0;
+            while let Some((a, d, at)) = self.lines.get(end).and_then(key) {
+                if a != align || d != depth {
+                    break;
+                }
+                let line = self.lines[end].as_ref().unwrap();
+                width = width.max(line.text[..at].chars().count());
+                end += 1;
+            }
+            for line in self.lines[start..end].iter_mut().flatten() {
+                let at = line.align.unwrap().1;
+                let pad = width - line.text[..at].chars().count();
+                line.text.insert_str(at, &" ".repeat(pad));
+            }
+            start = end;
+        }
+    }
+}
+
+/// Whether a token ends a value, so that a following `+` or `-` is binary
+fn ends_value(prev: Option<TokenKind>, prev2: Option<TokenKind>) -> bool {
+    use TokenKind::*;
+    match prev {
+        Some(Number | Id | PlaceName | RParen | RBracket | X | Y | Last | This) => true,
+        Some(ClassName) => matches!(prev2, Some(Last | Nth)),
+        Some(_) => matches!(prev2, Some(DotE | DotL)),
+        None => false,
+    }
+}
+
+/// Remove escapes which make no difference from a string
+///
+/// A backslash before any character other than `\`, `"` or `&` is dropped
+/// when the text is rendered, so it can be removed.
+pub(crate) fn normalize_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(next @ ('\\' | '"' | '&')) => {
+                    out.push(c);
+                    out.push(next);
+                }
+                Some(next) => out.push(next),
+                None => out.push(c),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    fn fmt(source: &str) -> String {
+        format(source, &FormatConfig::default())
+    }
+
+    #[test]
+    fn spaces_tokens() {
+        assert_eq!(
+            fmt("arrow from A.e+(1,-2)to 1/3<A.n,B.s> then down 50 %"),
+            "arrow from A.e + (1, -2) to 1 / 3 <A.n, B.s> then down 50%\n"
+        );
+        assert_eq!(fmt("v=max( -1 , abs(2) )"), "v = max(-1, abs(2))\n");
+        assert_eq!(fmt("box wid -1 ht A.ht-1"), "box wid -1 ht A.ht - 1\n");
+        assert_eq!(fmt("dot at last box -(1,0)"), "dot at last box - (1, 0)\n");
+        assert_eq!(fmt("assert ( A.x==B.x )"), "assert(A.x == B.x)\n");
+        assert_eq!(
+            fmt("line from 1 < -2,0> to 1"),
+            "line from 1 < -2, 0> to 1\n"
+        );
+    }
+
+    #[test]
+    fn keeps_macro_invocations() {
+        let source = "define m { box $1 }\nm(\"a\")\nm (\"b\")\n";
+        assert_eq!(fmt(source), source);
+        assert_eq!(
+            fmt("define pair {\nbox $1\n  circle $2\n}\n"),
+            "define pair {\n  box $1\n  circle $2\n}\n"
+        );
+    }
+
+    #[test]
+    fn indents_sublists_and_continuations() {
+        assert_eq!(
+            fmt("X: [\nbox\n[\ncircle\n]\n] with .n at (0,0)\nbox \\\nwid 2\n"),
+            "X: [\n  box\n  [\n    circle\n  ]\n] with .n at (0, 0)\nbox \\\n  wid 2\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_collapses_blank_lines() {
+        assert_eq!(
+            fmt("\n\n# heading\nbox;  # trailing\n\n\n\n/* block */ circle\n\n"),
+            "# heading\nbox # trailing\n\n/* block */ circle\n"
+        );
+        let config = FormatConfig::new().max_blank_lines(0).clone();
+        assert_eq!(format("box\n\ncircle\n", &config), "box\ncircle\n");
+    }
+
+    #[test]
+    fn aligns_runs() {
+        assert_eq!(
+            fmt("A: box\nBee: circle\narrow\nC: box\nDee:\n  [\n    x1 = 1\n    long += 2\n  ]\n"),
+            "A:   box\nBee: circle\narrow\nC:   box\nDee:\n[\n  x1   = 1\n  long += 2\n]\n"
+        );
+    }
+
+    #[test]
+    fn normalizes_strings() {
+        assert_eq!(fmt(r#"text "a\b\\c\"d\&e""#), "text \"ab\\\\c\\\"d\\&e\"\n");
+    }
+
+    #[test]
+    fn preserves_meaning() {
+        let sources = [
+            "A: box \"one\" fit\narrow right 200% from A.e then down\nB: circle at A.s + (0, -1)\n",
+            "define row { box $1; box $2 }\nrow(\"a\", \"b\")\nline from 2nd box.n to 1st box .s chop\n",
+            "X: [\n  box;circle\n] ; dot at X.ne\nprint 1+-2, \"hi\"\nassert(X.w.x==X.w.x)\n",
+            "boxwid*=2 ; C: circle rad 10% color Red\nline -> from C.e right until even with C.n\n",
+        ];
+        for source in &sources {
+            let formatted = fmt(source);
+            let before = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+            let after = Pikchr::render(&formatted, None, PikchrFlags::default()).unwrap();
+            assert_eq!(&*before, &*after, "{}", formatted);
+            assert_eq!(fmt(&formatted), formatted);
+        }
+    }
+}
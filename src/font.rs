@@ -0,0 +1,196 @@
+//! Embedding a web font
+//!
+//! The text of a diagram is drawn in whichever font the viewer's browser
+//! chooses, so a diagram can look different on each machine, and text can
+//! overflow the shapes that were fitted to it.  [`Pikchr::with_font`]
+//! embeds a font in the SVG with an `@font-face` rule and draws all of the
+//! text in it, so the diagram looks the same wherever it is shown.  To fit
+//! shapes to the font exactly, measure text with its metrics using
+//! [`Pikchr::render_measured`].
+//!
+//! ```
+//! use pikchr::{
+//!     font::{FontFormat, WebFont},
+//!     Pikchr, PikchrFlags,
+//! };
+//!
+//! // The contents of a .woff2 file
+//! let data = b"wOF2";
+//! let font = WebFont::new("Diagram Sans", FontFormat::Woff2, data);
+//! let pic = Pikchr::render("box \"Hello\"", None, PikchrFlags::default()).unwrap();
+//! let pic = pic.with_font(&font).unwrap();
+//! assert!(pic.contains("<style>@font-face{font-family:'Diagram Sans';\
+//!     src:url(data:font/woff2;base64,d09GMg==) format('woff2');}</style>"));
+//! assert!(pic.contains(" font-family=\"'Diagram Sans'\""));
+//! ```
+//!
+//! [`Pikchr::with_font`]: crate::Pikchr::with_font
+//! [`Pikchr::render_measured`]: crate::Pikchr::render_measured
+
+use crate::svg::{escape, Dom, Element, Node};
+
+/// The format of the data of a font
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FontFormat {
+    /// A `.woff2` file, which is the smallest and is supported by all
+    /// current browsers
+    Woff2,
+    /// A `.woff` file
+    Woff,
+    /// A TrueType `.ttf` file
+    TrueType,
+    /// An OpenType `.otf` file
+    OpenType,
+}
+
+impl FontFormat {
+    /// The MIME type and CSS `format()` of the format
+    fn names(self) -> (&'static str, &'static str) {
+        match self {
+            FontFormat::Woff2 => ("font/woff2", "woff2"),
+            FontFormat::Woff => ("font/woff", "woff"),
+            FontFormat::TrueType => ("font/ttf", "truetype"),
+            FontFormat::OpenType => ("font/otf", "opentype"),
+        }
+    }
+}
+
+/// A font to embed in a diagram
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebFont {
+    family: String,
+    format: FontFormat,
+    base64: String,
+}
+
+impl WebFont {
+    /// A font with a family name, from the contents of a font file
+    pub fn new(family: &str, format: FontFormat, data: &[u8]) -> WebFont {
+        WebFont {
+            family: family.to_string(),
+            format,
+            base64: base64(data),
+        }
+    }
+
+    /// A font with a family name, from the contents of a font file which
+    /// are already base64 encoded
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the data is not valid base64.
+    pub fn from_base64(family: &str, format: FontFormat, base64: &str) -> Result<WebFont, String> {
+        let base64: String = base64.split_whitespace().collect();
+        let digits = base64.trim_end_matches('=');
+        if !base64.len().is_multiple_of(4)
+            || base64.len() - digits.len() > 2
+            || !digits
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+        {
+            return Err("the font data is not valid base64".to_string());
+        }
+        Ok(WebFont {
+            family: family.to_string(),
+            format,
+            base64,
+        })
+    }
+
+    /// The family name of the font
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+}
+
+/// Encode bytes as base64
+pub(crate) fn base64(data: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(char::from(DIGITS[(bits >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Quote a string for CSS
+fn css_string(text: &str) -> String {
+    let mut out = String::from("'");
+    for c in text.chars() {
+        match c {
+            '\'' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\a "),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+/// Embed a font in a rendered diagram and draw its text in it
+pub(crate) fn embed(dom: &mut Dom, font: &WebFont) {
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return,
+    };
+    let family = css_string(&font.family);
+    let (mime, format) = font.format.names();
+    let mut style = Element::new("style");
+    style.children.push(Node::Text(escape(&format!(
+        "@font-face{{font-family:{};src:url(data:{};base64,{}) format('{}');}}",
+        family, mime, font.base64, format
+    ))));
+    svg.children.insert(0, Node::Element(style));
+    svg.set_attr("font-family", &escape(&family));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe, 0x00, 0x01]), "//4AAQ==");
+    }
+
+    #[test]
+    fn checks_base64() {
+        let font = |data| WebFont::from_base64("F", FontFormat::Woff, data);
+        assert_eq!(font("Zm9v\nYmE=").unwrap().base64, "Zm9vYmE=");
+        assert!(font("Zm9vY").is_err());
+        assert!(font("Zm9v Y===").is_err());
+        assert!(font("Zm-v").is_err());
+    }
+
+    #[test]
+    fn quotes_family_names() {
+        let font = WebFont::new("Bob's <Font>", FontFormat::TrueType, b"x");
+        let mut dom =
+            Dom::parse("<svg xmlns='http://www.w3.org/2000/svg'><text>a</text></svg>").unwrap();
+        embed(&mut dom, &font);
+        assert_eq!(
+            dom.to_string(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" font-family=\"'Bob\\'s &lt;Font&gt;'\">\
+             <style>@font-face{font-family:'Bob\\'s &lt;Font&gt;';\
+             src:url(data:font/ttf;base64,eA==) format('truetype');}</style><text>a</text></svg>"
+        );
+    }
+}
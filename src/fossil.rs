@@ -0,0 +1,255 @@
+//! Rendering diagrams in documents written for Fossil
+//!
+//! The [Fossil](https://fossil-scm.org) version control system, where
+//! pikchr began, renders diagrams in its Markdown and wiki pages.  A
+//! Markdown page has them in ```` ```pikchr ```` fences and a wiki page in
+//! `<verbatim type="pikchr">` blocks, and either can be followed by
+//! modifiers which change how the diagram is shown:
+//!
+//! - `indent` or `center`, or `float-left` or `float-right`, to place it
+//! - `toggle`, so that clicking the diagram shows its source
+//! - `source`, to show the source as well, or `source-inline` to show it
+//!   beside the diagram
+//!
+//! [`process_markdown`] and [`process_wiki`] replace each block with the
+//! same markup Fossil writes for it, so that pages moved out of a Fossil
+//! repository look the same with Fossil's stylesheet and scripts.  The rest
+//! of the page is left for a Markdown or wiki renderer to deal with.
+//!
+//! ```
+//! use pikchr::{fossil, PikchrFlags};
+//!
+//! let page = "# Flow\n\n```pikchr center\nbox \"start\"; arrow\n```\n";
+//! let html = fossil::process_markdown(page, PikchrFlags::default());
+//! assert!(html.starts_with("# Flow\n\n<div class='pikchr-wrapper center'>"));
+//! assert!(html.contains("margin-left:auto;margin-right:auto;"));
+//! ```
+
+use crate::markdown::replace_fences;
+use crate::svg::escape;
+use crate::{Pikchr, PikchrFlags};
+use std::convert::Infallible;
+
+/// The modifiers which Fossil recognises after `pikchr`
+#[derive(Default)]
+struct Modifiers {
+    toggle: bool,
+    source: bool,
+    source_inline: bool,
+    placement: Option<&'static str>,
+}
+
+impl Modifiers {
+    fn parse(words: &str) -> Modifiers {
+        let mut modifiers = Modifiers::default();
+        for word in words.split_whitespace() {
+            match word {
+                "toggle" => modifiers.toggle = true,
+                "source" => modifiers.source = true,
+                "source-inline" => modifiers.source_inline = true,
+                "indent" => modifiers.placement = Some("indent"),
+                "center" => modifiers.placement = Some("center"),
+                "float-left" => modifiers.placement = Some("float-left"),
+                "float-right" => modifiers.placement = Some("float-right"),
+                // Fossil ignores modifiers it does not know
+                _ => {}
+            }
+        }
+        modifiers
+    }
+}
+
+/// The markup Fossil writes for a diagram with some modifiers
+///
+/// ```
+/// use pikchr::{fossil, PikchrFlags};
+///
+/// let html = fossil::block("box", "toggle", PikchrFlags::default());
+/// assert!(html.starts_with("<div class='pikchr-wrapper'><div class=\"pikchr-svg toggle\""));
+/// assert!(html.ends_with("<pre class='pikchr-src'>box</pre>\n</div>\n"));
+/// ```
+///
+/// Errors are written as plain text in a `<pre class='error'>`, as Fossil
+/// does.
+pub fn block(source: &str, modifiers: &str, flags: PikchrFlags) -> String {
+    let modifiers = Modifiers::parse(modifiers);
+    let mut flags = flags;
+    flags.generate_plain_errors();
+    let pic = match Pikchr::render(source, None, flags) {
+        Ok(pic) => pic,
+        Err(error) => return format!("<pre class='error'>\n{}\n</pre>\n", escape(error.raw())),
+    };
+    let mut style = format!("max-width:{}px;", pic.width());
+    let wrapper = match modifiers.placement {
+        Some("center") => {
+            style.push_str("display:block;margin-left:auto;margin-right:auto;");
+            " center"
+        }
+        Some("float-left") => {
+            style.push_str("float:left;padding:4em;");
+            " float-left"
+        }
+        Some("float-right") => {
+            style.push_str("float:right;padding:4em;");
+            " float-right"
+        }
+        Some("indent") => " indent",
+        _ => "",
+    };
+    let toggle = if modifiers.toggle { " toggle" } else { "" };
+    let shown = if modifiers.source_inline {
+        " source source-inline"
+    } else if modifiers.source {
+        " source"
+    } else {
+        ""
+    };
+    format!(
+        "<div class='pikchr-wrapper{}'><div class=\"pikchr-svg{}{}\" style='{}'>{}</div>\
+         <pre class='pikchr-src'>{}</pre>\n</div>\n",
+        wrapper,
+        toggle,
+        shown,
+        style,
+        pic.rendered().trim_end(),
+        escape(source.trim_end()),
+    )
+}
+
+/// Replace the ```` ```pikchr ```` fences in Markdown with Fossil's markup
+/// for the diagrams in them
+///
+/// Other fenced code blocks, and any pikchr fences inside them, are left
+/// as they are.  A fence which is never closed runs to the end of the
+/// text, as CommonMark says.
+pub fn process_markdown(text: &str, flags: PikchrFlags) -> String {
+    let replaced = replace_fences(text, |modifiers, source| {
+        Ok::<_, Infallible>(block(source, modifiers, flags))
+    });
+    match replaced {
+        Ok(out) => out,
+        Err(never) => match never {},
+    }
+}
+
+/// Replace the `<verbatim type="pikchr">` blocks in Fossil wiki text with
+/// Fossil's markup for the diagrams in them
+///
+/// The modifiers follow `pikchr` in the `type` attribute, as in
+/// `<verbatim type="pikchr center">`.  Other `<verbatim>` blocks are left
+/// as they are.
+///
+/// ```
+/// use pikchr::{fossil, PikchrFlags};
+///
+/// let page = "See <verbatim type=\"pikchr indent\">circle</verbatim> here";
+/// let html = fossil::process_wiki(page, PikchrFlags::default());
+/// assert!(html.starts_with("See <div class='pikchr-wrapper indent'>"));
+/// assert!(html.ends_with("</div>\n here"));
+/// ```
+pub fn process_wiki(text: &str, flags: PikchrFlags) -> String {
+    const OPEN: &str = "<verbatim type=";
+    const CLOSE: &str = "</verbatim>";
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(OPEN) {
+        let after = &rest[start + OPEN.len()..];
+        let quote = after.chars().next().filter(|&c| c == '"' || c == '\'');
+        let parsed = quote.and_then(|quote| {
+            let value_end = after[1..].find(quote)? + 1;
+            let tag_end = value_end + after[value_end..].find('>')? + 1;
+            let body_end = tag_end + after[tag_end..].find(CLOSE)?;
+            Some((
+                &after[1..value_end],
+                &after[tag_end..body_end],
+                body_end + CLOSE.len(),
+            ))
+        });
+        let pikchr = parsed.and_then(|(kind, body, end)| {
+            let modifiers = kind.trim_start().strip_prefix("pikchr")?;
+            match modifiers.chars().next() {
+                Some(c) if !c.is_whitespace() => None,
+                _ => Some((modifiers, body, end)),
+            }
+        });
+        match pikchr {
+            Some((modifiers, body, end)) => {
+                out.push_str(&rest[..start]);
+                out.push_str(&block(body, modifiers, flags));
+                rest = &after[end..];
+            }
+            _ => {
+                out.push_str(&rest[..start + OPEN.len()]);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markdown(text: &str) -> String {
+        process_markdown(text, PikchrFlags::default())
+    }
+
+    #[test]
+    fn finds_pikchr_fences() {
+        let html = markdown("a\n~~~~ pikchr source\nbox\n~~~~~\nb\n");
+        assert!(html.starts_with("a\n<div class='pikchr-wrapper'><div class=\"pikchr-svg source\""));
+        assert!(html.ends_with("<pre class='pikchr-src'>box</pre>\n</div>\nb\n"));
+        // A shorter run of the fence does not close it
+        let html = markdown("```pikchr\nbox \"``\"\n```");
+        assert!(html.contains("<pre class='pikchr-src'>box &quot;``&quot;</pre>"));
+    }
+
+    #[test]
+    fn leaves_other_fences_alone() {
+        let text = "````md\n```pikchr\nbox\n```\n````\n```pikchrs\nbox\n```\n";
+        assert_eq!(markdown(text), text);
+    }
+
+    #[test]
+    fn removes_the_fence_indent() {
+        let html = markdown("  ```pikchr float-right\n  box\n    \"x\"\n  ```\n");
+        assert!(html.contains(" float-right'>"));
+        assert!(html.contains("float:right;padding:4em;"));
+        assert!(html.contains("<pre class='pikchr-src'>box\n  &quot;x&quot;</pre>"));
+    }
+
+    #[test]
+    fn shows_errors() {
+        let html = markdown("```pikchr\nbox wid\n```\n");
+        assert!(html.starts_with("<pre class='error'>\n"));
+        assert!(html.contains("syntax error"));
+        let text = "<verbatim type=\"pikchr\">box wid</verbatim>";
+        assert!(process_wiki(text, PikchrFlags::default()).starts_with("<pre class='error'>"));
+    }
+
+    #[test]
+    fn leaves_other_verbatim_blocks_alone() {
+        let text = "<verbatim type=\"c\">x</verbatim> <verbatim type=\"pikchr";
+        assert_eq!(process_wiki(text, PikchrFlags::default()), text);
+        let text = "<verbatim type=\"pikchrs\">box</verbatim>";
+        assert_eq!(process_wiki(text, PikchrFlags::default()), text);
+    }
+
+    #[test]
+    fn allows_space_before_the_type() {
+        let flags = PikchrFlags::default();
+        let html = process_wiki("<verbatim type=\" pikchr center\">box</verbatim>", flags);
+        assert!(
+            html.starts_with("<div class='pikchr-wrapper center'>"),
+            "{}",
+            html
+        );
+        let html = process_wiki(
+            "<verbatim type=\"\u{a0}\u{3000}\u{a0}pikchr\">box</verbatim>",
+            flags,
+        );
+        assert!(html.starts_with("<div class='pikchr-wrapper'>"), "{}", html);
+    }
+}
@@ -0,0 +1,304 @@
+//! Grid and matrix layouts
+//!
+//! Laying out a grid of boxes by hand means getting a lot of coordinate
+//! arithmetic right.  The [`Grid`] type does that arithmetic for you: you
+//! say how many rows and columns you want, label the cells, and optionally
+//! connect them together.
+//!
+//! ```
+//! use pikchr::PikchrFlags;
+//! use pikchr::grid::Grid;
+//!
+//! let pic = Grid::new(2, 2)
+//!     .cell(0, 0, "Parse")
+//!     .cell(0, 1, "Layout")
+//!     .cell(1, 1, "Render")
+//!     .connect((0, 0), (0, 1))
+//!     .connect((0, 1), (1, 1))
+//!     .render(None, PikchrFlags::default())
+//!     .unwrap();
+//! assert!(pic.contains("Layout"));
+//! ```
+//!
+//! Each cell is given a name of the form `R<row>C<col>` (1-based), so the
+//! generated source can be extended by hand to refer to individual cells.
+
+use crate::source::quote;
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::fmt::Write;
+
+/// Approximate width of a character, matching pikchr's `charwid`
+const CHAR_WIDTH: f64 = 0.08;
+
+#[derive(Clone, Debug)]
+struct Connection {
+    from: (usize, usize),
+    to: (usize, usize),
+    arrow: bool,
+    label: Option<String>,
+}
+
+/// A grid of labelled boxes
+///
+/// All cells in the grid are the same size.  By default cells are `0.75`
+/// by `0.5` inches (pikchr's default box size) with a `0.25` inch gap
+/// between them, but cells are widened to fit the longest label.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    rows: usize,
+    cols: usize,
+    labels: Vec<Option<String>>,
+    connections: Vec<Connection>,
+    cell_width: f64,
+    cell_height: f64,
+    gap: f64,
+    hide_empty: bool,
+}
+
+impl Grid {
+    /// Create a new grid with the given number of rows and columns
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            labels: vec![None; rows * cols],
+            connections: Vec::new(),
+            cell_width: 0.75,
+            cell_height: 0.5,
+            gap: 0.25,
+            hide_empty: false,
+        }
+    }
+
+    /// The number of rows in the grid
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The number of columns in the grid
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn index(&self, (row, col): (usize, usize)) -> usize {
+        assert!(
+            row < self.rows && col < self.cols,
+            "cell ({}, {}) is outside of a {}x{} grid",
+            row,
+            col,
+            self.rows,
+            self.cols
+        );
+        row * self.cols + col
+    }
+
+    /// Set the label of a cell
+    ///
+    /// Rows and columns are numbered from zero, with row zero at the top
+    /// and column zero at the left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell is outside of the grid.
+    pub fn cell(&mut self, row: usize, col: usize, label: &str) -> &mut Grid {
+        let idx = self.index((row, col));
+        self.labels[idx] = Some(label.to_string());
+        self
+    }
+
+    /// Retrieve the label of a cell, if it has one
+    ///
+    /// ```
+    /// # use pikchr::grid::Grid;
+    /// let mut grid = Grid::new(1, 2);
+    /// grid.cell(0, 1, "B");
+    /// assert_eq!(grid.label(0, 0), None);
+    /// assert_eq!(grid.label(0, 1), Some("B"));
+    /// ```
+    pub fn label(&self, row: usize, col: usize) -> Option<&str> {
+        self.labels[self.index((row, col))].as_deref()
+    }
+
+    /// Set the minimum size of each cell, in inches
+    pub fn cell_size(&mut self, width: f64, height: f64) -> &mut Grid {
+        self.cell_width = width;
+        self.cell_height = height;
+        self
+    }
+
+    /// Set the gap between neighbouring cells, in inches
+    pub fn gap(&mut self, gap: f64) -> &mut Grid {
+        self.gap = gap;
+        self
+    }
+
+    /// Do not draw cells which have no label
+    ///
+    /// Hidden cells still occupy their space in the grid and can still be
+    /// connected to.
+    pub fn hide_empty(&mut self) -> &mut Grid {
+        self.hide_empty = true;
+        self
+    }
+
+    fn add_connection(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        arrow: bool,
+        label: Option<&str>,
+    ) -> &mut Grid {
+        self.index(from);
+        self.index(to);
+        self.connections.push(Connection {
+            from,
+            to,
+            arrow,
+            label: label.map(str::to_string),
+        });
+        self
+    }
+
+    /// Draw an arrow from one cell to another
+    ///
+    /// Cells are given as `(row, col)` pairs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either cell is outside of the grid.
+    pub fn connect(&mut self, from: (usize, usize), to: (usize, usize)) -> &mut Grid {
+        self.add_connection(from, to, true, None)
+    }
+
+    /// Draw a labelled arrow from one cell to another
+    pub fn connect_labelled(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        label: &str,
+    ) -> &mut Grid {
+        self.add_connection(from, to, true, Some(label))
+    }
+
+    /// Draw a plain line (with no arrowhead) between two cells
+    pub fn link(&mut self, from: (usize, usize), to: (usize, usize)) -> &mut Grid {
+        self.add_connection(from, to, false, None)
+    }
+
+    /// The width of each cell, after widening to fit the labels
+    fn effective_width(&self) -> f64 {
+        self.labels
+            .iter()
+            .flatten()
+            .map(|l| l.chars().count() as f64 * CHAR_WIDTH + 0.2)
+            .fold(self.cell_width, f64::max)
+    }
+
+    /// Generate the pikchr source for the grid
+    ///
+    /// ```
+    /// # use pikchr::grid::Grid;
+    /// let source = Grid::new(1, 2).cell(0, 0, "A").connect((0, 0), (0, 1)).to_source();
+    /// assert!(source.contains("R1C1: box \"A\""));
+    /// assert!(source.contains("arrow from R1C1 to R1C2 chop"));
+    /// ```
+    pub fn to_source(&self) -> String {
+        let width = self.effective_width();
+        let mut out = String::new();
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let label = &self.labels[row * self.cols + col];
+                write!(out, "R{}C{}: box", row + 1, col + 1).unwrap();
+                if let Some(label) = label {
+                    write!(out, " {}", quote(label)).unwrap();
+                } else if self.hide_empty {
+                    out.push_str(" invis");
+                }
+                writeln!(
+                    out,
+                    " wid {:.3} ht {:.3} at ({:.3}, {:.3})",
+                    width,
+                    self.cell_height,
+                    col as f64 * (width + self.gap),
+                    0.0 - row as f64 * (self.cell_height + self.gap)
+                )
+                .unwrap();
+            }
+        }
+        for conn in &self.connections {
+            write!(
+                out,
+                "{} from R{}C{} to R{}C{} chop",
+                if conn.arrow { "arrow" } else { "line" },
+                conn.from.0 + 1,
+                conn.from.1 + 1,
+                conn.to.0 + 1,
+                conn.to.1 + 1
+            )
+            .unwrap();
+            if let Some(label) = &conn.label {
+                let placement = if conn.from.0 == conn.to.0 {
+                    "above"
+                } else {
+                    "ljust"
+                };
+                write!(out, " {} {}", quote(label), placement).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Render the grid
+    ///
+    /// This is a convenience wrapper which passes the result of
+    /// [`Grid::to_source`] to [`Pikchr::render`].
+    pub fn render(&self, class: Option<&str>, flags: PikchrFlags) -> Result<Pikchr, PikchrError> {
+        Pikchr::render(&self.to_source(), class, flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_are_placed_on_a_regular_grid() {
+        let mut grid = Grid::new(2, 3);
+        grid.cell_size(1.0, 0.5).gap(0.5);
+        let source = grid.to_source();
+        assert!(source.contains("R1C1: box wid 1.000 ht 0.500 at (0.000, 0.000)"));
+        assert!(source.contains("R1C3: box wid 1.000 ht 0.500 at (3.000, 0.000)"));
+        assert!(source.contains("R2C2: box wid 1.000 ht 0.500 at (1.500, -1.000)"));
+    }
+
+    #[test]
+    fn long_labels_widen_every_cell() {
+        let mut grid = Grid::new(1, 2);
+        grid.cell(0, 0, "a very long label indeed");
+        assert!(grid.effective_width() > 0.75);
+        assert!(!grid.to_source().contains("wid 0.750"));
+    }
+
+    #[test]
+    fn connectors_render() {
+        let mut grid = Grid::new(3, 3);
+        grid.hide_empty()
+            .cell(0, 0, "A")
+            .cell(2, 2, "B")
+            .connect_labelled((0, 0), (0, 2), "across")
+            .connect_labelled((0, 2), (2, 2), "down")
+            .link((2, 2), (0, 0));
+        let source = grid.to_source();
+        if let Err(e) = grid.render(None, PikchrFlags::default()) {
+            panic!("{}\n{}", source, e);
+        }
+        assert_eq!(source.matches(" invis ").count(), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of a 2x2 grid")]
+    fn out_of_range_cells_panic() {
+        Grid::new(2, 2).cell(2, 0, "nope");
+    }
+}
@@ -0,0 +1,268 @@
+//! Stable digests of diagrams
+//!
+//! A cache of rendered diagrams, an HTTP `ETag` or the name of a file
+//! which a diagram is saved to needs a key which is the same on every run
+//! and every platform.  There are two: [`hash_source`] digests what goes
+//! into rendering, so a cache can be checked without rendering at all,
+//! and [`Pikchr::content_hash`] digests the rendered diagram itself, so
+//! that sources which draw the same thing share a key.
+//!
+//! ```
+//! use pikchr::{hash::hash_source, Pikchr, PikchrFlags};
+//!
+//! let flags = PikchrFlags::default();
+//! let key = hash_source("box", None, flags);
+//! assert_eq!(key, hash_source("box", None, flags));
+//! assert_ne!(key, hash_source("box", Some("diagram"), flags));
+//! assert_eq!(key.to_string().len(), 64);
+//!
+//! let one = Pikchr::render("box", None, flags).unwrap();
+//! let two = Pikchr::render("box # the same box", None, flags).unwrap();
+//! assert_eq!(one.content_hash().unwrap(), two.content_hash().unwrap());
+//! ```
+//!
+//! Both are SHA-256 digests.  The digest of a source includes the version
+//! of this crate, the backend behind [`Pikchr::render`] and whether the
+//! `unicode-metrics` feature is on, as any of them may change how it is
+//! rendered.
+//!
+//! [`Pikchr::content_hash`]: crate::Pikchr::content_hash
+//! [`Pikchr::render`]: crate::Pikchr::render
+
+use crate::svg::{Dom, Node};
+use crate::{Backend, PikchrFlags};
+use core::ffi::c_uint;
+use std::fmt;
+
+/// A SHA-256 digest, which displays as 64 lowercase hex digits
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    /// The bytes of the digest
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The SHA-256 digest of some bytes
+pub(crate) fn sha256(data: &[u8]) -> Digest {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    Digest(digest)
+}
+
+/// Add a field to a message, prefixed by its length so that fields cannot
+/// run into each other
+fn field(message: &mut Vec<u8>, bytes: &[u8]) {
+    message.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+    message.extend_from_slice(bytes);
+}
+
+/// Digest pikchr source and the options it is rendered with
+///
+/// Sources which differ only in comments or spacing have different
+/// digests; use [`Pikchr::content_hash`](crate::Pikchr::content_hash) to
+/// digest what was drawn.
+pub fn hash_source(source: &str, class: Option<&str>, flags: PikchrFlags) -> Digest {
+    let renderer = Renderer {
+        backend: crate::version().backend,
+        unicode_metrics: cfg!(feature = "unicode-metrics"),
+    };
+    hash_rendering(source, class, flags, renderer)
+}
+
+/// What a source is rendered by, besides the version of the crate
+#[derive(Copy, Clone)]
+struct Renderer {
+    backend: Backend,
+    unicode_metrics: bool,
+}
+
+/// Digest pikchr source as rendered by a given renderer
+fn hash_rendering(
+    source: &str,
+    class: Option<&str>,
+    flags: PikchrFlags,
+    renderer: Renderer,
+) -> Digest {
+    let mut message = Vec::with_capacity(source.len() + 64);
+    field(&mut message, b"pikchr source");
+    field(&mut message, env!("CARGO_PKG_VERSION").as_bytes());
+    message.push(match renderer.backend {
+        Backend::C => b'c',
+        Backend::Rust => b'r',
+    });
+    message.push(u8::from(renderer.unicode_metrics));
+    field(&mut message, source.as_bytes());
+    match class {
+        Some(class) => {
+            message.push(1);
+            field(&mut message, class.as_bytes());
+        }
+        None => message.push(0),
+    }
+    message.extend_from_slice(&c_uint::from(flags).to_be_bytes());
+    sha256(&message)
+}
+
+/// Remove comments and the whitespace between tags, which pikchr does not
+/// always write the same way
+pub(crate) fn canonicalize(nodes: &mut Vec<Node>) {
+    nodes.retain(|node| match node {
+        Node::Comment(_) => false,
+        Node::Text(text) => !text.trim().is_empty(),
+        Node::Element(_) => true,
+    });
+    for node in nodes.iter_mut() {
+        if let Node::Element(element) = node {
+            if element.name != "text" {
+                canonicalize(&mut element.children);
+            }
+        }
+    }
+}
+
+/// Digest a rendered diagram
+pub(crate) fn hash_output(mut dom: Dom) -> Digest {
+    canonicalize(&mut dom.nodes);
+    let mut message = Vec::new();
+    field(&mut message, b"pikchr output");
+    field(&mut message, dom.to_string().as_bytes());
+    sha256(&message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_known_values() {
+        assert_eq!(
+            sha256(b"").to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc").to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").to_string(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn ignores_layout_of_markup() {
+        let one = Dom::parse("<svg>\n  <path d=\"M0,0\"/>\n<!-- a box --></svg>\n").unwrap();
+        let two = Dom::parse("<svg><path d='M0,0'/></svg>").unwrap();
+        assert_eq!(hash_output(one), hash_output(two));
+        // Text inside <text> is kept exactly
+        let one = Dom::parse("<svg><text> a</text></svg>").unwrap();
+        let two = Dom::parse("<svg><text>a</text></svg>").unwrap();
+        assert_ne!(hash_output(one), hash_output(two));
+    }
+
+    #[test]
+    fn keys_sources_by_renderer() {
+        let flags = PikchrFlags::default();
+        let key = |backend, unicode_metrics| {
+            let renderer = Renderer {
+                backend,
+                unicode_metrics,
+            };
+            hash_rendering("box", None, flags, renderer)
+        };
+        let keys = [
+            key(Backend::C, false),
+            key(Backend::C, true),
+            key(Backend::Rust, false),
+            key(Backend::Rust, true),
+        ];
+        for (i, one) in keys.iter().enumerate() {
+            for two in &keys[i + 1..] {
+                assert_ne!(one, two);
+            }
+        }
+        let here = if cfg!(pikchr_c) {
+            Backend::C
+        } else {
+            Backend::Rust
+        };
+        assert_eq!(
+            hash_source("box", None, flags),
+            key(here, cfg!(feature = "unicode-metrics"))
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Serving rendered diagrams over HTTP
+//!
+//! A server which renders diagrams on request should let browsers and
+//! proxies cache them.  [`Pikchr::http_headers`] gives the values of the
+//! `Content-Type`, `ETag` and `Cache-Control` headers for a diagram, and
+//! [`Headers::not_modified`] checks a request's `If-None-Match` header, so
+//! that a server can answer `304 Not Modified` instead of sending the
+//! diagram again.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box", None, PikchrFlags::default()).unwrap();
+//! let headers = pic.http_headers(false).unwrap();
+//! assert_eq!(headers.content_type(), "image/svg+xml");
+//! assert_eq!(headers.cache_control(), "no-cache");
+//! let etag = headers.etag().to_string();
+//! assert!(headers.not_modified(&format!("W/{}, \"other\"", etag)));
+//! assert!(!headers.not_modified("\"other\""));
+//! ```
+//!
+//! The `ETag` is the [`content_hash`](crate::Pikchr::content_hash) of the
+//! diagram, so it is the same on every server and after restarts.  By
+//! default caches must check with the server before each use; a diagram
+//! served at a URL which already identifies its content, such as one with
+//! the hash in it, can instead be cached for good.
+//!
+//! A server can also offer a diagram as JSON, with the error and the
+//! [`lint`](crate::lint) diagnostics for its source, for editors which show
+//! them alongside the picture.  [`negotiate`] picks the [`Format`] which a
+//! request's `Accept` header prefers, and [`json_body`] builds the JSON.
+//!
+//! ```
+//! use pikchr::http::{self, Format};
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let accept = "application/json, image/svg+xml;q=0.5";
+//! assert_eq!(http::negotiate(Some(accept)), Some(Format::Json));
+//! let source = "gap = 1\nbox";
+//! let body = http::json_body(source, &Pikchr::render(source, None, PikchrFlags::default()));
+//! assert!(body.starts_with("{\"svg\":\"<svg "));
+//! assert!(body.contains("\"rule\":\"unused-variable\""));
+//! ```
+//!
+//! [`Pikchr::http_headers`]: crate::Pikchr::http_headers
+
+use crate::lint::{lint, LintConfig};
+use crate::{Pikchr, PikchrError};
+use std::fmt::Write;
+
+/// The headers for serving a rendered diagram
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Headers {
+    etag: String,
+    cache_control: &'static str,
+}
+
+impl Headers {
+    /// The headers for a diagram with a digest, which can be cached for
+    /// good if it is `immutable`
+    pub(crate) fn new(digest: crate::hash::Digest, immutable: bool) -> Headers {
+        Headers {
+            etag: format!("\"{}\"", digest),
+            cache_control: if immutable {
+                "public, max-age=31536000, immutable"
+            } else {
+                "no-cache"
+            },
+        }
+    }
+
+    /// The value of the `Content-Type` header
+    pub fn content_type(&self) -> &'static str {
+        "image/svg+xml"
+    }
+
+    /// The value of the `ETag` header, a strong entity tag in quotes
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// The value of the `Cache-Control` header
+    pub fn cache_control(&self) -> &str {
+        self.cache_control
+    }
+
+    /// Whether a request with an `If-None-Match` header of some value can
+    /// be answered with `304 Not Modified`
+    ///
+    /// Entity tags are compared weakly, as RFC 9110 requires for
+    /// `If-None-Match`, so `W/"..."` matches too, as does `*`.
+    pub fn not_modified(&self, if_none_match: &str) -> bool {
+        if if_none_match.trim() == "*" {
+            return true;
+        }
+        entity_tags(if_none_match).any(|tag| tag == self.etag)
+    }
+}
+
+/// The forms in which a server can send a diagram
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The SVG of the diagram
+    Svg,
+    /// The SVG with its size, any error and the diagnostics for its
+    /// source, as built by [`json_body`]
+    Json,
+}
+
+impl Format {
+    /// Every format, in the order a server prefers them
+    pub const ALL: &'static [Format] = &[Format::Svg, Format::Json];
+
+    /// The value of the `Content-Type` header
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Svg => "image/svg+xml",
+            Format::Json => "application/json",
+        }
+    }
+}
+
+/// The quality which an `Accept` header gives a media type, taken from the
+/// most specific range which matches it, or zero if none does
+fn quality(accept: &str, content_type: &str) -> f64 {
+    let kind = content_type.split('/').next().unwrap_or("");
+    let mut best = (0, 0.0);
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let specificity = if media == content_type {
+            3
+        } else if media.strip_suffix("/*") == Some(kind) {
+            2
+        } else if media == "*/*" {
+            1
+        } else {
+            continue;
+        };
+        let q = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f64>().ok())
+            .unwrap_or(1.0);
+        if specificity > best.0 {
+            best = (specificity, q);
+        }
+    }
+    best.1
+}
+
+/// Choose the format to answer a request with, from its `Accept` header
+///
+/// A request without the header gets SVG.  This returns `None` if the
+/// header accepts none of the formats, which a server can answer with
+/// `406 Not Acceptable`.
+pub fn negotiate(accept: Option<&str>) -> Option<Format> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return Some(Format::Svg),
+    };
+    let mut best = None;
+    for &format in Format::ALL {
+        let q = quality(accept, format.content_type());
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((format, q));
+        }
+    }
+    best.map(|(format, _)| format)
+}
+
+/// Quote a string for JSON
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The JSON for a render of some source
+///
+/// The JSON has `svg`, `width`, `height` and `error` members as the
+/// JavaScript wrapper's results do, which are `null` when they do not
+/// apply, and a `diagnostics` array of the source's lint diagnostics with
+/// the default configuration.  Each diagnostic has `severity`, `rule` and
+/// `message` members, and the `start` and `end` of its span in bytes.
+pub fn json_body(source: &str, result: &Result<Pikchr, PikchrError>) -> String {
+    let mut out = match result {
+        Ok(pic) => format!(
+            "{{\"svg\":{},\"width\":{},\"height\":{},\"error\":null",
+            json_string(pic),
+            pic.width(),
+            pic.height()
+        ),
+        Err(error) => format!(
+            "{{\"svg\":null,\"width\":null,\"height\":null,\"error\":{}",
+            json_string(error.raw())
+        ),
+    };
+    out.push_str(",\"diagnostics\":[");
+    for (i, diagnostic) in lint(source, &LintConfig::default()).iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(
+            out,
+            "{{\"severity\":\"{}\",\"rule\":\"{}\",\"message\":{},\"start\":{},\"end\":{}}}",
+            diagnostic.severity,
+            diagnostic.rule,
+            json_string(&diagnostic.message),
+            diagnostic.span.start,
+            diagnostic.span.end
+        );
+    }
+    out.push_str("]}");
+    out
+}
+
+/// The entity tags in a list, without any `W/`, stopping at anything
+/// malformed
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        let tag = rest.strip_prefix("W/").unwrap_or(rest);
+        let end = tag.strip_prefix('"')?.find('"')? + 2;
+        rest = &tag[end..];
+        Some(&tag[..end])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_entity_tags() {
+        let tags: Vec<_> = entity_tags(" \"a\",W/\"b,c\" ,  \"\"").collect();
+        assert_eq!(tags, ["\"a\"", "\"b,c\"", "\"\""]);
+        let tags: Vec<_> = entity_tags("\"a\", b, \"c\"").collect();
+        assert_eq!(tags, ["\"a\""]);
+        assert_eq!(entity_tags("\"a").count(), 0);
+    }
+
+    #[test]
+    fn checks_if_none_match() {
+        let pic = crate::Pikchr::render("circle", None, crate::PikchrFlags::default()).unwrap();
+        let headers = pic.http_headers(true).unwrap();
+        assert_eq!(
+            headers.cache_control(),
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(headers.etag().len(), 66);
+        assert!(headers.not_modified(headers.etag()));
+        assert!(headers.not_modified(" * "));
+        assert!(!headers.not_modified(""));
+        assert!(!headers.not_modified(&headers.etag()[1..65]));
+    }
+
+    #[test]
+    fn negotiates_formats() {
+        assert_eq!(negotiate(None), Some(Format::Svg));
+        assert_eq!(negotiate(Some("*/*")), Some(Format::Svg));
+        assert_eq!(negotiate(Some("application/*")), Some(Format::Json));
+        assert_eq!(
+            negotiate(Some("image/*;q=0.4, application/json;q=0.8")),
+            Some(Format::Json)
+        );
+        // The most specific range decides
+        assert_eq!(
+            negotiate(Some("*/*, image/svg+xml;q=0")),
+            Some(Format::Json)
+        );
+        assert_eq!(negotiate(Some("image/png")), None);
+    }
+
+    #[test]
+    fn builds_json_bodies() {
+        let result = Pikchr::render("box wid", None, crate::PikchrFlags::default());
+        let body = json_body("box wid", &result);
+        assert!(body.starts_with("{\"svg\":null,\"width\":null,\"height\":null,\"error\":\""));
+        assert!(body.contains("\"diagnostics\":[{\"severity\":\"error\",\"rule\":\"syntax\","));
+        assert!(body.ends_with("}]}"));
+    }
+}
@@ -0,0 +1,1061 @@
+//! Completions and signature help for editors
+//!
+//! [`completions`] suggests what could come next at a point in some pikchr
+//! source, and [`signature_help`] describes the function or macro call
+//! which the point is in.  Both work from the tokens of the source, so
+//! they are useful while the source is being typed and does not parse.
+//! [`rename`] changes the name of a label or variable everywhere it is
+//! used, and [`folding_ranges`] and [`symbols`] describe the structure of
+//! the source for navigating it.
+//!
+//! ```
+//! use pikchr::ide::{completions, CompletionKind};
+//!
+//! let source = "A: box\nB: circle\narrow from ";
+//! let labels: Vec<_> = completions(source, source.len())
+//!     .into_iter()
+//!     .filter(|c| c.kind == CompletionKind::Label)
+//!     .map(|c| c.label)
+//!     .collect();
+//! assert_eq!(labels, ["A", "B"]);
+//! ```
+
+use crate::ast::{
+    self, tokenize, ObjectKind, Span, Statement, StatementKind, Token, TokenKind, CLASSES,
+};
+use crate::semantic::{builtin, Model, Target, BUILTINS, COLORS};
+
+/// What sort of thing a [`Completion`] is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CompletionKind {
+    /// A keyword which starts a statement, such as `print` or `down`
+    Keyword,
+    /// An object class, such as `box`
+    Class,
+    /// An attribute of an object, such as `wid` or `->`
+    Attribute,
+    /// A point on an object, such as `ne` in `A.ne`
+    Edge,
+    /// A property of an object, such as `wid` in `A.wid`
+    Property,
+    /// The label of an object
+    Label,
+    /// A variable, either built in or assigned in the source
+    Variable,
+    /// A named colour
+    Color,
+    /// A built-in function
+    Function,
+    /// A macro defined in the source
+    Macro,
+}
+
+/// A suggestion for what could come next in the source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    /// The text to insert
+    pub label: String,
+    /// What sort of thing this is
+    pub kind: CompletionKind,
+    /// A short description, such as the class of a labelled object or the
+    /// default value of a variable
+    pub detail: Option<String>,
+    /// The source which the completion replaces, which is the partly typed
+    /// word at the point, if any
+    pub span: Span,
+}
+
+/// A description of a function or macro call
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureHelp {
+    /// The signature, such as `max(x, y)`
+    pub label: String,
+    /// The parameters in the signature
+    pub parameters: Vec<String>,
+    /// The parameter which the point is in
+    pub active_parameter: usize,
+}
+
+/// Suggest what could come next at a byte offset in the source
+///
+/// The suggestions depend on where the offset is.  At the start of a
+/// statement they are object classes, keywords, variables and macros;
+/// after an object class they are the attributes which suit that class;
+/// after `.` they are the edges and properties of an object; and where a
+/// position is expected they are the labels of the objects which can be
+/// referred to there.  Only suggestions which start with any partly typed
+/// word at the offset are returned.
+///
+/// ```
+/// use pikchr::ide::completions;
+///
+/// let labels = |source: &str| -> Vec<String> {
+///     completions(source, source.len()).into_iter().map(|c| c.label).collect()
+/// };
+/// assert_eq!(labels("circle fill Bl"), ["Black", "BlanchedAlmond", "Blue", "BlueViolet"]);
+/// assert!(labels("arrow ").contains(&"chop".to_string()));
+/// assert!(!labels("box ").contains(&"chop".to_string()));
+/// ```
+///
+/// # Panics
+///
+/// Panics if the offset is not on a character boundary of the source.
+pub fn completions(source: &str, offset: usize) -> Vec<Completion> {
+    let tokens = tokenize(source);
+    let word = match tokens
+        .iter()
+        .find(|t| t.span.start < offset && offset <= t.span.end)
+    {
+        Some(t) if matches!(t.kind, TokenKind::String | TokenKind::Comment) => return Vec::new(),
+        Some(t) if t.kind == TokenKind::CodeBlock => return Vec::new(),
+        Some(t) if t.text(source).starts_with(is_word) => t.span,
+        _ => Span::new(offset, offset),
+    };
+    let before: Vec<&Token> = tokens
+        .iter()
+        .filter(|t| t.span.end <= word.start && !t.kind.is_trivia())
+        .collect();
+    let scope = Scope::new(source, &before);
+    let mut suggestions = Suggestions {
+        prefix: Span::new(word.start, offset).text(source),
+        span: word,
+        items: Vec::new(),
+    };
+    let text = |i: usize| before.len().checked_sub(i).map(|i| before[i].text(source));
+    let kind = |i: usize| before.len().checked_sub(i).map(|i| before[i].kind);
+
+    if text(1) == Some(".") {
+        if kind(2) != Some(TokenKind::With) {
+            for name in PROPERTIES {
+                suggestions.add(name, CompletionKind::Property, None);
+            }
+            if kind(2) == Some(TokenKind::PlaceName) {
+                let parent = text(2).unwrap_or_default();
+                for (label, class) in scope.children(parent) {
+                    suggestions.add(&label, CompletionKind::Label, class);
+                }
+            }
+        }
+        for name in EDGES {
+            suggestions.add(name, CompletionKind::Edge, None);
+        }
+        return suggestions.items;
+    }
+
+    let statement = before
+        .iter()
+        .rposition(|t| matches!(t.kind, TokenKind::Eol | TokenKind::LBracket))
+        .map_or(0, |i| i + 1);
+    let mut head = &before[statement..];
+    if let [label, colon, rest @ ..] = head {
+        if label.kind == TokenKind::PlaceName && colon.kind == TokenKind::Colon {
+            head = rest;
+        }
+    }
+    match kind(1) {
+        None | Some(TokenKind::Eol) | Some(TokenKind::LBracket) => {
+            for name in CLASSES {
+                suggestions.add(name, CompletionKind::Class, None);
+            }
+            for name in STATEMENT_KEYWORDS {
+                suggestions.add(name, CompletionKind::Keyword, None);
+            }
+            scope.variables(&mut suggestions);
+            scope.macros(&mut suggestions);
+        }
+        Some(TokenKind::Colon) => {
+            for name in CLASSES {
+                suggestions.add(name, CompletionKind::Class, None);
+            }
+            scope.labels(&mut suggestions);
+        }
+        Some(TokenKind::Nth) | Some(TokenKind::Last) => {
+            for name in CLASSES {
+                suggestions.add(name, CompletionKind::Class, None);
+            }
+            if kind(1) == Some(TokenKind::Nth) {
+                suggestions.add("vertex", CompletionKind::Keyword, None);
+            }
+        }
+        Some(TokenKind::Until) => suggestions.add("even", CompletionKind::Keyword, None),
+        Some(TokenKind::Even) => suggestions.add("with", CompletionKind::Keyword, None),
+        Some(TokenKind::With) if kind(2) != Some(TokenKind::Even) => {
+            for name in EDGES {
+                suggestions.add(&format!(".{}", name), CompletionKind::Edge, None);
+            }
+        }
+        Some(TokenKind::At)
+        | Some(TokenKind::From)
+        | Some(TokenKind::To)
+        | Some(TokenKind::With)
+        | Some(TokenKind::Of)
+        | Some(TokenKind::In)
+        | Some(TokenKind::Behind)
+        | Some(TokenKind::As)
+        | Some(TokenKind::Between)
+        | Some(TokenKind::And)
+        | Some(TokenKind::Lt) => {
+            scope.labels(&mut suggestions);
+            for name in REFERENCES {
+                suggestions.add(name, CompletionKind::Keyword, None);
+            }
+        }
+        Some(TokenKind::Fill) | Some(TokenKind::Color) if head.len() > 1 => {
+            for (name, rgb) in COLORS {
+                suggestions.add(name, CompletionKind::Color, Some(format!("#{:06x}", rgb)));
+            }
+            scope.variables(&mut suggestions);
+        }
+        Some(TokenKind::Assign)
+        | Some(TokenKind::Plus)
+        | Some(TokenKind::Minus)
+        | Some(TokenKind::Star)
+        | Some(TokenKind::Slash)
+        | Some(TokenKind::LParen)
+        | Some(TokenKind::Comma)
+        | Some(TokenKind::Eq)
+        | Some(TokenKind::Print)
+        | Some(TokenKind::Height)
+        | Some(TokenKind::Width)
+        | Some(TokenKind::Radius)
+        | Some(TokenKind::Diameter)
+        | Some(TokenKind::Thickness)
+        | Some(TokenKind::Heading) => {
+            scope.variables(&mut suggestions);
+            for (name, signature) in FUNCTIONS {
+                suggestions.add(name, CompletionKind::Function, Some(signature.to_string()));
+            }
+            scope.labels(&mut suggestions);
+        }
+        _ => {
+            let class = match head.first() {
+                Some(t) if t.kind == TokenKind::ClassName => t.text(source),
+                Some(t) if t.kind == TokenKind::String => "text",
+                Some(t) if t.kind == TokenKind::RBracket || t.kind == TokenKind::LBracket => "[]",
+                _ => "",
+            };
+            if !class.is_empty() {
+                if kind(1) == Some(TokenKind::String)
+                    || TEXT_ATTRIBUTES.contains(&text(1).unwrap_or_default())
+                {
+                    for name in TEXT_ATTRIBUTES {
+                        suggestions.add(name, CompletionKind::Attribute, None);
+                    }
+                }
+                attributes(class, &mut suggestions);
+                scope.macros(&mut suggestions);
+            }
+        }
+    }
+    suggestions.items
+}
+
+/// Describe the function or macro call at a byte offset in the source
+///
+/// ```
+/// use pikchr::ide::signature_help;
+///
+/// let help = signature_help("box wid max(1, ", 15).unwrap();
+/// assert_eq!(help.label, "max(x, y)");
+/// assert_eq!(help.active_parameter, 1);
+/// assert_eq!(signature_help("box at (1, ", 11), None);
+/// ```
+pub fn signature_help(source: &str, offset: usize) -> Option<SignatureHelp> {
+    let tokens = tokenize(source);
+    let before: Vec<&Token> = tokens
+        .iter()
+        .filter(|t| t.span.end <= offset && !t.kind.is_trivia())
+        .collect();
+    let scope = Scope::new(source, &before);
+    // The token before each open `(`, and the number of commas since it
+    let mut calls: Vec<(Option<&Token>, usize)> = Vec::new();
+    for (i, token) in before.iter().enumerate() {
+        match token.kind {
+            TokenKind::LParen => calls.push((i.checked_sub(1).map(|i| before[i]), 0)),
+            TokenKind::RParen => {
+                calls.pop();
+            }
+            TokenKind::Comma | TokenKind::Eq => {
+                if let Some((_, commas)) = calls.last_mut() {
+                    *commas += 1;
+                }
+            }
+            TokenKind::Eol => calls.clear(),
+            _ => {}
+        }
+    }
+    let (name, active_parameter) = calls.pop()?;
+    let name = name?;
+    let text = name.text(source);
+    let parameters: Vec<String> = match name.kind {
+        TokenKind::Func1 => vec!["x".to_string()],
+        TokenKind::Func2 => vec!["x".to_string(), "y".to_string()],
+        TokenKind::Dist => vec!["A".to_string(), "B".to_string()],
+        TokenKind::Assert => vec!["x".to_string(), "y".to_string()],
+        TokenKind::Id | TokenKind::PlaceName => {
+            let count = scope.macro_parameters(text)?;
+            (1..=count).map(|i| format!("${}", i)).collect()
+        }
+        _ => return None,
+    };
+    let separator = if name.kind == TokenKind::Assert {
+        " == "
+    } else {
+        ", "
+    };
+    Some(SignatureHelp {
+        label: format!("{}({})", text, parameters.join(separator)),
+        active_parameter: active_parameter.min(parameters.len().saturating_sub(1)),
+        parameters,
+    })
+}
+
+/// A change to the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextEdit {
+    /// The source to replace
+    pub span: Span,
+    /// The text to replace it with
+    pub replacement: String,
+}
+
+/// Rename the label or variable at a byte offset in the source
+///
+/// The offset can be in the definition of the name or in any reference to
+/// it.  Only the references which resolve to the same object or variable
+/// are changed, so another object with the same label, or a reference
+/// which is resolved before the label is defined, is left alone.  The
+/// edits are in source order.
+///
+/// ```
+/// use pikchr::ide::rename;
+///
+/// let source = "A: box\narrow from A.e right\nA: circle\nline from A.s";
+/// let edits = rename(source, 0, "Start").unwrap();
+/// let spans: Vec<_> = edits.iter().map(|e| e.span.start).collect();
+/// assert_eq!(spans, [0, 18]);
+/// assert!(rename(source, 0, "lower").is_err());
+/// ```
+///
+/// # Errors
+///
+/// It is an error if the source does not parse, if there is no label or
+/// variable at the offset, if the name is built in, or if the new name is
+/// not valid for the label or variable or is already in use.
+pub fn rename(source: &str, offset: usize, new_name: &str) -> Result<Vec<TextEdit>, String> {
+    let document = ast::parse(source).map_err(|e| e.to_string())?;
+    let model = Model::new(&document);
+    let reference = model
+        .reference_at(offset)
+        .ok_or_else(|| "there is no name to rename here".to_string())?;
+    let old_name = match &reference.target {
+        Target::Object(id) => match &model.object(*id).label {
+            Some(label) => label.text.as_str(),
+            None => return Err("only labelled objects can be renamed".to_string()),
+        },
+        Target::Variable(name) if builtin(name).is_some() => {
+            return Err(format!("`{}` is a built-in variable", name));
+        }
+        Target::Variable(name) => name.as_str(),
+        _ => return Err("there is no name to rename here".to_string()),
+    };
+    if new_name == old_name {
+        return Ok(Vec::new());
+    }
+    let expected = match reference.target {
+        Target::Object(_) => TokenKind::PlaceName,
+        _ => TokenKind::Id,
+    };
+    let valid = match tokenize(new_name).as_slice() {
+        [token] => token.kind == expected,
+        _ => false,
+    };
+    if !valid {
+        let what = match expected {
+            TokenKind::PlaceName => "label",
+            _ => "variable",
+        };
+        return Err(format!("`{}` is not a valid {} name", new_name, what));
+    }
+    let in_use = match reference.target {
+        Target::Object(_) => model
+            .objects()
+            .iter()
+            .any(|o| matches!(&o.label, Some(label) if label.text == new_name)),
+        _ => {
+            builtin(new_name).is_some()
+                || model
+                    .references()
+                    .iter()
+                    .any(|r| r.target == Target::Variable(new_name.to_string()))
+        }
+    };
+    if in_use {
+        return Err(format!("`{}` is already in use", new_name));
+    }
+    let mut edits: Vec<TextEdit> = model
+        .references_to(&reference.target)
+        .filter(|r| r.span.text(source) == old_name)
+        .map(|r| TextEdit {
+            span: r.span,
+            replacement: new_name.to_string(),
+        })
+        .collect();
+    // A name in the body of a macro is found once for each expansion
+    edits.dedup();
+    Ok(edits)
+}
+
+/// What sort of source a [`FoldingRange`] covers
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FoldingKind {
+    /// A top-level statement
+    Statement,
+    /// The body of a macro definition
+    Macro,
+    /// A `[...]` sublist
+    Sublist,
+}
+
+/// A part of the source which an editor can fold away
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FoldingRange {
+    /// The source to fold
+    pub span: Span,
+    /// What the source is
+    pub kind: FoldingKind,
+}
+
+/// Find the parts of the source which an editor can fold away
+///
+/// These are the top-level statements, macro bodies and sublists which
+/// span more than one line, in the order they start.  Sublists are found
+/// at any depth, and a statement which is only a sublist is reported as
+/// the sublist.
+///
+/// ```
+/// use pikchr::ide::{folding_ranges, FoldingKind};
+///
+/// let source = "box\n[\n  circle\n  arrow\n]\ndefine row {\n  box; box\n}";
+/// let kinds: Vec<_> = folding_ranges(source).into_iter().map(|r| r.kind).collect();
+/// assert_eq!(kinds, [FoldingKind::Sublist, FoldingKind::Macro]);
+/// ```
+pub fn folding_ranges(source: &str) -> Vec<FoldingRange> {
+    let (document, _) = ast::parse_recovering(source);
+    let mut ranges = Vec::new();
+    let mut add = |span: Span, kind| {
+        if span.text(source).trim_end().contains('\n') {
+            ranges.push(FoldingRange { span, kind });
+        }
+    };
+    for statement in &document.statements {
+        if statement.macro_call.is_some() {
+            continue;
+        }
+        match &statement.kind {
+            StatementKind::Define { body, .. } => add(*body, FoldingKind::Macro),
+            StatementKind::Object(object) if matches!(object.kind, ObjectKind::Sublist(_)) => {}
+            _ => add(statement.span, FoldingKind::Statement),
+        }
+        walk_sublists(std::slice::from_ref(statement), &mut |object, _| {
+            add(object.span, FoldingKind::Sublist)
+        });
+    }
+    ranges.sort_by_key(|r| (r.span.start, std::cmp::Reverse(r.span.end)));
+    ranges
+}
+
+/// Call a function with every sublist in some statements, outermost first
+fn walk_sublists<'a>(
+    statements: &'a [Statement],
+    f: &mut dyn FnMut(&'a ast::Object, &'a [Statement]),
+) {
+    for statement in statements {
+        if let StatementKind::Object(object) = &statement.kind {
+            if let ObjectKind::Sublist(inner) = &object.kind {
+                f(object, inner);
+                walk_sublists(inner, f);
+            }
+        }
+    }
+}
+
+/// What sort of thing a [`Symbol`] is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// An object, which is labelled unless it is a sublist
+    Object,
+    /// A named place, such as `P: (1, 2)`
+    Place,
+    /// A variable
+    Variable,
+    /// A macro
+    Macro,
+}
+
+/// A named part of the source, for an outline of it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    /// The name of the symbol, or the class of an unlabelled sublist
+    pub name: String,
+    /// What sort of thing the symbol is
+    pub kind: SymbolKind,
+    /// The class of an object, or the value first assigned to a variable
+    pub detail: Option<String>,
+    /// The statement which defines the symbol
+    pub span: Span,
+    /// The name in that statement, or the whole statement if the symbol
+    /// is an unlabelled sublist
+    pub selection: Span,
+    /// The symbols inside a sublist
+    pub children: Vec<Symbol>,
+}
+
+/// Make an outline of the source
+///
+/// The outline has the labelled objects and places, every sublist, the
+/// first assignment to each variable and the macro definitions, in source
+/// order.  The symbols inside a sublist are its children.
+///
+/// ```
+/// use pikchr::ide::symbols;
+///
+/// let outline = symbols("gap = 0.5\nA: box\n[ B: circle; line ]\ncircle");
+/// let names: Vec<_> = outline.iter().map(|s| s.name.as_str()).collect();
+/// assert_eq!(names, ["gap", "A", "[]"]);
+/// assert_eq!(outline[2].children[0].name, "B");
+/// ```
+pub fn symbols(source: &str) -> Vec<Symbol> {
+    let (document, _) = ast::parse_recovering(source);
+    let mut variables = Vec::new();
+    outline(source, &document.statements, &mut variables)
+}
+
+fn outline<'a>(
+    source: &str,
+    statements: &'a [Statement],
+    variables: &mut Vec<&'a str>,
+) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for statement in statements {
+        if statement.macro_call.is_some() {
+            continue;
+        }
+        let (name, kind, detail, selection, children) = match (&statement.kind, &statement.label) {
+            (StatementKind::Object(object), label) => {
+                let children = match &object.kind {
+                    ObjectKind::Sublist(inner) => outline(source, inner, variables),
+                    _ if label.is_none() => continue,
+                    _ => Vec::new(),
+                };
+                let (name, selection) = match label {
+                    Some(label) => (label.text.clone(), label.span),
+                    None => (object.class_name().to_string(), statement.span),
+                };
+                let detail = Some(object.class_name().to_string());
+                (name, SymbolKind::Object, detail, selection, children)
+            }
+            (StatementKind::Place(_), Some(label)) => {
+                let name = label.text.clone();
+                (name, SymbolKind::Place, None, label.span, Vec::new())
+            }
+            (
+                StatementKind::Assignment {
+                    variable, value, ..
+                },
+                _,
+            ) => {
+                if variables.contains(&variable.text.as_str()) {
+                    continue;
+                }
+                variables.push(&variable.text);
+                let detail = Some(value.span.text(source).to_string());
+                let name = variable.text.clone();
+                (
+                    name,
+                    SymbolKind::Variable,
+                    detail,
+                    variable.span,
+                    Vec::new(),
+                )
+            }
+            (StatementKind::Define { name, .. }, _) => {
+                let symbol = name.text.clone();
+                (symbol, SymbolKind::Macro, None, name.span, Vec::new())
+            }
+            _ => continue,
+        };
+        symbols.push(Symbol {
+            name,
+            kind,
+            detail,
+            span: statement.span,
+            selection,
+            children,
+        });
+    }
+    symbols
+}
+
+/// Whether a character can start a word which completions may replace
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '$' || c == '@' || c == '<' || c == '-'
+}
+
+/// Keywords which can start a statement
+static STATEMENT_KEYWORDS: &[&str] = &["assert", "define", "down", "left", "print", "right", "up"];
+
+/// Words which can follow `.` to name a point on an object
+static EDGES: &[&str] = &[
+    "bottom", "c", "center", "e", "end", "n", "ne", "nw", "s", "se", "start", "sw", "top", "w",
+];
+
+/// Words which can follow `.` to name a property of an object
+static PROPERTIES: &[&str] = &[
+    "color",
+    "dashed",
+    "diameter",
+    "dotted",
+    "fill",
+    "ht",
+    "rad",
+    "thickness",
+    "wid",
+    "x",
+    "y",
+];
+
+/// Words which can start a reference to an object
+static REFERENCES: &[&str] = &["first", "last", "previous", "this"];
+
+/// The built-in functions and their signatures
+static FUNCTIONS: &[(&str, &str)] = &[
+    ("abs", "abs(x)"),
+    ("cos", "cos(x)"),
+    ("dist", "dist(A, B)"),
+    ("int", "int(x)"),
+    ("max", "max(x, y)"),
+    ("min", "min(x, y)"),
+    ("sin", "sin(x)"),
+    ("sqrt", "sqrt(x)"),
+];
+
+/// Attributes which position text
+static TEXT_ATTRIBUTES: &[&str] = &[
+    "above", "aligned", "below", "big", "bold", "center", "italic", "ljust", "rjust", "small",
+];
+
+/// Attributes which suit every class of object
+static COMMON_ATTRIBUTES: &[&str] = &[
+    "behind",
+    "color",
+    "dashed",
+    "dotted",
+    "fill",
+    "ht",
+    "invis",
+    "same",
+    "solid",
+    "thick",
+    "thickness",
+    "thin",
+    "wid",
+];
+
+/// Attributes which only suit lines
+static LINE_ATTRIBUTES: &[&str] = &[
+    "->", "<-", "<->", "chop", "close", "down", "from", "go", "heading", "left", "right", "then",
+    "to", "until", "up",
+];
+
+/// Attributes which only suit objects which are not lines
+static SHAPE_ATTRIBUTES: &[&str] = &["at", "fit", "rad", "with"];
+
+/// Suggest the attributes which suit a class of object
+fn attributes(class: &str, suggestions: &mut Suggestions<'_>) {
+    let is_line = matches!(class, "arc" | "arrow" | "line" | "move" | "spline");
+    for name in COMMON_ATTRIBUTES {
+        suggestions.add(name, CompletionKind::Attribute, None);
+    }
+    let specific = if is_line {
+        LINE_ATTRIBUTES
+    } else {
+        SHAPE_ATTRIBUTES
+    };
+    for name in specific {
+        suggestions.add(name, CompletionKind::Attribute, None);
+    }
+    match class {
+        "arc" => {
+            suggestions.add("cw", CompletionKind::Attribute, None);
+            suggestions.add("ccw", CompletionKind::Attribute, None);
+        }
+        "circle" | "dot" => suggestions.add("diameter", CompletionKind::Attribute, None),
+        "line" | "spline" | "arrow" => suggestions.add("rad", CompletionKind::Attribute, None),
+        _ => {}
+    }
+}
+
+/// Collects the completions which match the partly typed word
+struct Suggestions<'a> {
+    prefix: &'a str,
+    span: Span,
+    items: Vec<Completion>,
+}
+
+impl Suggestions<'_> {
+    fn add(&mut self, label: &str, kind: CompletionKind, detail: Option<String>) {
+        let matches = if kind == CompletionKind::Color {
+            let prefix = self.prefix.as_bytes();
+            label.len() >= prefix.len()
+                && label.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix)
+        } else {
+            label.starts_with(self.prefix)
+        };
+        if matches && !self.items.iter().any(|c| c.label == label) {
+            self.items.push(Completion {
+                label: label.to_string(),
+                kind,
+                detail,
+                span: self.span,
+            });
+        }
+    }
+}
+
+/// The names defined by the source before the point
+struct Scope<'a> {
+    source: &'a str,
+    /// The labels which can be referred to, with the classes they label
+    labels: Vec<(&'a str, Option<String>)>,
+    /// The variables assigned to
+    variables: Vec<&'a str>,
+    /// The macros defined, with their bodies
+    macros: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(source: &'a str, before: &[&Token]) -> Self {
+        let mut scope = Scope {
+            source,
+            labels: Vec::new(),
+            variables: Vec::new(),
+            macros: Vec::new(),
+        };
+        // The labels in each enclosing sublist, innermost last
+        let mut lists: Vec<Vec<(&str, Option<String>)>> = vec![Vec::new()];
+        let mut start = true;
+        for (i, token) in before.iter().enumerate() {
+            let next = |n: usize| before.get(i + n).map(|t| t.kind);
+            match token.kind {
+                TokenKind::PlaceName if start && next(1) == Some(TokenKind::Colon) => {
+                    let class = before.get(i + 2).and_then(|t| match t.kind {
+                        TokenKind::ClassName => Some(t.text(source).to_string()),
+                        TokenKind::String => Some("text".to_string()),
+                        TokenKind::LBracket => Some("[]".to_string()),
+                        _ => None,
+                    });
+                    if let Some(list) = lists.last_mut() {
+                        list.push((token.text(source), class));
+                    }
+                }
+                TokenKind::Id | TokenKind::Fill | TokenKind::Color | TokenKind::Thickness
+                    if start && next(1) == Some(TokenKind::Assign) =>
+                {
+                    scope.variables.push(token.text(source));
+                }
+                TokenKind::Define => {
+                    if let (Some(name), Some(body)) = (before.get(i + 1), before.get(i + 2)) {
+                        if body.kind == TokenKind::CodeBlock {
+                            scope.macros.push((name.text(source), body.text(source)));
+                        }
+                    }
+                }
+                TokenKind::LBracket => lists.push(Vec::new()),
+                TokenKind::RBracket if lists.len() > 1 => {
+                    lists.pop();
+                }
+                _ => {}
+            }
+            start = matches!(token.kind, TokenKind::Eol | TokenKind::LBracket);
+        }
+        scope.labels = lists.pop().unwrap_or_default();
+        scope
+    }
+
+    fn labels(&self, suggestions: &mut Suggestions<'_>) {
+        for (label, class) in &self.labels {
+            suggestions.add(label, CompletionKind::Label, class.clone());
+        }
+    }
+
+    fn variables(&self, suggestions: &mut Suggestions<'_>) {
+        for name in &self.variables {
+            suggestions.add(name, CompletionKind::Variable, None);
+        }
+        for (name, value) in BUILTINS {
+            suggestions.add(name, CompletionKind::Variable, Some(format!("{}", value)));
+        }
+    }
+
+    fn macros(&self, suggestions: &mut Suggestions<'_>) {
+        for (name, _) in &self.macros {
+            suggestions.add(name, CompletionKind::Macro, None);
+        }
+    }
+
+    /// The labels inside a labelled sublist, and the classes they label
+    fn children(&self, parent: &str) -> Vec<(String, Option<String>)> {
+        let document = ast::parse_recovering(self.source).0;
+        let model = Model::new(&document);
+        let objects = model.objects();
+        let parent = match objects
+            .iter()
+            .rev()
+            .find(|o| o.label.as_ref().map(|l| l.text.as_str()) == Some(parent))
+        {
+            Some(parent) => parent,
+            None => return Vec::new(),
+        };
+        parent
+            .children
+            .iter()
+            .map(|&id| model.object(id))
+            .filter_map(|o| Some((o.label.as_ref()?.text.clone(), o.class.clone())))
+            .collect()
+    }
+
+    /// The number of parameters a macro uses, if it is a macro
+    fn macro_parameters(&self, name: &str) -> Option<usize> {
+        let (_, body) = self.macros.iter().rev().find(|(n, _)| *n == name)?;
+        let body = &body[1..body.len() - 1];
+        let count = tokenize(body)
+            .iter()
+            .filter(|t| t.kind == TokenKind::Parameter)
+            .map(|t| usize::from(t.text(body).as_bytes()[1] - b'0'))
+            .max()
+            .unwrap_or(0);
+        Some(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(source: &str, kind: CompletionKind) -> Vec<String> {
+        let offset = source.find('|').expect("a cursor");
+        let source = source.replacen('|', "", 1);
+        completions(&source, offset)
+            .into_iter()
+            .filter(|c| c.kind == kind)
+            .map(|c| c.label)
+            .collect()
+    }
+
+    #[test]
+    fn completes_statements() {
+        assert_eq!(labels("gap = 1\nci|", CompletionKind::Class), ["circle"]);
+        assert_eq!(
+            labels("gap = 1\n|", CompletionKind::Keyword),
+            ["assert", "define", "down", "left", "print", "right", "up"]
+        );
+        let variables = labels("gap = 1\ng|", CompletionKind::Variable);
+        assert_eq!(variables, ["gap"]);
+        assert_eq!(
+            labels("define blob { circle }\nbox; b|", CompletionKind::Macro),
+            ["blob"]
+        );
+        assert!(labels("box \"a|\"", CompletionKind::Class).is_empty());
+        assert!(labels("box # a|", CompletionKind::Class).is_empty());
+    }
+
+    #[test]
+    fn completes_attributes_for_the_class() {
+        let line = labels("A: arrow |", CompletionKind::Attribute);
+        assert!(line.contains(&"from".to_string()));
+        assert!(!line.contains(&"fit".to_string()));
+        let shape = labels("box wid 1 |", CompletionKind::Attribute);
+        assert!(shape.contains(&"fit".to_string()));
+        assert!(!shape.contains(&"then".to_string()));
+        assert!(!shape.contains(&"bold".to_string()));
+        let text = labels("box \"hi\" |", CompletionKind::Attribute);
+        assert!(text.contains(&"bold".to_string()));
+        assert_eq!(
+            labels("arc c|", CompletionKind::Attribute),
+            ["color", "chop", "close", "cw", "ccw"]
+        );
+    }
+
+    #[test]
+    fn completes_references() {
+        let source = "A: box\n[ B: circle; C: dot ]\nX: [ D: box ]\narrow from |";
+        assert_eq!(labels(source, CompletionKind::Label), ["A", "X"]);
+        assert_eq!(
+            labels("A: box\n[ B: circle; arrow from | ]", CompletionKind::Label),
+            ["B"]
+        );
+        assert_eq!(
+            labels(
+                "X: [ D: box; E: circle ]\nline from X.|",
+                CompletionKind::Label
+            ),
+            ["D", "E"]
+        );
+        assert_eq!(
+            labels("A: box\nline from A.s|", CompletionKind::Edge),
+            ["s", "se", "start", "sw"]
+        );
+        assert_eq!(
+            labels("box with .n|", CompletionKind::Edge),
+            ["n", "ne", "nw"]
+        );
+        assert_eq!(labels("box with |", CompletionKind::Edge)[0], ".bottom");
+        assert_eq!(
+            labels("line from 2nd ci|", CompletionKind::Class),
+            ["circle"]
+        );
+    }
+
+    #[test]
+    fn completes_values() {
+        assert_eq!(
+            labels("box fill lightgr|", CompletionKind::Color),
+            ["LightGray", "LightGreen", "LightGrey"]
+        );
+        assert!(labels("fill = |", CompletionKind::Color).is_empty());
+        assert_eq!(
+            labels("box wid boxw|", CompletionKind::Variable),
+            ["boxwid"]
+        );
+        assert_eq!(labels("x = m|", CompletionKind::Function), ["max", "min"]);
+        let completion = &completions("box wid cyl", 11)[0];
+        assert_eq!(completion.span, Span::new(8, 11));
+        assert_eq!(completion.detail.as_deref(), Some("0.5"));
+    }
+
+    fn renamed(source: &str, needle: &str, new_name: &str) -> Result<String, String> {
+        let edits = rename(source, source.find(needle).unwrap(), new_name)?;
+        let mut out = source.to_string();
+        for edit in edits.iter().rev() {
+            out.replace_range(edit.span.start..edit.span.end, &edit.replacement);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn renames_labels_and_variables() {
+        let source = "A: box\nX: [ A: circle ]\nline from A.e to X.A.w\ntext \"A\" at A";
+        assert_eq!(
+            renamed(source, "A: c", "B").unwrap(),
+            "A: box\nX: [ B: circle ]\nline from A.e to X.B.w\ntext \"A\" at A"
+        );
+        assert_eq!(
+            renamed(source, "A.e", "Start").unwrap(),
+            "Start: box\nX: [ A: circle ]\nline from Start.e to X.A.w\ntext \"A\" at Start"
+        );
+        assert_eq!(
+            renamed("k = 1\nk += 2\nbox wid k", "k", "gap").unwrap(),
+            "gap = 1\ngap += 2\nbox wid gap"
+        );
+        let source = "define two { box wid sz; box wid sz }\nsz = 1\ntwo";
+        assert_eq!(
+            renamed(source, "sz = ", "size").unwrap(),
+            "define two { box wid size; box wid size }\nsize = 1\ntwo"
+        );
+    }
+
+    #[test]
+    fn refuses_bad_renames() {
+        let source = "A: box\nB: box \"T\"\nk = 1\nbox wid boxwid\narrow from T";
+        assert!(renamed(source, "A", "B").is_err());
+        assert!(renamed(source, "A", "a").is_err());
+        assert!(renamed(source, "A", "Two Words").is_err());
+        assert!(rename(source, source.len(), "U").is_ok());
+        assert!(renamed(source, "k =", "boxht").is_err());
+        assert!(renamed(source, "k =", "N").is_err());
+        assert!(renamed(source, "boxwid", "w").is_err());
+        assert!(renamed(source, "from", "C").is_err());
+        assert!(renamed("box wid (", "box", "B").is_err());
+        assert_eq!(renamed(source, "k =", "k"), Ok(source.to_string()));
+    }
+
+    #[test]
+    fn finds_folding_ranges() {
+        let source = "box \\\n  wid 2\nA: [\n  B: [\n    circle\n  ]\n  [ line ]\n]\nbox";
+        let ranges: Vec<_> = folding_ranges(source)
+            .into_iter()
+            .map(|r| (r.kind, r.span.text(source)))
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                (FoldingKind::Statement, "box \\\n  wid 2"),
+                (
+                    FoldingKind::Sublist,
+                    "[\n  B: [\n    circle\n  ]\n  [ line ]\n]"
+                ),
+                (FoldingKind::Sublist, "[\n    circle\n  ]"),
+            ]
+        );
+        assert!(folding_ranges("box; circle\n\n").is_empty());
+        let source = "define two {\n  box; box\n}\ntwo";
+        assert_eq!(
+            folding_ranges(source),
+            [FoldingRange {
+                span: Span::new(12, 24),
+                kind: FoldingKind::Macro
+            }]
+        );
+    }
+
+    #[test]
+    fn outlines_the_source() {
+        let source =
+            "k = 1\nk = 2\nP: (1, 2)\nX: [ box; C: circle; j = k ]\ndefine m { A: box }\nm";
+        let outline = symbols(source);
+        let summary: Vec<_> = outline
+            .iter()
+            .map(|s| {
+                (
+                    s.name.as_str(),
+                    s.kind,
+                    s.detail.as_deref(),
+                    s.selection.text(source),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("k", SymbolKind::Variable, Some("1"), "k"),
+                ("P", SymbolKind::Place, None, "P"),
+                ("X", SymbolKind::Object, Some("[]"), "X"),
+                ("m", SymbolKind::Macro, None, "m"),
+            ]
+        );
+        let children: Vec<_> = outline[2]
+            .children
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(children, ["C", "j"]);
+        assert_eq!(outline[2].span.text(source), "X: [ box; C: circle; j = k ]");
+    }
+
+    #[test]
+    fn describes_calls() {
+        let help = |source: &str| signature_help(source, source.len());
+        assert_eq!(help("x = sqrt(").unwrap().label, "sqrt(x)");
+        assert_eq!(help("x = max(1, min(2, ").unwrap().label, "min(x, y)");
+        assert_eq!(help("x = max(1, min(2, 3), ").unwrap().active_parameter, 1);
+        let assert = help("assert(A.x == ").unwrap();
+        assert_eq!(assert.label, "assert(x == y)");
+        assert_eq!(assert.active_parameter, 1);
+        let call = help("define row { box $1; box $2 }\nrow(\"a\", ").unwrap();
+        assert_eq!(call.label, "row($1, $2)");
+        assert_eq!(call.parameters, ["$1", "$2"]);
+        assert_eq!(help("x = max(1, 2)"), None);
+        assert_eq!(help("x = max(1,\ny = ("), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! HTML image maps for rendered diagrams
+//!
+//! When a diagram is shown as a raster image, an HTML `<map>` from
+//! [`Pikchr::to_image_map`](crate::Pikchr::to_image_map) can still make
+//! its objects clickable.  Each object with a label gets an `<area>` over
+//! it, with the label as its `alt` and `title`; add an `href` to each area
+//! to link it.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box \"Start\"; arrow; circle \"End\"", None, PikchrFlags::default())
+//!     .unwrap();
+//! assert_eq!(
+//!     pic.to_image_map("flow").unwrap(),
+//!     "<map name=\"flow\">\n\
+//!      <area shape=\"rect\" coords=\"2,2,110,74\" alt=\"Start\" title=\"Start\">\n\
+//!      <area shape=\"circle\" coords=\"218,38,36\" alt=\"End\" title=\"End\">\n\
+//!      </map>\n"
+//! );
+//! ```
+//!
+//! The rendered diagram does not say which text belongs to which object,
+//! so text is the label of the closed shape which it is inside, or else of
+//! a line which it is beside.  Text on its own has no area.
+
+use crate::svg::{escape, Dom, Element, Rect};
+
+/// How far the label of a line can be from it, in the coordinates of the
+/// SVG, which is a little more than the height of a line of text
+const LINE_REACH: f64 = 24.0;
+
+/// An object and the texts which label it
+struct Labelled<'a> {
+    shape: &'a Element,
+    texts: Vec<&'a Element>,
+}
+
+/// Make an image map for a rendered diagram which is shown at a size in
+/// pixels
+pub(crate) fn image_map(dom: &Dom, name: &str, width: isize, height: isize) -> String {
+    let mut out = format!("<map name=\"{}\">\n", escape(name));
+    let (view_box, svg) = match (dom.view_box(), dom.svg()) {
+        (Some(view_box), Some(svg)) if view_box.width > 0.0 && view_box.height > 0.0 => {
+            (view_box, svg)
+        }
+        _ => {
+            out.push_str("</map>\n");
+            return out;
+        }
+    };
+    let scale_x = width as f64 / view_box.width;
+    let scale_y = height as f64 / view_box.height;
+    let pixel = |(x, y): (f64, f64)| {
+        (
+            ((x - view_box.x) * scale_x).round() as i64,
+            ((y - view_box.y) * scale_y).round() as i64,
+        )
+    };
+
+    let mut objects: Vec<Labelled> = Vec::new();
+    for element in svg.elements() {
+        match element.name.as_str() {
+            "text" => {
+                let (x, y) = match element.bounds() {
+                    Some(point) => (point.x, point.y),
+                    None => continue,
+                };
+                let object = objects.iter_mut().rev().find(|o| {
+                    let bounds = o.shape.bounds().unwrap_or_default();
+                    if closed(o.shape) {
+                        bounds.contains(x, y)
+                    } else {
+                        reach(bounds).contains(x, y)
+                    }
+                });
+                if let Some(object) = object {
+                    object.texts.push(element);
+                }
+            }
+            "path" | "circle" | "ellipse" => objects.push(Labelled {
+                shape: element,
+                texts: Vec::new(),
+            }),
+            // Arrowheads
+            _ => {}
+        }
+    }
+
+    for object in objects.iter().filter(|o| !o.texts.is_empty()) {
+        let label: Vec<_> = object
+            .texts
+            .iter()
+            .map(|t| t.text().replace('\u{a0}', " "))
+            .collect();
+        let label = label.join(" ").replace('"', "&quot;");
+        let (shape, coords) = area(object, &pixel, scale_x);
+        let coords: Vec<_> = coords.iter().map(i64::to_string).collect();
+        out.push_str(&format!(
+            "<area shape=\"{}\" coords=\"{}\" alt=\"{}\" title=\"{}\">\n",
+            shape,
+            coords.join(","),
+            label,
+            label
+        ));
+    }
+    out.push_str("</map>\n");
+    out
+}
+
+/// Whether an element is a shape which text can be inside
+fn closed(element: &Element) -> bool {
+    match element.name.as_str() {
+        "circle" | "ellipse" => true,
+        "path" => element
+            .attr("d")
+            .is_some_and(|d| d.trim_end().ends_with(['Z', 'z'])),
+        _ => false,
+    }
+}
+
+/// The shape and pixel coordinates of the area for an object
+fn area(
+    object: &Labelled,
+    pixel: &dyn Fn((f64, f64)) -> (i64, i64),
+    scale: f64,
+) -> (&'static str, Vec<i64>) {
+    let shape = object.shape;
+    if shape.name == "circle" {
+        if let Some(b) = shape.bounds() {
+            let (x, y) = pixel((b.x + b.width / 2.0, b.y + b.height / 2.0));
+            let r = (b.width / 2.0 * scale).round() as i64;
+            return ("circle", vec![x, y, r]);
+        }
+    }
+    if closed(shape) {
+        let mut points = shape.points();
+        points.dedup();
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        if points.len() > 2 && !is_rectangle(&points) {
+            let coords = points
+                .into_iter()
+                .flat_map(|p| {
+                    let (x, y) = pixel(p);
+                    vec![x, y]
+                })
+                .collect();
+            return ("poly", coords);
+        }
+    }
+    let mut bounds = shape.bounds().unwrap_or_default();
+    if !closed(shape) {
+        bounds = reach(bounds);
+        for text in &object.texts {
+            if let Some(point) = text.bounds() {
+                bounds = bounds.union(point);
+            }
+        }
+    }
+    let (x0, y0) = pixel((bounds.x, bounds.y));
+    let (x1, y1) = pixel((bounds.x + bounds.width, bounds.y + bounds.height));
+    ("rect", vec![x0, y0, x1, y1])
+}
+
+/// The bounds of a line, made at least wide and high enough to reach
+/// the text beside it
+fn reach(bounds: Rect) -> Rect {
+    let grow = |start: f64, size: f64| {
+        let extra = (2.0 * LINE_REACH - size).max(0.0) / 2.0;
+        (start - extra, size + 2.0 * extra)
+    };
+    let (x, width) = grow(bounds.x, bounds.width);
+    let (y, height) = grow(bounds.y, bounds.height);
+    Rect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+/// Whether some points are the corners of a rectangle which is square to
+/// the axes
+fn is_rectangle(points: &[(f64, f64)]) -> bool {
+    points.len() == 4
+        && (0..4).all(|i| {
+            let (a, b) = (points[i], points[(i + 1) % 4]);
+            a.0 == b.0 || a.1 == b.1
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Pikchr, PikchrFlags};
+
+    fn areas(source: &str) -> Vec<String> {
+        let pic = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+        let map = pic.to_image_map("m").unwrap();
+        map.lines()
+            .filter(|l| l.starts_with("<area"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn maps_labelled_objects() {
+        assert_eq!(
+            areas("box \"a\" \"b\"; arrow \"go\" above; text \"alone\"; file \"x&lt;y\""),
+            [
+                "<area shape=\"rect\" coords=\"2,20,110,92\" alt=\"a b\" title=\"a b\">",
+                "<area shape=\"rect\" coords=\"110,32,176,80\" alt=\"go\" title=\"go\">",
+                "<area shape=\"poly\" coords=\"239,110,310,110,310,23,288,2,239,2\" \
+                 alt=\"x&lt;y\" title=\"x&lt;y\">",
+            ]
+        );
+    }
+
+    #[test]
+    fn scales_to_the_image() {
+        assert_eq!(
+            areas("scale = 2\ncircle \"c\""),
+            ["<area shape=\"circle\" coords=\"76,76,72\" alt=\"c\" title=\"c\">"]
+        );
+    }
+
+    #[test]
+    fn skips_unlabelled_objects() {
+        assert!(areas("box; arrow; circle").is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+//! Rendering lazily from an iterator
+//!
+//! A [`RenderIter`] renders each source which another iterator yields, as
+//! it is asked for, so that a pipeline reading diagrams out of a large
+//! file need not collect them all first.  Each diagram is rendered with
+//! the same class and flags, and a [`PikchrLruCache`] can be shared to
+//! avoid rendering the same source twice.
+//!
+//! ```
+//! use pikchr::RenderIter;
+//!
+//! let sources = "box\n\ncircle\n\nbox wid".split("\n\n");
+//! let mut rendered = RenderIter::new(sources).class("diagram");
+//! assert!(rendered.next().unwrap().unwrap().contains("class=\"diagram\""));
+//! assert!(rendered.next().unwrap().unwrap().contains("<circle"));
+//! assert!(rendered.next().unwrap().is_err());
+//! assert!(rendered.next().is_none());
+//! ```
+
+use crate::cache::PikchrLruCache;
+use crate::{Pikchr, PikchrError, PikchrFlags};
+
+/// An iterator which renders the sources of another iterator
+///
+/// Errors are yielded in place of the diagrams which fail to render, and
+/// the iterator carries on with the next source.
+pub struct RenderIter<'c, I> {
+    sources: I,
+    class: Option<String>,
+    flags: PikchrFlags,
+    cache: Option<&'c PikchrLruCache>,
+}
+
+impl<'c, I> RenderIter<'c, I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    /// Render the sources of an iterator with the default flags and no
+    /// class
+    pub fn new(sources: impl IntoIterator<IntoIter = I>) -> Self {
+        RenderIter {
+            sources: sources.into_iter(),
+            class: None,
+            flags: PikchrFlags::default(),
+            cache: None,
+        }
+    }
+
+    /// Add a class to the SVG of every diagram
+    pub fn class(mut self, class: &str) -> Self {
+        self.class = Some(class.to_string());
+        self
+    }
+
+    /// Render every diagram with some flags
+    pub fn flags(mut self, flags: PikchrFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Look diagrams up in a cache before rendering them, and keep them in
+    /// it after
+    pub fn cache(mut self, cache: &'c PikchrLruCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+}
+
+impl<I> Iterator for RenderIter<'_, I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    type Item = Result<Pikchr, PikchrError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let source = self.sources.next()?;
+        let source = source.as_ref();
+        let class = self.class.as_deref();
+        Some(match self.cache {
+            Some(cache) => cache
+                .render(source, class, self.flags)
+                .map(|pic| Pikchr::clone(&pic)),
+            None => Pikchr::render(source, class, self.flags),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.sources.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheOptions;
+
+    #[test]
+    fn renders_lazily() {
+        let mut asked = 0;
+        let sources = ["box", "circle", "oval"].iter().inspect(|_| asked += 1);
+        let mut rendered = RenderIter::new(sources);
+        assert_eq!(rendered.size_hint(), (3, Some(3)));
+        assert!(rendered.next().unwrap().is_ok());
+        drop(rendered);
+        assert_eq!(asked, 1);
+    }
+
+    #[test]
+    fn shares_a_cache() {
+        let cache = PikchrLruCache::new(CacheOptions::default());
+        let sources = vec!["box".to_string(), "box".to_string(), "box wid".to_string()];
+        let results: Vec<_> = RenderIter::new(sources)
+            .flags(PikchrFlags::default())
+            .cache(&cache)
+            .collect();
+        let first: &str = results[0].as_ref().unwrap();
+        let second: &str = results[1].as_ref().unwrap();
+        assert_eq!(first, second);
+        assert!(results[2].is_err());
+        let metrics = cache.metrics();
+        assert_eq!((metrics.hits, metrics.misses, metrics.entries), (1, 2, 1));
+    }
+}
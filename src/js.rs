@@ -0,0 +1,132 @@
+//! Exports for the JavaScript wrapper in `js/`
+//!
+//! When the crate is built for WebAssembly with the `js` feature, these
+//! functions are exported from the module and `js/index.js` wraps them as
+//! `render(source, options)`.  Strings cross the boundary as UTF-8 in
+//! memory allocated with [`pikchr_js_alloc`], and the result of a render
+//! is a JSON object which is prefixed by its length as four little-endian
+//! bytes.
+
+use crate::http::json_string;
+use crate::{Pikchr, PikchrFlags};
+
+/// Use plain text rather than HTML for errors
+const PLAIN_ERRORS: u32 = 0x0001;
+
+/// Use colours which suit a dark background
+const DARK_MODE: u32 = 0x0002;
+
+/// Allocate memory for the JavaScript side to copy a string into
+#[no_mangle]
+pub extern "C" fn pikchr_js_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Free memory returned by [`pikchr_js_alloc`] or [`pikchr_js_render`]
+///
+/// # Safety
+///
+/// The pointer and length must be those of a single allocation from one of
+/// those functions, which must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pikchr_js_free(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Render pikchr source, returning the result as length-prefixed JSON
+///
+/// The JSON has `svg`, `width`, `height` and `error` members, which are
+/// `null` when they do not apply.  The class may be null for no class.
+/// The returned allocation is four bytes longer than the JSON.
+///
+/// # Safety
+///
+/// The source and class must point to that many bytes of memory.
+#[no_mangle]
+pub unsafe extern "C" fn pikchr_js_render(
+    source: *const u8,
+    source_len: usize,
+    class: *const u8,
+    class_len: usize,
+    flags: u32,
+) -> *mut u8 {
+    let source = String::from_utf8_lossy(std::slice::from_raw_parts(source, source_len));
+    let class = if class.is_null() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(std::slice::from_raw_parts(
+            class, class_len,
+        )))
+    };
+    let json = render(&source, class.as_deref(), flags);
+    let mut out = Vec::with_capacity(json.len() + 4);
+    out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    out.extend_from_slice(json.as_bytes());
+    Box::into_raw(out.into_boxed_slice()) as *mut u8
+}
+
+/// Render pikchr source as a JSON result
+fn render(source: &str, class: Option<&str>, bits: u32) -> String {
+    let mut flags = PikchrFlags::default();
+    if bits & PLAIN_ERRORS == 0 {
+        flags.generate_html_errors();
+    }
+    if bits & DARK_MODE != 0 {
+        flags.use_dark_mode();
+    }
+    match Pikchr::render(source, class, flags) {
+        Ok(pic) => format!(
+            "{{\"svg\":{},\"width\":{},\"height\":{},\"error\":null}}",
+            json_string(&pic),
+            pic.width(),
+            pic.height()
+        ),
+        Err(error) => format!(
+            "{{\"svg\":null,\"width\":null,\"height\":null,\"error\":{}}}",
+            json_string(error.raw())
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(source: &str, flags: u32) -> String {
+        unsafe {
+            let ptr = pikchr_js_render(source.as_ptr(), source.len(), std::ptr::null(), 0, flags);
+            let mut len = [0; 4];
+            len.copy_from_slice(std::slice::from_raw_parts(ptr, 4));
+            let len = u32::from_le_bytes(len) as usize;
+            let json = std::str::from_utf8(std::slice::from_raw_parts(ptr.add(4), len))
+                .unwrap()
+                .to_string();
+            pikchr_js_free(ptr, len + 4);
+            json
+        }
+    }
+
+    #[test]
+    fn renders_as_json() {
+        let json = call("box", PLAIN_ERRORS);
+        assert!(json.starts_with("{\"svg\":\"<svg xmlns='http://www.w3.org/2000/svg' viewBox="));
+        assert!(json.ends_with("</svg>\\n\",\"width\":112,\"height\":76,\"error\":null}"));
+        let json = call("box wid", PLAIN_ERRORS);
+        assert!(json.starts_with("{\"svg\":null,\"width\":null,\"height\":null,\"error\":\""));
+        assert!(json.contains("syntax error"));
+    }
+
+    #[test]
+    fn quotes_strings() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}é"), "\"a\\\"b\\\\c\\n\\u0001é\"");
+    }
+
+    #[test]
+    fn allocates_and_frees() {
+        let ptr = pikchr_js_alloc(16);
+        unsafe {
+            std::slice::from_raw_parts_mut(ptr, 16).copy_from_slice(&[7; 16]);
+            pikchr_js_free(ptr, 16);
+        }
+    }
+}
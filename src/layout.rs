@@ -0,0 +1,3348 @@
+//! Laying out and rendering diagrams without the C library
+//!
+//! This module is only available with the experimental `rust-backend`
+//! feature.  It lays out a parsed [`Document`] in the same way as the C
+//! implementation does, and renders the result as SVG, without any
+//! `unsafe` code.  The SVG is intended to be byte-for-byte identical to
+//! the output of [`Pikchr::render`](crate::Pikchr::render).
+//!
+//! ```
+//! use pikchr::{layout, Pikchr, PikchrFlags};
+//!
+//! let source = "A: box \"Hello\" fit\narrow\ncircle \"World\"";
+//! let svg = layout::render(source, None, PikchrFlags::default()).unwrap();
+//! let c = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+//! assert_eq!(svg, &*c);
+//! ```
+//!
+//! The [`Layout`] of a diagram can also be inspected directly, to find out
+//! where each object ended up.  All measurements are in inches, with `y`
+//! increasing upwards.
+//!
+//! ```
+//! use pikchr::layout::{layout, Point};
+//!
+//! let diagram = layout("A: box\narrow right 1\nB: circle").unwrap();
+//! let objects = diagram.objects();
+//! assert_eq!(objects[0].name(), Some("A"));
+//! assert_eq!(objects[1].path(), &[Point::new(0.375, 0.0), Point::new(1.375, 0.0)]);
+//! assert_eq!(objects[2].center(), Point::new(1.625, 0.0));
+//! ```
+//!
+//! Errors are reported as a [`LayoutError`] rather than as the text which
+//! the C implementation produces.  The `debug` and `debug_label_color`
+//! variables are ignored.
+
+use crate::ast::{
+    self, Assertion, AssignOp, Attribute, AttributeKind, Axis, BinaryOp, ColorProperty, DashStyle,
+    Direction, Document, Edge, Expr, ExprKind, Flag, Function, Name, NthClass, NumericProperty,
+    ObjectKind, ObjectProperty, ObjectRef, ObjectRefKind, ParseError, Place, PlaceKind, Position,
+    PositionKind, PrintItem, RelExpr, Span, Statement, StatementKind, Text, TextPosition, UnaryOp,
+};
+use crate::measure::{displayed, MeasureFn};
+use crate::semantic::{builtin, color};
+use crate::trace::Step;
+use crate::PikchrFlags;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+/// Lay out pikchr source
+///
+/// ```
+/// let diagram = pikchr::layout::layout("box wid 2").unwrap();
+/// assert_eq!(diagram.objects()[0].width(), 2.0);
+/// ```
+pub fn layout(source: &str) -> Result<Layout> {
+    let document = ast::parse(source)?;
+    Layout::new(source, &document)
+}
+
+/// Lay out pikchr source, measuring text with a callback
+///
+/// The callback is given the text of each label as it is displayed, and
+/// the size of its font relative to normal text, and returns its width in
+/// SVG pixels, of which there are 144 in an inch.  A negative width means
+/// that the text should be measured with the built-in estimate.
+///
+/// ```
+/// let narrow = pikchr::layout::layout_measured("box \"hello\" fit", &|_, _| 0.0).unwrap();
+/// let wide = pikchr::layout::layout_measured("box \"hello\" fit", &|_, _| 200.0).unwrap();
+/// assert!(narrow.objects()[0].width() < wide.objects()[0].width());
+/// ```
+pub fn layout_measured(source: &str, measure: &dyn Fn(&str, f64) -> f64) -> Result<Layout> {
+    let document = ast::parse(source)?;
+    Layout::build(source, &document, Some(measure))
+}
+
+/// Lay out a parsed document, recording the state after each statement
+///
+/// The steps up to any error are returned with it.
+pub(crate) fn traced(source: &str, document: &Document) -> (Vec<Step>, Option<LayoutError>) {
+    let mut engine = Engine::new(source, None);
+    engine.trace = Some(Vec::new());
+    let error = engine.statements(&document.statements).err();
+    (engine.trace.unwrap_or_default(), error)
+}
+
+/// Render pikchr source as SVG
+///
+/// The class and flags have the same meaning as for
+/// [`Pikchr::render`](crate::Pikchr::render), except that errors are
+/// always returned as a [`LayoutError`].
+///
+/// ```
+/// use pikchr::{layout, PikchrFlags};
+///
+/// let err = layout::render("box wid 1 wid 2", None, PikchrFlags::default()).unwrap_err();
+/// assert_eq!(err.message(), "value is already set");
+/// ```
+pub fn render(source: &str, class: Option<&str>, flags: PikchrFlags) -> Result<String> {
+    Ok(layout(source)?.to_svg(class, flags))
+}
+
+/// An error found while laying out a diagram
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayoutError {
+    message: String,
+    span: Span,
+}
+
+impl LayoutError {
+    fn new(message: &str, span: Span) -> Self {
+        LayoutError {
+            message: message.to_string(),
+            span,
+        }
+    }
+
+    /// A description of the error
+    ///
+    /// Where possible these match the messages of the C implementation.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The location of the error in the source
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at bytes {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+impl From<ParseError> for LayoutError {
+    fn from(err: ParseError) -> Self {
+        LayoutError::new(err.message(), err.span())
+    }
+}
+
+type Result<T> = std::result::Result<T, LayoutError>;
+
+/// A point, in inches
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Point {
+    /// The horizontal position, increasing to the right
+    pub x: f64,
+    /// The vertical position, increasing upwards
+    pub y: f64,
+}
+
+impl Point {
+    /// Create a new point
+    pub fn new(x: f64, y: f64) -> Self {
+        Point { x, y }
+    }
+}
+
+/// A bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds {
+    /// The bottom left corner
+    pub sw: Point,
+    /// The top right corner
+    pub ne: Point,
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        Bounds {
+            sw: Point::new(1.0, 1.0),
+            ne: Point::new(0.0, 0.0),
+        }
+    }
+
+    /// Whether the box contains nothing at all
+    pub fn is_empty(&self) -> bool {
+        self.sw.x > self.ne.x
+    }
+
+    /// The width of the box
+    pub fn width(&self) -> f64 {
+        self.ne.x - self.sw.x
+    }
+
+    /// The height of the box
+    pub fn height(&self) -> f64 {
+        self.ne.y - self.sw.y
+    }
+
+    fn add_bounds(&mut self, other: &Bounds) {
+        if self.is_empty() {
+            *self = *other;
+        }
+        if other.is_empty() {
+            return;
+        }
+        self.sw.x = self.sw.x.min(other.sw.x);
+        self.sw.y = self.sw.y.min(other.sw.y);
+        self.ne.x = self.ne.x.max(other.ne.x);
+        self.ne.y = self.ne.y.max(other.ne.y);
+    }
+
+    fn add_point(&mut self, x: f64, y: f64) {
+        self.add_ellipse(x, y, 0.0, 0.0);
+    }
+
+    fn add_ellipse(&mut self, x: f64, y: f64, rx: f64, ry: f64) {
+        if self.is_empty() {
+            self.ne = Point::new(x + rx, y + ry);
+            self.sw = Point::new(x - rx, y - ry);
+            return;
+        }
+        self.sw.x = self.sw.x.min(x - rx);
+        self.sw.y = self.sw.y.min(y - ry);
+        self.ne.x = self.ne.x.max(x + rx);
+        self.ne.y = self.ne.y.max(y + ry);
+    }
+}
+
+/// A laid out diagram
+#[derive(Clone, Debug)]
+pub struct Layout {
+    objects: Vec<Object>,
+    variables: HashMap<String, f64>,
+    printed: String,
+}
+
+impl Layout {
+    /// Lay out a parsed document
+    ///
+    /// The source is only used to estimate the width of text in the same
+    /// way as the C implementation, which looks past the end of strings
+    /// containing HTML entities.
+    pub fn new(source: &str, document: &Document) -> Result<Layout> {
+        Layout::build(source, document, None)
+    }
+
+    fn build(source: &str, document: &Document, measure: Option<MeasureFn>) -> Result<Layout> {
+        let mut engine = Engine::new(source, measure);
+        engine.statements(&document.statements)?;
+        Ok(Layout {
+            objects: engine.list,
+            variables: engine.variables,
+            printed: engine.printed,
+        })
+    }
+
+    /// The top-level objects, in order
+    ///
+    /// The objects within a `[...]` sublist are its
+    /// [`children`](Object::children).
+    pub fn objects(&self) -> &[Object] {
+        &self.objects
+    }
+
+    /// The width and height of the SVG in pixels
+    ///
+    /// This is `None` if the diagram has no objects, in which case there
+    /// is no SVG.
+    ///
+    /// ```
+    /// let diagram = pikchr::layout::layout("box").unwrap();
+    /// assert_eq!(diagram.size(), Some((112, 76)));
+    /// ```
+    pub fn size(&self) -> Option<(isize, isize)> {
+        let (_, bounds, _) = self.frame()?;
+        let scale = value(&self.variables, "scale").unwrap_or(0.0);
+        let mut width = (SCALE * bounds.width()) as i32;
+        let mut height = (SCALE * bounds.height()) as i32;
+        if (0.001..=1000.0).contains(&scale) && !(0.99..=1.01).contains(&scale) {
+            width = (f64::from(width) * scale) as i32;
+            height = (f64::from(height) * scale) as i32;
+        }
+        Some((width as isize, height as isize))
+    }
+
+    /// The bounds of the diagram including its margins, which the SVG
+    /// shows
+    ///
+    /// This is `None` if the diagram has no objects.
+    ///
+    /// ```
+    /// let diagram = pikchr::layout::layout("box").unwrap();
+    /// let bounds = diagram.bounds().unwrap();
+    /// assert!((bounds.width() - 0.78).abs() < 1e-9);
+    /// ```
+    pub fn bounds(&self) -> Option<Bounds> {
+        let (_, bounds, _) = self.frame()?;
+        Some(bounds)
+    }
+
+    /// The objects ready to render, the bounds of the diagram including
+    /// its margins, and the settings to render with
+    fn frame(&self) -> Option<(Vec<Object>, Bounds, Settings)> {
+        if self.objects.is_empty() {
+            return None;
+        }
+        let var = |name: &str| value(&self.variables, name).unwrap_or(0.0);
+        let settings = Settings::new(&self.variables);
+        let thickness = var("thickness").max(0.01);
+        let margin = var("margin") + thickness;
+        let w_arrow = settings.w_arrow * thickness;
+        let mut objects = self.objects.clone();
+        let mut bounds = Bounds::empty();
+        add_list_bounds(&mut bounds, &mut objects, w_arrow, &settings);
+        bounds.ne.x += margin + var("rightmargin");
+        bounds.ne.y += margin + var("topmargin");
+        bounds.sw.x -= margin + var("leftmargin");
+        bounds.sw.y -= margin + var("bottommargin");
+        Some((objects, bounds, settings))
+    }
+
+    /// Render the diagram as SVG
+    ///
+    /// Any output of `print` statements comes first, as it does with the C
+    /// implementation.
+    pub fn to_svg(&self, class: Option<&str>, flags: PikchrFlags) -> String {
+        let mut out = self.printed.clone();
+        let (mut objects, bounds, settings) = match self.frame() {
+            Some(frame) => frame,
+            None => {
+                if out.is_empty() {
+                    out.push_str("<!-- empty pikchr diagram -->\n");
+                }
+                return out;
+            }
+        };
+        let var = |name: &str| value(&self.variables, name).unwrap_or(0.0);
+
+        out.push_str("<svg xmlns='http://www.w3.org/2000/svg'");
+        if let Some(class) = class {
+            let _ = write!(out, " class=\"{}\"", class);
+        }
+        let w = bounds.width();
+        let h = bounds.height();
+        let scale = var("scale");
+        if (0.001..=1000.0).contains(&scale) && !(0.99..=1.01).contains(&scale) {
+            let width = f64::from((SCALE * w) as i32);
+            let height = f64::from((SCALE * h) as i32);
+            let _ = write!(
+                out,
+                " width=\"{}\" height=\"{}\"",
+                format_g(f64::from((width * scale) as i32), 10),
+                format_g(f64::from((height * scale) as i32), 10)
+            );
+        }
+        let _ = writeln!(
+            out,
+            " viewBox=\"0 0 {} {}\">",
+            format_g(SCALE * w, 6),
+            format_g(SCALE * h, 6)
+        );
+        let mut renderer = Renderer {
+            out,
+            settings,
+            bounds,
+            dark: flags.dark_mode(),
+            fg: value(&self.variables, "fgcolor").map_or(-99, |v| v as i32),
+            bg: value(&self.variables, "bgcolor").map_or(-99, |v| v as i32),
+        };
+        renderer.list(&mut objects);
+        renderer.out.push_str("</svg>\n");
+        renderer.out
+    }
+}
+
+/// An object in a laid out diagram
+#[derive(Clone, Debug)]
+pub struct Object {
+    class: Class,
+    name: Option<String>,
+    labels: Vec<Label>,
+    span: Span,
+    at: Point,
+    with: Point,
+    with_edge: Edge,
+    enter: Point,
+    exit: Point,
+    w: f64,
+    h: f64,
+    rad: f64,
+    sw: f64,
+    dotted: f64,
+    dashed: f64,
+    fill: f64,
+    color: f64,
+    cw: bool,
+    larrow: bool,
+    rarrow: bool,
+    close: bool,
+    chop: bool,
+    in_dir: Direction,
+    out_dir: Direction,
+    layer: i32,
+    props: u32,
+    calc: u32,
+    path: Vec<Point>,
+    children: Vec<Object>,
+    bounds: Bounds,
+}
+
+impl Object {
+    fn new(class: Class, span: Span) -> Self {
+        Object {
+            class,
+            name: None,
+            labels: Vec::new(),
+            span,
+            at: Point::default(),
+            with: Point::default(),
+            with_edge: Edge::Center,
+            enter: Point::default(),
+            exit: Point::default(),
+            w: 0.0,
+            h: 0.0,
+            rad: 0.0,
+            sw: 0.0,
+            dotted: 0.0,
+            dashed: 0.0,
+            fill: 0.0,
+            color: 0.0,
+            cw: false,
+            larrow: false,
+            rarrow: false,
+            close: false,
+            chop: false,
+            in_dir: Direction::Right,
+            out_dir: Direction::Right,
+            layer: 0,
+            props: 0,
+            calc: 0,
+            path: Vec::new(),
+            children: Vec::new(),
+            bounds: Bounds {
+                sw: Point::default(),
+                ne: Point::default(),
+            },
+        }
+    }
+
+    /// The class of the object, such as `"box"` or `"[]"`
+    ///
+    /// Places, as in `P: A.ne`, have no class.
+    pub fn class(&self) -> Option<&'static str> {
+        self.class.name()
+    }
+
+    /// The label of the object
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Where the object is in the source
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The center of the object
+    pub fn center(&self) -> Point {
+        self.at
+    }
+
+    /// The width of the object
+    pub fn width(&self) -> f64 {
+        self.w
+    }
+
+    /// The height of the object
+    pub fn height(&self) -> f64 {
+        self.h
+    }
+
+    /// The corner radius of the object
+    pub fn radius(&self) -> f64 {
+        self.rad
+    }
+
+    /// The stroke width of the object, which is zero if it is invisible
+    pub fn thickness(&self) -> f64 {
+        self.sw
+    }
+
+    /// Where the object starts, its `.start`
+    pub fn start(&self) -> Point {
+        self.enter
+    }
+
+    /// Where the object ends, its `.end`
+    pub fn end(&self) -> Point {
+        self.exit
+    }
+
+    /// The bounding box of the object, ignoring any text
+    pub fn bounds(&self) -> Bounds {
+        self.bounds
+    }
+
+    /// A point on the object, as in `.n` or `.start`
+    ///
+    /// ```
+    /// use pikchr::ast::Edge;
+    ///
+    /// let diagram = pikchr::layout::layout("box wid 2 ht 1").unwrap();
+    /// let ne = diagram.objects()[0].anchor(Edge::NorthEast);
+    /// assert_eq!((ne.x, ne.y), (1.0, 0.5));
+    /// ```
+    pub fn anchor(&self, edge: Edge) -> Point {
+        match edge {
+            Edge::Start => self.enter,
+            Edge::End => self.exit,
+            _ => {
+                let offset = self.offset(edge);
+                Point::new(self.at.x + offset.x, self.at.y + offset.y)
+            }
+        }
+    }
+
+    /// The vertices of a line, which are empty for other objects
+    pub fn path(&self) -> &[Point] {
+        &self.path
+    }
+
+    /// The objects within a `[...]` sublist
+    pub fn children(&self) -> &[Object] {
+        &self.children
+    }
+
+    /// The text of the object, with backslash escapes as written
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        self.labels.iter().map(|label| label.text.as_str())
+    }
+
+    fn param_ok(&mut self, prop: u32, span: Span) -> Result<()> {
+        if self.props & prop != 0 {
+            return Err(LayoutError::new("value is already set", span));
+        }
+        if self.calc & prop != 0 {
+            return Err(LayoutError::new(
+                "value already fixed by prior constraints",
+                span,
+            ));
+        }
+        self.props |= prop;
+        Ok(())
+    }
+
+    /// Keep the size of circles and ovals consistent after a change
+    fn size_changed(&mut self, property: NumericProperty) {
+        match self.class {
+            Class::Circle => match property {
+                NumericProperty::Radius => {
+                    self.w = 2.0 * self.rad;
+                    self.h = self.w;
+                }
+                NumericProperty::Width => {
+                    self.h = self.w;
+                    self.rad = 0.5 * self.w;
+                }
+                NumericProperty::Height => {
+                    self.w = self.h;
+                    self.rad = 0.5 * self.w;
+                }
+                // As in C, a diameter only changes the radius
+                NumericProperty::Diameter | NumericProperty::Thickness => {}
+            },
+            Class::Oval => self.rad = 0.5 * self.h.min(self.w),
+            _ => {}
+        }
+    }
+
+    /// Keep the colours of dots consistent after a change
+    fn color_changed(&mut self, property: ColorProperty) {
+        match self.class {
+            Class::Dot => match property {
+                ColorProperty::Color => self.fill = self.color,
+                ColorProperty::Fill => self.color = self.fill,
+            },
+            Class::Oval => self.rad = 0.5 * self.h.min(self.w),
+            _ => {}
+        }
+    }
+
+    /// The offset from the center to a compass point
+    fn offset(&self, edge: Edge) -> Point {
+        let w2 = 0.5 * self.w;
+        let h2 = 0.5 * self.h;
+        let (x, y) = match self.class {
+            Class::Circle | Class::Ellipse => {
+                let w = self.w * 0.5;
+                let wd = w * DIAGONAL;
+                let h = self.h * 0.5;
+                let hd = h * DIAGONAL;
+                compass(edge, (0.0, h), (wd, hd), (w, 0.0), (wd, -hd), (0.0, -h))
+            }
+            Class::Cylinder => {
+                let h1 = self.h * 0.5;
+                let h2 = h1 - self.rad;
+                compass(edge, (0.0, h1), (w2, h2), (w2, 0.0), (w2, -h2), (0.0, -h1))
+            }
+            Class::Dot => (0.0, 0.0),
+            Class::File => {
+                let mn = if w2 < h2 { w2 } else { h2 };
+                let mut rx = self.rad;
+                if rx > mn {
+                    rx = mn;
+                }
+                if rx < mn * 0.25 {
+                    rx = mn * 0.25;
+                }
+                rx *= 0.5;
+                let (x, y) = compass(
+                    edge,
+                    (0.0, h2),
+                    (w2 - rx, h2 - rx),
+                    (w2, 0.0),
+                    (w2, -h2),
+                    (0.0, -h2),
+                );
+                match edge {
+                    Edge::SouthWest => (-w2, -h2),
+                    Edge::NorthWest => (-w2, h2),
+                    _ => (x, y),
+                }
+            }
+            _ => {
+                let mut rad = self.rad;
+                let rx = if rad <= 0.0 {
+                    0.0
+                } else {
+                    if rad > w2 {
+                        rad = w2;
+                    }
+                    if rad > h2 {
+                        rad = h2;
+                    }
+                    0.292_893_218_813_452_54 * rad
+                };
+                compass(
+                    edge,
+                    (0.0, h2),
+                    (w2 - rx, h2 - rx),
+                    (w2, 0.0),
+                    (w2 - rx, rx - h2),
+                    (0.0, -h2),
+                )
+            }
+        };
+        Point::new(x, y)
+    }
+
+    /// Where a line from `from` to the center of the object should stop
+    fn chop(&self, from: Point) -> Point {
+        match self.class {
+            Class::Circle | Class::Dot => {
+                let dx = from.x - self.at.x;
+                let dy = from.y - self.at.y;
+                let dist = dx.hypot(dy);
+                if dist < self.rad {
+                    return self.at;
+                }
+                Point::new(
+                    self.at.x + dx * self.rad / dist,
+                    self.at.y + dy * self.rad / dist,
+                )
+            }
+            Class::Ellipse => {
+                let dx = from.x - self.at.x;
+                let dy = from.y - self.at.y;
+                if self.w <= 0.0 || self.h <= 0.0 {
+                    return self.at;
+                }
+                let s = self.h / self.w;
+                let dq = dx * s;
+                let dist = dq.hypot(dy);
+                if dist < self.h {
+                    return self.at;
+                }
+                Point::new(
+                    self.at.x + 0.5 * dq * self.h / (dist * s),
+                    self.at.y + 0.5 * dy * self.h / dist,
+                )
+            }
+            _ => {
+                if self.w <= 0.0 || self.h <= 0.0 {
+                    return self.at;
+                }
+                let dx = (from.x - self.at.x) * self.h / self.w;
+                let dy = from.y - self.at.y;
+                let edge = if dx > 0.0 {
+                    if dy >= 2.414 * dx {
+                        Edge::North
+                    } else if dy >= 0.414 * dx {
+                        Edge::NorthEast
+                    } else if dy >= -0.414 * dx {
+                        Edge::East
+                    } else if dy > -2.414 * dx {
+                        Edge::SouthEast
+                    } else {
+                        Edge::South
+                    }
+                } else if dy >= -2.414 * dx {
+                    Edge::North
+                } else if dy >= -0.414 * dx {
+                    Edge::NorthWest
+                } else if dy >= 0.414 * dx {
+                    Edge::West
+                } else if dy > 2.414 * dx {
+                    Edge::SouthWest
+                } else {
+                    Edge::South
+                };
+                let offset = self.offset(edge);
+                Point::new(self.at.x + offset.x, self.at.y + offset.y)
+            }
+        }
+    }
+
+    /// Resize the object to fit the given width and height, where a size
+    /// of zero leaves that dimension alone
+    fn fit(&mut self, w: f64, h: f64) {
+        match self.class {
+            Class::Circle => {
+                let mut mx = 0.0;
+                if w > 0.0 {
+                    mx = w;
+                }
+                if h > mx {
+                    mx = h;
+                }
+                if w * h > 0.0 && (w * w + h * h) > mx * mx {
+                    mx = w.hypot(h);
+                }
+                if mx > 0.0 {
+                    self.rad = 0.5 * mx;
+                    self.w = mx;
+                    self.h = mx;
+                }
+            }
+            Class::Cylinder | Class::File => {
+                if w > 0.0 {
+                    self.w = w;
+                }
+                if h > 0.0 {
+                    self.h = if self.class == Class::File {
+                        h + 2.0 * self.rad
+                    } else {
+                        h + 0.25 * self.rad + self.sw
+                    };
+                }
+            }
+            _ => {
+                if w > 0.0 {
+                    self.w = w;
+                }
+                if h > 0.0 {
+                    self.h = h;
+                }
+                if self.class == Class::Oval {
+                    if self.w < self.h {
+                        self.w = self.h;
+                    }
+                    self.rad = 0.5 * self.h.min(self.w);
+                }
+            }
+        }
+    }
+
+    /// Point the exit of the object in a new direction
+    fn set_exit(&mut self, dir: Direction) {
+        self.out_dir = dir;
+        if !self.class.is_line() || self.close {
+            self.exit = self.at;
+            match dir {
+                Direction::Right => self.exit.x += self.w * 0.5,
+                Direction::Left => self.exit.x -= self.w * 0.5,
+                Direction::Up => self.exit.y += self.h * 0.5,
+                Direction::Down => self.exit.y -= self.h * 0.5,
+            }
+        }
+    }
+
+    fn shift(&mut self, dx: f64, dy: f64) {
+        let points = [
+            &mut self.at,
+            &mut self.enter,
+            &mut self.exit,
+            &mut self.bounds.ne,
+            &mut self.bounds.sw,
+        ];
+        for pt in IntoIterator::into_iter(points).chain(self.path.iter_mut()) {
+            pt.x += dx;
+            pt.y += dy;
+        }
+        for child in &mut self.children {
+            child.shift(dx, dy);
+        }
+    }
+}
+
+/// Pick the offset for a compass point, given those for the eastern half
+/// of a shape which is symmetrical about its vertical axis
+fn compass(
+    edge: Edge,
+    n: (f64, f64),
+    ne: (f64, f64),
+    e: (f64, f64),
+    se: (f64, f64),
+    s: (f64, f64),
+) -> (f64, f64) {
+    match edge {
+        Edge::North => n,
+        Edge::NorthEast => ne,
+        Edge::East => e,
+        Edge::SouthEast => se,
+        Edge::South => s,
+        Edge::SouthWest => (-se.0, se.1),
+        Edge::West => (-e.0, e.1),
+        Edge::NorthWest => (-ne.0, ne.1),
+        Edge::Center | Edge::Start | Edge::End => (0.0, 0.0),
+    }
+}
+
+/// The sine of 45 degrees as written in the C implementation, which is
+/// one bit less than [`std::f64::consts::FRAC_1_SQRT_2`]
+#[allow(clippy::approx_constant)]
+const DIAGONAL: f64 = 0.707_106_781_186_547_5;
+
+/// SVG units per inch
+pub(crate) const SCALE: f64 = 144.0;
+
+const A_WIDTH: u32 = 0x0001;
+const A_HEIGHT: u32 = 0x0002;
+const A_RADIUS: u32 = 0x0004;
+const A_THICKNESS: u32 = 0x0008;
+const A_FILL: u32 = 0x0020;
+const A_COLOR: u32 = 0x0040;
+const A_FROM: u32 = 0x0100;
+const A_AT: u32 = 0x0400;
+const A_FIT: u32 = 0x1000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Class {
+    Arc,
+    Arrow,
+    Box,
+    Circle,
+    Cylinder,
+    Dot,
+    Ellipse,
+    File,
+    Line,
+    Move,
+    Oval,
+    Spline,
+    Text,
+    Sublist,
+    Place,
+}
+
+impl Class {
+    fn named(name: &str) -> Option<Class> {
+        Some(match name {
+            "arc" => Class::Arc,
+            "arrow" => Class::Arrow,
+            "box" => Class::Box,
+            "circle" => Class::Circle,
+            "cylinder" => Class::Cylinder,
+            "dot" => Class::Dot,
+            "ellipse" => Class::Ellipse,
+            "file" => Class::File,
+            "line" => Class::Line,
+            "move" => Class::Move,
+            "oval" => Class::Oval,
+            "spline" => Class::Spline,
+            "text" => Class::Text,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Class::Arc => "arc",
+            Class::Arrow => "arrow",
+            Class::Box => "box",
+            Class::Circle => "circle",
+            Class::Cylinder => "cylinder",
+            Class::Dot => "dot",
+            Class::Ellipse => "ellipse",
+            Class::File => "file",
+            Class::Line => "line",
+            Class::Move => "move",
+            Class::Oval => "oval",
+            Class::Spline => "spline",
+            Class::Text => "text",
+            Class::Sublist => "[]",
+            Class::Place => return None,
+        })
+    }
+
+    fn is_line(self) -> bool {
+        matches!(
+            self,
+            Class::Arc | Class::Arrow | Class::Line | Class::Move | Class::Spline
+        )
+    }
+
+    /// Whether text can be justified towards the sides of the object
+    fn justifies(self) -> bool {
+        matches!(
+            self,
+            Class::Box | Class::Cylinder | Class::File | Class::Oval
+        )
+    }
+
+    /// Whether lines to the center of the object are chopped at its edge
+    fn chops(self) -> bool {
+        matches!(
+            self,
+            Class::Box
+                | Class::Circle
+                | Class::Cylinder
+                | Class::Dot
+                | Class::Ellipse
+                | Class::File
+                | Class::Oval
+                | Class::Text
+        )
+    }
+
+    /// Whether the object can be resized to fit its text
+    fn fits(self) -> bool {
+        matches!(
+            self,
+            Class::Box
+                | Class::Circle
+                | Class::Cylinder
+                | Class::Ellipse
+                | Class::File
+                | Class::Oval
+                | Class::Text
+        )
+    }
+}
+
+const TP_LJUST: u16 = 0x0001;
+const TP_RJUST: u16 = 0x0002;
+const TP_JMASK: u16 = 0x0003;
+const TP_ABOVE2: u16 = 0x0004;
+const TP_ABOVE: u16 = 0x0008;
+const TP_CENTER: u16 = 0x0010;
+const TP_BELOW: u16 = 0x0020;
+const TP_BELOW2: u16 = 0x0040;
+const TP_VMASK: u16 = 0x007c;
+const TP_BIG: u16 = 0x0100;
+const TP_SMALL: u16 = 0x0200;
+const TP_XTRA: u16 = 0x0400;
+const TP_SZMASK: u16 = 0x0700;
+const TP_ITALIC: u16 = 0x1000;
+const TP_BOLD: u16 = 0x2000;
+const TP_ALIGN: u16 = 0x4000;
+
+/// A piece of text attached to an object
+#[derive(Clone, Debug)]
+struct Label {
+    text: String,
+    flags: u16,
+    /// The closing quote and a few following bytes of source, which the C
+    /// implementation looks at when measuring entities near the end
+    #[cfg_attr(feature = "unicode-metrics", allow(dead_code))]
+    trailing: Vec<u8>,
+    /// The width of the text in inches when `fontscale` is 1, if a
+    /// callback measured it
+    width: Option<f64>,
+}
+
+impl Label {
+    fn scale(&self) -> f64 {
+        let mut scale = 1.0;
+        if self.flags & TP_BIG != 0 {
+            scale *= 1.25;
+        }
+        if self.flags & TP_SMALL != 0 {
+            scale *= 0.8;
+        }
+        if self.flags & TP_XTRA != 0 {
+            scale *= scale;
+        }
+        scale
+    }
+
+    /// The width of the text, in hundredths of a character
+    #[cfg(feature = "unicode-metrics")]
+    fn length(&self) -> i32 {
+        (crate::measure::text_width(&self.text) * 100.0).round() as i32
+    }
+
+    /// The estimated width of the text, in hundredths of a character
+    #[cfg(not(feature = "unicode-metrics"))]
+    fn length(&self) -> i32 {
+        let z = self.text.as_bytes();
+        let n = z.len();
+        let at = |j: usize| -> u8 {
+            match z.get(j) {
+                Some(&c) => c,
+                None => self.trailing.get(j - n).copied().unwrap_or(0),
+            }
+        };
+        let mut count = 0;
+        let mut j = 0;
+        while j < n {
+            let mut c = z[j];
+            if c == b'\\' && at(j + 1) != b'&' {
+                j += 1;
+                c = at(j);
+            } else if c == b'&' {
+                let mut k = j + 1;
+                while k < j + 7 && at(k) != 0 && at(k) != b';' {
+                    k += 1;
+                }
+                if at(k) == b';' {
+                    j = k;
+                }
+                count += 150;
+                j += 1;
+                continue;
+            }
+            if c & 0xc0 == 0xc0 {
+                while j + 1 < n && z[j + 1] & 0xc0 == 0x80 {
+                    j += 1;
+                }
+                count += 100;
+            } else if (0x20..=0x7e).contains(&c) {
+                count += i32::from(crate::measure::CHAR_WIDTHS[usize::from(c - 0x20)]);
+            } else {
+                count += 100;
+            }
+            j += 1;
+        }
+        count
+    }
+}
+
+fn text_position(flags: u16, position: TextPosition) -> u16 {
+    match position {
+        TextPosition::LJust => (flags & !TP_JMASK) | TP_LJUST,
+        TextPosition::RJust => (flags & !TP_JMASK) | TP_RJUST,
+        TextPosition::Above => (flags & !TP_VMASK) | TP_ABOVE,
+        TextPosition::Center => (flags & !TP_VMASK) | TP_CENTER,
+        TextPosition::Below => (flags & !TP_VMASK) | TP_BELOW,
+        TextPosition::Italic => flags | TP_ITALIC,
+        TextPosition::Bold => flags | TP_BOLD,
+        TextPosition::Aligned => flags | TP_ALIGN,
+        TextPosition::Big if flags & TP_BIG != 0 => flags | TP_XTRA,
+        TextPosition::Big => (flags & !TP_SZMASK) | TP_BIG,
+        TextPosition::Small if flags & TP_SMALL != 0 => flags | TP_XTRA,
+        TextPosition::Small => (flags & !TP_SZMASK) | TP_SMALL,
+    }
+}
+
+/// Give every label exactly one vertical position
+///
+/// Like the C implementation, this is applied to the labels in place each
+/// time the text is measured or drawn.
+fn vertical_layout(labels: &mut [Label]) {
+    let n = labels.len();
+    if n == 0 {
+        return;
+    }
+    if n == 1 {
+        if labels[0].flags & TP_VMASK == 0 {
+            labels[0].flags |= TP_CENTER;
+        }
+        return;
+    }
+    // If there is more than one `above`, the first becomes the higher row
+    let (mut seen, mut just) = (0, 0);
+    for label in labels.iter_mut().rev() {
+        if label.flags & TP_ABOVE != 0 {
+            if seen == 0 {
+                seen += 1;
+                just = label.flags & TP_JMASK;
+            } else if seen == 1 && just != 0 && label.flags & just == 0 {
+                seen += 1;
+            } else {
+                label.flags = (label.flags & !TP_VMASK) | TP_ABOVE2;
+                break;
+            }
+        }
+    }
+    // Similarly the last of several `below` becomes the lower row
+    let (mut seen, mut just) = (0, 0);
+    for label in labels.iter_mut() {
+        if label.flags & TP_BELOW != 0 {
+            if seen == 0 {
+                seen += 1;
+                just = label.flags & TP_JMASK;
+            } else if seen == 1 && just != 0 && label.flags & just == 0 {
+                seen += 1;
+            } else {
+                label.flags = (label.flags & !TP_VMASK) | TP_BELOW2;
+                break;
+            }
+        }
+    }
+    let used = labels.iter().fold(0, |used, l| used | (l.flags & TP_VMASK));
+    let mut free = Vec::new();
+    if n == 2 && (labels[0].flags | labels[1].flags) & TP_JMASK == TP_LJUST | TP_RJUST {
+        free.extend_from_slice(&[TP_CENTER, TP_CENTER]);
+    } else {
+        if n >= 4 && used & TP_ABOVE2 == 0 {
+            free.push(TP_ABOVE2);
+        }
+        if used & TP_ABOVE == 0 {
+            free.push(TP_ABOVE);
+        }
+        if n & 1 != 0 {
+            free.push(TP_CENTER);
+        }
+        if used & TP_BELOW == 0 {
+            free.push(TP_BELOW);
+        }
+        if n >= 4 && used & TP_BELOW2 == 0 {
+            free.push(TP_BELOW2);
+        }
+    }
+    let mut free = free.into_iter();
+    for label in labels.iter_mut() {
+        if label.flags & TP_VMASK == 0 {
+            label.flags |= free.next().unwrap_or(0);
+        }
+    }
+}
+
+/// The layout settings which are derived from variables
+struct Settings {
+    w_arrow: f64,
+    h_arrow: f64,
+    font_scale: f64,
+    char_width: f64,
+    char_height: f64,
+}
+
+impl Settings {
+    fn new(variables: &HashMap<String, f64>) -> Self {
+        let var = |name: &str| value(variables, name).unwrap_or(0.0);
+        let mut thickness = var("thickness");
+        if thickness <= 0.01 {
+            thickness = 0.01;
+        }
+        let mut font_scale = var("fontscale");
+        if font_scale <= 0.0 {
+            font_scale = 1.0;
+        }
+        Settings {
+            w_arrow: 0.5 * var("arrowwid") / thickness,
+            h_arrow: var("arrowht") / thickness,
+            font_scale,
+            char_width: var("charwid") * font_scale,
+            char_height: var("charht") * font_scale,
+        }
+    }
+}
+
+/// Look up a variable, falling back to the built-in value
+fn value(variables: &HashMap<String, f64>, name: &str) -> Option<f64> {
+    variables.get(name).copied().or_else(|| builtin(name))
+}
+
+/// Work out where each label goes, relative to the center of the object
+fn text_offsets(object: &mut Object, settings: &Settings) -> Vec<(f64, f64)> {
+    vertical_layout(&mut object.labels);
+    let labels = &object.labels;
+    let all = labels.iter().fold(0, |all, l| all | l.flags);
+    let row = |flag: u16, initial: f64| {
+        labels
+            .iter()
+            .filter(|l| l.flags & flag != 0)
+            .map(|l| l.scale() * settings.char_height)
+            .fold(initial, |h, s| if h < s { s } else { h })
+    };
+    let mut hc = 0.0;
+    let mut y_base = 0.0;
+    if object.class.is_line() {
+        hc = object.sw * 1.5;
+    } else if object.rad > 0.0 && object.class == Class::Cylinder {
+        y_base = -0.75 * object.rad;
+    }
+    if all & TP_CENTER != 0 {
+        hc = row(TP_CENTER, hc);
+    }
+    let (mut ha1, mut ha2, mut hb1, mut hb2) = (0.0, 0.0, 0.0, 0.0);
+    if all & TP_ABOVE != 0 {
+        ha1 = row(TP_ABOVE, 0.0);
+        if all & TP_ABOVE2 != 0 {
+            ha2 = row(TP_ABOVE2, 0.0);
+        }
+    }
+    if all & TP_BELOW != 0 {
+        hb1 = row(TP_BELOW, 0.0);
+        if all & TP_BELOW2 != 0 {
+            hb2 = row(TP_BELOW2, 0.0);
+        }
+    }
+    let jw = if object.class.justifies() {
+        0.5 * (object.w - 0.5 * (settings.char_width + object.sw))
+    } else {
+        0.0
+    };
+    labels
+        .iter()
+        .map(|label| {
+            let mut y = y_base;
+            let mut nx = 0.0;
+            if label.flags & TP_ABOVE2 != 0 {
+                y += 0.5 * hc + ha1 + 0.5 * ha2;
+            }
+            if label.flags & TP_ABOVE != 0 {
+                y += 0.5 * hc + 0.5 * ha1;
+            }
+            if label.flags & TP_BELOW != 0 {
+                y -= 0.5 * hc + 0.5 * hb1;
+            }
+            if label.flags & TP_BELOW2 != 0 {
+                y -= 0.5 * hc + hb1 + 0.5 * hb2;
+            }
+            if label.flags & TP_LJUST != 0 {
+                nx -= jw;
+            }
+            if label.flags & TP_RJUST != 0 {
+                nx += jw;
+            }
+            (nx, y)
+        })
+        .collect()
+}
+
+/// The direction of a line from its start to its end, if it has one
+fn alignment(object: &Object) -> Option<(f64, f64)> {
+    let (first, last) = (object.path.first()?, object.path.last()?);
+    if object.path.len() < 2 {
+        return None;
+    }
+    let dx = last.x - first.x;
+    let dy = last.y - first.y;
+    if dx != 0.0 || dy != 0.0 {
+        Some((dx, dy))
+    } else {
+        None
+    }
+}
+
+/// Expand a bounding box to cover the text of an object
+fn add_text_bounds(bounds: &mut Bounds, object: &mut Object, settings: &Settings) {
+    if object.labels.is_empty() {
+        return;
+    }
+    let offsets = text_offsets(object, settings);
+    let x = object.at.x;
+    let y = object.at.y;
+    for (label, (nx, ny)) in object.labels.iter().zip(offsets) {
+        let scale = label.scale();
+        let mut cw = match label.width {
+            Some(width) => width * settings.font_scale,
+            None => f64::from(label.length()) * settings.char_width * scale * 0.01,
+        };
+        let ch = settings.char_height * 0.5 * scale;
+        if label.flags & TP_BOLD != 0 {
+            cw *= 1.1;
+        }
+        let (mut x0, mut y0, mut x1, mut y1) = if label.flags & TP_RJUST != 0 {
+            (nx, ny - ch, nx - cw, ny + ch)
+        } else if label.flags & TP_LJUST != 0 {
+            (nx, ny - ch, nx + cw, ny + ch)
+        } else {
+            (nx + cw / 2.0, ny + ch, nx - cw / 2.0, ny - ch)
+        };
+        if label.flags & TP_ALIGN != 0 {
+            if let Some((dx, dy)) = alignment(object) {
+                let dist = dx.hypot(dy);
+                let (dx, dy) = (dx / dist, dy / dist);
+                let t = dx * x0 - dy * y0;
+                y0 = dy * x0 - dx * y0;
+                x0 = t;
+                let t = dx * x1 - dy * y1;
+                y1 = dy * x1 - dx * y1;
+                x1 = t;
+            }
+        }
+        bounds.add_point(x + x0, y + y0);
+        bounds.add_point(x + x1, y + y1);
+    }
+}
+
+/// Expand a bounding box to cover a list of objects, their text and their
+/// arrowheads
+fn add_list_bounds(bounds: &mut Bounds, objects: &mut [Object], w_arrow: f64, settings: &Settings) {
+    for object in objects {
+        if object.sw > 0.0 {
+            bounds.add_bounds(&object.bounds);
+        }
+        add_text_bounds(bounds, object, settings);
+        if object.class == Class::Sublist {
+            add_list_bounds(bounds, &mut object.children, w_arrow, settings);
+        }
+        if object.class.is_line() {
+            if let (Some(first), Some(last)) = (object.path.first(), object.path.last()) {
+                if object.larrow {
+                    bounds.add_ellipse(first.x, first.y, w_arrow, w_arrow);
+                }
+                if object.rarrow {
+                    bounds.add_ellipse(last.x, last.y, w_arrow, w_arrow);
+                }
+            }
+        }
+    }
+}
+
+/// Find a choppable object centered exactly on a point
+fn find_chopper(objects: &[Object], center: Point) -> Option<&Object> {
+    objects.iter().rev().find_map(|object| {
+        if object.class.chops() && object.at == center {
+            Some(object)
+        } else {
+            find_chopper(&object.children, center)
+        }
+    })
+}
+
+/// The compass heading of a compass point, in degrees
+fn heading(edge: Edge) -> f64 {
+    match edge {
+        Edge::NorthEast => 45.0,
+        Edge::East => 90.0,
+        Edge::SouthEast => 135.0,
+        Edge::South => 180.0,
+        Edge::SouthWest => 225.0,
+        Edge::West => 270.0,
+        Edge::NorthWest => 315.0,
+        Edge::North | Edge::Center | Edge::Start | Edge::End => 0.0,
+    }
+}
+
+/// The point `dist` away from `pt` at a compass heading in degrees
+fn at_heading(dist: f64, heading: f64, pt: Point) -> Point {
+    let r = heading.to_radians();
+    Point::new(pt.x + dist * r.sin(), pt.y + dist * r.cos())
+}
+
+/// The control point of the quadratic curve used to draw an arc
+fn arc_control(cw: bool, f: Point, t: Point, scale: f64) -> Point {
+    let mut m = Point::new(0.5 * (f.x + t.x), 0.5 * (f.y + t.y));
+    let dx = t.x - f.x;
+    let dy = t.y - f.y;
+    if cw {
+        m.x -= 0.5 * scale * dy;
+        m.y += 0.5 * scale * dx;
+    } else {
+        m.x += 0.5 * scale * dy;
+        m.y -= 0.5 * scale * dx;
+    }
+    m
+}
+
+/// Format a number like C's `printf("%.*g")`
+pub(crate) fn format_g(value: f64, precision: usize) -> String {
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    if !value.is_finite() {
+        return if value.is_nan() {
+            "nan"
+        } else if value > 0.0 {
+            "inf"
+        } else {
+            "-inf"
+        }
+        .to_string();
+    }
+    let precision = precision.max(1);
+    let scientific = format!("{:.*e}", precision - 1, value);
+    let (mantissa, exponent) = scientific.split_at(scientific.find('e').unwrap_or(0));
+    let exponent: i32 = exponent[1..].parse().unwrap_or(0);
+    let trim = |s: &str| {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa), sign, exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        trim(&format!("{:.*}", decimals, value))
+    }
+}
+
+/// Escape `<` and `>`, and optionally turn spaces into non-breaking spaces
+fn escape(out: &mut String, text: &str, spaces: bool) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            ' ' if spaces => out.push('\u{a0}'),
+            c => out.push(c),
+        }
+    }
+}
+
+/// A reference to an object being laid out
+///
+/// This is a path of indices through sublists, starting from either the
+/// current object or the current list.
+#[derive(Clone, Debug)]
+struct Handle {
+    current: bool,
+    path: Vec<usize>,
+}
+
+/// The state of the layout, following the C implementation
+struct Engine<'a> {
+    source: &'a str,
+    measure: Option<MeasureFn<'a>>,
+    variables: HashMap<String, f64>,
+    dir: Direction,
+    list: Vec<Object>,
+    cur: Option<Object>,
+    path: Vec<Point>,
+    path_mask: u8,
+    then: bool,
+    same_path: bool,
+    printed: String,
+    /// The steps so far, if the layout is being traced
+    trace: Option<Vec<Step>>,
+    /// How many sublists the current statement is within
+    depth: usize,
+}
+
+/// The object currently being laid out
+fn current(cur: &mut Option<Object>) -> &mut Object {
+    cur.as_mut().expect("an object is being laid out")
+}
+
+/// A relative value, as in `wid 150%`
+#[derive(Copy, Clone)]
+struct Rel {
+    abs: f64,
+    rel: f64,
+}
+
+/// The maximum number of vertices in a line
+const MAX_PATH: usize = 1000;
+
+impl<'a> Engine<'a> {
+    fn new(source: &'a str, measure: Option<MeasureFn<'a>>) -> Self {
+        Engine {
+            source,
+            measure,
+            variables: HashMap::new(),
+            dir: Direction::Right,
+            list: Vec::new(),
+            cur: None,
+            path: vec![Point::default()],
+            path_mask: 0,
+            then: false,
+            same_path: false,
+            printed: String::new(),
+            trace: None,
+            depth: 0,
+        }
+    }
+
+    fn var(&self, name: &str) -> f64 {
+        value(&self.variables, name).unwrap_or(0.0)
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<()> {
+        for statement in statements {
+            self.statement(statement)?;
+            if self.trace.is_some() {
+                self.record(statement);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the state after a statement to the trace
+    fn record(&mut self, statement: &Statement) {
+        let object = match &statement.kind {
+            StatementKind::Object(_) | StatementKind::Place(_) => self.list.last().cloned(),
+            _ => None,
+        };
+        let variable = match &statement.kind {
+            StatementKind::Assignment { variable, .. } => {
+                Some((variable.text.clone(), self.var(&variable.text)))
+            }
+            _ => None,
+        };
+        let span = statement.span;
+        let step = Step {
+            span,
+            line: self.source[..span.start].matches('\n').count() + 1,
+            text: self.source[span.start..span.end].to_string(),
+            depth: self.depth,
+            direction: self.dir,
+            object,
+            variable,
+        };
+        if let Some(trace) = &mut self.trace {
+            trace.push(step);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<()> {
+        match &statement.kind {
+            StatementKind::Direction(dir) => {
+                self.dir = *dir;
+                if let Some(last) = self.list.last_mut() {
+                    last.set_exit(*dir);
+                }
+            }
+            StatementKind::Assignment {
+                variable,
+                op,
+                value,
+            } => {
+                let value = self.expr(value)?;
+                self.assign(variable, *op, value)?;
+            }
+            StatementKind::Object(object) => {
+                let mut object = self.object(object)?;
+                object.name = statement.label.as_ref().map(|l| l.text.clone());
+                self.list.push(object);
+            }
+            StatementKind::Place(position) => {
+                let at = self.position(position)?;
+                let mut object = self.create(Class::Place, statement.span);
+                object.enter = object.at;
+                object.exit = object.at;
+                object.at = at;
+                object.name = statement.label.as_ref().map(|l| l.text.clone());
+                self.list.push(object);
+            }
+            StatementKind::Print(items) => {
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        self.printed.push(' ');
+                    }
+                    match item {
+                        PrintItem::Text(text) => escape(&mut self.printed, &text.text, false),
+                        PrintItem::Value(expr) => {
+                            let value = self.expr(expr)?;
+                            self.printed.push_str(&format_g(value, 10));
+                        }
+                    }
+                }
+                self.printed.push_str("<br>\n");
+            }
+            StatementKind::Assert(Assertion::Equal(lhs, rhs)) => {
+                let lhs = format_g(self.expr(lhs)?, 6);
+                let rhs = format_g(self.expr(rhs)?, 6);
+                if lhs != rhs {
+                    let message = format!("{} != {}", lhs, rhs);
+                    return Err(LayoutError::new(&message, statement.span));
+                }
+            }
+            StatementKind::Assert(Assertion::SamePosition(lhs, rhs)) => {
+                let point = |pt: Point| format!("({},{})", format_g(pt.x, 6), format_g(pt.y, 6));
+                let lhs = point(self.position(lhs)?);
+                let rhs = point(self.position(rhs)?);
+                if lhs != rhs {
+                    let message = format!("{} != {}", lhs, rhs);
+                    return Err(LayoutError::new(&message, statement.span));
+                }
+            }
+            StatementKind::Define { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, variable: &Name, op: AssignOp, value: f64) -> Result<()> {
+        let old = self.var(&variable.text);
+        let new = match op {
+            AssignOp::Assign => value,
+            AssignOp::Add => old + value,
+            AssignOp::Subtract => old - value,
+            AssignOp::Multiply => old * value,
+            AssignOp::Divide if value == 0.0 => {
+                return Err(LayoutError::new("division by zero", variable.span))
+            }
+            AssignOp::Divide => old / value,
+        };
+        self.variables.insert(variable.text.clone(), new);
+        Ok(())
+    }
+
+    fn label(&self, text: &Text) -> Label {
+        let quoted = self.source.get(text.span.start..text.span.end);
+        let trailing = match quoted {
+            Some(q) if q.len() == text.text.len() + 2 && q[1..q.len() - 1] == text.text => {
+                self.source.as_bytes()[text.span.end - 1..]
+                    .iter()
+                    .take(8)
+                    .copied()
+                    .collect()
+            }
+            _ => b"\"".to_vec(),
+        };
+        let mut label = Label {
+            text: text.text.clone(),
+            flags: text.positions.iter().fold(0, |f, &p| text_position(f, p)),
+            trailing,
+            width: None,
+        };
+        if let Some(measure) = self.measure {
+            let font_scale = Settings::new(&self.variables).font_scale;
+            let width = measure(&displayed(&label.text), label.scale() * font_scale);
+            if width.is_finite() && width >= 0.0 {
+                label.width = Some(width / SCALE / font_scale);
+            }
+        }
+        label
+    }
+
+    /// Start a new object, positioned after the previous one
+    fn create(&mut self, class: Class, span: Span) -> Object {
+        let mut object = Object::new(class, span);
+        if let Some(prior) = self.list.last() {
+            object.at = prior.exit;
+            object.with_edge = match self.dir {
+                Direction::Right => Edge::West,
+                Direction::Left => Edge::East,
+                Direction::Up => Edge::South,
+                Direction::Down => Edge::North,
+            };
+        }
+        self.path = vec![object.at];
+        self.then = false;
+        object.with = object.at;
+        object.in_dir = self.dir;
+        object.out_dir = self.dir;
+        object.layer = value(&self.variables, "layer").map_or(1000, |l| l as i32);
+        if object.layer < 0 {
+            object.layer = 0;
+        }
+        object
+    }
+
+    fn object(&mut self, object: &ast::Object) -> Result<Object> {
+        let span = object.span;
+        let created = match &object.kind {
+            ObjectKind::Sublist(statements) => {
+                let outer = std::mem::take(&mut self.list);
+                self.depth += 1;
+                let result = self.statements(statements);
+                self.depth -= 1;
+                let children = std::mem::replace(&mut self.list, outer);
+                result?;
+                if children.is_empty() {
+                    // An empty sublist is just a place, as in C
+                    let mut created = self.create(Class::Place, span);
+                    created.enter = created.at;
+                    created.exit = created.at;
+                    created
+                } else {
+                    let mut created = self.create(Class::Sublist, span);
+                    let mut bounds = Bounds::empty();
+                    for child in &children {
+                        bounds.add_bounds(&child.bounds);
+                    }
+                    created.w = bounds.width();
+                    created.h = bounds.height();
+                    created.at = Point::new(
+                        0.5 * (bounds.ne.x + bounds.sw.x),
+                        0.5 * (bounds.ne.y + bounds.sw.y),
+                    );
+                    created.bounds = bounds;
+                    created.calc |= A_WIDTH | A_HEIGHT | A_RADIUS;
+                    created.children = children;
+                    created
+                }
+            }
+            ObjectKind::Text(text) => {
+                let mut created = self.create(Class::Text, span);
+                created.labels.push(self.label(text));
+                created
+            }
+            ObjectKind::Class(name) => {
+                let class = Class::named(&name.text)
+                    .ok_or_else(|| LayoutError::new("unknown object type", name.span))?;
+                let mut created = self.create(class, span);
+                created.sw = self.var("thickness");
+                created.fill = self.var("fill");
+                created.color = self.var("color");
+                self.init(&mut created);
+                created
+            }
+        };
+        self.cur = Some(created);
+
+        // A leading distance, as in `line 2`, is only applied after all of
+        // the other attributes
+        let mut attributes = &object.attributes[..];
+        let mut leading = None;
+        if let Some((
+            Attribute {
+                kind:
+                    AttributeKind::Go {
+                        direction: None,
+                        distance: Some(distance),
+                    },
+                span,
+            },
+            rest,
+        )) = attributes.split_first()
+        {
+            leading = Some((self.relexpr(distance)?, *span));
+            attributes = rest;
+        }
+        for attribute in attributes {
+            self.attribute(attribute)?;
+        }
+        if let Some((distance, span)) = leading {
+            self.go(None, distance, span)?;
+        }
+        self.finish()?;
+        Ok(self.cur.take().expect("an object is being laid out"))
+    }
+
+    /// Give a new object its default size
+    fn init(&self, object: &mut Object) {
+        let var = |name| self.var(name);
+        match object.class {
+            Class::Arc => {
+                object.w = var("arcrad");
+                object.h = object.w;
+            }
+            Class::Arrow | Class::Line => {
+                object.w = var("linewid");
+                object.h = var("lineht");
+                object.rad = var("linerad");
+                object.rarrow = object.class == Class::Arrow;
+            }
+            Class::Box => {
+                object.w = var("boxwid");
+                object.h = var("boxht");
+                object.rad = var("boxrad");
+            }
+            Class::Circle => {
+                object.w = var("circlerad") * 2.0;
+                object.h = object.w;
+                object.rad = 0.5 * object.w;
+            }
+            Class::Cylinder => {
+                object.w = var("cylwid");
+                object.h = var("cylht");
+                object.rad = var("cylrad");
+            }
+            Class::Dot => {
+                object.rad = var("dotrad");
+                object.w = object.rad * 6.0;
+                object.h = object.w;
+                object.fill = object.color;
+            }
+            Class::Ellipse => {
+                object.w = var("ellipsewid");
+                object.h = var("ellipseht");
+            }
+            Class::File => {
+                object.w = var("filewid");
+                object.h = var("fileht");
+                object.rad = var("filerad");
+            }
+            Class::Move => {
+                object.w = var("movewid");
+                object.h = object.w;
+                object.fill = -1.0;
+                object.color = -1.0;
+                object.sw = -1.0;
+            }
+            Class::Oval => {
+                object.h = var("ovalht");
+                object.w = var("ovalwid");
+                object.rad = 0.5 * object.h.min(object.w);
+            }
+            Class::Spline => {
+                object.w = var("linewid");
+                object.h = var("lineht");
+                object.rad = 1000.0;
+            }
+            Class::Text => object.sw = 0.0,
+            Class::Sublist | Class::Place => {}
+        }
+    }
+
+    fn attribute(&mut self, attribute: &Attribute) -> Result<()> {
+        let span = attribute.span;
+        match &attribute.kind {
+            AttributeKind::Numeric { property, value } => {
+                let value = self.relexpr(value)?;
+                let cur = current(&mut self.cur);
+                let prop = match property {
+                    NumericProperty::Height => A_HEIGHT,
+                    NumericProperty::Width => A_WIDTH,
+                    NumericProperty::Radius | NumericProperty::Diameter => A_RADIUS,
+                    NumericProperty::Thickness => A_THICKNESS,
+                };
+                cur.param_ok(prop, span)?;
+                match property {
+                    NumericProperty::Height => cur.h = cur.h * value.rel + value.abs,
+                    NumericProperty::Width => cur.w = cur.w * value.rel + value.abs,
+                    NumericProperty::Radius => cur.rad = cur.rad * value.rel + value.abs,
+                    NumericProperty::Diameter => cur.rad = cur.rad * value.rel + 0.5 * value.abs,
+                    NumericProperty::Thickness => cur.sw = cur.sw * value.rel + value.abs,
+                }
+                cur.size_changed(*property);
+            }
+            AttributeKind::Dash { style, spacing } => {
+                let v = match spacing {
+                    Some(spacing) => self.expr(spacing)?,
+                    None => self.var("dashwid"),
+                };
+                let cur = current(&mut self.cur);
+                match style {
+                    DashStyle::Dotted => {
+                        cur.dotted = v;
+                        cur.dashed = 0.0;
+                    }
+                    DashStyle::Dashed => {
+                        cur.dashed = v;
+                        cur.dotted = 0.0;
+                    }
+                }
+            }
+            AttributeKind::Color { property, value } => {
+                let value = self.expr(value)?;
+                let cur = current(&mut self.cur);
+                let prop = match property {
+                    ColorProperty::Fill => A_FILL,
+                    ColorProperty::Color => A_COLOR,
+                };
+                cur.param_ok(prop, span)?;
+                match property {
+                    ColorProperty::Fill => cur.fill = value,
+                    ColorProperty::Color => cur.color = value,
+                }
+                cur.color_changed(*property);
+            }
+            AttributeKind::Go {
+                direction,
+                distance,
+            } => {
+                let distance = self.opt_relexpr(distance.as_ref())?;
+                self.go(*direction, distance, span)?;
+            }
+            AttributeKind::Even {
+                direction,
+                position,
+            } => {
+                let pt = self.position(position)?;
+                self.even_with(*direction, pt, span)?;
+            }
+            AttributeKind::Heading {
+                distance, heading, ..
+            } => {
+                let distance = self.opt_relexpr(distance.as_ref())?;
+                let heading = (self.expr(heading)?, span);
+                self.go_heading(distance, Some(heading), None, span)?;
+            }
+            AttributeKind::EdgeHeading { distance, edge, .. } => {
+                let distance = self.opt_relexpr(distance.as_ref())?;
+                self.go_heading(distance, None, Some(*edge), span)?;
+            }
+            AttributeKind::Then => {
+                let cur = current(&mut self.cur);
+                if !cur.class.is_line() {
+                    return Err(LayoutError::new(
+                        "use with line-oriented objects only",
+                        span,
+                    ));
+                }
+                if self.path.len() < 2 && cur.props & A_FROM == 0 {
+                    return Err(LayoutError::new("no prior path points", span));
+                }
+                self.then = true;
+            }
+            AttributeKind::Close => {
+                if self.path.len() < 3 {
+                    return Err(LayoutError::new(
+                        "need at least 3 vertexes in order to close the polygon",
+                        span,
+                    ));
+                }
+                let cur = current(&mut self.cur);
+                if cur.close {
+                    return Err(LayoutError::new("polygon already closed", span));
+                }
+                cur.close = true;
+            }
+            AttributeKind::Chop => current(&mut self.cur).chop = true,
+            AttributeKind::From(position) => {
+                let pt = self.position(position)?;
+                self.set_from(pt, span)?;
+            }
+            AttributeKind::To(position) => {
+                let pt = self.position(position)?;
+                self.add_to(pt, span)?;
+            }
+            AttributeKind::At(position) => {
+                let pt = self.position(position)?;
+                self.set_at(Edge::Center, pt, span)?;
+            }
+            AttributeKind::With { edge, position } => {
+                let pt = self.position(position)?;
+                self.set_at(*edge, pt, span)?;
+            }
+            AttributeKind::Same(object) => {
+                let other = match object {
+                    Some(object) => Some(self.resolve(object)?),
+                    None => None,
+                };
+                self.same(other, span)?;
+            }
+            AttributeKind::Text(text) => {
+                let label = self.label(text);
+                let cur = current(&mut self.cur);
+                if cur.labels.len() >= 5 {
+                    return Err(LayoutError::new("too many text terms", text.span));
+                }
+                cur.labels.push(label);
+            }
+            AttributeKind::Fit => self.fit(3),
+            AttributeKind::Behind(object) => {
+                let other = self.resolve(object)?;
+                let layer = self.get(&other).layer;
+                let cur = current(&mut self.cur);
+                if cur.layer >= layer {
+                    cur.layer = layer - 1;
+                }
+            }
+            AttributeKind::Flag(flag) => {
+                let thickness = self.var("thickness");
+                let cur = current(&mut self.cur);
+                match flag {
+                    Flag::Cw => cur.cw = true,
+                    Flag::Ccw => cur.cw = false,
+                    Flag::LArrow => {
+                        cur.larrow = true;
+                        cur.rarrow = false;
+                    }
+                    Flag::RArrow => {
+                        cur.larrow = false;
+                        cur.rarrow = true;
+                    }
+                    Flag::LRArrow => {
+                        cur.larrow = true;
+                        cur.rarrow = true;
+                    }
+                    Flag::Invisible => cur.sw = 0.0,
+                    Flag::Thick => cur.sw *= 1.5,
+                    Flag::Thin => cur.sw *= 0.67,
+                    Flag::Solid => {
+                        cur.sw = thickness;
+                        cur.dotted = 0.0;
+                        cur.dashed = 0.0;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn relexpr(&mut self, rel: &RelExpr) -> Result<Rel> {
+        let value = self.expr(&rel.expr)?;
+        Ok(if rel.percent {
+            Rel {
+                abs: 0.0,
+                rel: value / 100.0,
+            }
+        } else {
+            Rel {
+                abs: value,
+                rel: 0.0,
+            }
+        })
+    }
+
+    fn opt_relexpr(&mut self, rel: Option<&RelExpr>) -> Result<Rel> {
+        match rel {
+            Some(rel) => self.relexpr(rel),
+            None => Ok(Rel { abs: 0.0, rel: 1.0 }),
+        }
+    }
+
+    fn line_only(&self, span: Span) -> Result<()> {
+        match &self.cur {
+            Some(cur) if cur.class.is_line() => Ok(()),
+            _ => Err(LayoutError::new(
+                "use with line-oriented objects only",
+                span,
+            )),
+        }
+    }
+
+    /// Forget a path copied by `same` once the path is changed
+    fn reset_same_path(&mut self) {
+        if self.same_path {
+            self.same_path = false;
+            self.path.truncate(1);
+        }
+    }
+
+    /// Start a new vertex at the end of the path
+    fn next_vertex(&mut self) -> usize {
+        let n = self.path.len() - 1;
+        if n + 1 >= MAX_PATH {
+            return n;
+        }
+        self.path.push(self.path[n]);
+        self.path_mask = 0;
+        n + 1
+    }
+
+    /// The vertex which a movement should change
+    fn moving_vertex(&mut self) -> usize {
+        let n = self.path.len() - 1;
+        if self.then || self.path_mask == 3 || n == 0 {
+            self.then = false;
+            self.next_vertex()
+        } else {
+            n
+        }
+    }
+
+    fn go(&mut self, direction: Option<Direction>, distance: Rel, span: Span) -> Result<()> {
+        if direction.is_some() {
+            self.line_only(span)?;
+        } else if !current(&mut self.cur).class.is_line() {
+            return Err(LayoutError::new("syntax error", span));
+        }
+        self.reset_same_path();
+        let mut n = self.moving_vertex();
+        let dir = direction.unwrap_or(self.dir);
+        let cur = current(&mut self.cur);
+        let (mask, size) = match dir {
+            Direction::Up | Direction::Down => (2, cur.h),
+            Direction::Left | Direction::Right => (1, cur.w),
+        };
+        if self.path_mask & mask != 0 {
+            n = self.next_vertex();
+        }
+        let delta = distance.abs + size * distance.rel;
+        let pt = &mut self.path[n];
+        match dir {
+            Direction::Up => pt.y += delta,
+            Direction::Down => pt.y -= delta,
+            Direction::Right => pt.x += delta,
+            Direction::Left => pt.x -= delta,
+        }
+        self.path_mask |= mask;
+        current(&mut self.cur).out_dir = dir;
+        Ok(())
+    }
+
+    fn go_heading(
+        &mut self,
+        distance: Rel,
+        heading: Option<(f64, Span)>,
+        edge: Option<Edge>,
+        span: Span,
+    ) -> Result<()> {
+        let dist = distance.abs + self.var("linewid") * distance.rel;
+        self.line_only(span)?;
+        self.reset_same_path();
+        let mut n = self.next_vertex();
+        while n < 1 {
+            n = self.next_vertex();
+        }
+        let angle = match (heading, edge) {
+            (Some((angle, span)), _) => {
+                if !(0.0..=360.0).contains(&angle) {
+                    return Err(LayoutError::new(
+                        "headings should be between 0 and 360",
+                        span,
+                    ));
+                }
+                angle
+            }
+            (None, Some(Edge::Center)) | (None, None) => {
+                return Err(LayoutError::new("syntax error", span));
+            }
+            (None, Some(edge)) => self::heading(edge),
+        };
+        current(&mut self.cur).out_dir = if angle <= 45.0 {
+            Direction::Up
+        } else if angle <= 135.0 {
+            Direction::Right
+        } else if angle <= 225.0 {
+            Direction::Down
+        } else if angle <= 315.0 {
+            Direction::Left
+        } else {
+            Direction::Up
+        };
+        self.path[n] = at_heading(dist, angle, self.path[n]);
+        self.path_mask = 2;
+        Ok(())
+    }
+
+    fn even_with(&mut self, dir: Direction, place: Point, span: Span) -> Result<()> {
+        self.line_only(span)?;
+        self.reset_same_path();
+        let mut n = self.moving_vertex();
+        match dir {
+            Direction::Up | Direction::Down => {
+                if self.path_mask & 2 != 0 {
+                    n = self.next_vertex();
+                }
+                self.path[n].y = place.y;
+                self.path_mask |= 2;
+            }
+            Direction::Left | Direction::Right => {
+                if self.path_mask & 1 != 0 {
+                    n = self.next_vertex();
+                }
+                self.path[n].x = place.x;
+                self.path_mask |= 1;
+            }
+        }
+        current(&mut self.cur).out_dir = dir;
+        Ok(())
+    }
+
+    fn set_from(&mut self, pt: Point, span: Span) -> Result<()> {
+        let cur = current(&mut self.cur);
+        if !cur.class.is_line() {
+            return Err(LayoutError::new("use \"at\" to position this object", span));
+        }
+        if cur.props & A_FROM != 0 {
+            return Err(LayoutError::new("line start location already fixed", span));
+        }
+        if cur.close {
+            return Err(LayoutError::new("polygon is closed", span));
+        }
+        cur.props |= A_FROM;
+        let dx = pt.x - self.path[0].x;
+        let dy = pt.y - self.path[0].y;
+        for vertex in &mut self.path[1..] {
+            vertex.x += dx;
+            vertex.y += dy;
+        }
+        self.path[0] = pt;
+        self.path_mask = 3;
+        Ok(())
+    }
+
+    fn add_to(&mut self, pt: Point, span: Span) -> Result<()> {
+        let cur = current(&mut self.cur);
+        if !cur.class.is_line() {
+            return Err(LayoutError::new("use \"at\" to position this object", span));
+        }
+        if cur.close {
+            return Err(LayoutError::new("polygon is closed", span));
+        }
+        self.reset_same_path();
+        let mut n = self.path.len() - 1;
+        if n == 0 || self.path_mask == 3 || self.then {
+            n = self.next_vertex();
+        }
+        self.path[n] = pt;
+        self.path_mask = 3;
+        Ok(())
+    }
+
+    fn set_at(&mut self, edge: Edge, pt: Point, span: Span) -> Result<()> {
+        let cur = current(&mut self.cur);
+        if cur.class.is_line() {
+            return Err(LayoutError::new(
+                "use \"from\" and \"to\" to position this object",
+                span,
+            ));
+        }
+        if cur.props & A_AT != 0 {
+            return Err(LayoutError::new("location fixed by prior \"at\"", span));
+        }
+        cur.props |= A_AT;
+        let compass = |dir| match dir {
+            Direction::Right => Edge::East,
+            Direction::Down => Edge::South,
+            Direction::Left => Edge::West,
+            Direction::Up => Edge::North,
+        };
+        cur.with_edge = match edge {
+            Edge::End => compass(cur.out_dir),
+            Edge::Start => compass(cur.in_dir),
+            edge => edge,
+        };
+        cur.with = pt;
+        Ok(())
+    }
+
+    fn same(&mut self, other: Option<Handle>, span: Span) -> Result<()> {
+        let class = current(&mut self.cur).class;
+        let other = match other {
+            Some(other) => other,
+            None => {
+                let idx = self
+                    .list
+                    .iter()
+                    .rposition(|o| o.class == class)
+                    .ok_or_else(|| LayoutError::new("no prior objects of the same type", span))?;
+                Handle {
+                    current: false,
+                    path: vec![idx],
+                }
+            }
+        };
+        let other = self.get(&other).clone();
+        let cur = current(&mut self.cur);
+        if !other.path.is_empty() && cur.class.is_line() {
+            let dx = self.path[0].x - other.path[0].x;
+            let dy = self.path[0].y - other.path[0].y;
+            self.path.truncate(1);
+            self.path.extend(
+                other.path[1..]
+                    .iter()
+                    .map(|pt| Point::new(pt.x + dx, pt.y + dy)),
+            );
+            self.path_mask = 3;
+            self.same_path = true;
+        }
+        if !cur.class.is_line() {
+            cur.w = other.w;
+            cur.h = other.h;
+        }
+        cur.rad = other.rad;
+        cur.sw = other.sw;
+        cur.dashed = other.dashed;
+        cur.dotted = other.dotted;
+        cur.fill = other.fill;
+        cur.color = other.color;
+        cur.cw = other.cw;
+        cur.larrow = other.larrow;
+        cur.rarrow = other.rarrow;
+        cur.close = other.close;
+        cur.chop = other.chop;
+        cur.in_dir = other.in_dir;
+        cur.out_dir = other.out_dir;
+        cur.layer = other.layer;
+        Ok(())
+    }
+
+    /// Resize the current object to fit its text, horizontally if `which`
+    /// has bit 1 set and vertically if it has bit 2 set
+    fn fit(&mut self, which: u8) {
+        let settings = Settings::new(&self.variables);
+        let cur = match &mut self.cur {
+            Some(cur) if !cur.labels.is_empty() && cur.class.fits() => cur,
+            _ => return,
+        };
+        let mut bounds = Bounds::empty();
+        add_text_bounds(&mut bounds, cur, &settings);
+        let w = if which & 1 != 0 {
+            bounds.width() + settings.char_width
+        } else {
+            0.0
+        };
+        let h = if which & 2 != 0 {
+            let h1 = bounds.ne.y - cur.at.y;
+            let h2 = cur.at.y - bounds.sw.y;
+            2.0 * (if h1 < h2 { h2 } else { h1 }) + 0.5 * settings.char_height
+        } else {
+            0.0
+        };
+        cur.fit(w, h);
+        cur.props |= A_FIT;
+    }
+
+    /// Finish laying out the current object once it has all of its
+    /// attributes
+    fn finish(&mut self) -> Result<()> {
+        let cur = current(&mut self.cur);
+        if !cur.class.is_line() {
+            if cur.h <= 0.0 {
+                if cur.labels.is_empty() {
+                    cur.h = 0.0;
+                } else if cur.w <= 0.0 {
+                    self.fit(3);
+                } else {
+                    self.fit(2);
+                }
+            }
+            let cur = current(&mut self.cur);
+            if cur.w <= 0.0 {
+                if cur.labels.is_empty() {
+                    cur.w = 0.0;
+                } else {
+                    self.fit(1);
+                }
+            }
+            let with_edge = current(&mut self.cur).with_edge;
+            let offset = self.offset(
+                &Handle {
+                    current: true,
+                    path: Vec::new(),
+                },
+                with_edge,
+            );
+            let cur = current(&mut self.cur);
+            let dx = (cur.with.x - offset.x) - cur.at.x;
+            let dy = (cur.with.y - offset.y) - cur.at.y;
+            if dx != 0.0 || dy != 0.0 {
+                cur.shift(dx, dy);
+            }
+        }
+
+        if current(&mut self.cur).class.is_line() && self.path.len() < 2 {
+            self.next_vertex();
+            let cur = current(&mut self.cur);
+            let step = |pt: &mut Point, dir, cur: &Object| match dir {
+                Direction::Right => pt.x += cur.w,
+                Direction::Down => pt.y -= cur.h,
+                Direction::Left => pt.x -= cur.w,
+                Direction::Up => pt.y += cur.h,
+            };
+            step(&mut self.path[1], cur.in_dir, cur);
+            if cur.class == Class::Arc {
+                cur.out_dir = match (cur.in_dir, cur.cw) {
+                    (Direction::Right, true) | (Direction::Left, false) => Direction::Down,
+                    (Direction::Down, true) | (Direction::Up, false) => Direction::Left,
+                    (Direction::Left, true) | (Direction::Right, false) => Direction::Up,
+                    (Direction::Up, true) | (Direction::Down, false) => Direction::Right,
+                };
+                self.dir = cur.out_dir;
+                step(&mut self.path[1], cur.out_dir, cur);
+            }
+        }
+
+        let cur = current(&mut self.cur);
+        cur.bounds = Bounds::empty();
+        match cur.class {
+            Class::Arc => {
+                if self.path.len() > 2 {
+                    return Err(LayoutError::new("arc geometry error", cur.span));
+                }
+                let m = arc_control(cur.cw, self.path[0], self.path[1], 0.5);
+                cur.bounds.add_point(m.x, m.y);
+            }
+            Class::Dot => {
+                cur.w = 0.0;
+                cur.h = 0.0;
+                cur.bounds.add_ellipse(cur.at.x, cur.at.y, cur.rad, cur.rad);
+            }
+            _ => {}
+        }
+
+        if cur.class.is_line() {
+            cur.path = self.path.clone();
+            let n = cur.path.len();
+            if cur.chop && n >= 2 {
+                if let Some(chopper) = find_chopper(&self.list, cur.path[n - 1]) {
+                    cur.path[n - 1] = chopper.chop(cur.path[n - 2]);
+                }
+                if let Some(chopper) = find_chopper(&self.list, cur.path[0]) {
+                    cur.path[0] = chopper.chop(cur.path[1]);
+                }
+            }
+            cur.enter = cur.path[0];
+            cur.exit = cur.path[n - 1];
+            for pt in &cur.path {
+                cur.bounds.add_point(pt.x, pt.y);
+            }
+            cur.at = Point::new(
+                (cur.bounds.ne.x + cur.bounds.sw.x) / 2.0,
+                (cur.bounds.ne.y + cur.bounds.sw.y) / 2.0,
+            );
+            cur.w = cur.bounds.width();
+            cur.h = cur.bounds.height();
+            if cur.close {
+                cur.set_exit(cur.in_dir);
+            }
+        } else {
+            let w2 = cur.w / 2.0;
+            let h2 = cur.h / 2.0;
+            cur.enter = cur.at;
+            cur.exit = cur.at;
+            match cur.in_dir {
+                Direction::Right => cur.enter.x -= w2,
+                Direction::Left => cur.enter.x += w2,
+                Direction::Up => cur.enter.y -= h2,
+                Direction::Down => cur.enter.y += h2,
+            }
+            match cur.out_dir {
+                Direction::Right => cur.exit.x += w2,
+                Direction::Left => cur.exit.x -= w2,
+                Direction::Up => cur.exit.y += h2,
+                Direction::Down => cur.exit.y -= h2,
+            }
+            cur.bounds.add_point(cur.at.x - w2, cur.at.y - h2);
+            cur.bounds.add_point(cur.at.x + w2, cur.at.y + h2);
+        }
+        self.dir = cur.out_dir;
+        Ok(())
+    }
+
+    fn get(&self, handle: &Handle) -> &Object {
+        let (mut object, rest) = if handle.current {
+            (
+                self.cur.as_ref().expect("an object is being laid out"),
+                &handle.path[..],
+            )
+        } else {
+            (&self.list[handle.path[0]], &handle.path[1..])
+        };
+        for &idx in rest {
+            object = &object.children[idx];
+        }
+        object
+    }
+
+    /// The offset from the center of an object to a compass point
+    ///
+    /// As in C, measuring text objects first resizes the current object to
+    /// fit its own text.
+    fn offset(&mut self, handle: &Handle, edge: Edge) -> Point {
+        if self.get(handle).class == Class::Text {
+            self.fit(3);
+        }
+        self.get(handle).offset(edge)
+    }
+
+    fn resolve(&self, object: &ObjectRef) -> Result<Handle> {
+        let not_found = |span| LayoutError::new("no such object", span);
+        match &object.kind {
+            ObjectRefKind::This => match self.cur {
+                Some(_) => Ok(Handle {
+                    current: true,
+                    path: Vec::new(),
+                }),
+                None => Err(not_found(object.span)),
+            },
+            ObjectRefKind::Named(names) => {
+                let mut found: Option<Handle> = None;
+                for name in names {
+                    let list = match &found {
+                        Some(handle) => &self.get(handle).children[..],
+                        None => &self.list[..],
+                    };
+                    let idx = list
+                        .iter()
+                        .rposition(|o| o.name.as_deref() == Some(name.text.as_str()))
+                        .or_else(|| {
+                            list.iter()
+                                .rposition(|o| o.labels.iter().any(|l| l.text == name.text))
+                        })
+                        .ok_or_else(|| not_found(name.span))?;
+                    found = Some(match found {
+                        Some(mut handle) => {
+                            handle.path.push(idx);
+                            handle
+                        }
+                        None => Handle {
+                            current: false,
+                            path: vec![idx],
+                        },
+                    });
+                }
+                found.ok_or_else(|| not_found(object.span))
+            }
+            ObjectRefKind::Nth { nth, within } => {
+                if nth.count > 1000 {
+                    return Err(LayoutError::new(
+                        "value too big - max '1000th'",
+                        object.span,
+                    ));
+                }
+                let within = match within {
+                    Some(within) => Some(self.resolve(within)?),
+                    None => None,
+                };
+                let list = match &within {
+                    Some(handle) => &self.get(handle).children[..],
+                    None => &self.list[..],
+                };
+                let class = match &nth.class {
+                    NthClass::Any => None,
+                    NthClass::Sublist => Some(Class::Sublist),
+                    NthClass::Class(name) => Some(
+                        Class::named(&name.text)
+                            .ok_or_else(|| LayoutError::new("no such object type", name.span))?,
+                    ),
+                };
+                let count = nth.count as usize;
+                let mut matching = list
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, o)| class.is_none() || class == Some(o.class))
+                    .map(|(idx, _)| idx);
+                let idx = match count {
+                    0 => None,
+                    _ if nth.from_end => matching.rev().nth(count - 1),
+                    _ => matching.nth(count - 1),
+                };
+                let idx = idx.ok_or_else(|| not_found(object.span))?;
+                Ok(match within {
+                    Some(mut handle) => {
+                        handle.path.push(idx);
+                        handle
+                    }
+                    None => Handle {
+                        current: false,
+                        path: vec![idx],
+                    },
+                })
+            }
+        }
+    }
+
+    fn place(&mut self, place: &Place) -> Result<Point> {
+        match &place.kind {
+            PlaceKind::Object(object) => {
+                let handle = self.resolve(object)?;
+                Ok(self.get(&handle).at)
+            }
+            PlaceKind::Edge { object, edge } => {
+                let handle = self.resolve(object)?;
+                Ok(match edge {
+                    Edge::Start => self.get(&handle).enter,
+                    Edge::End => self.get(&handle).exit,
+                    _ => {
+                        let offset = self.offset(&handle, *edge);
+                        let at = self.get(&handle).at;
+                        Point::new(at.x + offset.x, at.y + offset.y)
+                    }
+                })
+            }
+            PlaceKind::Vertex { nth, object } => {
+                let handle = self.resolve(object)?;
+                let object = self.get(&handle);
+                if !object.class.is_line() {
+                    return Err(LayoutError::new("object is not a line", place.span));
+                }
+                let n = *nth as usize;
+                if n < 1 || n > object.path.len() {
+                    return Err(LayoutError::new("no such vertex", place.span));
+                }
+                Ok(object.path[n - 1])
+            }
+        }
+    }
+
+    fn position(&mut self, position: &Position) -> Result<Point> {
+        Ok(match &position.kind {
+            PositionKind::Coordinates(x, y) => Point::new(self.expr(x)?, self.expr(y)?),
+            PositionKind::Place(place) => self.place(place)?,
+            PositionKind::Offset {
+                place,
+                subtract,
+                x,
+                y,
+            } => {
+                let pt = self.place(place)?;
+                let x = self.expr(x)?;
+                let y = self.expr(y)?;
+                if *subtract {
+                    Point::new(pt.x - x, pt.y - y)
+                } else {
+                    Point::new(pt.x + x, pt.y + y)
+                }
+            }
+            PositionKind::Combine(x, y) => {
+                let x = self.position(x)?;
+                let y = self.position(y)?;
+                Point::new(x.x, y.y)
+            }
+            PositionKind::Between { fraction, from, to } => {
+                let f = self.expr(fraction)?;
+                let a = self.position(from)?;
+                let b = self.position(to)?;
+                Point::new(b.x * f + a.x * (1.0 - f), b.y * f + a.y * (1.0 - f))
+            }
+            PositionKind::Relative {
+                distance,
+                direction,
+                position,
+            } => {
+                let d = self.expr(distance)?;
+                let mut pt = self.position(position)?;
+                match direction {
+                    Direction::Up => pt.y += d,
+                    Direction::Down => pt.y -= d,
+                    Direction::Left => pt.x -= d,
+                    Direction::Right => pt.x += d,
+                }
+                pt
+            }
+            PositionKind::EdgeHeading {
+                distance,
+                edge,
+                position,
+            } => {
+                let d = self.expr(distance)?;
+                let pt = self.position(position)?;
+                at_heading(d, heading(*edge), pt)
+            }
+            PositionKind::Heading {
+                distance,
+                heading,
+                position,
+            } => {
+                let d = self.expr(distance)?;
+                let h = self.expr(heading)?;
+                let pt = self.position(position)?;
+                at_heading(d, h, pt)
+            }
+        })
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<f64> {
+        Ok(match &expr.kind {
+            ExprKind::Number(value) => *value,
+            ExprKind::Variable(name) => value(&self.variables, &name.text)
+                .or_else(|| color(&name.text).map(|(_, v)| f64::from(v)))
+                .ok_or_else(|| LayoutError::new("no such variable", name.span))?,
+            ExprKind::Color(name) => color(&name.text)
+                .map(|(_, v)| f64::from(v))
+                .ok_or_else(|| LayoutError::new("not a known color name", name.span))?,
+            ExprKind::Unary { op, operand } => {
+                let operand = self.expr(operand)?;
+                match op {
+                    UnaryOp::Plus => operand,
+                    UnaryOp::Minus => -operand,
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                match op {
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Subtract => lhs - rhs,
+                    BinaryOp::Multiply => lhs * rhs,
+                    BinaryOp::Divide if rhs == 0.0 => {
+                        return Err(LayoutError::new("division by zero", expr.span))
+                    }
+                    BinaryOp::Divide => lhs / rhs,
+                }
+            }
+            ExprKind::Call { function, args } => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.expr(arg)?);
+                }
+                let x = values.first().copied().unwrap_or(0.0);
+                let y = values.get(1).copied().unwrap_or(0.0);
+                match function {
+                    // The C implementation's abs() always returns zero
+                    Function::Abs => 0.0,
+                    Function::Cos => x.cos(),
+                    Function::Int => x.round_ties_even(),
+                    Function::Sin => x.sin(),
+                    Function::Sqrt if x < 0.0 => {
+                        return Err(LayoutError::new("sqrt of negative value", expr.span))
+                    }
+                    Function::Sqrt => x.sqrt(),
+                    Function::Max => {
+                        if x > y {
+                            x
+                        } else {
+                            y
+                        }
+                    }
+                    Function::Min => {
+                        if x < y {
+                            x
+                        } else {
+                            y
+                        }
+                    }
+                }
+            }
+            ExprKind::Distance(from, to) => {
+                let from = self.position(from)?;
+                let to = self.position(to)?;
+                (to.x - from.x).hypot(to.y - from.y)
+            }
+            ExprKind::Coordinate { place, axis } => {
+                let pt = self.place(place)?;
+                match axis {
+                    Axis::X => pt.x,
+                    Axis::Y => pt.y,
+                }
+            }
+            ExprKind::Property { object, property } => {
+                let handle = self.resolve(object)?;
+                let object = self.get(&handle);
+                match property {
+                    ObjectProperty::Numeric(NumericProperty::Height) => object.h,
+                    ObjectProperty::Numeric(NumericProperty::Width) => object.w,
+                    ObjectProperty::Numeric(NumericProperty::Radius) => object.rad,
+                    ObjectProperty::Numeric(NumericProperty::Diameter) => object.rad * 2.0,
+                    ObjectProperty::Numeric(NumericProperty::Thickness) => object.sw,
+                    ObjectProperty::Dash(DashStyle::Dashed) => object.dashed,
+                    ObjectProperty::Dash(DashStyle::Dotted) => object.dotted,
+                    ObjectProperty::Color(ColorProperty::Fill) => object.fill,
+                    ObjectProperty::Color(ColorProperty::Color) => object.color,
+                }
+            }
+        })
+    }
+}
+
+/// Writes SVG for laid out objects
+struct Renderer {
+    out: String,
+    settings: Settings,
+    bounds: Bounds,
+    dark: bool,
+    fg: i32,
+    bg: i32,
+}
+
+impl Renderer {
+    fn x(&self, x: f64) -> i32 {
+        (SCALE * (x - self.bounds.sw.x)) as i32
+    }
+
+    fn y(&self, y: f64) -> i32 {
+        (SCALE * (self.bounds.ne.y - y)) as i32
+    }
+
+    fn xy(&mut self, prefix: &str, x: f64, y: f64) {
+        let (x, y) = (self.x(x), self.y(y));
+        let _ = write!(self.out, "{}{},{}", prefix, x, y);
+    }
+
+    fn arc(&mut self, r1: f64, r2: f64, x: f64, y: f64) {
+        let (x, y) = (self.x(x), self.y(y));
+        let _ = write!(
+            self.out,
+            "A{} {} 0 0 0 {} {}",
+            (SCALE * r1) as i32,
+            (SCALE * r2) as i32,
+            x,
+            y
+        );
+    }
+
+    fn color(&self, value: f64, bg: bool) -> String {
+        let mut x = value as i32;
+        if x == 0 && self.fg > 0 && !bg {
+            x = self.fg;
+        } else if bg && x >= 0xffffff && self.bg > 0 {
+            x = self.bg;
+        } else if self.dark {
+            x = dark_mode(x, bg);
+        }
+        format!("rgb({},{},{})", (x >> 16) & 0xff, (x >> 8) & 0xff, x & 0xff)
+    }
+
+    /// Append a style attribute, leaving its quotes open
+    ///
+    /// `fill` is 0 for no fill, 1 when the fill is a background and the
+    /// stroke a foreground, 2 when both are foregrounds, and 3 when both
+    /// are backgrounds.
+    fn style(&mut self, object: &Object, fill: u8) {
+        let mut color_is_bg = false;
+        self.out.push_str(" style=\"");
+        if object.fill >= 0.0 && fill != 0 {
+            let mut fill_is_bg = true;
+            if object.fill == object.color {
+                if fill == 2 {
+                    fill_is_bg = false;
+                }
+                if fill == 3 {
+                    color_is_bg = true;
+                }
+            }
+            let fill = self.color(object.fill, fill_is_bg);
+            let _ = write!(self.out, "fill:{};", fill);
+        } else {
+            self.out.push_str("fill:none;");
+        }
+        if object.sw > 0.0 && object.color >= 0.0 {
+            let mut sw = object.sw;
+            let _ = write!(self.out, "stroke-width:{};", format_g(SCALE * sw, 6));
+            if object.path.len() > 2 && object.rad <= object.sw {
+                self.out.push_str("stroke-linejoin:round;");
+            }
+            let stroke = self.color(object.color, color_is_bg);
+            let _ = write!(self.out, "stroke:{};", stroke);
+            if object.dotted > 0.0 {
+                if sw < 2.1 / SCALE {
+                    sw = 2.1 / SCALE;
+                }
+                let _ = write!(
+                    self.out,
+                    "stroke-dasharray:{},{};",
+                    format_g(SCALE * sw, 6),
+                    format_g(SCALE * object.dotted, 6)
+                );
+            } else if object.dashed > 0.0 {
+                let dash = format_g(SCALE * object.dashed, 6);
+                let _ = write!(self.out, "stroke-dasharray:{},{};", dash, dash);
+            }
+        }
+    }
+
+    /// Draw an arrowhead at `to`, and pull `to` back behind it
+    fn arrowhead(&mut self, object: &Object, from: Point, to: &mut Point) {
+        let dx = to.x - from.x;
+        let dy = to.y - from.y;
+        let dist = dx.hypot(dy);
+        let mut h = self.settings.h_arrow * object.sw;
+        let w = self.settings.w_arrow * object.sw;
+        if object.color < 0.0 || object.sw <= 0.0 || dist <= 0.0 {
+            return;
+        }
+        let (dx, dy) = (dx / dist, dy / dist);
+        let mut e1 = dist - h;
+        if e1 < 0.0 {
+            e1 = 0.0;
+            h = dist;
+        }
+        let ddx = -w * dy;
+        let ddy = w * dx;
+        let bx = from.x + e1 * dx;
+        let by = from.y + e1 * dy;
+        self.xy("<polygon points=\"", to.x, to.y);
+        self.xy(" ", bx - ddx, by - ddy);
+        self.xy(" ", bx + ddx, by + ddy);
+        let fill = self.color(object.color, false);
+        let _ = writeln!(self.out, "\" style=\"fill:{}\"/>", fill);
+
+        // Shorten the line so that it does not poke through the point
+        let amount = h / 2.0;
+        if dist <= amount {
+            *to = from;
+        } else {
+            let r = 1.0 - amount / dist;
+            *to = Point::new(from.x + r * (to.x - from.x), from.y + r * (to.y - from.y));
+        }
+    }
+
+    fn arrowheads(&mut self, object: &mut Object) {
+        let n = object.path.len();
+        if object.larrow {
+            let (from, mut to) = (object.path[1], object.path[0]);
+            self.arrowhead(object, from, &mut to);
+            object.path[0] = to;
+        }
+        if object.rarrow {
+            let (from, mut to) = (object.path[n - 2], object.path[n - 1]);
+            self.arrowhead(object, from, &mut to);
+            object.path[n - 1] = to;
+        }
+    }
+
+    fn text(&mut self, object: &mut Object) {
+        if object.labels.is_empty() {
+            return;
+        }
+        let offsets = text_offsets(object, &self.settings);
+        let x = object.at.x;
+        let y = object.at.y;
+        for (label, (nx, ny)) in object.labels.iter().zip(offsets) {
+            let flags = label.flags;
+            self.out.push_str("<text");
+            let tx = self.x(nx + x);
+            let ty = self.y(ny + y);
+            let _ = write!(self.out, " x=\"{}\" y=\"{}\"", tx, ty);
+            self.out.push_str(if flags & TP_RJUST != 0 {
+                " text-anchor=\"end\""
+            } else if flags & TP_LJUST != 0 {
+                " text-anchor=\"start\""
+            } else {
+                " text-anchor=\"middle\""
+            });
+            if flags & TP_ITALIC != 0 {
+                self.out.push_str(" font-style=\"italic\"");
+            }
+            if flags & TP_BOLD != 0 {
+                self.out.push_str(" font-weight=\"bold\"");
+            }
+            if object.color >= 0.0 {
+                let fill = self.color(object.color, false);
+                let _ = write!(self.out, " fill=\"{}\"", fill);
+            }
+            let scale = label.scale() * self.settings.font_scale;
+            if scale <= 0.99 || scale >= 1.01 {
+                let _ = write!(self.out, " font-size=\"{}%\"", format_g(scale * 100.0, 10));
+            }
+            if flags & TP_ALIGN != 0 {
+                if let Some((dx, dy)) = alignment(object) {
+                    let angle = dy.atan2(dx) * -180.0 / std::f64::consts::PI;
+                    let _ = write!(self.out, " transform=\"rotate({}", format_g(angle, 10));
+                    self.xy(" ", x, y);
+                    self.out.push_str(")\"");
+                }
+            }
+            self.out.push_str(" dominant-baseline=\"central\">");
+            let text = &label.text;
+            let bytes = text.as_bytes();
+            let mut start = 0;
+            while start < bytes.len() {
+                let mut j = text[start..].find('\\').map_or(bytes.len(), |j| start + j);
+                escape(&mut self.out, &text[start..j], true);
+                if j < bytes.len() && (j + 1 == bytes.len() || bytes[j + 1] == b'\\') {
+                    self.out.push_str("&#92;");
+                    j += 1;
+                }
+                start = j + 1;
+            }
+            self.out.push_str("</text>\n");
+        }
+    }
+
+    fn path_end(&mut self, object: &Object, fill: u8) {
+        self.out.push_str("\" ");
+        self.style(object, fill);
+        self.out.push_str("\" />\n");
+    }
+
+    fn line(&mut self, object: &mut Object) {
+        self.arrowheads(object);
+        let mut prefix = "<path d=\"M";
+        for i in 0..object.path.len() {
+            let pt = object.path[i];
+            self.xy(prefix, pt.x, pt.y);
+            prefix = "L";
+        }
+        if object.close {
+            self.out.push('Z');
+        } else {
+            object.fill = -1.0;
+        }
+        self.path_end(object, if object.close { 3 } else { 0 });
+    }
+
+    /// Draw a line with rounded corners
+    fn rounded(&mut self, object: &mut Object) {
+        self.arrowheads(object);
+        let r = object.rad;
+        let a = &object.path;
+        let n = a.len();
+        let last = if object.close { n } else { n - 1 };
+        let (_, m) = radius_midpoint(a[0], a[1], r);
+        let mut an = a[n - 1];
+        self.xy("<path d=\"M", a[0].x, a[0].y);
+        self.xy(" L ", m.x, m.y);
+        for i in 1..last {
+            an = if i < n - 1 { a[i + 1] } else { a[0] };
+            let (mid, m) = radius_midpoint(an, a[i], r);
+            self.xy(" Q ", a[i].x, a[i].y);
+            self.xy(" ", m.x, m.y);
+            if !mid {
+                let (_, m) = radius_midpoint(a[i], an, r);
+                self.xy(" L ", m.x, m.y);
+            }
+        }
+        self.xy(" L ", an.x, an.y);
+        if object.close {
+            self.out.push('Z');
+        } else {
+            object.fill = -1.0;
+        }
+        self.path_end(object, if object.close { 3 } else { 0 });
+    }
+
+    fn object(&mut self, object: &mut Object) {
+        let at = object.at;
+        let w2 = 0.5 * object.w;
+        let h2 = 0.5 * object.h;
+        match object.class {
+            Class::Arc => {
+                if object.path.len() < 2 || object.sw <= 0.0 {
+                    return;
+                }
+                let mut f = object.path[0];
+                let mut t = object.path[1];
+                let m = arc_control(object.cw, f, t, 1.0);
+                if object.larrow {
+                    self.arrowhead(object, m, &mut f);
+                }
+                if object.rarrow {
+                    self.arrowhead(object, m, &mut t);
+                }
+                self.xy("<path d=\"M", f.x, f.y);
+                self.xy("Q", m.x, m.y);
+                self.xy(" ", t.x, t.y);
+                self.path_end(object, 0);
+            }
+            Class::Arrow | Class::Line | Class::Spline => {
+                if object.sw > 0.0 {
+                    if object.path.len() < 3 || object.rad <= 0.0 {
+                        self.line(object);
+                    } else {
+                        self.rounded(object);
+                    }
+                }
+            }
+            Class::Box | Class::Oval | Class::Text => {
+                if object.sw > 0.0 {
+                    let mut rad = object.rad;
+                    if rad <= 0.0 {
+                        self.xy("<path d=\"M", at.x - w2, at.y - h2);
+                        self.xy("L", at.x + w2, at.y - h2);
+                        self.xy("L", at.x + w2, at.y + h2);
+                        self.xy("L", at.x - w2, at.y + h2);
+                        self.out.push('Z');
+                    } else {
+                        if rad > w2 {
+                            rad = w2;
+                        }
+                        if rad > h2 {
+                            rad = h2;
+                        }
+                        let x0 = at.x - w2;
+                        let x1 = x0 + rad;
+                        let x3 = at.x + w2;
+                        let x2 = x3 - rad;
+                        let y0 = at.y - h2;
+                        let y1 = y0 + rad;
+                        let y3 = at.y + h2;
+                        let y2 = y3 - rad;
+                        self.xy("<path d=\"M", x1, y0);
+                        if x2 > x1 {
+                            self.xy("L", x2, y0);
+                        }
+                        self.arc(rad, rad, x3, y1);
+                        if y2 > y1 {
+                            self.xy("L", x3, y2);
+                        }
+                        self.arc(rad, rad, x2, y3);
+                        if x2 > x1 {
+                            self.xy("L", x1, y3);
+                        }
+                        self.arc(rad, rad, x0, y2);
+                        if y2 > y1 {
+                            self.xy("L", x0, y1);
+                        }
+                        self.arc(rad, rad, x1, y0);
+                        self.out.push('Z');
+                    }
+                    self.path_end(object, 3);
+                }
+            }
+            Class::Circle | Class::Dot => {
+                if object.sw > 0.0 {
+                    let (x, y) = (self.x(at.x), self.y(at.y));
+                    let _ = write!(
+                        self.out,
+                        "<circle cx=\"{}\" cy=\"{}\" r=\"{}\"",
+                        x,
+                        y,
+                        format_g(SCALE * object.rad, 6)
+                    );
+                    if object.class == Class::Circle {
+                        self.out.push(' ');
+                        self.style(object, 3);
+                    } else {
+                        self.style(object, 2);
+                    }
+                    self.out.push_str("\" />\n");
+                }
+            }
+            Class::Cylinder => {
+                if object.sw > 0.0 {
+                    let mut rad = object.rad;
+                    if rad > h2 {
+                        rad = h2;
+                    } else if rad < 0.0 {
+                        rad = 0.0;
+                    }
+                    self.xy("<path d=\"M", at.x - w2, at.y + h2 - rad);
+                    self.xy("L", at.x - w2, at.y - h2 + rad);
+                    self.arc(w2, rad, at.x + w2, at.y - h2 + rad);
+                    self.xy("L", at.x + w2, at.y + h2 - rad);
+                    self.arc(w2, rad, at.x - w2, at.y + h2 - rad);
+                    self.arc(w2, rad, at.x + w2, at.y + h2 - rad);
+                    self.path_end(object, 3);
+                }
+            }
+            Class::Ellipse => {
+                if object.sw > 0.0 {
+                    let (x, y) = (self.x(at.x), self.y(at.y));
+                    let _ = write!(
+                        self.out,
+                        "<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" ",
+                        x,
+                        y,
+                        format_g(SCALE * (object.w / 2.0), 6),
+                        format_g(SCALE * (object.h / 2.0), 6)
+                    );
+                    self.style(object, 3);
+                    self.out.push_str("\" />\n");
+                }
+            }
+            Class::File => {
+                if object.sw > 0.0 {
+                    let mn = if w2 < h2 { w2 } else { h2 };
+                    let mut rad = object.rad;
+                    if rad > mn {
+                        rad = mn;
+                    }
+                    if rad < mn * 0.25 {
+                        rad = mn * 0.25;
+                    }
+                    self.xy("<path d=\"M", at.x - w2, at.y - h2);
+                    self.xy("L", at.x + w2, at.y - h2);
+                    self.xy("L", at.x + w2, at.y + (h2 - rad));
+                    self.xy("L", at.x + (w2 - rad), at.y + h2);
+                    self.xy("L", at.x - w2, at.y + h2);
+                    self.out.push('Z');
+                    self.path_end(object, 1);
+                    self.xy("<path d=\"M", at.x + (w2 - rad), at.y + h2);
+                    self.xy("L", at.x + (w2 - rad), at.y + (h2 - rad));
+                    self.xy("L", at.x + w2, at.y + (h2 - rad));
+                    self.path_end(object, 0);
+                }
+            }
+            Class::Move | Class::Sublist | Class::Place => return,
+        }
+        self.text(object);
+    }
+
+    /// Render a list of objects, one layer at a time
+    fn list(&mut self, objects: &mut [Object]) {
+        let mut next = 0;
+        loop {
+            let this = next;
+            next = i32::MAX;
+            let mut more = false;
+            for object in objects.iter_mut() {
+                if object.layer > this {
+                    next = next.min(object.layer);
+                    more = true;
+                    continue;
+                } else if object.layer < this {
+                    continue;
+                }
+                self.object(object);
+                if object.class == Class::Sublist {
+                    self.list(&mut object.children);
+                }
+            }
+            if !more {
+                break;
+            }
+        }
+    }
+}
+
+/// A point `r` before `t` on the way from `f`, or the midpoint if that is
+/// closer, along with whether it is the midpoint
+fn radius_midpoint(f: Point, t: Point, r: f64) -> (bool, Point) {
+    let dx = t.x - f.x;
+    let dy = t.y - f.y;
+    let dist = dx.hypot(dy);
+    if dist <= 0.0 {
+        return (false, t);
+    }
+    let (dx, dy) = (dx / dist, dy / dist);
+    let (mid, r) = if r > 0.5 * dist {
+        (true, 0.5 * dist)
+    } else {
+        (false, r)
+    };
+    (mid, Point::new(t.x - r * dx, t.y - r * dy))
+}
+
+/// Invert a colour for dark mode
+fn dark_mode(x: i32, bg: bool) -> i32 {
+    let x = 0xffffff - x;
+    let r = (x >> 16) & 0xff;
+    let g = (x >> 8) & 0xff;
+    let b = x & 0xff;
+    let mx = r.max(g).max(b);
+    let mn = r.min(g).min(b);
+    let (mut r, mut g, mut b) = (mn + (mx - r), mn + (mx - g), mn + (mx - b));
+    if bg {
+        if mx > 127 {
+            r = (127 * r) / mx;
+            g = (127 * g) / mx;
+            b = (127 * b) / mx;
+        }
+    } else if mn < 128 && mx > mn {
+        r = 127 + ((r - mn) * 128) / (mx - mn);
+        g = 127 + ((g - mn) * 128) / (mx - mn);
+        b = 127 + ((b - mn) * 128) / (mx - mn);
+    }
+    r * 0x10000 + g * 0x100 + b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pikchr;
+
+    fn check(source: &str, flags: PikchrFlags) {
+        let expected = Pikchr::render(source, None, flags).unwrap();
+        let actual = render(source, None, flags).unwrap_or_else(|e| panic!("{}: {}", source, e));
+        assert_eq!(actual, &*expected, "{}", source);
+    }
+
+    #[test]
+    fn matches_c_for_shapes() {
+        let sources = [
+            "box; circle; ellipse; oval; cylinder; file; dot; text \"t\"",
+            "box \"one\" \"two\" \"three\"; circle \"a\" fit; oval \"long text here\" fit",
+            "down\nbox rad 0.1 fill Bisque; cylinder \"db\" fill 0x87ceeb; file \"f\" thick",
+            "box wid 2 ht 50% dashed; circle rad 20% dotted color red; ellipse thin invis \"x\"",
+            "A: box \"a\" ljust \"b\" rjust \"c\" above \"d\" below\nB: box same \"e\" big bold \"f\" small italic",
+            "\"text\" at (1, 1); \"<a & b>\" big big; \"x\\\\y\\z\" small small",
+            "boxwid = 0.5; boxht *= 2; box; box same wid 1; fontscale = 1.5; box \"big\"",
+            "X: [ box; circle ]\nY: [ A: box \"in\"; arrow ] with .nw at X.se\ndot at Y.A.c",
+            "arrow right 200% \"Markdown\" \"Source\"\nbox rad 10px \"Markdown\" \"Formatter\" \"(docs.rs/markdown)\" fit\narrow right 200% \"HTML+SVG\" \"Output\"\narrow <-> down 70% from last box.s\nbox same \"Pikchr\" \"Formatter\" \"(docs.rs/pikchr)\" fit",
+        ];
+        for source in &sources {
+            check(source, PikchrFlags::default());
+        }
+    }
+
+    #[test]
+    fn matches_c_for_lines() {
+        let sources = [
+            "arrow; line; spline; arc; arrow <->; line <- thick; move; arc cw ->",
+            "line right 1 then down 0.5 then left 1 close fill yellow",
+            "spline right 1 then up 1 left 2 then right 0.5 ->; line from (0,0) to (1,1) to (2,0)",
+            "A: box; B: circle at A + (2, 1); arrow from A to B chop; line from A.n to B.s chop",
+            "line go 1 heading 45 then 1 ne then 0.5 se; arrow \"on\" aligned above \"below\" below",
+            "box; arrow down 50%; down; circle; arrow right until even with 1st box.e",
+            "A: box; line from A.e right 1 up 0.5 then to A.n; arrow from last line.start to 2nd vertex of last line",
+            "linerad = 0.1; line right then down then left ->; line same",
+            "C: circle; arrow from 1 heading 30 from C to 1 ne of C; line from 1/3 <C.n, C.s> to (C.w, C.n)",
+        ];
+        for source in &sources {
+            check(source, PikchrFlags::default());
+        }
+    }
+
+    #[test]
+    fn matches_c_for_settings() {
+        let mut dark = PikchrFlags::default();
+        dark.use_dark_mode();
+        check("box fill Red \"x\"; circle color Blue; dot", dark);
+        check(
+            "scale = 0.5; margin = 0.25; leftmargin = 1; box",
+            PikchrFlags::default(),
+        );
+        check(
+            "box \"front\" fill white; circle behind last box at last box.ne fill gray",
+            PikchrFlags::default(),
+        );
+        check(
+            "print 1/3, \"<hi>\", boxwid\nassert(boxwid == 0.75)\nbox",
+            PikchrFlags::default(),
+        );
+        check(
+            "thickness = 0.05; arrowht = 0.2; arrow; text \"x\" color green",
+            PikchrFlags::default(),
+        );
+        check(
+            "define row { box $1; box $2 }\nrow(\"a\", \"b\")",
+            PikchrFlags::default(),
+        );
+    }
+
+    #[test]
+    fn adds_class() {
+        let svg = render("box", Some("diagram"), PikchrFlags::default()).unwrap();
+        assert!(
+            svg.starts_with("<svg xmlns='http://www.w3.org/2000/svg' class=\"diagram\" viewBox=")
+        );
+    }
+
+    #[test]
+    fn exposes_layout() {
+        let diagram = layout("A: box\n[ B: circle rad 1 ]\nP: A.n").unwrap();
+        let objects = diagram.objects();
+        assert_eq!(objects.len(), 3);
+        assert_eq!(objects[0].class(), Some("box"));
+        assert_eq!(objects[0].start(), Point::new(-0.375, 0.0));
+        assert_eq!(objects[0].end(), Point::new(0.375, 0.0));
+        assert_eq!(objects[1].class(), Some("[]"));
+        assert_eq!(objects[1].children()[0].name(), Some("B"));
+        assert_eq!(objects[1].center(), Point::new(1.375, 0.0));
+        assert_eq!(objects[1].bounds().width(), 2.0);
+        assert_eq!(objects[2].class(), None);
+        assert_eq!(objects[2].center(), Point::new(0.0, 0.25));
+    }
+
+    #[test]
+    fn sizes_like_c() {
+        for source in [
+            "box",
+            "scale = 2\ncircle rad 1",
+            "arrow right 200% \"Markdown\"",
+        ] {
+            let c = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+            let size = layout(source).unwrap().size();
+            assert_eq!(size, Some((c.width(), c.height())), "{}", source);
+        }
+        assert_eq!(layout("gap = 1").unwrap().size(), None);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let message = |source| layout(source).unwrap_err().message().to_string();
+        assert_eq!(message("box wid"), "syntax error");
+        assert_eq!(
+            message("box from 1,1"),
+            "use \"at\" to position this object"
+        );
+        assert_eq!(
+            message("line at 1,1"),
+            "use \"from\" and \"to\" to position this object"
+        );
+        assert_eq!(message("box at A"), "no such object");
+        assert_eq!(message("$a = zz"), "no such variable");
+        assert_eq!(message("assert(1 == 2)"), "1 != 2");
+        assert_eq!(message("box; circle wid 1 wid 2"), "value is already set");
+        let err = layout("box\nline then down").unwrap_err();
+        assert_eq!(err.message(), "no prior path points");
+        assert_eq!(err.span(), Span::new(9, 13));
+    }
+
+    #[test]
+    fn formats_like_printf() {
+        assert_eq!(format_g(0.0, 6), "0");
+        assert_eq!(format_g(2.16, 6), "2.16");
+        assert_eq!(format_g(152.64, 6), "152.64");
+        assert_eq!(format_g(1.0 / 3.0, 10), "0.3333333333");
+        assert_eq!(format_g(1234567.0, 6), "1.23457e+06");
+        assert_eq!(format_g(0.0000123, 6), "1.23e-05");
+        assert_eq!(format_g(100.0, 6), "100");
+        assert_eq!(format_g(-2.5, 6), "-2.5");
+    }
+}
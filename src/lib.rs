@@ -10,6 +10,10 @@
 //! The main interface is the [`Pikchr`] struct, specifically its
 //! [`Pikchr::render`] function.
 //!
+//! On `wasm32-unknown-unknown`, which has no C library to build pikchr
+//! against, [`Pikchr::render`] uses the pure-Rust `layout` backend
+//! instead, so diagrams can be rendered in a browser.
+//!
 //! ```
 //! use pikchr::{Pikchr, PikchrFlags};
 //!
@@ -27,50 +31,95 @@
 //! ```
 //! <svg xmlns='http://www.w3.org/2000/svg' viewBox="0 0 475.315 195.84"><polygon points="146,37 134,41 134,33" style="fill:rgb(0,0,0)"/><path d="M2,37L140,37"  style="fill:none;stroke-width:2.16;stroke:rgb(0,0,0);" /><text x="74" y="25" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">Markdown</text><text x="74" y="49" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">Source</text><path d="M161,72L309,72A15 15 0 0 0 324 57L324,17A15 15 0 0 0 309 2L161,2A15 15 0 0 0 146 17L146,57A15 15 0 0 0 161 72Z"  style="fill:none;stroke-width:2.16;stroke:rgb(0,0,0);" /><text x="235" y="17" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">Markdown</text><text x="235" y="37" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">Formatter</text><text x="235" y="57" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">(docs.rs/markdown)</text><polygon points="468,37 457,41 457,33" style="fill:rgb(0,0,0)"/><path d="M324,37L463,37"  style="fill:none;stroke-width:2.16;stroke:rgb(0,0,0);" /><text x="396" y="25" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">HTML+SVG</text><text x="396" y="49" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">Output</text><polygon points="235,72 239,84 231,84" style="fill:rgb(0,0,0)"/><polygon points="235,123 231,111 239,111" style="fill:rgb(0,0,0)"/><path d="M235,78L235,117"  style="fill:none;stroke-width:2.16;stroke:rgb(0,0,0);" /><path d="M178,193L292,193A15 15 0 0 0 307 178L307,138A15 15 0 0 0 292 123L178,123A15 15 0 0 0 163 138L163,178A15 15 0 0 0 178 193Z"  style="fill:none;stroke-width:2.16;stroke:rgb(0,0,0);" /><text x="235" y="138" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">Pikchr</text><text x="235" y="158" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">Formatter</text><text x="235" y="178" text-anchor="middle" fill="rgb(0,0,0)" dominant-baseline="central">(docs.rs/pikchr)</text></svg>
 
-use libc::{c_char, c_int, c_uint, c_void, free};
+use core::ffi::c_uint;
+#[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
+use core::ffi::{c_char, c_int, c_void};
+#[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
 use std::ffi::{CStr, CString};
-use std::fmt;
 use std::ops::Deref;
 
-pub mod raw {
-    use libc::{c_char, c_int, c_uint};
-
-    extern "C" {
-        /// The main interface.  Invoke this routine to translate PIKCHR source
-        /// text into SVG. The SVG is returned in a buffer obtained from malloc().
-        /// The caller is responsible for freeing the buffer.
-        ///
-        /// If an error occurs, *pnWidth is filled with a negative number and
-        /// the return buffer contains error message text instead of SVG.  By
-        /// default, the error message is HTML encoded.  However, error messages
-        /// come out as plaintext if the PIKCHR_PLAINTEXT_ERRORS flag is included
-        /// as one of the bits in the mFlags parameter.
-        ///
-        /// - `zText`: Input PIKCHR source text.  zero-terminated
-        /// - `zClass`: Add class="%s" to <svg> markup
-        /// - `mFlags`: Flags used to influence rendering behavior
-        /// - `pnWidth`: OUT: Write width of <svg> here, if not NULL
-        /// - `pnHeight`: OUT: Write height here, if not NULL
-        #[allow(non_snake_case)]
-        pub fn pikchr(
-            zText: *const c_char,
-            zClass: *const c_char,
-            mFlags: c_uint,
-            pnWidth: *mut c_int,
-            pnHeight: *mut c_int,
-        ) -> *mut c_char;
-    }
-
-    /// Include PIKCHR_PLAINTEXT_ERRORS among the bits of mFlags on the 3rd
-    /// argument to pikchr() in order to cause error message text to come out
-    /// as text/plain instead of as text/html
-    pub const PIKCHR_PLAINTEXT_ERRORS: c_uint = 0x0001;
+pub mod accessibility;
+pub mod adaptive;
+pub mod animation;
+pub mod ast;
+pub mod batch;
+pub mod bidi;
+pub mod cache;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(any(feature = "rust-backend", not(pikchr_c)))]
+pub mod debug;
+pub mod diagram;
+pub mod diff;
+#[cfg(all(feature = "dlopen", unix))]
+pub mod dynamic;
+pub mod er;
+pub mod error;
+pub mod fmt;
+pub mod font;
+pub mod fossil;
+pub mod grid;
+pub mod hash;
+pub mod http;
+pub mod ide;
+pub mod image_map;
+pub mod iter;
+#[cfg(feature = "js")]
+mod js;
+#[cfg(any(feature = "rust-backend", not(pikchr_c)))]
+pub mod layout;
+pub mod lint;
+pub mod markdown;
+pub mod measure;
+pub mod message;
+pub mod minify;
+pub mod monochrome;
+pub mod options;
+pub mod page;
+pub mod palette;
+pub mod plantuml;
+pub mod rate_limit;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+pub mod sandbox;
+pub mod semantic;
+pub mod sequence;
+mod source;
+pub mod source_map;
+pub mod state;
+#[cfg(all(pikchr_c, feature = "rust-alloc"))]
+pub mod stats;
+pub mod svg;
+pub mod testing;
+pub mod thumbnail;
+pub mod tile;
+pub mod tooltip;
+#[cfg(any(feature = "rust-backend", not(pikchr_c)))]
+pub mod trace;
+
+#[cfg(feature = "derive")]
+pub use pikchr_derive::{PikchrEntity, PikchrStateDiagram};
+
+/// Raw bindings to the C implementation
+#[cfg(feature = "vendored")]
+pub use pikchr_sys as raw;
+
+/// The constants of the raw bindings, for when the vendored C is left out
+/// and pikchr is only loaded at runtime
+#[cfg(not(feature = "vendored"))]
+mod raw {
+    use core::ffi::c_uint;
 
-    /// Alter colour choices to make diagrams more suitable for rendering in
-    /// a dark settings such as dark-mode web pages.
+    pub const PIKCHR_PLAINTEXT_ERRORS: c_uint = 0x0001;
     pub const PIKCHR_DARK_MODE: c_uint = 0x0002;
+    pub const PIKCHR_DATE: &str = "2021-05-08";
 }
 
+pub use crate::batch::render_batch;
+pub use crate::diff::diff;
+pub use crate::error::PikchrError;
+pub use crate::iter::RenderIter;
+pub use crate::options::RenderOptions;
+
 /// Flags for converting pikchr source
 ///
 /// You can construct a default set of flags using the [`std::default::Default`] trait
@@ -181,55 +230,250 @@ impl std::default::Default for PikchrFlags {
     }
 }
 
+/// The renderer behind [`Pikchr::render`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// The vendored C implementation
+    C,
+    /// The pure-Rust `layout` backend, used where the C cannot be built
+    Rust,
+}
+
+/// The versions of this crate and of the renderer it uses
+///
+/// ```
+/// let version = pikchr::version();
+/// assert_eq!(version.crate_version, env!("CARGO_PKG_VERSION"));
+/// println!("rendering with {}", version);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    /// The version of this crate
+    pub crate_version: &'static str,
+    /// When the vendored `pikchr.c` was taken from upstream, as YYYY-MM-DD
+    pub pikchr_date: &'static str,
+    /// The upstream check-in which the vendored `pikchr.c` came from, if
+    /// it is recorded
+    pub pikchr_check_in: Option<&'static str>,
+    /// The renderer behind [`Pikchr::render`]
+    pub backend: Backend,
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pikchr {} (pikchr.c {}",
+            self.crate_version, self.pikchr_date
+        )?;
+        if let Some(check_in) = self.pikchr_check_in {
+            write!(f, " [{}]", check_in)?;
+        }
+        match self.backend {
+            Backend::C => write!(f, ")"),
+            Backend::Rust => write!(f, ", Rust backend)"),
+        }
+    }
+}
+
+/// Report the versions of this crate and of the renderer it uses
+pub fn version() -> Version {
+    Version {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        pikchr_date: raw::PIKCHR_DATE,
+        pikchr_check_in: None,
+        backend: if cfg!(pikchr_c) {
+            Backend::C
+        } else {
+            Backend::Rust
+        },
+    }
+}
+
+/// A rendering option which a renderer may or may not support
+///
+/// New flags are added here as they are wrapped, so that applications
+/// can check for them with [`supports`] before relying on them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Flag {
+    /// Errors as plain text, see [`PikchrFlags::generate_plain_errors`]
+    PlainErrors,
+    /// Errors as HTML, see [`PikchrFlags::generate_html_errors`]
+    HtmlErrors,
+    /// Colours for dark backgrounds, see [`PikchrFlags::use_dark_mode`]
+    DarkMode,
+}
+
+/// Whether [`Pikchr::render`] honours a flag
+///
+/// ```
+/// use pikchr::{supports, Flag};
+///
+/// assert!(supports(Flag::DarkMode));
+/// ```
+pub fn supports(flag: Flag) -> bool {
+    match flag {
+        Flag::PlainErrors | Flag::DarkMode => true,
+        // The Rust backend always reports errors as plain text
+        Flag::HtmlErrors => cfg!(pikchr_c),
+    }
+}
+
 /// A rendered pikchr diagram
 ///
 /// Pikchr renders diagrams as SVG.  This SVG is a given width
 /// and height.  The Pikchr derefs to the SVG string, or you
 /// can access it explicitly.  The width and height are accessible
 /// as plain numbers.
+///
+/// A Pikchr owns its SVG, so it can be sent and shared between threads,
+/// and since pikchr keeps no state between renders, diagrams can be
+/// rendered on many threads at once; see [`batch`].
+#[derive(Clone)]
 pub struct Pikchr {
-    rendered: *const c_char,
-    width: c_int,
-    height: c_int,
-}
-
-impl Drop for Pikchr {
-    fn drop(&mut self) {
-        if self.rendered.is_null() {
-            unsafe {
-                free(self.rendered as *mut c_void);
-            }
-            self.rendered = std::ptr::null();
-        }
-    }
+    rendered: String,
+    width: isize,
+    height: isize,
 }
 
 impl Deref for Pikchr {
     type Target = str;
     fn deref(&self) -> &Self::Target {
-        // We're assuming a Pikchr instance can only
-        // be constructed from valid utf8 and thus can
-        // only contain valid utf8
-        unsafe {
-            let cstr = CStr::from_ptr(self.rendered);
-            std::str::from_utf8_unchecked(cstr.to_bytes())
-        }
+        &self.rendered
+    }
+}
+
+/// The signature of the C `pikchr()` function
+#[cfg(all(feature = "dlopen", unix))]
+type RenderFn = unsafe extern "C" fn(
+    *const c_char,
+    *const c_char,
+    c_uint,
+    *mut c_int,
+    *mut c_int,
+) -> *mut c_char;
+
+/// Render with a C `pikchr()` function, or a closure which calls one,
+/// releasing its output with `free`
+///
+/// The output is copied, so that a [`Pikchr`] does not depend on the
+/// library which rendered it.
+///
+/// # Safety
+///
+/// The functions must behave as the C `pikchr()` and `free()` do.
+#[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
+unsafe fn render_with(
+    pikchr: impl FnOnce(*const c_char, *const c_char, c_uint, *mut c_int, *mut c_int) -> *mut c_char,
+    free: unsafe extern "C" fn(*mut c_void),
+    source: &str,
+    class: Option<&str>,
+    flags: PikchrFlags,
+) -> Result<Pikchr, PikchrError> {
+    let mut rendered = String::new();
+    let (width, height) = render_into_with(pikchr, free, source, class, flags, &mut rendered)?;
+    Ok(Pikchr {
+        rendered,
+        width,
+        height,
+    })
+}
+
+/// As [`render_with`], appending the output to a buffer and returning
+/// its width and height
+///
+/// The buffer is left as it was if the source cannot be rendered.
+///
+/// # Safety
+///
+/// The functions must behave as the C `pikchr()` and `free()` do.
+#[cfg(any(pikchr_c, all(feature = "dlopen", unix)))]
+unsafe fn render_into_with(
+    pikchr: impl FnOnce(*const c_char, *const c_char, c_uint, *mut c_int, *mut c_int) -> *mut c_char,
+    free: unsafe extern "C" fn(*mut c_void),
+    source: &str,
+    class: Option<&str>,
+    flags: PikchrFlags,
+    buf: &mut String,
+) -> Result<(isize, isize), PikchrError> {
+    let mut width: c_int = 0;
+    let mut height: c_int = 0;
+    let text = source;
+    let source = CString::new(source).map_err(|_| PikchrError::NulInInput)?;
+    let class = class
+        .map(CString::new)
+        .transpose()
+        .map_err(|_| PikchrError::NulInInput)?;
+    let res = pikchr(
+        source.as_ptr(),
+        class.as_ref().map_or(std::ptr::null(), |c| c.as_ptr()),
+        flags.into(),
+        &mut width,
+        &mut height,
+    );
+    if res.is_null() {
+        return Err(PikchrError::OutOfMemory);
     }
+    let result = {
+        let rendered = String::from_utf8_lossy(CStr::from_ptr(res).to_bytes());
+        if width < 0 {
+            Err(PikchrError::from_output(text, rendered.into_owned()))
+        } else {
+            buf.push_str(&rendered);
+            Ok((width as isize, height as isize))
+        }
+    };
+    free(res as *mut c_void);
+    result
 }
 
-impl fmt::Display for Pikchr {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl std::fmt::Display for Pikchr {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         fmt.write_str(self)
     }
 }
 
+/// Render some pikchr source with no class and the default flags
+///
+/// ```
+/// use std::convert::TryInto;
+/// # fn main() -> Result<(), pikchr::PikchrError> {
+/// let pic: pikchr::Pikchr = "box \"hello\"".try_into()?;
+/// assert!(pic.contains(">hello</text>"));
+/// # Ok(())
+/// # }
+/// ```
+impl std::convert::TryFrom<&str> for Pikchr {
+    type Error = PikchrError;
+
+    fn try_from(source: &str) -> Result<Self, Self::Error> {
+        Pikchr::render(source, None, PikchrFlags::default())
+    }
+}
+
+/// Render some pikchr source with no class and the default flags
+///
+/// ```
+/// let pic: pikchr::Pikchr = "circle".parse().unwrap();
+/// assert!(pic.contains("<circle"));
+/// assert!("circle rad".parse::<pikchr::Pikchr>().is_err());
+/// ```
+impl std::str::FromStr for Pikchr {
+    type Err = PikchrError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Pikchr::render(source, None, PikchrFlags::default())
+    }
+}
+
 impl Pikchr {
     /// Render some input pikchr source as an SVG
     ///
     /// You can convert arbitrary pikchr source into an SVG using this function.
     /// The class name is optional, and the flags field controls the generation
-    /// of errors.  Since pikchr does not have a structured error format, the
-    /// returned error is simply a string.
+    /// of errors.  An error says where in the source it is, when it can, and
+    /// keeps the text or HTML which pikchr reported; see [`PikchrError`].
     ///
     /// ```
     /// # use pikchr::{Pikchr, PikchrFlags};
@@ -243,38 +487,702 @@ impl Pikchr {
     ///     .unwrap();
     /// assert!(image.contains("<svg"))
     /// ```
-    pub fn render(source: &str, class: Option<&str>, flags: PikchrFlags) -> Result<Pikchr, String> {
-        let mut width: c_int = 0;
-        let mut height: c_int = 0;
-        let source = CString::new(source).map_err(|e| format!("{:?}", e))?;
-        let res: *mut c_char = unsafe {
-            raw::pikchr(
-                source.as_ptr() as *const c_char,
-                class
-                    .map(|s| s.as_ptr() as *const c_char)
-                    .unwrap_or(std::ptr::null()),
-                flags.into(),
-                &mut width as *mut c_int,
-                &mut height as *mut c_int,
+    #[cfg(pikchr_c)]
+    pub fn render(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        #[cfg(feature = "unicode-metrics")]
+        let pikchr = measure::pikchr_unicode;
+        #[cfg(not(feature = "unicode-metrics"))]
+        let pikchr = raw::pikchr;
+        unsafe {
+            render_with(
+                |source, class, flags, width, height| pikchr(source, class, flags, width, height),
+                raw::free,
+                source,
+                class,
+                flags,
             )
-        };
-        if width < 0 {
-            let err = unsafe { CStr::from_ptr(res) };
-            let err = err.to_bytes();
-            let err = String::from_utf8_lossy(err).into_owned();
-            unsafe {
-                free(res as *mut c_void);
-            }
-            Err(err)
-        } else {
-            Ok(Pikchr {
-                rendered: res,
+        }
+    }
+
+    /// Render some input pikchr source, appending the SVG to a buffer
+    ///
+    /// This returns the width and height of the diagram.  Reusing one
+    /// buffer saves allocating a string for each diagram when assembling a
+    /// page from many of them.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let mut page = String::from("<body>");
+    /// for source in &["box", "circle"] {
+    ///     let (width, _) = Pikchr::render_into(source, None, PikchrFlags::default(), &mut page)
+    ///         .unwrap();
+    ///     assert!(width > 0);
+    /// }
+    /// assert!(page.starts_with("<body><svg") && page.contains("<circle"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pikchr::render`], in which case the buffer is left as it
+    /// was.
+    #[cfg(pikchr_c)]
+    pub fn render_into(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+        buf: &mut String,
+    ) -> Result<(isize, isize), PikchrError> {
+        #[cfg(feature = "unicode-metrics")]
+        let pikchr = measure::pikchr_unicode;
+        #[cfg(not(feature = "unicode-metrics"))]
+        let pikchr = raw::pikchr;
+        unsafe {
+            render_into_with(
+                |source, class, flags, width, height| pikchr(source, class, flags, width, height),
+                raw::free,
+                source,
+                class,
+                flags,
+                buf,
+            )
+        }
+    }
+
+    /// Render some input pikchr source, appending the SVG to a buffer
+    ///
+    /// This target has no C library, so the source is rendered with the
+    /// pure-Rust [`layout`] backend.
+    #[cfg(not(pikchr_c))]
+    pub fn render_into(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+        buf: &mut String,
+    ) -> Result<(isize, isize), PikchrError> {
+        let pic = Pikchr::render(source, class, flags)?;
+        buf.push_str(&pic.rendered);
+        Ok((pic.width, pic.height))
+    }
+
+    /// Render some input pikchr source as an SVG
+    ///
+    /// This target has no C library, so the source is rendered with the
+    /// pure-Rust [`layout`] backend.
+    #[cfg(not(pikchr_c))]
+    pub fn render(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        let diagram = layout::layout(source).map_err(|e| PikchrError::from_layout(source, &e))?;
+        Pikchr::from_layout(&diagram, class, flags)
+    }
+
+    /// Render a diagram laid out by the pure-Rust backend
+    #[cfg(any(feature = "rust-backend", not(pikchr_c)))]
+    fn from_layout(
+        diagram: &layout::Layout,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        let rendered = diagram.to_svg(class, flags);
+        match diagram.size() {
+            Some((width, height)) => Ok(Pikchr {
+                rendered,
                 width,
                 height,
-            })
+            }),
+            // The C implementation reports an empty diagram as an error
+            None => Err(PikchrError::Other {
+                message: rendered.trim().to_string(),
+                raw: rendered,
+            }),
+        }
+    }
+
+    /// Render some input pikchr source as an SVG, measuring text with a
+    /// callback
+    ///
+    /// pikchr can only estimate how wide text will be, so `fit` can make a
+    /// shape too big or too small for its label.  An application which
+    /// knows the metrics of the font its diagrams are shown in can measure
+    /// text itself.  The callback is given the text of each label as it is
+    /// displayed, with escapes removed and common HTML entities decoded,
+    /// and the size of its font relative to normal text, which is more or
+    /// less than 1 for `big` or `small` text or with `fontscale`.  It
+    /// returns the width of the text in SVG pixels, of which there are 144
+    /// in an inch, or a negative number to use the built-in estimate.
+    ///
+    /// If the callback panics, the panic is passed on once rendering has
+    /// finished.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// // A monospace font, with characters half as wide as their size
+    /// let measure = |text: &str, size: f64| text.chars().count() as f64 * 8.0 * size;
+    /// let image =
+    ///     Pikchr::render_measured("box \"hello\" fit", None, PikchrFlags::default(), &measure)
+    ///         .unwrap();
+    /// assert!(image.contains("<svg"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pikchr::render`].
+    pub fn render_measured(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+        measure: &dyn Fn(&str, f64) -> f64,
+    ) -> Result<Pikchr, PikchrError> {
+        #[cfg(pikchr_c)]
+        return measure::render_measured(source, class, flags, measure);
+        #[cfg(not(pikchr_c))]
+        {
+            let diagram = layout::layout_measured(source, measure)
+                .map_err(|e| PikchrError::from_layout(source, &e))?;
+            Pikchr::from_layout(&diagram, class, flags)
         }
     }
 
+    /// Render some input pikchr source as an SVG, reporting the memory
+    /// which the C library allocated
+    ///
+    /// See [`stats`].
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let (image, stats) = Pikchr::render_with_stats("box", None, PikchrFlags::default()).unwrap();
+    /// assert!(stats.allocations() > 0);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pikchr::render`].
+    #[cfg(all(pikchr_c, feature = "rust-alloc"))]
+    pub fn render_with_stats(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<(Pikchr, stats::RenderStats), PikchrError> {
+        let (pic, alloc) = raw::alloc::track(|| Pikchr::render(source, class, flags));
+        Ok((pic?, stats::RenderStats::new(alloc)))
+    }
+
+    /// Render some input pikchr source as an SVG, passing any messages
+    /// from the renderer to a callback
+    ///
+    /// See [`message`] for which messages there are.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render_with_messages("box", None, PikchrFlags::default(), &mut |level, text| {
+    ///     eprintln!("pikchr {}: {}", level, text)
+    /// })
+    /// .unwrap();
+    /// assert!(image.contains("<svg"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pikchr::render`].
+    pub fn render_with_messages(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+        on_message: &mut dyn FnMut(message::Level, &str),
+    ) -> Result<Pikchr, PikchrError> {
+        let pic = Pikchr::render(source, class, flags)?;
+        message::report_prints(&pic.rendered, on_message);
+        Ok(pic)
+    }
+
+    /// Render some input pikchr source as an SVG, with a layer over it
+    /// showing the bounding box and anchors of each object and a grid of
+    /// coordinates
+    ///
+    /// See [`debug`].  The diagram is always rendered with the pure-Rust
+    /// [`layout`] backend, so that the layer matches it.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render_debug("circle", None, PikchrFlags::default()).unwrap();
+    /// assert!(image.contains("<title>.n (0, 0.25)</title>"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`layout::render`].
+    #[cfg(any(feature = "rust-backend", not(pikchr_c)))]
+    pub fn render_debug(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        let diagram = layout::layout(source).map_err(|e| PikchrError::from_layout(source, &e))?;
+        let pic = Pikchr::from_layout(&diagram, class, flags)?;
+        let mut dom = pic.dom()?;
+        debug::overlay(&mut dom, &diagram);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..pic
+        })
+    }
+
+    /// Make a change to this Pikchr which needs the statements of its
+    /// source
+    ///
+    /// The source map comes from the Rust parser, so if that cannot parse
+    /// source which the renderer took, the diagram is left unchanged rather
+    /// than not rendered at all.
+    fn with_source_map(
+        self,
+        map: Result<source_map::SourceMap, String>,
+        change: impl FnOnce(&mut svg::Dom, &source_map::SourceMap),
+    ) -> Result<Pikchr, PikchrError> {
+        let map = match map {
+            Ok(map) => map,
+            Err(_) => return Ok(self),
+        };
+        let mut dom = self.dom()?;
+        change(&mut dom, &map);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..self
+        })
+    }
+
+    /// Render some input pikchr source as an SVG, with the output of each
+    /// top-level statement in its own `<g data-step="N">`
+    ///
+    /// The statements which draw something are numbered from 1, so a
+    /// presentation can reveal the diagram a step at a time by showing the
+    /// groups in order.  See [`source_map`].  Source which only the
+    /// renderer can parse is rendered without the groups.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render_steps("box; arrow; circle", None, PikchrFlags::default())
+    ///     .unwrap();
+    /// assert!(image.contains("<g data-step=\"3\"><circle "));
+    /// ```
+    pub fn render_steps(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        let pic = Pikchr::render(source, class, flags)?;
+        pic.with_source_map(source_map::SourceMap::new(source), |dom, map| {
+            map.group_steps(dom)
+        })
+    }
+
+    /// Render some input pikchr source as an SVG, with the tooltips from
+    /// any `tooltip:` comments
+    ///
+    /// See [`tooltip`] for how comments give tooltips.  Source which only
+    /// the renderer can parse is rendered without them.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render_with_tooltips(
+    ///     "circle # tooltip: A circle",
+    ///     None,
+    ///     PikchrFlags::default(),
+    /// )
+    /// .unwrap();
+    /// assert!(image.contains("<title>A circle</title></circle>"));
+    /// ```
+    pub fn render_with_tooltips(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        let pic = Pikchr::render(source, class, flags)?;
+        pic.with_source_map(source_map::SourceMap::new(source), |dom, map| {
+            tooltip::add_tooltips(dom, source, map)
+        })
+    }
+
+    /// Render some input pikchr source as an SVG which is drawn for a dark
+    /// background when the reader prefers a dark color scheme
+    ///
+    /// See [`adaptive`].  Whether `flags` asks for dark mode makes no
+    /// difference, as the diagram is rendered both ways.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render_adaptive("circle", None, PikchrFlags::default()).unwrap();
+    /// assert!(image.contains("prefers-color-scheme: dark"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pikchr::render`].
+    pub fn render_adaptive(
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, PikchrError> {
+        let mut flags = flags;
+        let pic = Pikchr::render(source, class, *flags.clear_dark_mode())?;
+        let dark = Pikchr::render(source, class, *flags.use_dark_mode())?;
+        let mut dom = pic.dom()?;
+        let dark = dark.dom()?;
+        adaptive::combine(&mut dom, &dark);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..pic
+        })
+    }
+
+    /// Animate this Pikchr so that it draws itself a piece at a time
+    ///
+    /// See [`animation`] for how it is animated.
+    ///
+    /// ```
+    /// # use pikchr::{animation::AnimationOptions, Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box; arrow", None, PikchrFlags::default()).unwrap();
+    /// let animated = image.animated(AnimationOptions::default()).unwrap();
+    /// assert!(animated.contains("@keyframes pikchr-reveal"));
+    /// assert_eq!(animated.width(), image.width());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn animated(&self, options: animation::AnimationOptions) -> Result<Pikchr, PikchrError> {
+        let mut dom = self.dom()?;
+        animation::animate(&mut dom, options);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..*self
+        })
+    }
+
+    /// Give the text of this Pikchr an explicit direction
+    ///
+    /// See [`bidi`] for how the text is changed.
+    ///
+    /// ```
+    /// # use pikchr::{bidi::TextDirection, Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box \"مرحبا\"", None, PikchrFlags::default()).unwrap();
+    /// let image = image.with_text_direction(TextDirection::Rtl).unwrap();
+    /// assert!(image.contains(" direction=\"rtl\""));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn with_text_direction(
+        &self,
+        direction: bidi::TextDirection,
+    ) -> Result<Pikchr, PikchrError> {
+        let mut dom = self.dom()?;
+        bidi::set_direction(&mut dom, direction);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..*self
+        })
+    }
+
+    /// Embed a font in this Pikchr and draw all of its text in it
+    ///
+    /// See [`font`] for why.
+    ///
+    /// ```
+    /// # use pikchr::{font::{FontFormat, WebFont}, Pikchr, PikchrFlags};
+    /// let font = WebFont::from_base64("Mono", FontFormat::Woff2, "d09GMg==").unwrap();
+    /// let image = Pikchr::render("box \"Hello\"", None, PikchrFlags::default()).unwrap();
+    /// let image = image.with_font(&font).unwrap();
+    /// assert!(image.contains("@font-face{font-family:'Mono';"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn with_font(&self, font: &font::WebFont) -> Result<Pikchr, PikchrError> {
+        let mut dom = self.dom()?;
+        font::embed(&mut dom, font);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..*self
+        })
+    }
+
+    /// Remap the colors of this Pikchr to a palette which readers with a
+    /// color vision deficiency can tell apart
+    ///
+    /// See [`palette`] for how colors are remapped.
+    ///
+    /// ```
+    /// # use pikchr::{palette::Palette, Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("circle fill blue", None, PikchrFlags::default()).unwrap();
+    /// let image = image.with_palette(Palette::ColorBlindSafe).unwrap();
+    /// assert!(image.contains("fill:rgb(0,114,178);"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn with_palette(&self, palette: palette::Palette) -> Result<Pikchr, PikchrError> {
+        let mut dom = self.dom()?;
+        palette::recolor(&mut dom, palette);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..*self
+        })
+    }
+
+    /// Give this Pikchr a title and description for screen readers, or
+    /// hide it from them
+    ///
+    /// See [`accessibility`] for what is added.
+    ///
+    /// ```
+    /// # use pikchr::{accessibility::AccessibilityOptions, Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box \"Start\"", None, PikchrFlags::default()).unwrap();
+    /// let image = image
+    ///     .with_accessibility(AccessibilityOptions::default().title("Start"))
+    ///     .unwrap();
+    /// assert!(image.contains(" role=\"img\""));
+    /// assert!(image.contains("-title\">Start</title>"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn with_accessibility(
+        &self,
+        options: &accessibility::AccessibilityOptions,
+    ) -> Result<Pikchr, PikchrError> {
+        let mut dom = self.dom()?;
+        accessibility::annotate(&mut dom, options);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..*self
+        })
+    }
+
+    /// Make this Pikchr black and white, for printing
+    ///
+    /// See [`monochrome`] for how colors are replaced.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box \"a\" color red", None, PikchrFlags::default()).unwrap();
+    /// let image = image.monochrome().unwrap();
+    /// assert!(!image.contains("rgb(255,0,0)"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn monochrome(&self) -> Result<Pikchr, PikchrError> {
+        let mut dom = self.dom()?;
+        monochrome::monochrome(&mut dom);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            ..*self
+        })
+    }
+
+    /// Crop this Pikchr to part of its `viewBox`, keeping its scale
+    ///
+    /// The area is in the coordinates of the SVG.  See [`tile`].
+    ///
+    /// ```
+    /// # use pikchr::{svg::Rect, Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box; box", None, PikchrFlags::default()).unwrap();
+    /// let area = Rect { x: 108.0, y: 0.0, width: 112.0, height: 76.0 };
+    /// let second = image.crop(area).unwrap();
+    /// assert!(second.contains(" viewBox=\"108 0 112 76\""));
+    /// assert_eq!(second.width(), 112);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the area is empty, or if the rendered pikchr
+    /// cannot be parsed or has no `viewBox`; see [`Pikchr::dom`].
+    pub fn crop(&self, view_box: svg::Rect) -> Result<Pikchr, PikchrError> {
+        tile::crop(self, view_box)
+    }
+
+    /// Split this Pikchr into tiles of a size in pixels, in rows from the
+    /// top left
+    ///
+    /// The tiles at the right and bottom may be smaller.  See [`tile`].
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box wid 4 ht 3", None, PikchrFlags::default()).unwrap();
+    /// let tiles = image.tiles(256, 256).unwrap();
+    /// assert_eq!(tiles.len(), 3 * 2);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the size is not positive, or if the rendered
+    /// pikchr cannot be parsed or has no `viewBox`; see [`Pikchr::dom`].
+    pub fn tiles(&self, width: isize, height: isize) -> Result<Vec<tile::Tile>, PikchrError> {
+        tile::tiles(self, width, height)
+    }
+
+    /// Split this Pikchr into pages of at most a size in pixels, if it does
+    /// not fit on one
+    ///
+    /// The pages are at the same scale as this Pikchr, and are cut along
+    /// gaps between objects where possible.  See [`page`].
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box wid 3; box wid 3", None, PikchrFlags::default()).unwrap();
+    /// let pages = image.pages(600, 600).unwrap();
+    /// assert_eq!(pages.len(), 2);
+    /// assert!(pages.iter().all(|page| page.width() <= 600));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the page is too small for its margins, or if the
+    /// rendered pikchr cannot be parsed or has no `viewBox`; see
+    /// [`Pikchr::dom`].
+    pub fn pages(&self, width: isize, height: isize) -> Result<Vec<Pikchr>, PikchrError> {
+        page::pages(self, width, height)
+    }
+
+    /// Shrink this Pikchr to fit within a square of some pixels, for
+    /// galleries and search results
+    ///
+    /// Diagrams which already fit are not enlarged.  If `elide_text` is
+    /// set, text which would be too small to read is left out.  See
+    /// [`thumbnail`].
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box wid 4 ht 1", None, PikchrFlags::default()).unwrap();
+    /// let thumbnail = image.thumbnail(160, false).unwrap();
+    /// assert_eq!((thumbnail.width(), thumbnail.height()), (160, 41));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if `max_px` is not positive, or if the rendered
+    /// pikchr cannot be parsed; see [`Pikchr::dom`].
+    pub fn thumbnail(&self, max_px: isize, elide_text: bool) -> Result<Pikchr, PikchrError> {
+        if max_px <= 0 {
+            return Err(PikchrError::svg(
+                "a thumbnail must be at least a pixel in size",
+            ));
+        }
+        let mut dom = self.dom()?;
+        let (width, height) =
+            thumbnail::shrink(&mut dom, (self.width, self.height), max_px, elide_text);
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            width,
+            height,
+        })
+    }
+
+    /// Make an HTML `<map>` with an `<area>` over each labelled object,
+    /// for showing this Pikchr as a raster image of its [`width`] and
+    /// [`height`]
+    ///
+    /// See [`image_map`] for which objects have areas.
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box \"Hello\"", None, PikchrFlags::default()).unwrap();
+    /// let map = image.to_image_map("hello").unwrap();
+    /// assert!(map.contains("<area shape=\"rect\" coords=\"2,2,110,74\" alt=\"Hello\""));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    ///
+    /// [`width`]: Pikchr::width
+    /// [`height`]: Pikchr::height
+    pub fn to_image_map(&self, name: &str) -> Result<String, PikchrError> {
+        let dom = self.dom()?;
+        Ok(image_map::image_map(&dom, name, self.width, self.height))
+    }
+
+    /// Make a Markdown image of this Pikchr, with the SVG inline as a
+    /// `data:` URI, or linking to a path which it has been saved to
+    ///
+    /// The alt text is escaped.  See [`markdown`].
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box \"Hello\"", None, PikchrFlags::default()).unwrap();
+    /// let markdown = image.to_markdown_image("Hello", Some("hello.svg")).unwrap();
+    /// assert_eq!(markdown, "![Hello](hello.svg)");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn to_markdown_image(&self, alt: &str, path: Option<&str>) -> Result<String, PikchrError> {
+        let dom = self.dom()?;
+        Ok(markdown::image(&dom, alt, path))
+    }
+
+    /// A stable digest of what this Pikchr draws, for cache keys, `ETag`s
+    /// and file names
+    ///
+    /// Comments and whitespace between the tags of the SVG do not change
+    /// it.  See [`hash`].
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box", None, PikchrFlags::default()).unwrap();
+    /// let name = format!("{}.svg", image.content_hash().unwrap());
+    /// assert_eq!(name.len(), 64 + 4);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn content_hash(&self) -> Result<hash::Digest, PikchrError> {
+        Ok(hash::hash_output(self.dom()?))
+    }
+
+    /// The values of the HTTP headers for serving this Pikchr, with an
+    /// `ETag` for answering conditional requests
+    ///
+    /// If `immutable` is set, caches may keep the diagram for good, which
+    /// suits URLs which change whenever the diagram does.  See [`http`].
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// let image = Pikchr::render("box", None, PikchrFlags::default()).unwrap();
+    /// let headers = image.http_headers(false).unwrap();
+    /// assert!(headers.not_modified(headers.etag()));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the rendered pikchr cannot be parsed; see
+    /// [`Pikchr::dom`].
+    pub fn http_headers(&self, immutable: bool) -> Result<http::Headers, PikchrError> {
+        Ok(http::Headers::new(self.content_hash()?, immutable))
+    }
+
     /// Retrieve the width of this Pikchr
     ///
     /// ```
@@ -284,7 +1192,7 @@ impl Pikchr {
     /// println!("Picture is {} pixels wide", pic.width());
     /// ```
     pub fn width(&self) -> isize {
-        self.width as isize
+        self.width
     }
 
     /// Retrieve the height of this Pikchr
@@ -296,7 +1204,7 @@ impl Pikchr {
     /// println!("Picture is {} pixels tall", pic.height());
     /// ```
     pub fn height(&self) -> isize {
-        self.height as isize
+        self.height
     }
 
     /// Retrieve the rendered pikchr (same as dereferencing)
@@ -308,7 +1216,26 @@ impl Pikchr {
     /// println!("Picture content:\n{}", pic.rendered());
     /// ```
     pub fn rendered(&self) -> &str {
-        &*self
+        self
+    }
+
+    /// Parse the rendered pikchr into a tree of elements
+    ///
+    /// ```
+    /// # use pikchr::{Pikchr, PikchrFlags};
+    /// # let pic = Pikchr::render(r#"arrow right 200% "Markdown" "Source""#,
+    /// #     None, PikchrFlags::default()).unwrap();
+    /// let dom = pic.dom().unwrap();
+    /// assert_eq!(dom.svg().unwrap().find_all("text").len(), 2);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the output is not well formed, which can happen
+    /// when text or `print` statements contain markup.  See
+    /// [`svg::Dom::parse`].
+    pub fn dom(&self) -> Result<svg::Dom, PikchrError> {
+        svg::Dom::parse(self).map_err(PikchrError::svg)
     }
 }
 
@@ -329,4 +1256,56 @@ mod tests {
         let p = Pikchr::render(SOURCE, None, flags).unwrap();
         assert_eq!(OUTPUT, p.rendered());
     }
+
+    #[test]
+    fn renders_steps_without_a_source_map() {
+        let flags = PikchrFlags::default();
+        let pic = Pikchr::render("box; arrow", None, flags).unwrap();
+        let plain = pic.rendered().to_string();
+        let unmapped = Err("syntax error".to_string());
+        let pic = pic
+            .with_source_map(unmapped, |_, _| panic!("changed without a map"))
+            .unwrap();
+        assert_eq!(pic.rendered(), plain);
+        let steps = Pikchr::render_steps("box; arrow", None, flags).unwrap();
+        assert!(steps.contains("<g data-step=\"2\">"));
+    }
+
+    #[test]
+    fn renders_tooltips_without_a_source_map() {
+        let source = "circle # tooltip: A circle";
+        let pic = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+        let plain = pic.rendered().to_string();
+        let unmapped = Err("syntax error".to_string());
+        let pic = pic
+            .with_source_map(unmapped, |dom, map| tooltip::add_tooltips(dom, source, map))
+            .unwrap();
+        assert_eq!(pic.rendered(), plain);
+        assert!(!pic.contains("<title>"));
+    }
+
+    #[test]
+    fn renders_into_a_buffer() {
+        let flags = PikchrFlags::default();
+        let mut buf = String::from("<p>");
+        let size = Pikchr::render_into("box", None, flags, &mut buf).unwrap();
+        let pic = Pikchr::render("box", None, flags).unwrap();
+        assert_eq!(size, (pic.width(), pic.height()));
+        assert_eq!(buf, format!("<p>{}", pic));
+        assert!(Pikchr::render_into("box wid", None, flags, &mut buf).is_err());
+        assert_eq!(buf, format!("<p>{}", pic));
+    }
+
+    #[test]
+    fn describes_version() {
+        let mut version = version();
+        version.backend = Backend::C;
+        let expected = format!("pikchr {} (pikchr.c 2021-05-08)", env!("CARGO_PKG_VERSION"));
+        assert_eq!(version.to_string(), expected);
+        version.pikchr_check_in = Some("abc123");
+        version.backend = Backend::Rust;
+        assert!(version
+            .to_string()
+            .ends_with("(pikchr.c 2021-05-08 [abc123], Rust backend)"));
+    }
 }
@@ -0,0 +1,861 @@
+//! Checking pikchr source for likely mistakes
+//!
+//! [`lint`] parses pikchr source and reports [`Diagnostic`]s for things
+//! which are valid pikchr but are probably not what the author meant, such
+//! as variables which are set but never used or text which is too wide for
+//! the shape it is in.  Each [`Rule`] can be disabled or given a different
+//! [`Severity`] with a [`LintConfig`].
+//!
+//! ```
+//! use pikchr::lint::{lint, LintConfig, Rule};
+//!
+//! let source = "gap = 0.5\nA: box \"a long line of text\" wid 0.5\narrow right 0.5\n";
+//! let diagnostics = lint(source, &LintConfig::default());
+//! let rules: Vec<Rule> = diagnostics.iter().map(|d| d.rule).collect();
+//! assert_eq!(rules, [Rule::UnusedVariable, Rule::UnusedLabel, Rule::TextOverflow]);
+//! ```
+//!
+//! Some diagnostics come with a [`Fix`] which [`apply_fixes`] can apply to
+//! the source.
+
+use crate::ast::{
+    self, Assertion, Attribute, AttributeKind, Document, Expr, ExprKind, NumericProperty, Object,
+    ObjectKind, ObjectRef, ObjectRefKind, Place, PlaceKind, Position, PositionKind, PrintItem,
+    RelExpr, Span, Statement, StatementKind, Text, TextPosition,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// A suggestion which may safely be ignored
+    Hint,
+    /// Something worth knowing about
+    Info,
+    /// Probably a mistake
+    Warning,
+    /// Definitely a mistake
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Hint => "hint",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// The checks which the linter makes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// The source does not parse
+    Syntax,
+    /// A variable is assigned but never used
+    UnusedVariable,
+    /// A place (`P: A.n + (1, 0)`) is defined but never used
+    UnusedPlace,
+    /// An object is labelled but the label is never used
+    UnusedLabel,
+    /// Text is likely to be wider or taller than the shape containing it
+    TextOverflow,
+    /// A dimension is given as a literal number rather than a variable
+    MagicNumber,
+    /// A construct from legacy PIC which has no effect in pikchr
+    Deprecated,
+}
+
+impl Rule {
+    /// Every rule, in the order their diagnostics are reported
+    pub const ALL: &'static [Rule] = &[
+        Rule::Syntax,
+        Rule::UnusedVariable,
+        Rule::UnusedPlace,
+        Rule::UnusedLabel,
+        Rule::TextOverflow,
+        Rule::MagicNumber,
+        Rule::Deprecated,
+    ];
+
+    /// The name of the rule, as used in configuration
+    ///
+    /// ```
+    /// # use pikchr::lint::Rule;
+    /// assert_eq!(Rule::UnusedVariable.name(), "unused-variable");
+    /// assert_eq!(Rule::from_name("text-overflow"), Some(Rule::TextOverflow));
+    /// ```
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::Syntax => "syntax",
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnusedPlace => "unused-place",
+            Rule::UnusedLabel => "unused-label",
+            Rule::TextOverflow => "text-overflow",
+            Rule::MagicNumber => "magic-number",
+            Rule::Deprecated => "deprecated",
+        }
+    }
+
+    /// Look up a rule by its name
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+
+    /// The severity of the rule's diagnostics, or `None` if it is disabled,
+    /// when it is not configured
+    pub fn default_severity(&self) -> Option<Severity> {
+        match self {
+            Rule::Syntax => Some(Severity::Error),
+            Rule::UnusedVariable | Rule::UnusedPlace => Some(Severity::Warning),
+            Rule::UnusedLabel => Some(Severity::Hint),
+            Rule::TextOverflow | Rule::Deprecated => Some(Severity::Warning),
+            Rule::MagicNumber => None,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Which rules are enabled, and how severe their diagnostics are
+#[derive(Clone, Debug, Default)]
+pub struct LintConfig {
+    overrides: HashMap<Rule, Option<Severity>>,
+}
+
+impl LintConfig {
+    /// Create a configuration with every rule at its default severity
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable a rule, reporting its diagnostics with the given severity
+    ///
+    /// ```
+    /// # use pikchr::lint::{lint, LintConfig, Rule, Severity};
+    /// let mut config = LintConfig::new();
+    /// config.set(Rule::MagicNumber, Severity::Info);
+    /// let diagnostics = lint("box wid 2", &config);
+    /// assert_eq!(diagnostics[0].rule, Rule::MagicNumber);
+    /// assert_eq!(diagnostics[0].severity, Severity::Info);
+    /// ```
+    pub fn set(&mut self, rule: Rule, severity: Severity) -> &mut Self {
+        self.overrides.insert(rule, Some(severity));
+        self
+    }
+
+    /// Disable a rule
+    ///
+    /// ```
+    /// # use pikchr::lint::{lint, LintConfig, Rule};
+    /// let source = "gap = 1\nbox";
+    /// assert_eq!(lint(source, &LintConfig::new()).len(), 1);
+    /// assert!(lint(source, LintConfig::new().allow(Rule::UnusedVariable)).is_empty());
+    /// ```
+    pub fn allow(&mut self, rule: Rule) -> &mut Self {
+        self.overrides.insert(rule, None);
+        self
+    }
+
+    /// The severity of a rule's diagnostics, or `None` if it is disabled
+    pub fn severity(&self, rule: Rule) -> Option<Severity> {
+        match self.overrides.get(&rule) {
+            Some(severity) => *severity,
+            None => rule.default_severity(),
+        }
+    }
+}
+
+/// A change to the source which resolves a diagnostic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fix {
+    /// A short description of the change
+    pub message: String,
+    /// The source to replace
+    pub span: Span,
+    /// The text to replace it with
+    pub replacement: String,
+}
+
+/// A problem found by the linter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The rule which found the problem
+    pub rule: Rule,
+    /// How serious the problem is
+    pub severity: Severity,
+    /// A description of the problem
+    pub message: String,
+    /// Where the problem is
+    pub span: Span,
+    /// A change which would resolve the problem, if there is one
+    pub fix: Option<Fix>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {} at bytes {}..{}",
+            self.severity, self.rule, self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+/// Check pikchr source for likely mistakes
+///
+/// If the source does not parse then only the syntax errors are reported,
+/// since the other rules cannot be checked reliably.  Diagnostics are
+/// ordered by rule and then by position.
+pub fn lint(source: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let (document, errors) = ast::parse_recovering(source);
+    let mut linter = Linter {
+        source,
+        config,
+        diagnostics: Vec::new(),
+    };
+    if !errors.is_empty() {
+        for error in errors {
+            linter.report(
+                Rule::Syntax,
+                error.span(),
+                error.message().to_string(),
+                None,
+            );
+        }
+        return linter.diagnostics;
+    }
+    let mut uses = Uses::default();
+    uses.statements(&document.statements);
+    linter.unused(&document, &uses);
+    walk_objects(&document.statements, &mut |statement, object| {
+        linter.text_overflow(statement, object, &uses)
+    });
+    walk_objects(&document.statements, &mut |_, object| {
+        linter.magic_numbers(object)
+    });
+    walk_statements(&document.statements, &mut |statement| {
+        linter.deprecated(statement)
+    });
+    linter
+        .diagnostics
+        .sort_by_key(|d| (Rule::ALL.iter().position(|&r| r == d.rule), d.span.start));
+    linter.diagnostics
+}
+
+/// Apply the fixes attached to some diagnostics
+///
+/// Fixes which overlap one which has already been applied are skipped.
+///
+/// ```
+/// use pikchr::lint::{apply_fixes, lint, LintConfig};
+///
+/// let source = "gap = 1\nA: box\n";
+/// let fixed = apply_fixes(source, &lint(source, &LintConfig::default()));
+/// assert_eq!(fixed, "box\n");
+/// ```
+pub fn apply_fixes(source: &str, diagnostics: &[Diagnostic]) -> String {
+    let mut fixes: Vec<&Fix> = diagnostics.iter().filter_map(|d| d.fix.as_ref()).collect();
+    fixes.sort_by_key(|fix| (fix.span.start, fix.span.end));
+    let mut out = String::with_capacity(source.len());
+    let mut pos = 0;
+    for fix in fixes {
+        if fix.span.start < pos {
+            continue;
+        }
+        out.push_str(&source[pos..fix.span.start]);
+        out.push_str(&fix.replacement);
+        pos = fix.span.end;
+    }
+    out.push_str(&source[pos..]);
+    out
+}
+
+/// Variables set by pikchr itself, or read by it while rendering
+static BUILTIN_VARIABLES: &[&str] = &[
+    "arcrad",
+    "arrowhead",
+    "arrowht",
+    "arrowwid",
+    "bottommargin",
+    "boxht",
+    "boxrad",
+    "boxwid",
+    "charht",
+    "charwid",
+    "circlerad",
+    "color",
+    "cylht",
+    "cylrad",
+    "cylwid",
+    "dashwid",
+    "dotrad",
+    "ellipseht",
+    "ellipsewid",
+    "fileht",
+    "filerad",
+    "filewid",
+    "fill",
+    "fontscale",
+    "layer",
+    "leftmargin",
+    "lineht",
+    "linerad",
+    "linewid",
+    "margin",
+    "movewid",
+    "ovalht",
+    "ovalwid",
+    "rightmargin",
+    "scale",
+    "textht",
+    "textwid",
+    "thickness",
+    "topmargin",
+];
+
+/// Variables from legacy PIC which pikchr ignores
+static LEGACY_VARIABLES: &[&str] = &["fillval", "maxpsht", "maxpswid", "moveht"];
+
+/// Relative widths of the printable ASCII characters, from pikchr's `awChar`
+static CHAR_WIDTHS: [u8; 95] = [
+    45, 55, 62, 115, 90, 132, 125, 40, 55, 55, 71, 115, 45, 48, 45, 50, 91, 91, 91, 91, 91, 91, 91,
+    91, 91, 91, 50, 50, 120, 120, 120, 78, 142, 102, 105, 110, 115, 105, 98, 105, 125, 58, 58, 107,
+    95, 145, 125, 115, 95, 115, 107, 95, 97, 118, 102, 150, 100, 93, 100, 58, 50, 58, 119, 72, 72,
+    86, 92, 80, 92, 85, 52, 92, 92, 47, 47, 88, 48, 135, 92, 86, 92, 92, 69, 75, 58, 92, 80, 121,
+    81, 80, 76, 91, 49, 91, 118,
+];
+
+/// Estimate the width of some text in inches, as pikchr does for `fit`
+fn text_width(text: &Text) -> f64 {
+    let bytes = text.text.as_bytes();
+    let mut count = 0u32;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let mut c = bytes[idx];
+        if c == b'\\' && bytes.get(idx + 1) != Some(&b'&') && idx + 1 < bytes.len() {
+            idx += 1;
+            c = bytes[idx];
+        } else if c == b'&' {
+            let end = bytes[idx + 1..]
+                .iter()
+                .take(6)
+                .position(|&b| b == b';')
+                .map(|p| idx + 1 + p);
+            idx = end.unwrap_or(idx) + 1;
+            count += 150;
+            continue;
+        }
+        count += match c {
+            0x20..=0x7e => u32::from(CHAR_WIDTHS[usize::from(c - 0x20)]),
+            _ => 100,
+        };
+        idx += 1;
+        if c >= 0xc0 {
+            while idx < bytes.len() && bytes[idx] & 0xc0 == 0x80 {
+                idx += 1;
+            }
+        }
+    }
+    let mut width = f64::from(count) * 0.08 * 0.01 * font_scale(text);
+    if text.positions.contains(&TextPosition::Bold) {
+        width *= 1.1;
+    }
+    width
+}
+
+/// The font scaling of some text, as pikchr's `pik_font_scale`
+fn font_scale(text: &Text) -> f64 {
+    let mut scale = 1.0;
+    for position in &text.positions {
+        match position {
+            TextPosition::Big => scale *= 1.25,
+            TextPosition::Small => scale *= 0.8,
+            _ => {}
+        }
+    }
+    scale
+}
+
+/// The names which a document uses
+#[derive(Default)]
+struct Uses {
+    variables: HashSet<String>,
+    labels: HashSet<String>,
+    assigned: HashSet<String>,
+}
+
+impl Uses {
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Direction(_) | StatementKind::Define { .. } => {}
+                StatementKind::Assignment {
+                    variable, value, ..
+                } => {
+                    self.assigned.insert(variable.text.clone());
+                    self.expr(value);
+                }
+                StatementKind::Object(object) => self.object(object),
+                StatementKind::Place(position) => self.position(position),
+                StatementKind::Print(items) => {
+                    for item in items {
+                        if let PrintItem::Value(expr) = item {
+                            self.expr(expr);
+                        }
+                    }
+                }
+                StatementKind::Assert(Assertion::Equal(lhs, rhs)) => {
+                    self.expr(lhs);
+                    self.expr(rhs);
+                }
+                StatementKind::Assert(Assertion::SamePosition(lhs, rhs)) => {
+                    self.position(lhs);
+                    self.position(rhs);
+                }
+            }
+        }
+    }
+
+    fn object(&mut self, object: &Object) {
+        if let ObjectKind::Sublist(statements) = &object.kind {
+            self.statements(statements);
+        }
+        for attribute in &object.attributes {
+            self.attribute(attribute);
+        }
+    }
+
+    fn attribute(&mut self, attribute: &Attribute) {
+        match &attribute.kind {
+            AttributeKind::Numeric { value, .. } => self.expr(&value.expr),
+            AttributeKind::Dash { spacing, .. } => {
+                if let Some(spacing) = spacing {
+                    self.expr(spacing);
+                }
+            }
+            AttributeKind::Color { value, .. } => self.expr(value),
+            AttributeKind::Go { distance, .. } | AttributeKind::EdgeHeading { distance, .. } => {
+                if let Some(distance) = distance {
+                    self.expr(&distance.expr);
+                }
+            }
+            AttributeKind::Heading {
+                distance, heading, ..
+            } => {
+                if let Some(distance) = distance {
+                    self.expr(&distance.expr);
+                }
+                self.expr(heading);
+            }
+            AttributeKind::Even { position, .. }
+            | AttributeKind::From(position)
+            | AttributeKind::To(position)
+            | AttributeKind::At(position)
+            | AttributeKind::With { position, .. } => self.position(position),
+            AttributeKind::Same(Some(object)) | AttributeKind::Behind(object) => {
+                self.object_ref(object)
+            }
+            AttributeKind::Same(None)
+            | AttributeKind::Then
+            | AttributeKind::Close
+            | AttributeKind::Chop
+            | AttributeKind::Text(_)
+            | AttributeKind::Fit
+            | AttributeKind::Flag(_) => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::Color(_) => {}
+            ExprKind::Variable(name) => {
+                self.variables.insert(name.text.clone());
+            }
+            ExprKind::Unary { operand, .. } => self.expr(operand),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Call { args, .. } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Distance(from, to) => {
+                self.position(from);
+                self.position(to);
+            }
+            ExprKind::Coordinate { place, .. } => self.place(place),
+            ExprKind::Property { object, .. } => self.object_ref(object),
+        }
+    }
+
+    fn position(&mut self, position: &Position) {
+        match &position.kind {
+            PositionKind::Coordinates(x, y) => {
+                self.expr(x);
+                self.expr(y);
+            }
+            PositionKind::Place(place) => self.place(place),
+            PositionKind::Offset { place, x, y, .. } => {
+                self.place(place);
+                self.expr(x);
+                self.expr(y);
+            }
+            PositionKind::Combine(x, y) => {
+                self.position(x);
+                self.position(y);
+            }
+            PositionKind::Between { fraction, from, to } => {
+                self.expr(fraction);
+                self.position(from);
+                self.position(to);
+            }
+            PositionKind::Relative {
+                distance, position, ..
+            }
+            | PositionKind::EdgeHeading {
+                distance, position, ..
+            } => {
+                self.expr(distance);
+                self.position(position);
+            }
+            PositionKind::Heading {
+                distance,
+                heading,
+                position,
+            } => {
+                self.expr(distance);
+                self.expr(heading);
+                self.position(position);
+            }
+        }
+    }
+
+    fn place(&mut self, place: &Place) {
+        match &place.kind {
+            PlaceKind::Object(object)
+            | PlaceKind::Edge { object, .. }
+            | PlaceKind::Vertex { object, .. } => self.object_ref(object),
+        }
+    }
+
+    fn object_ref(&mut self, object: &ObjectRef) {
+        match &object.kind {
+            ObjectRefKind::This => {}
+            ObjectRefKind::Named(names) => {
+                for name in names {
+                    self.labels.insert(name.text.clone());
+                }
+            }
+            ObjectRefKind::Nth { within, .. } => {
+                if let Some(within) = within {
+                    self.object_ref(within);
+                }
+            }
+        }
+    }
+}
+
+/// Call `f` for every statement, including those in sublists
+fn walk_statements<'a>(statements: &'a [Statement], f: &mut dyn FnMut(&'a Statement)) {
+    for statement in statements {
+        f(statement);
+        if let StatementKind::Object(Object {
+            kind: ObjectKind::Sublist(inner),
+            ..
+        }) = &statement.kind
+        {
+            walk_statements(inner, f);
+        }
+    }
+}
+
+/// Call `f` for every object, including those in sublists
+fn walk_objects<'a>(statements: &'a [Statement], f: &mut dyn FnMut(&'a Statement, &'a Object)) {
+    walk_statements(statements, &mut |statement| {
+        if let StatementKind::Object(object) = &statement.kind {
+            f(statement, object)
+        }
+    });
+}
+
+/// The value of an expression if it is a literal number
+fn literal(value: &RelExpr) -> Option<f64> {
+    match value.expr.kind {
+        ExprKind::Number(n) if !value.percent => Some(n),
+        _ => None,
+    }
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    config: &'a LintConfig,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: Rule, span: Span, message: String, fix: Option<Fix>) {
+        if let Some(severity) = self.config.severity(rule) {
+            self.diagnostics.push(Diagnostic {
+                rule,
+                severity,
+                message,
+                span,
+                fix,
+            });
+        }
+    }
+
+    /// A fix which removes a statement, along with its line if the
+    /// statement is alone on it
+    fn remove_statement(&self, statement: &Statement, message: &str) -> Option<Fix> {
+        if statement.macro_call.is_some() {
+            return None;
+        }
+        let Span { start, end } = statement.span;
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[end..]
+            .find('\n')
+            .map_or(self.source.len(), |i| end + i + 1);
+        let alone = self.source[line_start..start].trim().is_empty()
+            && self.source[end..line_end].trim().is_empty();
+        Some(Fix {
+            message: message.to_string(),
+            span: if alone {
+                Span::new(line_start, line_end)
+            } else {
+                statement.span
+            },
+            replacement: String::new(),
+        })
+    }
+
+    fn unused(&mut self, document: &Document, uses: &Uses) {
+        walk_statements(&document.statements, &mut |statement| {
+            if let StatementKind::Assignment { variable, .. } = &statement.kind {
+                let name = variable.text.as_str();
+                if !uses.variables.contains(name)
+                    && !BUILTIN_VARIABLES.contains(&name)
+                    && !LEGACY_VARIABLES.contains(&name)
+                {
+                    let fix = self.remove_statement(statement, "remove the assignment");
+                    let message = format!("variable `{}` is never used", name);
+                    self.report(Rule::UnusedVariable, variable.span, message, fix);
+                }
+            }
+            let label = match &statement.label {
+                Some(label) if !uses.labels.contains(&label.text) => label,
+                _ => return,
+            };
+            match &statement.kind {
+                StatementKind::Place(_) => {
+                    let fix = self.remove_statement(statement, "remove the place");
+                    let message = format!("place `{}` is never used", label.text);
+                    self.report(Rule::UnusedPlace, label.span, message, fix);
+                }
+                StatementKind::Object(object) => {
+                    let fix = match statement.macro_call {
+                        Some(_) => None,
+                        None => Some(Fix {
+                            message: "remove the label".to_string(),
+                            span: Span::new(label.span.start, object.span.start),
+                            replacement: String::new(),
+                        }),
+                    };
+                    let message = format!("label `{}` is never used", label.text);
+                    self.report(Rule::UnusedLabel, label.span, message, fix);
+                }
+                _ => {}
+            }
+        });
+    }
+
+    fn text_overflow(&mut self, statement: &Statement, object: &Object, uses: &Uses) {
+        let (default, variable) = match object.class_name() {
+            "box" => (0.75, "boxwid"),
+            "circle" => (0.5, "circlerad"),
+            "cylinder" => (0.75, "cylwid"),
+            "ellipse" => (0.75, "ellipsewid"),
+            "file" => (0.5, "filewid"),
+            "oval" => (1.0, "ovalwid"),
+            _ => return,
+        };
+        let (default_height, height_variable) = match object.class_name() {
+            "box" => (0.5, "boxht"),
+            "circle" => (0.5, "circlerad"),
+            "cylinder" => (0.5, "cylht"),
+            "ellipse" => (0.5, "ellipseht"),
+            "file" => (0.75, "fileht"),
+            _ => (0.5, "ovalht"),
+        };
+        let assigned = |variable: &str| uses.assigned.contains(variable);
+        let mut width = if assigned(variable) {
+            None
+        } else {
+            Some(default)
+        };
+        let mut height = if assigned(height_variable) {
+            None
+        } else {
+            Some(default_height)
+        };
+        for attribute in &object.attributes {
+            match &attribute.kind {
+                AttributeKind::Fit | AttributeKind::Same(_) => return,
+                AttributeKind::Numeric { property, value } => match property {
+                    NumericProperty::Width => width = literal(value),
+                    NumericProperty::Height => height = literal(value),
+                    NumericProperty::Radius => {
+                        width = literal(value).map(|r| 2.0 * r);
+                        if object.class_name() == "circle" {
+                            height = width;
+                        }
+                    }
+                    NumericProperty::Diameter => {
+                        width = literal(value);
+                        height = width;
+                    }
+                    NumericProperty::Thickness => {}
+                },
+                _ => {}
+            }
+        }
+        let texts: Vec<&Text> = object.texts().collect();
+        if texts.is_empty() {
+            return;
+        }
+        let widest = texts.iter().map(|t| text_width(t)).fold(0.0, f64::max);
+        let tallest: f64 = texts.iter().map(|t| 0.14 * font_scale(t)).sum();
+        let too_wide = matches!(width, Some(width) if widest > width);
+        let too_tall = matches!(height, Some(height) if tallest > height);
+        let message = match (too_wide, too_tall) {
+            (false, false) => return,
+            (true, false) => "text is probably wider than its",
+            (false, true) => "text is probably taller than its",
+            (true, true) => "text is probably larger than its",
+        };
+        let message = format!("{} {}", message, object.class_name());
+        let fix = match statement.macro_call {
+            Some(_) => None,
+            None => Some(Fix {
+                message: "size the shape to fit the text".to_string(),
+                span: Span::new(object.span.end, object.span.end),
+                replacement: " fit".to_string(),
+            }),
+        };
+        self.report(Rule::TextOverflow, object.span, message, fix);
+    }
+
+    fn magic_numbers(&mut self, object: &Object) {
+        for attribute in &object.attributes {
+            if let AttributeKind::Numeric { property, value } = &attribute.kind {
+                if literal(value).is_some() {
+                    let message = format!(
+                        "{} is a literal number; consider using a variable",
+                        match property {
+                            NumericProperty::Height => "height",
+                            NumericProperty::Width => "width",
+                            NumericProperty::Radius => "radius",
+                            NumericProperty::Diameter => "diameter",
+                            NumericProperty::Thickness => "thickness",
+                        }
+                    );
+                    self.report(Rule::MagicNumber, value.span, message, None);
+                }
+            }
+        }
+    }
+
+    fn deprecated(&mut self, statement: &Statement) {
+        if let StatementKind::Assignment { variable, .. } = &statement.kind {
+            if LEGACY_VARIABLES.contains(&variable.text.as_str()) {
+                let fix = self.remove_statement(statement, "remove the assignment");
+                let message = format!(
+                    "`{}` is a legacy PIC variable which has no effect in pikchr",
+                    variable.text
+                );
+                self.report(Rule::Deprecated, variable.span, message, fix);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(source: &str) -> Vec<(Rule, &str)> {
+        lint(source, &LintConfig::default())
+            .into_iter()
+            .map(|d| (d.rule, &source[d.span.start..d.span.end]))
+            .collect()
+    }
+
+    #[test]
+    fn reports_syntax_errors_alone() {
+        assert_eq!(rules("gap = 1\nbox wid +"), [(Rule::Syntax, "")]);
+    }
+
+    #[test]
+    fn finds_unused_definitions() {
+        let source = "gap = 1\nused = 2\nboxwid = 1\nP: (1, 2)\nQ: (0, 0)\nA: box at Q\nB: [\n  C: circle\n]\narrow from B.C.e right used\n";
+        assert_eq!(
+            rules(source),
+            [
+                (Rule::UnusedVariable, "gap"),
+                (Rule::UnusedPlace, "P"),
+                (Rule::UnusedLabel, "A"),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_text_overflow() {
+        assert_eq!(rules("box \"short\""), []);
+        assert_eq!(
+            rules("box \"something rather long\""),
+            [(Rule::TextOverflow, "box \"something rather long\"")]
+        );
+        assert_eq!(rules("box \"something rather long\" fit"), []);
+        assert_eq!(rules("box \"something rather long\" wid 3"), []);
+        assert_eq!(rules("boxwid = 3\nbox \"something rather long\""), []);
+        assert_eq!(
+            rules("circle \"1\" \"2\" \"3\" \"4\""),
+            [(Rule::TextOverflow, "circle \"1\" \"2\" \"3\" \"4\"")]
+        );
+        assert_eq!(rules("line \"something rather long\""), []);
+    }
+
+    #[test]
+    fn finds_magic_numbers_only_when_enabled() {
+        let mut config = LintConfig::new();
+        assert!(lint("box wid 2 ht 50%", &config).is_empty());
+        config.set(Rule::MagicNumber, Severity::Hint);
+        let found = lint("box wid 2 ht 50%", &config);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].span, Span::new(8, 9));
+    }
+
+    #[test]
+    fn finds_legacy_variables() {
+        assert_eq!(rules("moveht = 1\nmove"), [(Rule::Deprecated, "moveht")]);
+    }
+
+    #[test]
+    fn fixes_problems() {
+        let source = "gap = 1; box\nA: box \"something rather long\"\nP: (0, 0)\nfillval = 0.5\n";
+        let fixed = apply_fixes(source, &lint(source, &LintConfig::default()));
+        assert_eq!(fixed, "; box\nbox \"something rather long\" fit\n");
+        assert!(lint(&fixed, &LintConfig::default()).is_empty());
+        assert!(crate::Pikchr::render(&fixed, None, crate::PikchrFlags::default()).is_ok());
+    }
+}
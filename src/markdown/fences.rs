@@ -0,0 +1,141 @@
+//! Rendering the diagrams in a Markdown document
+//!
+//! [`render_fences`] replaces each ```` ```pikchr ```` fence in a Markdown
+//! document with the SVG of its diagram, so that a static site generator
+//! need only hand the result to its Markdown renderer.
+
+use super::replace_fences;
+use crate::svg::escape;
+use crate::{Pikchr, PikchrError, PikchrFlags};
+
+/// Options for rendering the diagrams in a Markdown document
+///
+/// You can construct the default options using the [`std::default::Default`]
+/// trait, which render each diagram with no class and the default flags,
+/// and show errors in place of the diagrams which have them.
+#[derive(Clone, Default)]
+pub struct MarkdownOptions {
+    class: Option<String>,
+    flags: PikchrFlags,
+    fail_on_error: bool,
+}
+
+impl MarkdownOptions {
+    /// Set the class of each diagram's SVG
+    pub fn class(&mut self, class: &str) -> &mut MarkdownOptions {
+        self.class = Some(class.to_string());
+        self
+    }
+
+    /// Set the flags to render each diagram with
+    pub fn flags(&mut self, flags: PikchrFlags) -> &mut MarkdownOptions {
+        self.flags = flags;
+        self
+    }
+
+    /// Set whether a diagram which cannot be rendered fails the whole
+    /// document, rather than its error being shown in its place
+    pub fn fail_on_error(&mut self, fail_on_error: bool) -> &mut MarkdownOptions {
+        self.fail_on_error = fail_on_error;
+        self
+    }
+}
+
+/// Replace the ```` ```pikchr ```` fences in Markdown with the SVG of the
+/// diagrams in them
+///
+/// Each diagram is rendered with the options, which a fence can change
+/// for its own diagram with words after `pikchr`:
+///
+/// - `class=NAME` for the class of its SVG
+/// - `dark-mode` or `light-mode` for its colors
+/// - `html-errors` or `plain-errors` for how its errors are shown
+///
+/// Other words are ignored.  The SVG is put in a `<div class="pikchr">`,
+/// which CommonMark passes through as HTML even straight after a
+/// paragraph.  An error is put in its place, as the HTML which pikchr
+/// reports or in a `<pre class="pikchr-error">`, unless the options say
+/// to fail.
+///
+/// ```
+/// use pikchr::markdown::fences::{render_fences, MarkdownOptions};
+///
+/// let page = "# Flow\n\n```pikchr class=flow dark-mode\nbox \"start\"\n```\n";
+/// let html = render_fences(page, &MarkdownOptions::default()).unwrap();
+/// assert!(html.starts_with("# Flow\n\n<div class=\"pikchr\"><svg "));
+/// assert!(html.contains(" class=\"flow\""));
+/// assert!(html.ends_with("</svg>\n</div>\n"));
+/// ```
+///
+/// # Errors
+///
+/// It is an error if a diagram cannot be rendered and the options say to
+/// fail, as for [`Pikchr::render`].
+pub fn render_fences(input: &str, options: &MarkdownOptions) -> Result<String, PikchrError> {
+    replace_fences(input, |words, source| {
+        let mut class = options.class.clone();
+        let mut flags = options.flags;
+        for word in words.split_whitespace() {
+            match word {
+                "dark-mode" => {
+                    flags.use_dark_mode();
+                }
+                "light-mode" => {
+                    flags.clear_dark_mode();
+                }
+                "html-errors" => {
+                    flags.generate_html_errors();
+                }
+                "plain-errors" => {
+                    flags.generate_plain_errors();
+                }
+                _ => {
+                    if let Some(name) = word.strip_prefix("class=") {
+                        class = Some(name.to_string());
+                    }
+                }
+            }
+        }
+        match Pikchr::render(source, class.as_deref(), flags) {
+            Ok(pic) => Ok(format!("<div class=\"pikchr\">{}</div>\n", pic.rendered())),
+            Err(error) if options.fail_on_error => Err(error),
+            Err(error) if flags.plain_errors() => Ok(format!(
+                "<pre class=\"pikchr-error\">{}</pre>\n",
+                escape(error.raw().trim_end())
+            )),
+            Err(error) => Ok(format!("{}\n", error.raw().trim_end())),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(pikchr_c)]
+    fn shows_errors_in_place() {
+        let page = "a\n```pikchr\nbox wid\n```\n```pikchr html-errors\nbox \"<\" wid\n```\nb\n";
+        let html = render_fences(page, &MarkdownOptions::default()).unwrap();
+        assert!(html.starts_with("a\n<pre class=\"pikchr-error\">"));
+        assert!(html.contains("ERROR: syntax error</pre>\n"));
+        assert!(html.ends_with("ERROR: syntax error\n</pre></div>\nb\n"));
+        let mut options = MarkdownOptions::default();
+        options.fail_on_error(true);
+        let error = render_fences(page, &options).err().unwrap();
+        assert!(matches!(error, PikchrError::Syntax { line: 1, .. }));
+    }
+
+    #[test]
+    fn renders_with_block_settings() {
+        let mut options = MarkdownOptions::default();
+        options.class("doc");
+        let page = "```pikchr\nbox\n```\n\n~~~ pikchr class=other dark-mode\nbox\n~~~\n";
+        let html = render_fences(page, &options).unwrap();
+        let mut svgs = html.split("<svg ").skip(1);
+        let first = svgs.next().unwrap();
+        let second = svgs.next().unwrap();
+        assert!(first.contains("class=\"doc\"") && first.contains("rgb(0,0,0)"));
+        assert!(second.contains("class=\"other\"") && !second.contains("rgb(0,0,0)"));
+    }
+}
@@ -0,0 +1,184 @@
+//! Diagrams in Markdown
+//!
+//! Tools which write Markdown rather than HTML can include a diagram with
+//! [`Pikchr::to_markdown_image`], either inline as a `data:` URI or as a
+//! link to a file which the SVG has been saved to.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box \"[1]\"", None, PikchrFlags::default()).unwrap();
+//! let image = pic.to_markdown_image("Step [1]", Some("diagrams/step one.svg")).unwrap();
+//! assert_eq!(image, "![Step \\[1\\]](<diagrams/step one.svg>)");
+//! let image = pic.to_markdown_image("Step [1]", None).unwrap();
+//! assert!(image.starts_with("![Step \\[1\\]](data:image/svg+xml;base64,PHN2ZyB4bWxucz0i"));
+//! ```
+//!
+//! Going the other way, with the `markdown` feature the `fences` module
+//! renders the diagrams which a Markdown document has in
+//! ```` ```pikchr ```` fences.
+//!
+//! [`Pikchr::to_markdown_image`]: crate::Pikchr::to_markdown_image
+
+use crate::font::base64;
+use crate::svg::Dom;
+
+#[cfg(feature = "markdown")]
+pub mod fences;
+
+/// Escape alt text for Markdown, putting it all on one line
+fn escape_alt(alt: &str) -> String {
+    let mut out = String::with_capacity(alt.len());
+    for c in alt.chars() {
+        match c {
+            '\\' | '[' | ']' | '*' | '_' | '`' | '<' | '>' | '!' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\r' | '\n' => out.push(' '),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Write the destination of a Markdown link, in angle brackets if it has
+/// spaces or other characters which would end it
+fn destination(path: &str) -> String {
+    if !path
+        .chars()
+        .any(|c| c.is_whitespace() || matches!(c, '(' | ')' | '<' | '>' | '\\'))
+    {
+        return path.to_string();
+    }
+    let mut out = String::from("<");
+    for c in path.chars() {
+        match c {
+            '<' | '>' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\r' | '\n' => out.push_str("%0A"),
+            _ => out.push(c),
+        }
+    }
+    out.push('>');
+    out
+}
+
+/// A Markdown image of a rendered diagram, linking to a path if there is
+/// one
+pub(crate) fn image(dom: &Dom, alt: &str, path: Option<&str>) -> String {
+    let path = match path {
+        Some(path) => destination(path),
+        None => {
+            // Any output of `print` is left out, as it is not SVG
+            let svg = dom.svg().map(|svg| svg.to_string()).unwrap_or_default();
+            format!("data:image/svg+xml;base64,{}", base64(svg.as_bytes()))
+        }
+    };
+    format!("![{}]({})", escape_alt(alt), path)
+}
+
+/// The opening of a fenced code block: its indent, fence character and
+/// length, and info string
+fn opening_fence(line: &str) -> Option<(usize, char, usize, &str)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let fence = rest.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = rest.len() - rest.trim_start_matches(fence).len();
+    let info = rest[length..].trim();
+    if length < 3 || (fence == '`' && info.contains('`')) {
+        return None;
+    }
+    Some((indent, fence, length, info))
+}
+
+/// Whether a line closes a fenced code block
+fn closes(line: &str, fence: char, length: usize) -> bool {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return false;
+    }
+    let run = trimmed.len() - trimmed.trim_start_matches(fence).len();
+    run >= length && trimmed[run..].trim().is_empty()
+}
+
+/// Replace each ```` ```pikchr ```` fence in Markdown with what a function
+/// makes of the rest of its info string and its source
+///
+/// Other fenced code blocks, and any pikchr fences inside them, are left
+/// as they are.  A fence which is never closed runs to the end of the
+/// text, as CommonMark says.
+pub(crate) fn replace_fences<E>(
+    text: &str,
+    mut replace: impl FnMut(&str, &str) -> Result<String, E>,
+) -> Result<String, E> {
+    let mut out = String::with_capacity(text.len());
+    let mut lines = text.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        let (indent, fence, length, info) = match opening_fence(line) {
+            Some(opening) => opening,
+            None => {
+                out.push_str(line);
+                continue;
+            }
+        };
+        let (language, modifiers) =
+            info.split_at(info.find(char::is_whitespace).unwrap_or(info.len()));
+        let is_pikchr = language.eq_ignore_ascii_case("pikchr");
+        if !is_pikchr {
+            out.push_str(line);
+        }
+        let mut source = String::new();
+        for line in lines.by_ref() {
+            if closes(line, fence, length) {
+                if !is_pikchr {
+                    out.push_str(line);
+                }
+                break;
+            }
+            if is_pikchr {
+                // Remove as much of the fence's indent as the line has
+                let strip = line.len() - line.trim_start_matches(' ').len();
+                source.push_str(&line[strip.min(indent)..]);
+            } else {
+                out.push_str(line);
+            }
+        }
+        if is_pikchr {
+            out.push_str(&replace(modifiers, &source)?);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(escape_alt("a *b* [c]\nd\\"), "a \\*b\\* \\[c\\] d\\\\");
+        assert_eq!(destination("images/a.svg"), "images/a.svg");
+        assert_eq!(destination("my (1).svg"), "<my (1).svg>");
+        assert_eq!(destination("a<b>.svg"), "<a\\<b\\>.svg>");
+    }
+
+    #[test]
+    fn inlines_only_the_svg() {
+        let pic = crate::Pikchr::render("print \"hi\"; box", None, crate::PikchrFlags::default())
+            .unwrap();
+        let image = pic.to_markdown_image("", None).unwrap();
+        let encoded = image
+            .strip_prefix("![](data:image/svg+xml;base64,")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .unwrap();
+        let svg = pic.dom().unwrap().svg().unwrap().to_string();
+        assert_eq!(encoded, base64(svg.as_bytes()));
+        assert!(!pic.dom().unwrap().to_string().starts_with("<svg"));
+    }
+}
@@ -0,0 +1,428 @@
+//! Measuring text
+//!
+//! pikchr estimates the width of text from a table of ASCII character
+//! widths and counts every other character as one average character, so
+//! `fit` makes boxes too small for CJK text and emoji, and too big for
+//! text with combining marks.  [`text_width`] also knows which characters
+//! are wide or take no space.
+//!
+//! With the `unicode-metrics` feature, both backends measure text with
+//! [`text_width`] rather than their built-in estimate.
+//!
+//! An application which knows the metrics of the font its diagrams are
+//! shown in can measure text itself with
+//! [`Pikchr::render_measured`](crate::Pikchr::render_measured).
+//!
+//! ```
+//! use pikchr::measure::text_width;
+//!
+//! assert_eq!(text_width("MW"), 2.95);
+//! assert_eq!(text_width("日本"), 4.0);
+//! assert_eq!(text_width("e\u{301}"), text_width("e"));
+//! ```
+
+/// Estimated widths of the printable ASCII characters, where 100 is the
+/// width of an average character
+pub(crate) static CHAR_WIDTHS: [u8; 95] = [
+    45, 55, 62, 115, 90, 132, 125, 40, 55, 55, 71, 115, 45, 48, 45, 50, 91, 91, 91, 91, 91, 91, 91,
+    91, 91, 91, 50, 50, 120, 120, 120, 78, 142, 102, 105, 110, 115, 105, 98, 105, 125, 58, 58, 107,
+    95, 145, 125, 115, 95, 115, 107, 95, 97, 118, 102, 150, 100, 93, 100, 58, 50, 58, 119, 72, 72,
+    86, 92, 80, 92, 85, 52, 92, 92, 47, 47, 88, 48, 135, 92, 86, 92, 92, 69, 75, 58, 92, 80, 121,
+    81, 80, 76, 91, 49, 91, 118,
+];
+
+/// The width of the text of a string literal, in average characters
+///
+/// The text is as it appears between the quotes, so a backslash escapes
+/// the next character and an HTML entity such as `&rarr;` counts as one
+/// character, which like pikchr is taken to be one and a half average
+/// characters wide.
+pub fn text_width(text: &str) -> f64 {
+    let mut hundredths = 0;
+    let mut joined = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, mut c)) = chars.next() {
+        if c == '\\' && chars.peek().is_some_and(|&(_, next)| next != '&') {
+            c = chars.next().unwrap().1;
+        } else if c == '&' {
+            let entity = text[i + 1..]
+                .char_indices()
+                .take(6)
+                .find(|&(_, c)| c == ';');
+            if let Some((end, _)) = entity {
+                while chars.peek().is_some_and(|&(j, _)| j <= i + 1 + end) {
+                    chars.next();
+                }
+            }
+            hundredths += 150;
+            joined = false;
+            continue;
+        }
+        // The character after a zero width joiner is part of the same
+        // glyph, as in many emoji
+        if std::mem::replace(&mut joined, c == '\u{200D}') {
+            continue;
+        }
+        hundredths += char_width(c);
+    }
+    f64::from(hundredths) / 100.0
+}
+
+/// The width of a character, in hundredths of an average character
+fn char_width(c: char) -> u32 {
+    match c {
+        ' '..='~' => u32::from(CHAR_WIDTHS[c as usize - 0x20]),
+        _ if is_zero_width(c) => 0,
+        _ if is_wide(c) => 200,
+        _ => 100,
+    }
+}
+
+/// Whether a character combines with the one before it or is invisible
+fn is_zero_width(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{0483}'..='\u{0489}'
+        | '\u{0591}'..='\u{05BD}'
+        | '\u{05BF}' | '\u{05C1}'..='\u{05C2}' | '\u{05C4}'..='\u{05C5}' | '\u{05C7}'
+        | '\u{0610}'..='\u{061A}'
+        | '\u{064B}'..='\u{065F}'
+        | '\u{0670}'
+        | '\u{06D6}'..='\u{06DC}' | '\u{06DF}'..='\u{06E4}' | '\u{06E7}'..='\u{06E8}'
+        | '\u{06EA}'..='\u{06ED}'
+        | '\u{0E31}' | '\u{0E34}'..='\u{0E3A}' | '\u{0E47}'..='\u{0E4E}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{302A}'..='\u{302F}'
+        | '\u{3099}'..='\u{309A}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{FE20}'..='\u{FE2F}'
+        | '\u{FEFF}'
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0000}'..='\u{E007F}'
+        | '\u{E0100}'..='\u{E01EF}')
+}
+
+/// Whether a character is as wide as two average characters, as CJK
+/// characters and emoji are
+fn is_wide(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{115F}'
+        | '\u{231A}'..='\u{231B}'
+        | '\u{23E9}'..='\u{23EC}'
+        | '\u{2614}'..='\u{2615}'
+        | '\u{26A1}' | '\u{26BD}'..='\u{26BE}' | '\u{26C4}'..='\u{26C5}'
+        | '\u{2705}' | '\u{270A}'..='\u{270B}' | '\u{274C}' | '\u{2753}'..='\u{2755}'
+        | '\u{2B1B}'..='\u{2B1C}' | '\u{2B50}'
+        | '\u{2E80}'..='\u{303E}'
+        | '\u{3041}'..='\u{33FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{A000}'..='\u{A4CF}'
+        | '\u{A960}'..='\u{A97F}'
+        | '\u{AC00}'..='\u{D7A3}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FE10}'..='\u{FE19}'
+        | '\u{FE30}'..='\u{FE6F}'
+        | '\u{FF00}'..='\u{FF60}'
+        | '\u{FFE0}'..='\u{FFE6}'
+        | '\u{1B000}'..='\u{1B2FF}'
+        | '\u{1F004}' | '\u{1F0CF}' | '\u{1F18E}' | '\u{1F191}'..='\u{1F19A}'
+        | '\u{1F200}'..='\u{1F251}'
+        | '\u{1F300}'..='\u{1F64F}'
+        | '\u{1F680}'..='\u{1F6FF}'
+        | '\u{1F7E0}'..='\u{1F7EB}'
+        | '\u{1F900}'..='\u{1F9FF}'
+        | '\u{1FA70}'..='\u{1FAFF}'
+        | '\u{20000}'..='\u{2FFFD}'
+        | '\u{30000}'..='\u{3FFFD}')
+}
+
+/// The text which a string literal displays
+///
+/// Backslash escapes are removed and the HTML entities which are common in
+/// labels are decoded, so that the text can be measured with a font.  Other
+/// entities are left as they are.
+pub(crate) fn displayed(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\\' => {
+                if let Some(next) = rest.chars().next() {
+                    out.push(next);
+                    rest = &rest[next.len_utf8()..];
+                }
+            }
+            '&' => {
+                let decoded = rest.find(';').filter(|&end| end <= 8).and_then(|end| {
+                    let c = entity(&rest[..end])?;
+                    rest = &rest[end + 1..];
+                    Some(c)
+                });
+                out.push(decoded.unwrap_or('&'));
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The character for the name of an HTML entity, if it is a common one
+fn entity(name: &str) -> Option<char> {
+    let code = if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(decimal) = name.strip_prefix('#') {
+        decimal.parse().ok()?
+    } else {
+        return Some(match name {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            "nbsp" => '\u{a0}',
+            "larr" => '\u{2190}',
+            "uarr" => '\u{2191}',
+            "rarr" => '\u{2192}',
+            "darr" => '\u{2193}',
+            "harr" => '\u{2194}',
+            "hellip" => '\u{2026}',
+            "ndash" => '\u{2013}',
+            "mdash" => '\u{2014}',
+            "times" => '\u{d7}',
+            "deg" => '\u{b0}',
+            _ => return None,
+        });
+    };
+    char::from_u32(code)
+}
+
+/// A measurement callback for `pikchr_measured()` which uses
+/// [`text_width`]
+///
+/// # Safety
+///
+/// `text` must point to `len` bytes.
+#[cfg(all(pikchr_c, feature = "unicode-metrics"))]
+pub(crate) unsafe extern "C" fn measure_unicode(
+    _arg: *mut core::ffi::c_void,
+    text: *const core::ffi::c_char,
+    len: core::ffi::c_int,
+    char_width: f64,
+    _font_scale: f64,
+) -> f64 {
+    let bytes = std::slice::from_raw_parts(text as *const u8, len.max(0) as usize);
+    text_width(&String::from_utf8_lossy(bytes)) * char_width
+}
+
+/// `pikchr()`, but measuring text with [`text_width`]
+///
+/// # Safety
+///
+/// As for `pikchr()`.
+#[cfg(all(pikchr_c, feature = "unicode-metrics"))]
+pub(crate) unsafe extern "C" fn pikchr_unicode(
+    source: *const core::ffi::c_char,
+    class: *const core::ffi::c_char,
+    flags: core::ffi::c_uint,
+    width: *mut core::ffi::c_int,
+    height: *mut core::ffi::c_int,
+) -> *mut core::ffi::c_char {
+    crate::raw::pikchr_measured(
+        source,
+        class,
+        flags,
+        width,
+        height,
+        Some(measure_unicode),
+        std::ptr::null_mut(),
+    )
+}
+
+/// A callback which measures the displayed text of a label at a font size
+/// relative to normal text, giving its width in pixels
+pub(crate) type MeasureFn<'a> = &'a dyn Fn(&str, f64) -> f64;
+
+/// The number of SVG pixels in an inch, in which pikchr measures
+#[cfg(pikchr_c)]
+const PIXELS_PER_INCH: f64 = 144.0;
+
+/// A measurement callback, and the panic from it if it has panicked
+#[cfg(pikchr_c)]
+struct Measurer<'a> {
+    measure: MeasureFn<'a>,
+    panic: Option<Box<dyn std::any::Any + Send>>,
+}
+
+/// A measurement callback for `pikchr_measured()` which calls the
+/// [`Measurer`] it is given
+///
+/// A panic must not unwind into C, so it is caught and kept in the
+/// measurer, and the rest of the text is measured with the built-in
+/// estimate.
+///
+/// # Safety
+///
+/// `arg` must point to a [`Measurer`] and `text` must point to `len`
+/// bytes.
+#[cfg(pikchr_c)]
+unsafe extern "C" fn measure_with(
+    arg: *mut core::ffi::c_void,
+    text: *const core::ffi::c_char,
+    len: core::ffi::c_int,
+    _char_width: f64,
+    font_scale: f64,
+) -> f64 {
+    let measurer = &mut *(arg as *mut Measurer);
+    if measurer.panic.is_some() {
+        return -1.0;
+    }
+    let bytes = std::slice::from_raw_parts(text as *const u8, len.max(0) as usize);
+    let text = displayed(&String::from_utf8_lossy(bytes));
+    let measure = measurer.measure;
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| measure(&text, font_scale))) {
+        Ok(width) if width.is_finite() && width >= 0.0 => width / PIXELS_PER_INCH,
+        Ok(_) => -1.0,
+        Err(panic) => {
+            measurer.panic = Some(panic);
+            -1.0
+        }
+    }
+}
+
+/// Render with the C library, measuring text with a callback
+///
+/// If the callback panics, the panic resumes once pikchr has finished.
+#[cfg(pikchr_c)]
+pub(crate) fn render_measured(
+    source: &str,
+    class: Option<&str>,
+    flags: crate::PikchrFlags,
+    measure: MeasureFn,
+) -> Result<crate::Pikchr, crate::PikchrError> {
+    let mut measurer = Measurer {
+        measure,
+        panic: None,
+    };
+    let arg = &mut measurer as *mut Measurer as *mut core::ffi::c_void;
+    let result = unsafe {
+        crate::render_with(
+            |source, class, flags, width, height| {
+                crate::raw::pikchr_measured(
+                    source,
+                    class,
+                    flags,
+                    width,
+                    height,
+                    Some(measure_with),
+                    arg,
+                )
+            },
+            crate::raw::free,
+            source,
+            class,
+            flags,
+        )
+    };
+    if let Some(panic) = measurer.panic {
+        std::panic::resume_unwind(panic);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn measures_like_pikchr() {
+        assert_eq!(text_width("Hello"), 3.92);
+        assert_eq!(text_width("a\\\"b"), text_width("a\"b"));
+        assert_eq!(text_width("x&lt;y"), 3.11);
+        assert_eq!(text_width("&"), 1.5);
+        assert_eq!(text_width("\u{e9}"), 1.0);
+    }
+
+    #[test]
+    fn measures_unicode() {
+        assert_eq!(text_width("漢字かな"), 8.0);
+        assert_eq!(text_width("한글"), 4.0);
+        assert_eq!(text_width("a\u{308}o\u{308}"), text_width("ao"));
+        assert_eq!(text_width("\u{1F600}"), 2.0);
+        // A family emoji is one glyph
+        assert_eq!(
+            text_width("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}"),
+            2.0
+        );
+        assert_eq!(text_width("\u{1F44D}\u{1F3FD}"), 2.0);
+    }
+
+    #[test]
+    fn decodes_displayed_text() {
+        assert_eq!(displayed("a\\\"b \\\\"), "a\"b \\");
+        assert_eq!(displayed("x&lt;y&#65;&#x42;&rarr;"), "x<yAB\u{2192}");
+        assert_eq!(displayed("&unknown; & &amp"), "&unknown; & &amp");
+    }
+
+    #[test]
+    fn measures_with_a_callback() {
+        let seen = std::cell::RefCell::new(Vec::new());
+        let render = |measure: &dyn Fn(&str, f64) -> f64| {
+            Pikchr::render_measured(
+                "box \"x&lt;y\" big fit",
+                None,
+                PikchrFlags::default(),
+                measure,
+            )
+            .unwrap()
+        };
+        let wide = render(&|text, size| {
+            seen.borrow_mut().push((text.to_string(), size));
+            288.0
+        });
+        assert!(seen.borrow().contains(&("x<y".to_string(), 1.25)));
+        assert_eq!(wide.width(), 303);
+        let estimated = render(&|_, _| -1.0);
+        let plain = Pikchr::render("box \"x&lt;y\" big fit", None, PikchrFlags::default()).unwrap();
+        assert_eq!(estimated.width(), plain.width());
+    }
+
+    #[test]
+    fn passes_on_panics() {
+        let result = std::panic::catch_unwind(|| {
+            Pikchr::render_measured(
+                "box \"a\"; box \"b\" fit",
+                None,
+                PikchrFlags::default(),
+                &|_, _| panic!("no font"),
+            )
+        });
+        match result {
+            Ok(_) => panic!("the panic was lost"),
+            Err(panic) => assert_eq!(panic.downcast_ref::<&str>(), Some(&"no font")),
+        }
+    }
+
+    #[test]
+    fn fits_wide_text() {
+        let width = |text: &str| {
+            let source = format!("box \"{}\" fit", text);
+            Pikchr::render(&source, None, PikchrFlags::default())
+                .unwrap()
+                .width()
+        };
+        let (cjk, latin) = (width("漢字漢字"), width("\u{e9}\u{e9}\u{e9}\u{e9}"));
+        if cfg!(feature = "unicode-metrics") {
+            assert!(cjk > latin, "{} <= {}", cjk, latin);
+        } else {
+            assert_eq!(cjk, latin);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Messages from the renderer
+//!
+//! Besides the diagram itself, rendering can say things worth logging.
+//! [`Pikchr::render_with_messages`] passes each message to a callback with
+//! its [`Level`], so that an embedder can send them to its own logging.
+//!
+//! ```
+//! use pikchr::{message::Level, Pikchr, PikchrFlags};
+//!
+//! let mut messages = Vec::new();
+//! let source = "$gap = 0.5\nprint \"gap is\", $gap\nbox";
+//! Pikchr::render_with_messages(source, None, PikchrFlags::default(), &mut |level, text| {
+//!     messages.push((level, text.to_string()))
+//! })
+//! .unwrap();
+//! assert_eq!(messages, [(Level::Info, "gap is 0.5".to_string())]);
+//! ```
+//!
+//! For now the only messages are the output of `print` statements, which
+//! is also left at the start of the rendered diagram as before.  The C
+//! library does not report anything else yet; as it, or the pure-Rust
+//! backend, learns to report conditions which do not stop rendering, such
+//! as values it had to adjust, they will come through the same callback.
+//!
+//! [`Pikchr::render_with_messages`]: crate::Pikchr::render_with_messages
+
+use crate::measure::displayed;
+use std::fmt;
+
+/// How much a message matters
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Details of how the diagram was rendered, for debugging the renderer
+    Debug,
+    /// Something the source asked to be shown, such as `print` output
+    Info,
+    /// Something which did not stop rendering but may have changed the
+    /// diagram
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warning => "warning",
+        })
+    }
+}
+
+/// The callback which receives messages
+pub(crate) type MessageFn<'a> = &'a mut dyn FnMut(Level, &str);
+
+/// Report the output of `print` statements, which comes before the SVG as
+/// a line of escaped text ending in `<br>` for each statement
+pub(crate) fn report_prints(rendered: &str, on_message: MessageFn<'_>) {
+    let prints = match rendered.find("<svg") {
+        Some(start) => &rendered[..start],
+        None => return,
+    };
+    for line in prints.split("<br>") {
+        let line = line.trim_start_matches('\n');
+        if !line.is_empty() {
+            on_message(Level::Info, &displayed(line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_each_print() {
+        let mut messages = Vec::new();
+        report_prints(
+            "a&lt;b 3 6<br>\ndone<br>\n<svg></svg>\n",
+            &mut |level, text| messages.push((level, text.to_string())),
+        );
+        assert_eq!(
+            messages,
+            [
+                (Level::Info, "a<b 3 6".to_string()),
+                (Level::Info, "done".to_string())
+            ]
+        );
+        report_prints("<svg></svg>", &mut |_, text| panic!("{}", text));
+    }
+}
@@ -0,0 +1,306 @@
+//! Shrinking pikchr source
+//!
+//! [`minify`] produces the shortest source it can which draws the same
+//! diagram, which is useful when the source has to travel along with the
+//! rendered diagram, for example in a `data-` attribute or in the query
+//! string of an "edit this diagram" link.
+//!
+//! ```
+//! use pikchr::minify::minify;
+//!
+//! let source = "# A simple diagram\nA: box \"Hello\" bold bold dashed solid\n\narrow right 0.50\n";
+//! assert_eq!(minify(source), "A:box\"Hello\"bold solid;arrow right.5");
+//! ```
+//!
+//! Comments are removed, whitespace is only kept where it is needed to
+//! separate tokens, statements are separated by `;`, numbers lose any
+//! redundant zeros and strings any redundant escapes.  Attributes which
+//! are overridden later in the same object, such as the `dashed` above,
+//! are removed too.  Macro definitions are minified, but macros are not
+//! expanded.
+
+use crate::ast::{
+    self, abuts, tokenize, AttributeKind, Flag, NumericProperty, Object, ObjectKind, Span,
+    Statement, StatementKind, Token, TokenKind,
+};
+use crate::fmt::normalize_string;
+
+/// Minify pikchr source
+///
+/// Source which cannot be split into tokens, such as an unterminated
+/// string, is returned unchanged.  Source which has syntax errors is
+/// minified, but attributes are only removed from source which parses.
+///
+/// ```
+/// use pikchr::minify::minify;
+///
+/// assert_eq!(minify("box  thick  invis\n"), "box invis");
+/// assert_eq!(minify("box  thick  invis  ht\n"), "box thick invis ht");
+/// ```
+pub fn minify(source: &str) -> String {
+    minify_with(source, true)
+}
+
+fn minify_with(source: &str, remove_attributes: bool) -> String {
+    let tokens = tokenize(source);
+    if tokens.iter().any(|t| t.kind == TokenKind::Error) {
+        return source.to_string();
+    }
+    let mut redundant = Vec::new();
+    if remove_attributes {
+        if let Ok(document) = ast::parse(source) {
+            redundant_attributes(&document.statements, &tokens, &mut redundant);
+        }
+    }
+
+    let mut out = String::with_capacity(source.len());
+    // The kind and minified text of the last token written
+    let mut prev: Option<(TokenKind, String)> = None;
+    let mut separate = false;
+    let mut spaced = false;
+    let mut text_flags = Vec::new();
+    for token in &tokens {
+        if redundant
+            .iter()
+            .any(|span| span.start <= token.span.start && token.span.end <= span.end)
+        {
+            continue;
+        }
+        let text = &source[token.span.start..token.span.end];
+        let text = match token.kind {
+            TokenKind::Whitespace | TokenKind::Comment => {
+                spaced = true;
+                continue;
+            }
+            TokenKind::Eol => {
+                separate = true;
+                continue;
+            }
+            TokenKind::Bold | TokenKind::Italic | TokenKind::Aligned => {
+                if text_flags.contains(&token.kind) {
+                    continue;
+                }
+                text_flags.push(token.kind);
+                text.to_string()
+            }
+            TokenKind::String => normalize_string(text),
+            TokenKind::Number => shorten_number(text),
+            TokenKind::CodeBlock => format!("{{{}}}", minify_with(&text[1..text.len() - 1], false)),
+            _ => text.to_string(),
+        };
+        match token.kind {
+            TokenKind::String => text_flags.clear(),
+            TokenKind::Bold
+            | TokenKind::Italic
+            | TokenKind::Aligned
+            | TokenKind::Center
+            | TokenKind::LJust
+            | TokenKind::RJust
+            | TokenKind::Above
+            | TokenKind::Below
+            | TokenKind::Big
+            | TokenKind::Small => {}
+            _ => text_flags.clear(),
+        }
+        if let Some((kind, prev_text)) = &prev {
+            if separate && *kind != TokenKind::LBracket && token.kind != TokenKind::RBracket {
+                out.push(';');
+            } else if !abuts(prev_text, *kind, &text)
+                || (word_boundary(prev_text.chars().last()) && word_boundary(text.chars().next()))
+                // Whether a macro is given arguments depends on the space
+                || (*kind == TokenKind::Id && token.kind == TokenKind::LParen && spaced)
+            {
+                out.push(' ');
+            }
+        }
+        out.push_str(&text);
+        prev = Some((token.kind, text));
+        separate = false;
+        spaced = false;
+    }
+    out
+}
+
+/// Whether a character could be part of a word or number
+///
+/// Tokens which would run together like this are always separated, even
+/// if pikchr could tell them apart, so that `wid 1 ht 2` does not become
+/// `wid 1ht 2`.
+fn word_boundary(c: Option<char>) -> bool {
+    matches!(c, Some(c) if c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '@'))
+}
+
+/// Remove redundant zeros from a number
+///
+/// Numbers with exponents, hexadecimal numbers and numbers with units
+/// which could be mistaken for an exponent are left alone.
+fn shorten_number(text: &str) -> String {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    if !unit.bytes().all(|b| b.is_ascii_lowercase()) || unit.starts_with('e') {
+        return text.to_string();
+    }
+    let (whole, fraction) = match number.find('.') {
+        Some(dot) => (&number[..dot], &number[dot + 1..]),
+        None => (number, ""),
+    };
+    let whole = whole.trim_start_matches('0');
+    let fraction = fraction.trim_end_matches('0');
+    match (whole, fraction) {
+        ("", "") => format!("0{}", unit),
+        (whole, "") => format!("{}{}", whole, unit),
+        (whole, fraction) => format!("{}.{}{}", whole, fraction, unit),
+    }
+}
+
+/// Find attributes which are overridden later in the same object
+///
+/// Objects which refer to `this` are skipped, since their attributes may
+/// depend on the values of earlier ones, as are objects which come from
+/// macro expansions, since their attributes cannot be removed separately.
+fn redundant_attributes(statements: &[Statement], tokens: &[Token], redundant: &mut Vec<Span>) {
+    for statement in statements {
+        let object = match &statement.kind {
+            StatementKind::Object(object) => object,
+            _ => continue,
+        };
+        if let ObjectKind::Sublist(inner) = &object.kind {
+            redundant_attributes(inner, tokens, redundant);
+        }
+        let mentions_this = tokens
+            .iter()
+            .any(|t| t.kind == TokenKind::This && object.span.contains(t.span.start));
+        if statement.macro_call.is_some() || mentions_this {
+            continue;
+        }
+        overridden(object, redundant);
+    }
+}
+
+/// The state of an object which attributes can change
+#[derive(Copy, Clone, PartialEq)]
+enum Field {
+    Arrows,
+    Direction,
+    Stroke,
+    Dashes,
+}
+
+/// The fields an attribute replaces, and those it adjusts
+fn fields(kind: &AttributeKind) -> (&'static [Field], &'static [Field]) {
+    match kind {
+        AttributeKind::Flag(Flag::LArrow | Flag::RArrow | Flag::LRArrow) => (&[Field::Arrows], &[]),
+        AttributeKind::Flag(Flag::Cw | Flag::Ccw) => (&[Field::Direction], &[]),
+        AttributeKind::Flag(Flag::Invisible) => (&[Field::Stroke], &[]),
+        AttributeKind::Flag(Flag::Solid) => (&[Field::Stroke, Field::Dashes], &[]),
+        AttributeKind::Flag(Flag::Thick | Flag::Thin) => (&[], &[Field::Stroke]),
+        AttributeKind::Dash { .. } => (&[Field::Dashes], &[]),
+        AttributeKind::Numeric {
+            property: NumericProperty::Thickness,
+            value,
+        } if !value.percent => (&[Field::Stroke], &[]),
+        _ => (&[], &[]),
+    }
+}
+
+/// Find the flags and dash styles whose effect is entirely replaced by
+/// later attributes
+///
+/// Numeric properties and colours are never redundant, since pikchr does
+/// not allow them to be set twice.
+fn overridden(object: &Object, redundant: &mut Vec<Span>) {
+    let mut replaced = Vec::new();
+    for attribute in object.attributes.iter().rev() {
+        let (set, adjusted) = fields(&attribute.kind);
+        let removable = matches!(
+            attribute.kind,
+            AttributeKind::Flag(_) | AttributeKind::Dash { .. }
+        );
+        if removable
+            && set
+                .iter()
+                .chain(adjusted)
+                .all(|field| replaced.contains(field))
+        {
+            redundant.push(attribute.span);
+        }
+        replaced.extend_from_slice(set);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn removes_comments_and_whitespace() {
+        assert_eq!(
+            minify("/* top */ box \"a\" # note\n\n  [\n  circle;\n\n]  \narrow from A.e + (1, -2) to 1/3 <A.n, B.s>\n"),
+            "box\"a\";[circle];arrow from A.e+(1,-2)to 1/3<A.n,B.s>"
+        );
+        assert_eq!(
+            minify("line from 1 < -2, 0> to 1"),
+            "line from 1< -2,0>to 1"
+        );
+    }
+
+    #[test]
+    fn shortens_numbers_and_strings() {
+        assert_eq!(shorten_number("0.50"), ".5");
+        assert_eq!(shorten_number("010"), "10");
+        assert_eq!(shorten_number("0"), "0");
+        assert_eq!(shorten_number("2.00cm"), "2cm");
+        assert_eq!(shorten_number("1e3"), "1e3");
+        assert_eq!(shorten_number("0x10"), "0x10");
+        assert_eq!(minify(r#"text "a\b""#), r#"text"ab""#);
+    }
+
+    #[test]
+    fn keeps_macros_working() {
+        assert_eq!(
+            minify("define m {\n  box $1 # comment\n  wid 1 wid 2\n}\nm(\"a\")\nm (\"b\")\n"),
+            "define m{box $1;wid 1 wid 2};m(\"a\");m (\"b\")"
+        );
+    }
+
+    #[test]
+    fn removes_overridden_attributes() {
+        assert_eq!(minify("arrow -> <- cw ccw"), "arrow<-ccw");
+        assert_eq!(minify("box dashed thick invis"), "box dashed invis");
+        assert_eq!(minify("box dotted thin solid"), "box solid");
+        assert_eq!(
+            minify("box invis thickness 150%"),
+            "box invis thickness 150%"
+        );
+        assert_eq!(
+            minify("box thin thickness this.wid"),
+            "box thin thickness this.wid"
+        );
+        assert_eq!(
+            minify("box \"a\" big big bold bold"),
+            "box\"a\"big big bold"
+        );
+        assert_eq!(minify("box \"a\" bold \"b\" bold"), "box\"a\"bold\"b\"bold");
+    }
+
+    #[test]
+    fn preserves_meaning() {
+        let sources = [
+            "A: box \"one\" fit\narrow right 200% from A.e then down\nB: circle at A.s + (0, -1)\n",
+            "define row { box $1; box $2 }\nrow(\"a\", \"b\")\nline from 2nd box.n to 1st box .s chop\n",
+            "X: [\n  box;circle\n] ; dot at X.ne\nprint 1+-2, \"hi\"\nassert(X.w.x==X.w.x)\n",
+            "boxwid*=2 ; C: circle rad 10% color Red dashed solid \"x\" big big\nline -> from C.e right until even with C.n\n",
+            "circle \"a\" bold bold invis fill 0x00ff00 thick invis; spline right 1 then up 1 left 2.50 <- ->\n",
+        ];
+        for source in &sources {
+            let minified = minify(source);
+            let before = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+            let after = Pikchr::render(&minified, None, PikchrFlags::default()).unwrap();
+            assert_eq!(&*before, &*after, "{}", minified);
+            assert_eq!(minify(&minified), minified);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Monochrome diagrams for printing
+//!
+//! [`Pikchr::monochrome`] draws every line and all text of a rendered
+//! diagram in black, for grayscale printing and for figures which must be
+//! black and white.  So that colors which told objects apart still do,
+//! each fill color becomes its own hatching pattern and each line color its
+//! own dash style, unless the line is already dashed.  White fills stay
+//! white, so that they still hide what is behind them, and the background
+//! stays transparent.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box fill lightblue; arrow color red", None, PikchrFlags::default())
+//!     .unwrap();
+//! let pic = pic.monochrome().unwrap();
+//! assert!(pic.contains("style=\"fill:url(#pikchr-mono-0);stroke-width:2.16;stroke:rgb(0,0,0);\""));
+//! assert!(pic.contains("stroke:rgb(0,0,0);stroke-dasharray:6,4;\""));
+//! ```
+//!
+//! [`Pikchr::monochrome`]: crate::Pikchr::monochrome
+
+use crate::palette::parse_rgb;
+use crate::svg::{Dom, Element, Node};
+
+const BLACK: &str = "rgb(0,0,0)";
+
+/// The hatching patterns for fill colors, as the path of one 8 by 8 tile
+const PATTERNS: [&str; 6] = [
+    "M0,8L8,0M-2,2L2,-2M6,10L10,6",
+    "M0,4L8,4",
+    "M4,0L4,8",
+    "M0,4L8,4M4,0L4,8",
+    "M0,0L8,8M-2,6L2,10M6,-2L10,2",
+    "M3,4L5,4M4,3L4,5",
+];
+
+/// The dash styles for line colors
+const DASHES: [&str; 5] = ["6,4", "2,3", "8,3,2,3", "12,4", "1,6"];
+
+/// The fill and line colors found so far, in the order they were found
+#[derive(Default)]
+struct Colors {
+    fills: Vec<[u8; 3]>,
+    strokes: Vec<[u8; 3]>,
+}
+
+/// The position of a color in a list, adding it if it is new
+fn index(colors: &mut Vec<[u8; 3]>, color: [u8; 3]) -> usize {
+    match colors.iter().position(|&c| c == color) {
+        Some(i) => i,
+        None => {
+            colors.push(color);
+            colors.len() - 1
+        }
+    }
+}
+
+/// Make a `style` attribute monochrome
+///
+/// Arrowheads are filled in the color of their line, so they are made
+/// solid black rather than hatched.
+fn restyle(style: &str, solid: bool, colors: &mut Colors) -> Option<String> {
+    let mut declarations: Vec<(&str, String)> = style
+        .split(';')
+        .filter_map(|d| d.split_once(':'))
+        .map(|(property, value)| (property.trim(), value.trim().to_string()))
+        .collect();
+    let dashed = declarations
+        .iter()
+        .any(|(property, _)| *property == "stroke-dasharray");
+    let mut changed = false;
+    let mut dash = None;
+    for (property, value) in declarations.iter_mut() {
+        let color = match parse_rgb(value) {
+            Some(color) if color != [0, 0, 0] => color,
+            _ => continue,
+        };
+        match *property {
+            "fill" if solid => *value = BLACK.to_string(),
+            "fill" if color == [255, 255, 255] => continue,
+            "fill" => *value = format!("url(#pikchr-mono-{})", index(&mut colors.fills, color)),
+            "stroke" => {
+                let i = index(&mut colors.strokes, color);
+                if !dashed {
+                    dash = Some(DASHES[i % DASHES.len()]);
+                }
+                *value = BLACK.to_string();
+            }
+            _ => continue,
+        }
+        changed = true;
+    }
+    if let Some(dash) = dash {
+        declarations.push(("stroke-dasharray", dash.to_string()));
+    }
+    if !changed {
+        return None;
+    }
+    Some(
+        declarations
+            .iter()
+            .map(|(property, value)| format!("{}:{};", property, value))
+            .collect(),
+    )
+}
+
+/// The `<defs>` with a hatching pattern for each fill color
+fn patterns(count: usize) -> Element {
+    let mut defs = Element::new("defs");
+    for i in 0..count {
+        let mut pattern = Element::new("pattern");
+        pattern
+            .set_attr("id", &format!("pikchr-mono-{}", i))
+            .set_attr("patternUnits", "userSpaceOnUse")
+            .set_attr("width", "8")
+            .set_attr("height", "8");
+        let mut path = Element::new("path");
+        path.set_attr("d", PATTERNS[i % PATTERNS.len()])
+            .set_attr("style", "fill:none;stroke:rgb(0,0,0);stroke-width:1;");
+        pattern.children.push(Node::Element(path));
+        defs.children.push(Node::Element(pattern));
+    }
+    defs
+}
+
+/// Make a rendered diagram monochrome
+pub(crate) fn monochrome(dom: &mut Dom) {
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return,
+    };
+    let mut colors = Colors::default();
+    svg.for_each_mut(&mut |element| {
+        if element.name == "text" {
+            if element.attr("fill").is_some() {
+                element.set_attr("fill", BLACK);
+            }
+            return;
+        }
+        let solid = element.name == "polygon";
+        let style = element
+            .attr("style")
+            .and_then(|style| restyle(style, solid, &mut colors));
+        if let Some(style) = style {
+            element.set_attr("style", &style);
+        }
+    });
+    if !colors.fills.is_empty() {
+        svg.children
+            .insert(0, Node::Element(patterns(colors.fills.len())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restyles_colors() {
+        let mut colors = Colors::default();
+        let mut restyle = |style| restyle(style, false, &mut colors);
+        assert_eq!(restyle("fill:none;stroke:rgb(0,0,0);"), None);
+        assert_eq!(restyle("fill:rgb(255,255,255);stroke-width:2;"), None);
+        assert_eq!(
+            restyle("fill:rgb(255,0,0);stroke:rgb(0,0,255);"),
+            Some("fill:url(#pikchr-mono-0);stroke:rgb(0,0,0);stroke-dasharray:6,4;".to_string())
+        );
+        assert_eq!(
+            restyle("fill:rgb(0,255,0);stroke:rgb(255,0,0);"),
+            Some("fill:url(#pikchr-mono-1);stroke:rgb(0,0,0);stroke-dasharray:2,3;".to_string())
+        );
+        // Lines which are already dashed keep their dashes
+        assert_eq!(
+            restyle("fill:rgb(255,0,0);stroke:rgb(0,0,255);stroke-dasharray:7,7;"),
+            Some("fill:url(#pikchr-mono-0);stroke:rgb(0,0,0);stroke-dasharray:7,7;".to_string())
+        );
+        assert_eq!(
+            super::restyle("fill:rgb(255,0,0)", true, &mut Colors::default()),
+            Some("fill:rgb(0,0,0);".to_string())
+        );
+    }
+
+    #[test]
+    fn makes_diagrams_monochrome() {
+        let pic = crate::Pikchr::render(
+            "box fill yellow \"a\" color blue; arrow color green; circle fill yellow",
+            None,
+            crate::PikchrFlags::default(),
+        )
+        .unwrap();
+        let mut dom = pic.monochrome().unwrap().dom().unwrap();
+        let once = dom.to_string();
+        monochrome(&mut dom);
+        assert_eq!(dom.to_string(), once);
+        let svg = dom.svg().unwrap();
+        assert_eq!(svg.find_all("pattern").len(), 1);
+        assert!(svg
+            .find_all("text")
+            .iter()
+            .all(|t| t.attr("fill") == Some(BLACK)));
+        let rendered = dom.to_string();
+        assert!(!rendered.contains("rgb(0,0,255)") && !rendered.contains("rgb(0,128,0)"));
+        assert_eq!(rendered.matches("url(#pikchr-mono-0)").count(), 2);
+    }
+}
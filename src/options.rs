@@ -0,0 +1,391 @@
+//! Rendering diagrams ready to embed in a page
+//!
+//! The SVG which pikchr writes is sized for showing on its own.  A page
+//! which embeds it usually wants more: a diagram which shrinks to fit a
+//! narrow screen, an `id` to link to or script against, and some CSS of
+//! its own.  [`RenderOptions`] renders a diagram and makes those changes
+//! to it, so that they need not be made by editing the SVG as text.
+//!
+//! ```
+//! use pikchr::{options::Dimensions, RenderOptions};
+//!
+//! let mut options = RenderOptions::default();
+//! options
+//!     .class("diagram")
+//!     .id("flow")
+//!     .responsive(true)
+//!     .dimensions(Dimensions::Remove)
+//!     .style("text { font-weight: bold }");
+//! let pic = options.render("scale = 2; box \"start\"").unwrap();
+//! assert!(pic.starts_with(
+//!     "<svg xmlns=\"http://www.w3.org/2000/svg\" class=\"diagram\" viewBox=\"0 0 112.32 76.32\" \
+//!      id=\"flow\" style=\"max-width:100%;height:auto;\"><style>.diagram text { font-weight: bold }</style>"
+//! ));
+//! ```
+
+use crate::accessibility::{annotate, AccessibilityOptions};
+use crate::animation::add_style;
+use crate::svg::{escape, Element, Node};
+use crate::{Pikchr, PikchrError, PikchrFlags};
+
+/// What to do with the `width` and `height` of the SVG
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dimensions {
+    /// Leave them as pikchr wrote them, which is only when the diagram
+    /// sets its `scale`
+    Unchanged,
+    /// Set them to the size of the diagram in pixels
+    Intrinsic,
+    /// Remove them, so that the diagram is as wide as its container allows
+    Remove,
+    /// Set them to a size in pixels
+    Fixed {
+        /// The width in pixels
+        width: isize,
+        /// The height in pixels
+        height: isize,
+    },
+}
+
+/// Options for rendering a diagram to embed in a page
+///
+/// You can construct the default options using the [`std::default::Default`]
+/// trait, which render as [`Pikchr::render`] does with no class and the
+/// default flags.
+#[derive(Clone)]
+pub struct RenderOptions {
+    class: Option<String>,
+    flags: PikchrFlags,
+    dimensions: Dimensions,
+    responsive: bool,
+    id: Option<String>,
+    style: Option<String>,
+    accessibility: Option<AccessibilityOptions>,
+}
+
+impl RenderOptions {
+    /// Set the class of the SVG
+    pub fn class(&mut self, class: &str) -> &mut RenderOptions {
+        self.class = Some(class.to_string());
+        self
+    }
+
+    /// Set the flags to render with
+    pub fn flags(&mut self, flags: PikchrFlags) -> &mut RenderOptions {
+        self.flags = flags;
+        self
+    }
+
+    /// Set what to do with the `width` and `height` of the SVG
+    pub fn dimensions(&mut self, dimensions: Dimensions) -> &mut RenderOptions {
+        self.dimensions = dimensions;
+        self
+    }
+
+    /// Set whether the diagram shrinks to fit its container, keeping its
+    /// shape, when the container is narrower than it
+    pub fn responsive(&mut self, responsive: bool) -> &mut RenderOptions {
+        self.responsive = responsive;
+        self
+    }
+
+    /// Set the `id` of the SVG
+    pub fn id(&mut self, id: &str) -> &mut RenderOptions {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Set some CSS to put in a `<style>` at the start of the SVG
+    ///
+    /// A style in an SVG which is part of a page applies to the whole page,
+    /// so each selector is scoped to the diagram by putting its class in
+    /// front, or its `id` if it has no class.  `@` rules, such as `@media`,
+    /// are left as they are, as are braces and commas inside strings and
+    /// comments.
+    pub fn style(&mut self, css: &str) -> &mut RenderOptions {
+        self.style = Some(css.to_string());
+        self
+    }
+
+    /// Set a title and description for screen readers, or hide the
+    /// diagram from them
+    ///
+    /// The ids of the title and description start with the `id` of the
+    /// SVG, if it has one.  See [`accessibility`](crate::accessibility).
+    pub fn accessibility(&mut self, accessibility: AccessibilityOptions) -> &mut RenderOptions {
+        self.accessibility = Some(accessibility);
+        self
+    }
+
+    /// Render some pikchr source with these options
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pikchr::render`].
+    pub fn render(&self, source: &str) -> Result<Pikchr, PikchrError> {
+        let pic = Pikchr::render(source, self.class.as_deref(), self.flags)?;
+        let mut dom = pic.dom()?;
+        let (mut width, mut height) = (pic.width, pic.height);
+        if let Some(svg) = dom.svg_mut() {
+            match self.dimensions {
+                Dimensions::Unchanged => {}
+                Dimensions::Intrinsic => set_size(svg, width, height),
+                Dimensions::Remove => {
+                    svg.remove_attr("width");
+                    svg.remove_attr("height");
+                }
+                Dimensions::Fixed {
+                    width: fixed_width,
+                    height: fixed_height,
+                } => {
+                    set_size(svg, fixed_width, fixed_height);
+                    width = fixed_width;
+                    height = fixed_height;
+                }
+            }
+            if let Some(id) = &self.id {
+                svg.set_attr("id", &escape(id));
+            }
+            if self.responsive {
+                add_style(svg, "max-width:100%;height:auto;");
+            }
+            if let Some(css) = &self.style {
+                let scope = match (&self.class, &self.id) {
+                    (Some(class), _) => {
+                        let classes: Vec<_> = class.split_whitespace().collect();
+                        let classes: Vec<_> = classes.into_iter().map(identifier).collect();
+                        Some(format!(".{}", classes.join(".")))
+                    }
+                    (None, Some(id)) => Some(format!("#{}", identifier(id))),
+                    (None, None) => None,
+                };
+                let css = match &scope {
+                    Some(scope) => scoped(css, scope),
+                    None => css.clone(),
+                };
+                let mut style = Element::new("style");
+                style.children.push(Node::Text(escape(&css)));
+                svg.children.insert(0, Node::Element(style));
+            }
+        }
+        if let Some(accessibility) = &self.accessibility {
+            annotate(&mut dom, accessibility);
+        }
+        Ok(Pikchr {
+            rendered: dom.to_string(),
+            width,
+            height,
+        })
+    }
+}
+
+impl std::default::Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            class: None,
+            flags: PikchrFlags::default(),
+            dimensions: Dimensions::Unchanged,
+            responsive: false,
+            id: None,
+            style: None,
+            accessibility: None,
+        }
+    }
+}
+
+fn set_size(svg: &mut Element, width: isize, height: isize) {
+    svg.set_attr("width", &width.to_string());
+    svg.set_attr("height", &height.to_string());
+}
+
+/// Escape a class or `id` for use in a CSS selector
+fn identifier(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '_' | '-' => out.push(c),
+            '0'..='9' if i > 1 || (i == 1 && !name.starts_with('-')) => out.push(c),
+            // A leading digit, also after a hyphen, and control characters,
+            // as hex escapes
+            c if c.is_ascii_digit() || c.is_control() => {
+                out.push_str(&format!("\\{:x} ", c as u32))
+            }
+            c if !c.is_ascii() => out.push(c),
+            c => {
+                out.push('\\');
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+/// Where the braces and commas of some CSS are, leaving out any inside
+/// strings and comments
+fn punctuation(css: &str) -> Vec<(usize, char)> {
+    let mut found = Vec::new();
+    let mut chars = css.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                while let Some((_, d)) = chars.next() {
+                    if d == '\\' {
+                        chars.next();
+                    } else if d == c {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut star = false;
+                for (_, d) in chars.by_ref() {
+                    if star && d == '/' {
+                        break;
+                    }
+                    star = d == '*';
+                }
+            }
+            '{' | '}' | ',' => found.push((i, c)),
+            _ => {}
+        }
+    }
+    found
+}
+
+/// Put a scope in front of each selector of the rules in some CSS
+fn scoped(css: &str, scope: &str) -> String {
+    let marks = punctuation(css);
+    let mut out = String::with_capacity(css.len());
+    // Where the next rule starts, in the CSS and in the marks
+    let (mut start, mut next) = (0, 0);
+    while let Some(open) = marks[next..].iter().position(|&(_, c)| c == '{') {
+        let open = next + open;
+        // The end of the rule's block, counting any blocks inside it
+        let mut depth = 0;
+        let (mut close, mut after) = (css.len(), marks.len());
+        for (j, &(i, c)) in marks.iter().enumerate().skip(open) {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = i + 1;
+                        after = j + 1;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let prelude = &css[start..marks[open].0];
+        let selectors = prelude.trim();
+        let leading = &prelude[..prelude.len() - prelude.trim_start().len()];
+        out.push_str(leading);
+        if selectors.starts_with('@') || selectors.is_empty() {
+            out.push_str(selectors);
+        } else {
+            let mut from = start;
+            let mut scoped = Vec::new();
+            for &(i, _) in marks[next..open].iter().filter(|&&(_, c)| c == ',') {
+                scoped.push(format!("{} {}", scope, css[from..i].trim()));
+                from = i + 1;
+            }
+            scoped.push(format!("{} {}", scope, css[from..marks[open].0].trim()));
+            out.push_str(&scoped.join(", "));
+        }
+        out.push(' ');
+        out.push_str(&css[marks[open].0..close]);
+        start = close;
+        next = after;
+    }
+    out.push_str(&css[start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_selectors() {
+        assert_eq!(
+            scoped(
+                "text, path.x{fill:red}\n@media print { text { fill: black } }",
+                ".d"
+            ),
+            ".d text, .d path.x {fill:red}\n@media print { text { fill: black } }"
+        );
+        // Braces and commas in strings and comments are not rules
+        assert_eq!(
+            scoped(
+                "text::after{content:\"}\"} /* {, */ [title='a,b'], a{x:'\\'}'}",
+                ".d"
+            ),
+            ".d text::after {content:\"}\"} .d /* {, */ [title='a,b'], .d a {x:'\\'}'}"
+        );
+    }
+
+    #[test]
+    fn escapes_identifiers() {
+        assert_eq!(identifier("flow-2_a"), "flow-2_a");
+        assert_eq!(identifier("2a"), "\\32 a");
+        assert_eq!(identifier("-2"), "-\\32 ");
+        assert_eq!(identifier("a.b:c"), "a\\.b\\:c");
+        assert_eq!(identifier("é\n"), "é\\a ");
+    }
+
+    #[test]
+    fn sets_dimensions() {
+        let mut options = RenderOptions::default();
+        let pic = options.render("box").unwrap();
+        assert!(!pic.contains(" width="));
+        let pic = options
+            .dimensions(Dimensions::Intrinsic)
+            .render("box")
+            .unwrap();
+        assert!(pic.contains(" width=\"112\" height=\"76\""));
+        let fixed = Dimensions::Fixed {
+            width: 224,
+            height: 152,
+        };
+        let pic = options.dimensions(fixed).render("box").unwrap();
+        assert!(pic.contains(" width=\"224\" height=\"152\""));
+        assert_eq!((pic.width(), pic.height()), (224, 152));
+    }
+
+    #[test]
+    fn scopes_styles() {
+        let mut options = RenderOptions::default();
+        options.id("a&b").style("text{fill:red}");
+        let pic = options.render("box").unwrap();
+        assert!(pic.contains(" id=\"a&amp;b\""));
+        assert!(pic.contains("<style>#a\\&amp;b text {fill:red}</style>"));
+        let pic = options.class("x y").render("box").unwrap();
+        assert!(pic.contains("<style>.x.y text {fill:red}</style>"));
+    }
+
+    #[test]
+    fn titles_after_styles() {
+        let mut accessibility = AccessibilityOptions::default();
+        accessibility.title("A box");
+        let mut options = RenderOptions::default();
+        options
+            .id("d")
+            .style("text{fill:red}")
+            .accessibility(accessibility);
+        let pic = options.render("box").unwrap();
+        assert!(pic.contains(
+            " aria-labelledby=\"d-title\">\
+             <title id=\"d-title\">A box</title><style>"
+        ));
+    }
+
+    #[test]
+    fn reports_render_errors() {
+        assert!(matches!(
+            RenderOptions::default().render("box wid"),
+            Err(PikchrError::Syntax { .. })
+        ));
+    }
+}
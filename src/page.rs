@@ -0,0 +1,287 @@
+//! Splitting diagrams across pages
+//!
+//! A diagram can be too large to print on one page without shrinking its
+//! text past reading.  [`Pikchr::pages`] splits a diagram which does not fit
+//! a page size into several SVGs at the same scale, each of which fits.
+//! Where it can, it cuts along gaps between objects, so that boxes and
+//! their text stay whole; otherwise it cuts at the edge of the page, and
+//! the objects which cross the cut are drawn on both pages.
+//!
+//! Each page has a margin, and where the diagram carries on beyond an edge
+//! of a page, a marker in the margin there says which page it continues
+//! on.  Pages are numbered from 1, in rows from the top left, and parts of
+//! the diagram with nothing in them are not made into pages.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let source = "box \"one\"; move; box \"two\"; move; box \"three\"";
+//! let pic = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+//! let pages = pic.pages(360, 200).unwrap();
+//! assert_eq!(pages.len(), 2);
+//! assert!(pages[0].contains(">two</text>") && !pages[0].contains(">three</text>"));
+//! assert!(pages[0].contains(">continued on page 2</text>"));
+//! assert!(pages[1].contains(">continued on page 1</text>"));
+//! ```
+//!
+//! [`Pikchr::pages`]: crate::Pikchr::pages
+
+use crate::svg::{Element, Node, Rect};
+use crate::tile::reframe;
+use crate::{Pikchr, PikchrError};
+
+/// The margin around the diagram on each page, in the coordinates of the
+/// SVG
+const MARGIN: f64 = 16.0;
+
+/// A rough size for text, as pikchr's text is about 16 units high
+const CHAR_WIDTH: f64 = 8.0;
+const TEXT_HEIGHT: f64 = 16.0;
+
+/// The area which an element covers, guessing the extent of text from its
+/// length
+fn extent(element: &Element) -> Option<Rect> {
+    let bounds = element.bounds()?;
+    if element.name != "text" {
+        return Some(bounds);
+    }
+    let scale = element
+        .attr("font-size")
+        .and_then(|size| size.strip_suffix('%'))
+        .and_then(|percent| percent.parse::<f64>().ok())
+        .map_or(1.0, |percent| percent / 100.0);
+    let width = element.text().chars().count() as f64 * CHAR_WIDTH * scale;
+    let x = match element.attr("text-anchor") {
+        Some("start") => bounds.x,
+        Some("end") => bounds.x - width,
+        _ => bounds.x - width / 2.0,
+    };
+    Some(Rect {
+        x,
+        y: bounds.y - TEXT_HEIGHT * scale / 2.0,
+        width,
+        height: TEXT_HEIGHT * scale,
+    })
+}
+
+/// Where to cut a length into parts of at most `capacity`, preferring the
+/// middle of gaps between the spans which are covered
+///
+/// The cuts include the start and the end.
+fn cuts(mut spans: Vec<(f64, f64)>, start: f64, end: f64, capacity: f64) -> Vec<f64> {
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut gaps = Vec::new();
+    let mut covered = start;
+    for (from, to) in spans {
+        if from > covered {
+            gaps.push((covered + from) / 2.0);
+        }
+        covered = covered.max(to);
+    }
+    let mut cuts = vec![start];
+    let mut at = start;
+    while end - at > capacity {
+        let limit = at + capacity;
+        at = gaps
+            .iter()
+            .copied()
+            .rev()
+            .find(|&gap| gap > at && gap <= limit)
+            .unwrap_or(limit);
+        cuts.push(at);
+    }
+    cuts.push(end);
+    cuts
+}
+
+/// The marker for where a diagram continues beyond an edge of a page,
+/// which is rotated on the left and right
+fn marker(x: f64, y: f64, angle: i32, page: usize) -> Node {
+    let mut text = Element::new("text");
+    text.set_attr("class", "pikchr-continued")
+        .set_attr("x", &x.to_string())
+        .set_attr("y", &y.to_string())
+        .set_attr("text-anchor", "middle")
+        .set_attr("fill", "rgb(128,128,128)")
+        .set_attr("font-size", "9");
+    if angle != 0 {
+        text.set_attr("transform", &format!("rotate({} {} {})", angle, x, y));
+    }
+    text.set_attr("dominant-baseline", "central");
+    text.children
+        .push(Node::Text(format!("continued on page {}", page)));
+    Node::Element(text)
+}
+
+/// Split a rendered diagram into pages of at most a size in pixels
+pub(crate) fn pages(pic: &Pikchr, width: isize, height: isize) -> Result<Vec<Pikchr>, PikchrError> {
+    let dom = pic.dom()?;
+    let view_box = dom
+        .view_box()
+        .ok_or_else(|| PikchrError::svg("the diagram has no viewBox"))?;
+    if pic.width <= width && pic.height <= height {
+        return Ok(vec![Pikchr {
+            rendered: pic.rendered.clone(),
+            ..*pic
+        }]);
+    }
+    let scale_x = pic.width as f64 / view_box.width;
+    let scale_y = pic.height as f64 / view_box.height;
+    // The room for the diagram on a page, in the coordinates of the SVG
+    let room_x = width as f64 / scale_x - 2.0 * MARGIN;
+    let room_y = height as f64 / scale_y - 2.0 * MARGIN;
+    if room_x < 1.0 || room_y < 1.0 {
+        return Err(PikchrError::svg("the page is too small for its margins"));
+    }
+    let svg = dom
+        .svg()
+        .ok_or_else(|| PikchrError::svg("the diagram has no <svg>"))?;
+    let extents: Vec<Rect> = svg.elements().filter_map(extent).collect();
+    let columns = cuts(
+        extents.iter().map(|r| (r.x, r.x + r.width)).collect(),
+        view_box.x,
+        view_box.x + view_box.width,
+        room_x,
+    );
+    let rows = cuts(
+        extents.iter().map(|r| (r.y, r.y + r.height)).collect(),
+        view_box.y,
+        view_box.y + view_box.height,
+        room_y,
+    );
+    let area = |row: usize, column: usize| Rect {
+        x: columns[column],
+        y: rows[row],
+        width: columns[column + 1] - columns[column],
+        height: rows[row + 1] - rows[row],
+    };
+    // Number the parts of the diagram with something in them
+    let mut numbers = vec![vec![None; columns.len() - 1]; rows.len() - 1];
+    let mut count = 0;
+    for (row, numbers) in numbers.iter_mut().enumerate() {
+        for (column, number) in numbers.iter_mut().enumerate() {
+            let area = area(row, column);
+            if extents.iter().any(|e| e.intersects(&area)) {
+                count += 1;
+                *number = Some(count);
+            }
+        }
+    }
+    let number = |row: Option<usize>, column: Option<usize>| -> Option<usize> {
+        *numbers.get(row?)?.get(column?)?
+    };
+    let mut pages = Vec::with_capacity(count);
+    for row in 0..rows.len() - 1 {
+        for column in 0..columns.len() - 1 {
+            if number(Some(row), Some(column)).is_none() {
+                continue;
+            }
+            let area = area(row, column);
+            let mut dom = dom.clone();
+            let svg = dom
+                .svg_mut()
+                .ok_or_else(|| PikchrError::svg("the diagram has no <svg>"))?;
+            // Text is on the one page which its position is on, and
+            // everything else on each page which it reaches
+            svg.children.retain(|node| match node {
+                Node::Element(element) if element.name == "text" => {
+                    element.bounds().is_none_or(|b| {
+                        (area.x..area.x + area.width).contains(&b.x)
+                            && (area.y..area.y + area.height).contains(&b.y)
+                    })
+                }
+                Node::Element(element) => extent(element).is_none_or(|e| e.intersects(&area)),
+                _ => true,
+            });
+            let page = Rect {
+                x: area.x - MARGIN,
+                y: area.y - MARGIN,
+                width: area.width + 2.0 * MARGIN,
+                height: area.height + 2.0 * MARGIN,
+            };
+            let (middle_x, middle_y) = (area.x + area.width / 2.0, area.y + area.height / 2.0);
+            let (left, right) = (area.x - MARGIN / 2.0, area.x + area.width + MARGIN / 2.0);
+            let (top, bottom) = (area.y - MARGIN / 2.0, area.y + area.height + MARGIN / 2.0);
+            let neighbours = [
+                (Some(row), column.checked_sub(1), left, middle_y, -90),
+                (Some(row), Some(column + 1), right, middle_y, 90),
+                (row.checked_sub(1), Some(column), middle_x, top, 0),
+                (Some(row + 1), Some(column), middle_x, bottom, 0),
+            ];
+            for &(row, column, x, y, angle) in neighbours.iter() {
+                if let Some(next) = number(row, column) {
+                    svg.children.push(marker(x, y, angle, next));
+                }
+            }
+            let size = (
+                (page.width * scale_x).round() as isize,
+                (page.height * scale_y).round() as isize,
+            );
+            reframe(svg, page, size);
+            pages.push(Pikchr {
+                rendered: dom.to_string(),
+                width: size.0,
+                height: size.1,
+            });
+        }
+    }
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PikchrFlags;
+
+    #[test]
+    fn cuts_at_gaps() {
+        let spans = vec![(0.0, 40.0), (50.0, 90.0), (30.0, 45.0), (100.0, 130.0)];
+        assert_eq!(cuts(spans.clone(), 0.0, 130.0, 200.0), [0.0, 130.0]);
+        assert_eq!(cuts(spans.clone(), 0.0, 130.0, 100.0), [0.0, 95.0, 130.0]);
+        assert_eq!(
+            cuts(spans.clone(), 0.0, 130.0, 60.0),
+            [0.0, 47.5, 95.0, 130.0]
+        );
+        // Without a gap in reach, it cuts at the edge of the page
+        assert_eq!(
+            cuts(spans, 0.0, 130.0, 30.0),
+            [0.0, 30.0, 47.5, 77.5, 95.0, 125.0, 130.0]
+        );
+    }
+
+    #[test]
+    fn splits_into_pages() {
+        let pic = Pikchr::render(
+            "box \"a\"; move; box \"b\"\nmove to 1st box.s; down; move\nbox \"c\"",
+            None,
+            PikchrFlags::default(),
+        )
+        .unwrap();
+        let one = pic.pages(1000, 1000).unwrap();
+        assert_eq!(one.len(), 1);
+        assert_eq!(one[0].to_string(), pic.to_string());
+        // Four parts, with nothing below "b"
+        let pages = pic.pages(200, 200).unwrap();
+        assert_eq!(pages.len(), 3);
+        let markers = |page: &Pikchr| -> Vec<String> {
+            let dom = page.dom().unwrap();
+            dom.svg()
+                .unwrap()
+                .find_all("text")
+                .iter()
+                .filter(|t| t.attr("class") == Some("pikchr-continued"))
+                .map(|t| t.text())
+                .collect()
+        };
+        assert_eq!(
+            markers(&pages[0]),
+            ["continued on page 2", "continued on page 3"]
+        );
+        assert_eq!(markers(&pages[1]), ["continued on page 1"]);
+        assert_eq!(markers(&pages[2]), ["continued on page 1"]);
+        for page in &pages {
+            assert!(page.width() <= 200 && page.height() <= 200);
+        }
+        assert!(pic.pages(20, 20).is_err());
+    }
+}
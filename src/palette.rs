@@ -0,0 +1,216 @@
+//! Palettes for readers with color vision deficiencies
+//!
+//! Diagrams often tell objects apart by color alone, and many of pikchr's
+//! colors, such as `red` and `green`, look alike to a reader with a color
+//! vision deficiency.  [`Pikchr::with_palette`] remaps each color of a
+//! rendered diagram to the nearest color of a safer palette, keeping light
+//! fills light, so that a diagram can be made accessible without changing
+//! its source.
+//!
+//! ```
+//! use pikchr::{palette::Palette, Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box fill red; box fill green", None, PikchrFlags::default())
+//!     .unwrap();
+//! let pic = pic.with_palette(Palette::ColorBlindSafe).unwrap();
+//! assert!(pic.contains("fill:rgb(213,94,0);"));
+//! assert!(pic.contains("fill:rgb(0,158,115);"));
+//! ```
+//!
+//! [`Pikchr::with_palette`]: crate::Pikchr::with_palette
+
+use crate::svg::Dom;
+
+/// A palette to remap the colors of a diagram to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Palette {
+    /// The colors of Okabe and Ito, which can be told apart with any of
+    /// the common color vision deficiencies
+    ColorBlindSafe,
+    /// The same hues, darkened until they have a contrast ratio of at
+    /// least 7:1 with white, with dark grays made black
+    ///
+    /// This assumes a light background, so it does not suit diagrams
+    /// rendered in dark mode.
+    HighContrast,
+}
+
+/// The families of hues, as the upper bound of each in degrees, with the
+/// color each becomes in the safe and high contrast palettes
+const HUES: [(f64, [u8; 3], [u8; 3]); 7] = [
+    // Red becomes vermillion
+    (15.0, [213, 94, 0], [146, 64, 0]),
+    (45.0, [230, 159, 0], [119, 82, 0]),
+    (70.0, [240, 228, 66], [95, 91, 26]),
+    // Green becomes bluish green
+    (165.0, [0, 158, 115], [0, 102, 74]),
+    (200.0, [86, 180, 233], [44, 93, 121]),
+    (260.0, [0, 114, 178], [0, 93, 145]),
+    // Purple and magenta become reddish purple
+    (345.0, [204, 121, 167], [124, 73, 101]),
+];
+
+/// The hue in degrees, saturation and lightness of a color
+fn hsl([r, g, b]: [u8; 3]) -> (f64, f64, f64) {
+    let [r, g, b] = [r, g, b].map(|c| f64::from(c) / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (hue, saturation, lightness)
+}
+
+/// The color in a palette for a color
+fn remap(color: [u8; 3], palette: Palette) -> [u8; 3] {
+    let (hue, saturation, lightness) = hsl(color);
+    if saturation < 0.25 {
+        // Black, white and grays have no hue to confuse
+        return match palette {
+            Palette::HighContrast if lightness < 0.6 => [0, 0, 0],
+            _ => color,
+        };
+    }
+    let (_, safe, contrast) = HUES
+        .iter()
+        .find(|(end, _, _)| hue < *end)
+        .unwrap_or(&HUES[0]);
+    let (strong, tint) = match palette {
+        Palette::ColorBlindSafe => (*safe, 0.35),
+        Palette::HighContrast => (*contrast, 0.2),
+    };
+    if lightness >= 0.7 {
+        // Keep light fills light enough for text over them
+        strong.map(|c| (f64::from(c) * tint + 255.0 * (1.0 - tint)).round() as u8)
+    } else {
+        strong
+    }
+}
+
+/// Parse a color written as `rgb(r,g,b)`, as pikchr writes them
+pub(crate) fn parse_rgb(value: &str) -> Option<[u8; 3]> {
+    let channels = value.trim().strip_prefix("rgb(")?.strip_suffix(')')?;
+    let mut channels = channels.split(',').map(|c| c.trim().parse::<u8>());
+    let color = [
+        channels.next()?.ok()?,
+        channels.next()?.ok()?,
+        channels.next()?.ok()?,
+    ];
+    match channels.next() {
+        Some(_) => None,
+        None => Some(color),
+    }
+}
+
+/// Remap each `rgb(r,g,b)` in an attribute value
+fn remap_all(value: &str, palette: Palette) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("rgb(") {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(')') {
+            Some(end) => end,
+            None => break,
+        };
+        match parse_rgb(&rest[..=end]) {
+            Some(color) => {
+                let [r, g, b] = remap(color, palette);
+                out.push_str(&format!("rgb({},{},{})", r, g, b));
+            }
+            None => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Remap the colors of a rendered diagram to a palette
+pub(crate) fn recolor(dom: &mut Dom, palette: Palette) {
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return,
+    };
+    svg.for_each_mut(&mut |element| {
+        for (name, value) in element.attributes.iter_mut() {
+            if matches!(name.as_str(), "style" | "fill" | "stroke") && value.contains("rgb(") {
+                *value = remap_all(value, palette);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The contrast ratio of a color with white
+    fn contrast(color: [u8; 3]) -> f64 {
+        let [r, g, b] = color.map(|c| {
+            let c = f64::from(c) / 255.0;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        });
+        1.05 / (0.2126 * r + 0.7152 * g + 0.0722 * b + 0.05)
+    }
+
+    #[test]
+    fn remaps_by_hue() {
+        let safe = |color| remap(color, Palette::ColorBlindSafe);
+        assert_eq!(safe([255, 0, 0]), [213, 94, 0]);
+        assert_eq!(safe([0, 128, 0]), [0, 158, 115]);
+        assert_eq!(safe([0, 0, 255]), [0, 114, 178]);
+        assert_eq!(safe([255, 0, 255]), [204, 121, 167]);
+        // lightblue and lightgreen stay light
+        assert_eq!(safe([173, 216, 230]), [196, 229, 247]);
+        assert_eq!(safe([144, 238, 144]), [166, 221, 206]);
+        assert_eq!(safe([0, 0, 0]), [0, 0, 0]);
+        assert_eq!(safe([128, 128, 128]), [128, 128, 128]);
+    }
+
+    #[test]
+    fn has_high_contrast() {
+        for (_, _, color) in HUES.iter() {
+            assert!(contrast(*color) >= 7.0, "{:?}", color);
+        }
+        let high = |color| remap(color, Palette::HighContrast);
+        assert_eq!(high([128, 128, 128]), [0, 0, 0]);
+        assert_eq!(high([211, 211, 211]), [211, 211, 211]);
+        assert_eq!(high([255, 0, 0]), [146, 64, 0]);
+    }
+
+    #[test]
+    fn recolors_attributes() {
+        assert_eq!(
+            remap_all(
+                "fill:rgb(255,0,0);stroke:rgb(0,0,0);stroke-width:2.16;",
+                Palette::ColorBlindSafe
+            ),
+            "fill:rgb(213,94,0);stroke:rgb(0,0,0);stroke-width:2.16;"
+        );
+        assert_eq!(remap_all("rgb(1,2", Palette::HighContrast), "rgb(1,2");
+        assert_eq!(remap_all("rgb(a,b,c)", Palette::HighContrast), "rgb(a,b,c)");
+        let pic = crate::Pikchr::render(
+            "text \"warning\" color red",
+            None,
+            crate::PikchrFlags::default(),
+        )
+        .unwrap();
+        let pic = pic.with_palette(Palette::HighContrast).unwrap();
+        assert!(pic.contains(" fill=\"rgb(146,64,0)\""), "{}", pic);
+    }
+}
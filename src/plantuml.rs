@@ -0,0 +1,515 @@
+//! Best-effort PlantUML import
+//!
+//! This module converts simple PlantUML component and activity diagrams
+//! into pikchr source.  Only a small, commonly used subset of PlantUML is
+//! understood; anything else is skipped and recorded in the conversion
+//! report so that the result can be finished off by hand.
+//!
+//! ```
+//! use pikchr::plantuml;
+//!
+//! let conversion = plantuml::convert(r#"
+//! @startuml
+//! [Web] --> [Database] : SQL
+//! skinparam monochrome true
+//! @enduml
+//! "#);
+//!
+//! assert!(conversion.source().contains("\"Database\""));
+//! assert_eq!(conversion.unsupported().len(), 1);
+//! assert_eq!(conversion.unsupported()[0].line(), 4);
+//! ```
+
+use crate::source::{quote, row_route};
+use std::fmt::Write;
+
+/// The kind of PlantUML diagram which was detected in the input
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiagramKind {
+    /// A component diagram (`[A] --> [B]`)
+    Component,
+    /// An activity diagram (`start`, `:action;`, `stop`)
+    Activity,
+}
+
+/// A PlantUML construct which could not be converted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unsupported {
+    line: usize,
+    text: String,
+}
+
+impl Unsupported {
+    /// The (1-based) line number of the construct in the PlantUML input
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The text of the construct which was skipped
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// The result of converting a PlantUML diagram
+#[derive(Clone, Debug)]
+pub struct Conversion {
+    kind: DiagramKind,
+    source: String,
+    unsupported: Vec<Unsupported>,
+}
+
+impl Conversion {
+    /// The kind of diagram which was detected in the input
+    ///
+    /// ```
+    /// # use pikchr::plantuml::{self, DiagramKind};
+    /// let conversion = plantuml::convert("start\n:Hello;\nstop\n");
+    /// assert_eq!(conversion.kind(), DiagramKind::Activity);
+    /// ```
+    pub fn kind(&self) -> DiagramKind {
+        self.kind
+    }
+
+    /// The generated pikchr source
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The constructs which were not converted, in input order
+    pub fn unsupported(&self) -> &[Unsupported] {
+        &self.unsupported
+    }
+
+    /// Whether every construct in the input was converted
+    ///
+    /// ```
+    /// # use pikchr::plantuml;
+    /// assert!(plantuml::convert("[A] -> [B]").is_complete());
+    /// ```
+    pub fn is_complete(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+
+    /// Consume the conversion, returning the generated pikchr source
+    pub fn into_source(self) -> String {
+        self.source
+    }
+}
+
+/// Convert a PlantUML component or activity diagram into pikchr source
+///
+/// The kind of diagram is guessed from its content.  Activity diagrams are
+/// recognised by `start`, `stop` or `:action;` lines, everything else is
+/// treated as a component diagram.  Constructs which are not understood
+/// are listed in [`Conversion::unsupported`].
+///
+/// ```
+/// # use pikchr::{plantuml, Pikchr, PikchrFlags};
+/// let conversion = plantuml::convert("start\n:Fetch;\n:Process;\nstop\n");
+/// assert!(conversion.is_complete());
+/// let pic = Pikchr::render(conversion.source(), None, PikchrFlags::default()).unwrap();
+/// assert!(pic.contains("Process"));
+/// ```
+pub fn convert(input: &str) -> Conversion {
+    let lines = logical_lines(input);
+    if lines.iter().any(|(_, l)| is_activity_line(l)) {
+        convert_activity(&lines)
+    } else {
+        convert_component(&lines)
+    }
+}
+
+/// Split the input into trimmed, non-empty lines which are not comments
+/// or `@startuml`/`@enduml` markers, retaining their line numbers.
+fn logical_lines(input: &str) -> Vec<(usize, &str)> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(n, l)| (n + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('\'') && !l.starts_with('@'))
+        .collect()
+}
+
+fn is_activity_line(line: &str) -> bool {
+    matches!(line, "start" | "stop" | "end") || line.starts_with(':')
+}
+
+/// Render some (possibly multi-line) text as a sequence of pikchr strings
+fn quote_lines(text: &str) -> String {
+    text.split("\\n")
+        .map(str::trim)
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Shape {
+    Component,
+    Interface,
+    Database,
+    Node,
+}
+
+struct Element {
+    label: String,
+    alias: String,
+    shape: Shape,
+}
+
+struct Relation {
+    from: usize,
+    to: usize,
+    dashed: bool,
+    head: &'static str,
+    label: Option<String>,
+}
+
+#[derive(Default)]
+struct ComponentModel {
+    elements: Vec<Element>,
+    relations: Vec<Relation>,
+}
+
+impl ComponentModel {
+    fn find_or_add(&mut self, name: &str) -> usize {
+        let (label, shape) = if let Some(inner) = bracketed(name, '[', ']') {
+            (inner, Shape::Component)
+        } else if let Some(inner) = name.strip_prefix("()") {
+            (unquote(inner.trim()), Shape::Interface)
+        } else {
+            (unquote(name), Shape::Component)
+        };
+        if let Some(idx) = self
+            .elements
+            .iter()
+            .position(|e| e.alias == label || e.label == label)
+        {
+            return idx;
+        }
+        self.declare(label, label, shape)
+    }
+
+    fn declare(&mut self, label: &str, alias: &str, shape: Shape) -> usize {
+        if let Some(idx) = self.elements.iter().position(|e| e.alias == alias) {
+            self.elements[idx].label = label.to_string();
+            self.elements[idx].shape = shape;
+            return idx;
+        }
+        self.elements.push(Element {
+            label: label.to_string(),
+            alias: alias.to_string(),
+            shape,
+        });
+        self.elements.len() - 1
+    }
+}
+
+fn bracketed(text: &str, open: char, close: char) -> Option<&str> {
+    text.strip_prefix(open)
+        .and_then(|t| t.strip_suffix(close))
+        .map(str::trim)
+}
+
+fn unquote(text: &str) -> &str {
+    bracketed(text, '"', '"').unwrap_or(text)
+}
+
+/// Parse a declaration such as `component "Long name" as L` or `[Web] as W`
+fn parse_declaration(line: &str) -> Option<(&str, &str, Shape)> {
+    let (shape, rest) = if line.starts_with('[') {
+        (Shape::Component, line)
+    } else if let Some(rest) = line.strip_prefix("()") {
+        (Shape::Interface, rest)
+    } else {
+        let (keyword, rest) = line.split_at(line.find(char::is_whitespace)?);
+        let shape = match keyword {
+            "component" => Shape::Component,
+            "interface" => Shape::Interface,
+            "database" => Shape::Database,
+            "node" => Shape::Node,
+            _ => return None,
+        };
+        (shape, rest)
+    };
+    let rest = rest.trim();
+    let (name, alias) = match rest.find(" as ") {
+        Some(pos) => (rest[..pos].trim(), Some(rest[pos + 4..].trim())),
+        None => (rest, None),
+    };
+    let label = bracketed(name, '[', ']').unwrap_or_else(|| unquote(name));
+    if label.is_empty() || (label.contains(char::is_whitespace) && alias.is_none() && name == label)
+    {
+        return None;
+    }
+    Some((label, alias.unwrap_or(label), shape))
+}
+
+/// A PlantUML relation operator and how it maps onto pikchr
+struct Operator {
+    text: &'static str,
+    dashed: bool,
+    head: &'static str,
+    reversed: bool,
+}
+
+const fn op(text: &'static str, dashed: bool, head: &'static str, reversed: bool) -> Operator {
+    Operator {
+        text,
+        dashed,
+        head,
+        reversed,
+    }
+}
+
+/// The relation operators we understand, longest first so that prefixes of
+/// longer operators do not match early.
+const OPERATORS: &[Operator] = &[
+    op("<-->", false, "<->", false),
+    op("<..>", true, "<->", false),
+    op("-->", false, "->", false),
+    op("..>", true, "->", false),
+    op("<--", false, "->", true),
+    op("<..", true, "->", true),
+    op("->", false, "->", false),
+    op("<-", false, "->", true),
+    op("--", false, "", false),
+    op("..", true, "", false),
+    op("-", false, "", false),
+];
+
+/// Parse a relation such as `[A] --> [B] : label`
+fn parse_relation(line: &str) -> Option<(&str, &str, &'static Operator, Option<&str>)> {
+    let (body, label) = match line.find(':') {
+        Some(pos) => (line[..pos].trim(), Some(line[pos + 1..].trim())),
+        None => (line, None),
+    };
+    for op in OPERATORS {
+        if let Some(pos) = body
+            .find(&format!(" {} ", op.text))
+            .map(|p| p + 1)
+            .or_else(|| {
+                // Operators can also be written without surrounding space
+                // when both ends are bracketed, e.g. `[A]->[B]`
+                body.find(&format!("]{}[", op.text)).map(|p| p + 1)
+            })
+        {
+            let lhs = body[..pos].trim();
+            let rhs = body[pos + op.text.len()..].trim();
+            if lhs.is_empty() || rhs.is_empty() {
+                return None;
+            }
+            return Some((lhs, rhs, op, label));
+        }
+    }
+    None
+}
+
+fn convert_component(lines: &[(usize, &str)]) -> Conversion {
+    let mut model = ComponentModel::default();
+    let mut unsupported = Vec::new();
+
+    for &(lineno, line) in lines {
+        if let Some((lhs, rhs, op, label)) = parse_relation(line) {
+            let lhs = model.find_or_add(lhs);
+            let rhs = model.find_or_add(rhs);
+            let (from, to) = if op.reversed { (rhs, lhs) } else { (lhs, rhs) };
+            model.relations.push(Relation {
+                from,
+                to,
+                dashed: op.dashed,
+                head: op.head,
+                label: label.filter(|l| !l.is_empty()).map(str::to_string),
+            });
+        } else if let Some((label, alias, shape)) = parse_declaration(line) {
+            model.declare(label, alias, shape);
+        } else {
+            unsupported.push(Unsupported {
+                line: lineno,
+                text: line.to_string(),
+            });
+        }
+    }
+
+    let mut source = String::new();
+    for (idx, element) in model.elements.iter().enumerate() {
+        let shape = match element.shape {
+            Shape::Component => "box",
+            Shape::Interface => "circle rad 0.15",
+            Shape::Database => "cylinder",
+            Shape::Node => "box thick",
+        };
+        let text = if element.shape == Shape::Interface {
+            format!("{} below", quote(&element.label))
+        } else {
+            format!("{} fit", quote(&element.label))
+        };
+        if idx > 0 {
+            source.push_str("move\n");
+        }
+        writeln!(source, "C{}: {} {}", idx + 1, shape, text).unwrap();
+    }
+
+    // Relations between neighbours are drawn directly, everything else is
+    // routed around the row, alternating above and below to reduce clutter.
+    for (idx, rel) in model.relations.iter().enumerate() {
+        let kind = if rel.head.is_empty() { "line" } else { "arrow" };
+        let route = row_route("C", rel.from, rel.to, idx);
+        write!(source, "{} {}", kind, route).unwrap();
+        if !rel.head.is_empty() {
+            write!(source, " {}", rel.head).unwrap();
+        }
+        if rel.dashed {
+            source.push_str(" dashed");
+        }
+        if let Some(label) = &rel.label {
+            write!(source, " {} above", quote(label)).unwrap();
+        }
+        source.push('\n');
+    }
+
+    Conversion {
+        kind: DiagramKind::Component,
+        source,
+        unsupported,
+    }
+}
+
+/// Characters which may terminate an activity `:action` in PlantUML
+const ACTION_TERMINATORS: &[char] = &[';', '|', '<', '>', '/', ']', '}'];
+
+fn convert_activity(lines: &[(usize, &str)]) -> Conversion {
+    let mut source = String::from("down\n");
+    let mut unsupported = Vec::new();
+    let mut first = true;
+    let mut pending: Option<(usize, String)> = None;
+
+    let mut emit = |source: &mut String, stmt: &str| {
+        if !first {
+            source.push_str("arrow 0.3\n");
+        }
+        first = false;
+        source.push_str(stmt);
+        source.push('\n');
+    };
+
+    for &(lineno, line) in lines {
+        // Multi-line actions accumulate until a terminator is seen
+        let text = match pending.take() {
+            Some((start, mut text)) => {
+                text.push_str("\\n");
+                text.push_str(line);
+                (start, text)
+            }
+            None => (lineno, line.to_string()),
+        };
+        let (start, text) = text;
+        if let Some(action) = text.strip_prefix(':') {
+            match action.strip_suffix(ACTION_TERMINATORS) {
+                Some(action) => emit(
+                    &mut source,
+                    &format!("box rad 0.1 {} fit", quote_lines(action)),
+                ),
+                None => pending = Some((start, text)),
+            }
+            continue;
+        }
+        match text.as_str() {
+            "start" => emit(&mut source, "circle rad 0.1 fill black"),
+            "stop" => {
+                emit(&mut source, "circle rad 0.12");
+                source.push_str("circle rad 0.08 fill black at last circle\n");
+            }
+            "end" => {
+                emit(&mut source, "circle rad 0.12");
+                source.push_str("\"X\" at last circle\n");
+            }
+            _ => unsupported.push(Unsupported { line: start, text }),
+        }
+    }
+    if let Some((line, text)) = pending {
+        unsupported.push(Unsupported { line, text });
+    }
+
+    Conversion {
+        kind: DiagramKind::Activity,
+        source,
+        unsupported,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    fn assert_renders(conversion: &Conversion) {
+        if let Err(e) = Pikchr::render(conversion.source(), None, PikchrFlags::default()) {
+            panic!("{}\n{}", conversion.source(), e);
+        }
+    }
+
+    #[test]
+    fn component_diagram() {
+        let conversion = convert(
+            r#"
+@startuml
+' a comment
+component "Web Server" as Web
+database "Users DB" as DB
+[Cache]
+Web --> DB : SQL
+Web ..> [Cache]
+DB <-- [Cache]
+[Cache] -- Web
+note left of Web : unsupported
+@enduml
+"#,
+        );
+        assert_eq!(conversion.kind(), DiagramKind::Component);
+        assert_eq!(conversion.unsupported().len(), 1);
+        assert_eq!(conversion.unsupported()[0].line(), 11);
+        let source = conversion.source();
+        assert!(source.contains("C1: box \"Web Server\" fit"));
+        assert!(source.contains("C2: cylinder \"Users DB\" fit"));
+        assert!(source.contains("arrow from C1.e to C2.w -> \"SQL\" above"));
+        assert!(source.contains("dashed"));
+        assert_renders(&conversion);
+    }
+
+    #[test]
+    fn activity_diagram() {
+        let conversion = convert(
+            r#"
+@startuml
+start
+:Read "input";
+:Process
+the data;
+if (ok?) then (yes)
+  :Write output;
+endif
+stop
+@enduml
+"#,
+        );
+        assert_eq!(conversion.kind(), DiagramKind::Activity);
+        let skipped: Vec<_> = conversion.unsupported().iter().map(|u| u.line()).collect();
+        assert_eq!(skipped, vec![7, 9]);
+        assert!(conversion
+            .source()
+            .contains(r#"box rad 0.1 "Read \"input\"" fit"#));
+        assert!(conversion
+            .source()
+            .contains(r#"box rad 0.1 "Process" "the data" fit"#));
+        assert_renders(&conversion);
+    }
+
+    #[test]
+    fn unterminated_action_is_reported() {
+        let conversion = convert("start\n:never ends\nstop\n");
+        assert_eq!(conversion.unsupported().len(), 1);
+        assert_eq!(conversion.unsupported()[0].line(), 2);
+    }
+}
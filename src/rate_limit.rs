@@ -0,0 +1,312 @@
+//! Rate-limited rendering for public services
+//!
+//! A public playground which renders pikchr for anyone must not let one
+//! user take all of its time.  A [`RateLimitedRenderer`] gives each key,
+//! such as a user or an IP address, a bucket of tokens which refills at a
+//! steady rate, and each render takes a token.  It also caps how many
+//! renders run at once and how many may wait for their turn.  A request
+//! over either limit fails at once with
+//! [`RateLimitError::TooManyRequests`], which maps to HTTP status 429.
+//!
+//! ```
+//! use pikchr::rate_limit::{RateLimitError, RateLimitedRenderer, RateLimits};
+//! use pikchr::PikchrFlags;
+//!
+//! let mut limits = RateLimits::default();
+//! limits.per_second(0.5).burst(2);
+//! let renderer = RateLimitedRenderer::new(limits);
+//! let flags = PikchrFlags::default();
+//! assert!(renderer.render("alice", "box", None, flags).is_ok());
+//! assert!(renderer.render("alice", "box", None, flags).is_ok());
+//! match renderer.render("alice", "box", None, flags) {
+//!     Err(RateLimitError::TooManyRequests { retry_after }) => {
+//!         assert!(retry_after.unwrap().as_secs_f64() > 1.9)
+//!     }
+//!     other => panic!("{:?}", other.map(|pic| pic.to_string())),
+//! }
+//! // Each key has its own bucket
+//! assert!(renderer.render("bob", "box", None, flags).is_ok());
+//! ```
+
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The limits of a [`RateLimitedRenderer`]
+///
+/// You can construct the default limits using the [`std::default::Default`]
+/// trait, which allow each key one render a second with bursts of up to
+/// 10, and up to 4 renders at once with up to 32 waiting.
+#[derive(Copy, Clone, Debug)]
+pub struct RateLimits {
+    per_second: f64,
+    burst: u32,
+    concurrency: usize,
+    max_queue: usize,
+}
+
+impl RateLimits {
+    /// Set how many renders each key may make per second, in the long run
+    pub fn per_second(&mut self, per_second: f64) -> &mut RateLimits {
+        self.per_second = per_second;
+        self
+    }
+
+    /// Set how many renders a key may make at once after being idle
+    pub fn burst(&mut self, burst: u32) -> &mut RateLimits {
+        self.burst = burst;
+        self
+    }
+
+    /// Set how many renders may run at once, across all keys
+    pub fn concurrency(&mut self, concurrency: usize) -> &mut RateLimits {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set how many renders may wait for one of the others to finish
+    pub fn max_queue(&mut self, max_queue: usize) -> &mut RateLimits {
+        self.max_queue = max_queue;
+        self
+    }
+}
+
+impl std::default::Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            per_second: 1.0,
+            burst: 10,
+            concurrency: 4,
+            max_queue: 32,
+        }
+    }
+}
+
+/// Why a rate-limited render failed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RateLimitError {
+    /// The key has used up its renders for now, or too many renders are
+    /// waiting already
+    TooManyRequests {
+        /// How long until the key may render again, if it was the key
+        /// which was over its limit, for a `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+    /// The source could not be rendered
+    Render(PikchrError),
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimitError::TooManyRequests {
+                retry_after: Some(after),
+            } => write!(
+                f,
+                "too many requests; try again in {:.1}s",
+                after.as_secs_f64()
+            ),
+            RateLimitError::TooManyRequests { retry_after: None } => {
+                f.write_str("too many requests; the render queue is full")
+            }
+            RateLimitError::Render(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RateLimitError {}
+
+/// The tokens of one key
+#[derive(Copy, Clone, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refill the bucket for the time since it was last used
+    fn refill(&mut self, now: Instant, limits: &RateLimits) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limits.per_second).min(f64::from(limits.burst));
+        self.updated = now;
+    }
+}
+
+/// Buckets are dropped once they are full, when there are this many
+const PRUNE_AT: usize = 1024;
+
+#[derive(Default)]
+struct State {
+    buckets: HashMap<String, Bucket>,
+    running: usize,
+    waiting: usize,
+}
+
+/// A renderer which limits how often each key may render
+///
+/// It can be shared between threads, and renders on the thread which
+/// calls [`render`](RateLimitedRenderer::render).
+pub struct RateLimitedRenderer {
+    limits: RateLimits,
+    state: Mutex<State>,
+    finished: Condvar,
+}
+
+/// Frees a render's place when it finishes, even by panicking
+struct Running<'a>(&'a RateLimitedRenderer);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.lock().running -= 1;
+        self.0.finished.notify_one();
+    }
+}
+
+impl RateLimitedRenderer {
+    /// Create a renderer with some limits
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimitedRenderer {
+            limits,
+            state: Mutex::new(State::default()),
+            finished: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state is consistent between statements, so a panic
+        // elsewhere cannot leave it broken
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Take a token from a key's bucket
+    fn take(&self, state: &mut State, key: &str) -> Result<(), RateLimitError> {
+        let now = Instant::now();
+        let limits = &self.limits;
+        if state.buckets.len() >= PRUNE_AT && !state.buckets.contains_key(key) {
+            state.buckets.retain(|_, bucket| {
+                bucket.refill(now, limits);
+                bucket.tokens < f64::from(limits.burst)
+            });
+        }
+        let bucket = state.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: f64::from(limits.burst),
+            updated: now,
+        });
+        bucket.refill(now, limits);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let retry_after = if limits.per_second > 0.0 {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / limits.per_second)
+        } else {
+            Duration::MAX
+        };
+        Err(RateLimitError::TooManyRequests {
+            retry_after: Some(retry_after),
+        })
+    }
+
+    /// Render some pikchr source for a key, if the key and the queue are
+    /// within their limits
+    ///
+    /// If as many renders are running as are allowed, this waits for one
+    /// of them to finish.
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the key has no tokens left, if the queue is full,
+    /// or if the source cannot be rendered.
+    pub fn render(
+        &self,
+        key: &str,
+        source: &str,
+        class: Option<&str>,
+        flags: PikchrFlags,
+    ) -> Result<Pikchr, RateLimitError> {
+        let mut state = self.lock();
+        let queue_full =
+            state.running >= self.limits.concurrency && state.waiting >= self.limits.max_queue;
+        if queue_full {
+            return Err(RateLimitError::TooManyRequests { retry_after: None });
+        }
+        self.take(&mut state, key)?;
+        state.waiting += 1;
+        while state.running >= self.limits.concurrency {
+            state = self.finished.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.waiting -= 1;
+        state.running += 1;
+        drop(state);
+        let _running = Running(self);
+        Pikchr::render(source, class, flags).map_err(RateLimitError::Render)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn refills_over_time() {
+        let mut limits = RateLimits::default();
+        limits.per_second(1000.0).burst(1);
+        let renderer = RateLimitedRenderer::new(limits);
+        let flags = PikchrFlags::default();
+        assert!(renderer.render("k", "box", None, flags).is_ok());
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(renderer.render("k", "box", None, flags).is_ok());
+        assert!(matches!(
+            renderer.render("other", "box wid", None, flags),
+            Err(RateLimitError::Render(_))
+        ));
+    }
+
+    #[test]
+    fn caps_the_queue() {
+        let mut limits = RateLimits::default();
+        limits
+            .per_second(0.0)
+            .burst(100)
+            .concurrency(1)
+            .max_queue(0);
+        let renderer = Arc::new(RateLimitedRenderer::new(limits));
+        // Hold the only place, as a long render would
+        renderer.lock().running = 1;
+        assert_eq!(
+            renderer
+                .render("k", "box", None, PikchrFlags::default())
+                .map(|_| ()),
+            Err(RateLimitError::TooManyRequests { retry_after: None })
+        );
+        renderer.lock().running = 0;
+        assert!(renderer
+            .render("k", "box", None, PikchrFlags::default())
+            .is_ok());
+        assert_eq!(renderer.lock().running, 0);
+    }
+
+    #[test]
+    fn waits_for_a_place() {
+        let mut limits = RateLimits::default();
+        limits.concurrency(1).max_queue(1);
+        let renderer = Arc::new(RateLimitedRenderer::new(limits));
+        renderer.lock().running = 1;
+        let waiting = {
+            let renderer = Arc::clone(&renderer);
+            std::thread::spawn(move || renderer.render("k", "box", None, PikchrFlags::default()))
+        };
+        while renderer.lock().waiting == 0 {
+            std::thread::yield_now();
+        }
+        // The queue is full now
+        assert!(renderer
+            .render("j", "box", None, PikchrFlags::default())
+            .is_err());
+        drop(Running(&renderer));
+        assert!(waiting.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,336 @@
+//! Sandboxing the thread which renders untrusted input
+//!
+//! pikchr only needs memory to render a diagram, so a service rendering
+//! diagrams from anyone can take everything else away from the thread
+//! which does it.  With the `sandbox` feature on Linux, [`restrict`] stops
+//! the calling thread, and any threads it starts later, from touching the
+//! filesystem or the network.  This cannot be undone, so it suits a
+//! worker thread or process which only renders.
+//!
+//! ```no_run
+//! use pikchr::{sandbox, Pikchr, PikchrFlags};
+//!
+//! let worker = std::thread::spawn(|| {
+//!     sandbox::restrict(sandbox::Policy::Seccomp).unwrap();
+//!     Pikchr::render("box \"untrusted\"", None, PikchrFlags::default())
+//! });
+//! println!("{}", worker.join().unwrap().unwrap());
+//! ```
+//!
+//! There are two policies, and a thread can be given both:
+//!
+//! - [`Policy::Seccomp`] filters system calls, so that those which open
+//!   or change files or their metadata, make or connect sockets, or run
+//!   programs fail with
+//!   `EPERM`.  It works on any Linux kernel from the last decade, on
+//!   x86-64 and AArch64.
+//! - [`Policy::Landlock`] denies all access to the filesystem, and on
+//!   kernels from 6.7 on, binding and connecting TCP sockets.  It needs a
+//!   kernel with Landlock enabled, but works on any architecture.
+//!
+//! Neither takes away files or sockets which are already open.  This
+//! complements running the renderer in a separate process rather than
+//! replacing it: a sandboxed thread still shares memory with the rest of
+//! the process.
+
+use core::ffi::{c_int, c_long, c_void};
+use std::io;
+
+extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+    fn prctl(option: c_int, ...) -> c_int;
+    fn close(fd: c_int) -> c_int;
+}
+
+const PR_SET_NO_NEW_PRIVS: c_int = 38;
+const PR_SET_SECCOMP: c_int = 22;
+const SECCOMP_MODE_FILTER: c_long = 2;
+
+/// How to sandbox a thread
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Filter system calls with seccomp
+    Seccomp,
+    /// Deny access to files and TCP with Landlock
+    Landlock,
+}
+
+/// The error from the last system call, with what was being done
+fn os_error(doing: &str) -> String {
+    format!("{}: {}", doing, io::Error::last_os_error())
+}
+
+/// Stop the calling thread from gaining privileges, which both policies
+/// need unless the thread has `CAP_SYS_ADMIN`
+fn no_new_privs() -> Result<(), String> {
+    let on: c_long = 1;
+    let unused: c_long = 0;
+    if unsafe { prctl(PR_SET_NO_NEW_PRIVS, on, unused, unused, unused) } != 0 {
+        return Err(os_error("cannot set no_new_privs"));
+    }
+    Ok(())
+}
+
+/// Sandbox the calling thread, and any threads it starts from now on
+///
+/// # Errors
+///
+/// It is an error if the kernel does not support the policy, in which
+/// case the thread is as it was.
+pub fn restrict(policy: Policy) -> Result<(), String> {
+    match policy {
+        Policy::Seccomp => seccomp(),
+        Policy::Landlock => landlock(),
+    }
+}
+
+/// An instruction of a classic BPF program, as `struct sock_filter`
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// A classic BPF program, as `struct sock_fprog`
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_ERRNO_EPERM: u32 = 0x0005_0000 | 1;
+
+/// The offsets of the system call number and architecture in
+/// `struct seccomp_data`
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// The system calls which the seccomp policy denies: those which open,
+/// make or change files or their metadata, use sockets, run programs or reach into other
+/// processes, and io_uring, which can open files and sockets without
+/// making any of them
+#[cfg(target_arch = "x86_64")]
+const DENIED: &[u32] = &[
+    2, 85, 257, 437, // open, creat, openat, openat2
+    83, 258, 84, 87, 263, // mkdir, mkdirat, rmdir, unlink, unlinkat
+    82, 264, 316, 86, 265, 88, 266, // renames, links and symlinks
+    76, 90, 268, 452, 92, 94, 260, // truncate, chmods and chowns
+    188, 189, 190, 197, 198, 199, // setting and removing xattrs
+    132, 235, 261, 280, // utime, utimes, futimesat, utimensat
+    133, 259, 165, 303, 304, // mknod, mknodat, mount, handles
+    41, 53, 42, 49, 50, 43, 288, // sockets
+    59, 322, 101, 311, // execve, execveat, ptrace, process_vm_writev
+    425, 426, 427, // io_uring_setup, io_uring_enter, io_uring_register
+];
+#[cfg(target_arch = "aarch64")]
+const DENIED: &[u32] = &[
+    56, 437, // openat, openat2
+    34, 35, // mkdirat, unlinkat
+    38, 276, 37, 36, // renameat, renameat2, linkat, symlinkat
+    45, 53, 452, 54, // truncate, fchmodat, fchmodat2, fchownat
+    5, 6, 7, 14, 15, 16, // setting and removing xattrs
+    88, // utimensat
+    33, 40, 264, 265, // mknodat, mount, handles
+    198, 199, 203, 200, 201, 202, 242, // sockets
+    221, 281, 117, 271, // execve, execveat, ptrace, process_vm_writev
+    425, 426, 427, // io_uring_setup, io_uring_enter, io_uring_register
+];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const DENIED: &[u32] = &[];
+
+/// The seccomp filter, which denies the system calls in [`DENIED`] and
+/// everything from any other architecture, such as the x32 ABI
+fn filter(arch: u32) -> Vec<SockFilter> {
+    let op = |code, k, jt, jf| SockFilter { code, jt, jf, k };
+    let mut program = vec![
+        op(BPF_LD_W_ABS, SECCOMP_DATA_ARCH, 0, 0),
+        op(BPF_JEQ_K, arch, 1, 0),
+        op(BPF_RET_K, SECCOMP_RET_ERRNO_EPERM, 0, 0),
+        op(BPF_LD_W_ABS, SECCOMP_DATA_NR, 0, 0),
+    ];
+    let deny = DENIED.len() as u8 + 2;
+    program.push(op(BPF_JGE_K, 0x4000_0000, deny - 1, 0));
+    for (i, &nr) in DENIED.iter().enumerate() {
+        // Jump over the rest of the comparisons and the allow
+        program.push(op(BPF_JEQ_K, nr, deny - 2 - i as u8, 0));
+    }
+    program.push(op(BPF_RET_K, SECCOMP_RET_ALLOW, 0, 0));
+    program.push(op(BPF_RET_K, SECCOMP_RET_ERRNO_EPERM, 0, 0));
+    program
+}
+
+fn seccomp() -> Result<(), String> {
+    let arch = AUDIT_ARCH.ok_or("seccomp is not supported on this architecture")?;
+    let program = filter(arch);
+    let fprog = SockFprog {
+        len: program.len() as u16,
+        filter: program.as_ptr(),
+    };
+    no_new_privs()?;
+    let fprog: *const SockFprog = &fprog;
+    if unsafe { prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER, fprog as *const c_void) } != 0 {
+        return Err(os_error("cannot install the seccomp filter"));
+    }
+    Ok(())
+}
+
+const SYS_LANDLOCK_CREATE_RULESET: c_long = 444;
+const SYS_LANDLOCK_RESTRICT_SELF: c_long = 446;
+const LANDLOCK_CREATE_RULESET_VERSION: c_long = 1;
+
+/// `struct landlock_ruleset_attr`, as far as version 4 of the ABI
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+    handled_access_net: u64,
+}
+
+/// Every kind of access to files in a version of the Landlock ABI
+fn handled_access_fs(abi: c_long) -> u64 {
+    match abi {
+        1 => (1 << 13) - 1,
+        2 => (1 << 14) - 1,
+        3 | 4 => (1 << 15) - 1,
+        _ => (1 << 16) - 1,
+    }
+}
+
+fn landlock() -> Result<(), String> {
+    let abi = unsafe {
+        syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            std::ptr::null::<c_void>(),
+            0 as c_long,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        return Err(os_error("Landlock is not available"));
+    }
+    let attr = RulesetAttr {
+        handled_access_fs: handled_access_fs(abi),
+        // Binding and connecting TCP sockets
+        handled_access_net: if abi >= 4 { 0b11 } else { 0 },
+    };
+    // Older kernels reject the size of fields they do not know
+    let size = if abi >= 4 {
+        std::mem::size_of::<RulesetAttr>()
+    } else {
+        std::mem::size_of::<u64>()
+    };
+    let attr: *const RulesetAttr = &attr;
+    let ruleset = unsafe {
+        syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            attr,
+            size as c_long,
+            0 as c_long,
+        )
+    };
+    if ruleset < 0 {
+        return Err(os_error("cannot create a Landlock ruleset"));
+    }
+    let ruleset = ruleset as c_int;
+    let result = no_new_privs().and_then(|()| {
+        // A ruleset with no rules denies everything it handles
+        if unsafe { syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset as c_long, 0 as c_long) } != 0 {
+            return Err(os_error("cannot apply the Landlock ruleset"));
+        }
+        Ok(())
+    });
+    unsafe { close(ruleset) };
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+    use std::fs::File;
+    use std::net::TcpListener;
+
+    /// Render and try to reach the outside world, in a sandboxed thread
+    fn sandboxed(policy: Policy) -> Result<(bool, bool, bool), String> {
+        std::thread::spawn(move || {
+            restrict(policy)?;
+            let rendered = Pikchr::render("box; arrow", None, PikchrFlags::default()).is_ok();
+            let opened = File::open("Cargo.toml").is_ok();
+            let listened = TcpListener::bind("127.0.0.1:0").is_ok();
+            Ok((rendered, opened, listened))
+        })
+        .join()
+        .unwrap()
+    }
+
+    const EPERM: i32 = 1;
+    const AT_FDCWD: c_long = -100;
+    #[cfg(target_arch = "x86_64")]
+    const SYS_UTIMENSAT: c_long = 280;
+    #[cfg(not(target_arch = "x86_64"))]
+    const SYS_UTIMENSAT: c_long = 88;
+
+    /// Make a system call in a thread with the seccomp policy, returning
+    /// the error it failed with
+    fn denied(call: impl FnOnce() -> c_long + Send + 'static) -> Result<Option<i32>, String> {
+        std::thread::spawn(move || {
+            restrict(Policy::Seccomp)?;
+            assert!(call() < 0);
+            Ok(std::io::Error::last_os_error().raw_os_error())
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_system_calls() {
+        let program = filter(0);
+        // The jump to the final deny from the first comparison
+        assert_eq!(program[5].jt as usize, program.len() - 7);
+        if AUDIT_ARCH.is_none() {
+            return;
+        }
+        assert_eq!(sandboxed(Policy::Seccomp), Ok((true, false, false)));
+        // io_uring_setup, with a zeroed struct io_uring_params
+        let params = [0u8; 120];
+        let errno = denied(move || unsafe { syscall(425, 1 as c_long, params.as_ptr()) });
+        assert_eq!(errno, Ok(Some(EPERM)));
+        // utimensat, on a file which is not there so that nothing is
+        // changed if it is allowed
+        let errno = denied(|| unsafe {
+            syscall(
+                SYS_UTIMENSAT,
+                AT_FDCWD,
+                b"no-such-file\0".as_ptr(),
+                std::ptr::null::<c_void>(),
+                0 as c_long,
+            )
+        });
+        assert_eq!(errno, Ok(Some(EPERM)));
+        // Other threads are left alone
+        assert!(File::open("Cargo.toml").is_ok());
+    }
+
+    #[test]
+    fn denies_files_with_landlock() {
+        match sandboxed(Policy::Landlock) {
+            Ok((rendered, opened, _)) => assert!(rendered && !opened),
+            Err(err) => assert!(err.starts_with("Landlock is not available"), "{}", err),
+        }
+        assert!(File::open("Cargo.toml").is_ok());
+    }
+}
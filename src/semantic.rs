@@ -0,0 +1,899 @@
+//! Resolving the names in pikchr source
+//!
+//! A [`Model`] is built from a parsed [`Document`].  It knows which objects
+//! the diagram contains, what every object reference (`A`, `last box`,
+//! `2nd circle in B`, ...) and variable refers to, and the value of each
+//! expression which can be computed without laying the diagram out.
+//!
+//! ```
+//! use pikchr::ast;
+//! use pikchr::semantic::{Model, Target};
+//!
+//! let source = "gap = 0.5\nA: box\nbox\narrow from last box.e right gap\nline from A.s down";
+//! let model = Model::new(&ast::parse(source).unwrap());
+//! assert_eq!(model.objects().len(), 4);
+//!
+//! // `last box` is the second box, which has no label
+//! let reference = model.reference_at(source.find("last").unwrap()).unwrap();
+//! let target = model.object(reference.target.object().unwrap());
+//! assert_eq!(target.class.as_deref(), Some("box"));
+//! assert!(target.label.is_none());
+//!
+//! // `A` resolves to the first box, and `gap` to the variable
+//! let a = model.reference_at(source.rfind('A').unwrap()).unwrap();
+//! assert_eq!(model.definition(&a.target), Some(ast::Span::new(10, 11)));
+//! assert_eq!(model.variable("gap"), Some(0.5));
+//! ```
+//!
+//! References are resolved in the same way as the C implementation does,
+//! so an object can only be referred to once it exists, and only from
+//! within the same `[...]` sublist unless it is named through its parent
+//! (`Outer.Inner`).  Values which depend on the layout, such as `A.x` or
+//! `B.wid`, are not computed.
+
+use crate::ast::{
+    Assertion, AssignOp, AttributeKind, BinaryOp, Document, Expr, ExprKind, Function, Name,
+    NthClass, Object, ObjectKind, ObjectRef, ObjectRefKind, Place, PlaceKind, Position,
+    PositionKind, PrintItem, Span, Statement, StatementKind, UnaryOp,
+};
+use std::collections::HashMap;
+
+/// Identifies an object in a [`Model`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(usize);
+
+impl ObjectId {
+    /// The position of the object in [`Model::objects`]
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// An object in the diagram
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectInfo {
+    /// The class of the object (`box`, `text`, `[]`, ...), or `None` for
+    /// a place such as `P: (1, 2)`
+    pub class: Option<String>,
+    /// The label of the object, if it has one
+    pub label: Option<Name>,
+    /// The source of the statement which creates the object
+    pub span: Span,
+    /// The `[...]` object which contains this one, if any
+    pub parent: Option<ObjectId>,
+    /// The objects inside this one, if it is a `[...]` object
+    pub children: Vec<ObjectId>,
+    /// The text attached to the object, without quotes
+    pub texts: Vec<String>,
+}
+
+/// What a reference refers to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// An object or place
+    Object(ObjectId),
+    /// A variable, either one the source assigns or a built-in one
+    Variable(String),
+    /// A named colour, with its RGB value
+    Color(String, i32),
+    /// Nothing; the C implementation would report an error
+    Unresolved,
+}
+
+impl Target {
+    /// The object referred to, if this refers to one
+    pub fn object(&self) -> Option<ObjectId> {
+        match self {
+            Target::Object(id) => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+/// A use or definition of a name in the source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    /// The source of the reference
+    pub span: Span,
+    /// What it refers to
+    pub target: Target,
+    /// Whether this is where the target is defined, such as the label of
+    /// an object or the variable being assigned to
+    pub definition: bool,
+}
+
+/// The semantic model of a pikchr document
+#[derive(Clone, Debug, Default)]
+pub struct Model {
+    objects: Vec<ObjectInfo>,
+    references: Vec<Reference>,
+    values: HashMap<Span, f64>,
+    variables: HashMap<String, f64>,
+}
+
+impl Model {
+    /// Analyse a document
+    pub fn new(document: &Document) -> Model {
+        let mut builder = Builder::default();
+        builder.statements(&document.statements, None);
+        let mut model = builder.model;
+        model.references.sort_by_key(|r| (r.span.start, r.span.end));
+        model
+    }
+
+    /// Every object in the diagram, in the order they are created
+    ///
+    /// Objects inside a `[...]` object come before it.
+    pub fn objects(&self) -> &[ObjectInfo] {
+        &self.objects
+    }
+
+    /// Look up an object
+    ///
+    /// # Panics
+    ///
+    /// Panics if the id does not come from this model.
+    pub fn object(&self, id: ObjectId) -> &ObjectInfo {
+        &self.objects[id.0]
+    }
+
+    /// The objects which are not inside a `[...]` object
+    pub fn top_level(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        (0..self.objects.len())
+            .map(ObjectId)
+            .filter(move |&id| self.objects[id.0].parent.is_none())
+    }
+
+    /// Every reference in the source, in source order
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    /// The innermost reference at a byte offset
+    ///
+    /// An offset just past the end of a reference counts as being in it,
+    /// so that a cursor at the end of a name finds it.
+    pub fn reference_at(&self, offset: usize) -> Option<&Reference> {
+        self.references
+            .iter()
+            .filter(|r| r.span.contains(offset))
+            .min_by_key(|r| r.span.len())
+    }
+
+    /// Every reference to a target, including its definitions
+    ///
+    /// ```
+    /// # use pikchr::{ast, semantic::{Model, Target}};
+    /// let source = "n1 = 1\nn1 += 2\nbox wid n1";
+    /// let model = Model::new(&ast::parse(source).unwrap());
+    /// let target = Target::Variable("n1".to_string());
+    /// assert_eq!(model.references_to(&target).count(), 3);
+    /// ```
+    pub fn references_to<'a>(&'a self, target: &'a Target) -> impl Iterator<Item = &'a Reference> {
+        self.references.iter().filter(move |r| r.target == *target)
+    }
+
+    /// Where a target is defined
+    ///
+    /// For an object this is its label, or the whole statement if it has
+    /// no label.  For a variable it is the first assignment to it.
+    /// Built-in variables and colours have no definition in the source.
+    pub fn definition(&self, target: &Target) -> Option<Span> {
+        match target {
+            Target::Object(id) => {
+                let object = &self.objects[id.0];
+                Some(object.label.as_ref().map_or(object.span, |l| l.span))
+            }
+            _ => self
+                .references_to(target)
+                .find(|r| r.definition)
+                .map(|r| r.span),
+        }
+    }
+
+    /// The value of an expression from the analysed document
+    ///
+    /// This is `None` if the value depends on the layout of the diagram,
+    /// or if it cannot be computed because of an error such as a
+    /// division by zero.
+    ///
+    /// ```
+    /// # use pikchr::{ast::{self, StatementKind}, semantic::Model};
+    /// let doc = ast::parse("base = 2\nwide = base * 1.5 + 1cm").unwrap();
+    /// let model = Model::new(&doc);
+    /// match &doc.statements[1].kind {
+    ///     StatementKind::Assignment { value, .. } => {
+    ///         assert_eq!(model.value(value), Some(3.0 + 1.0 / 2.54))
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn value(&self, expr: &Expr) -> Option<f64> {
+        self.values.get(&expr.span).copied()
+    }
+
+    /// The value of a variable at the end of the document
+    pub fn variable(&self, name: &str) -> Option<f64> {
+        self.variables.get(name).copied().or_else(|| builtin(name))
+    }
+}
+
+/// The built-in variables and their default values, sorted by name
+pub(crate) static BUILTINS: &[(&str, f64)] = &[
+    ("arcrad", 0.25),
+    ("arrowhead", 2.0),
+    ("arrowht", 0.08),
+    ("arrowwid", 0.06),
+    ("boxht", 0.5),
+    ("boxrad", 0.0),
+    ("boxwid", 0.75),
+    ("charht", 0.14),
+    ("charwid", 0.08),
+    ("circlerad", 0.25),
+    ("color", 0.0),
+    ("cylht", 0.5),
+    ("cylrad", 0.075),
+    ("cylwid", 0.75),
+    ("dashwid", 0.05),
+    ("dotrad", 0.015),
+    ("ellipseht", 0.5),
+    ("ellipsewid", 0.75),
+    ("fileht", 0.75),
+    ("filerad", 0.15),
+    ("filewid", 0.5),
+    ("fill", -1.0),
+    ("lineht", 0.5),
+    ("linewid", 0.5),
+    ("movewid", 0.5),
+    ("ovalht", 0.5),
+    ("ovalwid", 1.0),
+    ("scale", 1.0),
+    ("textht", 0.5),
+    ("textwid", 0.75),
+    ("thickness", 0.015),
+];
+
+/// The named colours and their RGB values, sorted by name ignoring case
+///
+/// `None` and `Off` are not real colours; they turn colouring off.
+pub(crate) static COLORS: &[(&str, i32)] = &[
+    ("AliceBlue", 0xf0f8ff),
+    ("AntiqueWhite", 0xfaebd7),
+    ("Aqua", 0x00ffff),
+    ("Aquamarine", 0x7fffd4),
+    ("Azure", 0xf0ffff),
+    ("Beige", 0xf5f5dc),
+    ("Bisque", 0xffe4c4),
+    ("Black", 0x000000),
+    ("BlanchedAlmond", 0xffebcd),
+    ("Blue", 0x0000ff),
+    ("BlueViolet", 0x8a2be2),
+    ("Brown", 0xa52a2a),
+    ("BurlyWood", 0xdeb887),
+    ("CadetBlue", 0x5f9ea0),
+    ("Chartreuse", 0x7fff00),
+    ("Chocolate", 0xd2691e),
+    ("Coral", 0xff7f50),
+    ("CornflowerBlue", 0x6495ed),
+    ("Cornsilk", 0xfff8dc),
+    ("Crimson", 0xdc143c),
+    ("Cyan", 0x00ffff),
+    ("DarkBlue", 0x00008b),
+    ("DarkCyan", 0x008b8b),
+    ("DarkGoldenrod", 0xb8860b),
+    ("DarkGray", 0xa9a9a9),
+    ("DarkGreen", 0x006400),
+    ("DarkGrey", 0xa9a9a9),
+    ("DarkKhaki", 0xbdb76b),
+    ("DarkMagenta", 0x8b008b),
+    ("DarkOliveGreen", 0x556b2f),
+    ("DarkOrange", 0xff8c00),
+    ("DarkOrchid", 0x9932cc),
+    ("DarkRed", 0x8b0000),
+    ("DarkSalmon", 0xe9967a),
+    ("DarkSeaGreen", 0x8fbc8f),
+    ("DarkSlateBlue", 0x483d8b),
+    ("DarkSlateGray", 0x2f4f4f),
+    ("DarkSlateGrey", 0x2f4f4f),
+    ("DarkTurquoise", 0x00ced1),
+    ("DarkViolet", 0x9400d3),
+    ("DeepPink", 0xff1493),
+    ("DeepSkyBlue", 0x00bfff),
+    ("DimGray", 0x696969),
+    ("DimGrey", 0x696969),
+    ("DodgerBlue", 0x1e90ff),
+    ("Firebrick", 0xb22222),
+    ("FloralWhite", 0xfffaf0),
+    ("ForestGreen", 0x228b22),
+    ("Fuchsia", 0xff00ff),
+    ("Gainsboro", 0xdcdcdc),
+    ("GhostWhite", 0xf8f8ff),
+    ("Gold", 0xffd700),
+    ("Goldenrod", 0xdaa520),
+    ("Gray", 0x808080),
+    ("Green", 0x008000),
+    ("GreenYellow", 0xadff2f),
+    ("Grey", 0x808080),
+    ("Honeydew", 0xf0fff0),
+    ("HotPink", 0xff69b4),
+    ("IndianRed", 0xcd5c5c),
+    ("Indigo", 0x4b0082),
+    ("Ivory", 0xfffff0),
+    ("Khaki", 0xf0e68c),
+    ("Lavender", 0xe6e6fa),
+    ("LavenderBlush", 0xfff0f5),
+    ("LawnGreen", 0x7cfc00),
+    ("LemonChiffon", 0xfffacd),
+    ("LightBlue", 0xadd8e6),
+    ("LightCoral", 0xf08080),
+    ("LightCyan", 0xe0ffff),
+    ("LightGoldenrodYellow", 0xfafad2),
+    ("LightGray", 0xd3d3d3),
+    ("LightGreen", 0x90ee90),
+    ("LightGrey", 0xd3d3d3),
+    ("LightPink", 0xffb6c1),
+    ("LightSalmon", 0xffa07a),
+    ("LightSeaGreen", 0x20b2aa),
+    ("LightSkyBlue", 0x87cefa),
+    ("LightSlateGray", 0x778899),
+    ("LightSlateGrey", 0x778899),
+    ("LightSteelBlue", 0xb0c4de),
+    ("LightYellow", 0xffffe0),
+    ("Lime", 0x00ff00),
+    ("LimeGreen", 0x32cd32),
+    ("Linen", 0xfaf0e6),
+    ("Magenta", 0xff00ff),
+    ("Maroon", 0x800000),
+    ("MediumAquamarine", 0x66cdaa),
+    ("MediumBlue", 0x0000cd),
+    ("MediumOrchid", 0xba55d3),
+    ("MediumPurple", 0x9370db),
+    ("MediumSeaGreen", 0x3cb371),
+    ("MediumSlateBlue", 0x7b68ee),
+    ("MediumSpringGreen", 0x00fa9a),
+    ("MediumTurquoise", 0x48d1cc),
+    ("MediumVioletRed", 0xc71585),
+    ("MidnightBlue", 0x191970),
+    ("MintCream", 0xf5fffa),
+    ("MistyRose", 0xffe4e1),
+    ("Moccasin", 0xffe4b5),
+    ("NavajoWhite", 0xffdead),
+    ("Navy", 0x000080),
+    ("None", -1),
+    ("Off", -1),
+    ("OldLace", 0xfdf5e6),
+    ("Olive", 0x808000),
+    ("OliveDrab", 0x6b8e23),
+    ("Orange", 0xffa500),
+    ("OrangeRed", 0xff4500),
+    ("Orchid", 0xda70d6),
+    ("PaleGoldenrod", 0xeee8aa),
+    ("PaleGreen", 0x98fb98),
+    ("PaleTurquoise", 0xafeeee),
+    ("PaleVioletRed", 0xdb7093),
+    ("PapayaWhip", 0xffefd5),
+    ("PeachPuff", 0xffdab9),
+    ("Peru", 0xcd853f),
+    ("Pink", 0xffc0cb),
+    ("Plum", 0xdda0dd),
+    ("PowderBlue", 0xb0e0e6),
+    ("Purple", 0x800080),
+    ("RebeccaPurple", 0x663399),
+    ("Red", 0xff0000),
+    ("RosyBrown", 0xbc8f8f),
+    ("RoyalBlue", 0x4169e1),
+    ("SaddleBrown", 0x8b4513),
+    ("Salmon", 0xfa8072),
+    ("SandyBrown", 0xf4a460),
+    ("SeaGreen", 0x2e8b57),
+    ("Seashell", 0xfff5ee),
+    ("Sienna", 0xa0522d),
+    ("Silver", 0xc0c0c0),
+    ("SkyBlue", 0x87ceeb),
+    ("SlateBlue", 0x6a5acd),
+    ("SlateGray", 0x708090),
+    ("SlateGrey", 0x708090),
+    ("Snow", 0xfffafa),
+    ("SpringGreen", 0x00ff7f),
+    ("SteelBlue", 0x4682b4),
+    ("Tan", 0xd2b48c),
+    ("Teal", 0x008080),
+    ("Thistle", 0xd8bfd8),
+    ("Tomato", 0xff6347),
+    ("Turquoise", 0x40e0d0),
+    ("Violet", 0xee82ee),
+    ("Wheat", 0xf5deb3),
+    ("White", 0xffffff),
+    ("WhiteSmoke", 0xf5f5f5),
+    ("Yellow", 0xffff00),
+    ("YellowGreen", 0x9acd32),
+];
+
+/// The default value of a built-in variable
+pub(crate) fn builtin(name: &str) -> Option<f64> {
+    BUILTINS
+        .binary_search_by_key(&name, |&(n, _)| n)
+        .ok()
+        .map(|idx| BUILTINS[idx].1)
+}
+
+/// Look up a colour by name, ignoring case
+pub(crate) fn color(name: &str) -> Option<(&'static str, i32)> {
+    COLORS
+        .iter()
+        .copied()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+}
+
+#[derive(Default)]
+struct Builder {
+    model: Model,
+    /// The object whose attributes are being analysed, for `this`
+    current: Option<ObjectId>,
+}
+
+impl Builder {
+    fn reference(&mut self, span: Span, target: Target, definition: bool) {
+        self.model.references.push(Reference {
+            span,
+            target,
+            definition,
+        });
+    }
+
+    /// Analyse a statement list, returning the objects it creates
+    fn statements(&mut self, statements: &[Statement], parent: Option<ObjectId>) -> Vec<ObjectId> {
+        let mut list = Vec::new();
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Direction(_) | StatementKind::Define { .. } => {}
+                StatementKind::Assignment {
+                    variable,
+                    op,
+                    value,
+                } => {
+                    let value = self.expr(value, &list);
+                    let name = variable.text.clone();
+                    let old = self.model.variables.get(&name).copied();
+                    let old = old.or_else(|| builtin(&name)).unwrap_or(0.0);
+                    let new = value.and_then(|value| match op {
+                        AssignOp::Assign => Some(value),
+                        AssignOp::Add => Some(old + value),
+                        AssignOp::Subtract => Some(old - value),
+                        AssignOp::Multiply => Some(old * value),
+                        AssignOp::Divide if value == 0.0 => None,
+                        AssignOp::Divide => Some(old / value),
+                    });
+                    match new {
+                        Some(new) => self.model.variables.insert(name.clone(), new),
+                        None => self.model.variables.remove(&name),
+                    };
+                    let first = !self
+                        .model
+                        .references
+                        .iter()
+                        .any(|r| r.definition && r.target == Target::Variable(name.clone()));
+                    self.reference(variable.span, Target::Variable(name), first);
+                }
+                StatementKind::Object(object) => {
+                    let id = self.object(statement, object, &list, parent);
+                    list.push(id);
+                }
+                StatementKind::Place(position) => {
+                    self.position(position, &list);
+                    let id = self.add(ObjectInfo {
+                        class: None,
+                        label: statement.label.clone(),
+                        span: statement.span,
+                        parent,
+                        children: Vec::new(),
+                        texts: Vec::new(),
+                    });
+                    list.push(id);
+                }
+                StatementKind::Print(items) => {
+                    for item in items {
+                        if let PrintItem::Value(expr) = item {
+                            self.expr(expr, &list);
+                        }
+                    }
+                }
+                StatementKind::Assert(Assertion::Equal(lhs, rhs)) => {
+                    self.expr(lhs, &list);
+                    self.expr(rhs, &list);
+                }
+                StatementKind::Assert(Assertion::SamePosition(lhs, rhs)) => {
+                    self.position(lhs, &list);
+                    self.position(rhs, &list);
+                }
+            }
+        }
+        list
+    }
+
+    fn add(&mut self, info: ObjectInfo) -> ObjectId {
+        let id = ObjectId(self.model.objects.len());
+        if let Some(label) = &info.label {
+            self.reference(label.span, Target::Object(id), true);
+        }
+        self.model.objects.push(info);
+        id
+    }
+
+    fn object(
+        &mut self,
+        statement: &Statement,
+        object: &Object,
+        list: &[ObjectId],
+        parent: Option<ObjectId>,
+    ) -> ObjectId {
+        let children = match &object.kind {
+            ObjectKind::Sublist(inner) => {
+                // The children need to know their parent, but the parent
+                // comes after them; fix up their parents afterwards
+                let first = self.model.objects.len();
+                let children = self.statements(inner, None);
+                let id = ObjectId(self.model.objects.len());
+                for child in &mut self.model.objects[first..] {
+                    if child.parent.is_none() {
+                        child.parent = Some(id);
+                    }
+                }
+                children
+            }
+            _ => Vec::new(),
+        };
+        let id = ObjectId(self.model.objects.len());
+        let outer = self.current.replace(id);
+        // The object is not part of the list until it is complete, but
+        // `this` needs to find it
+        self.model.objects.push(ObjectInfo {
+            class: Some(object.class_name().to_string()),
+            label: None,
+            span: statement.span,
+            parent,
+            children,
+            texts: object.texts().map(|t| t.text.clone()).collect(),
+        });
+        for attribute in &object.attributes {
+            match &attribute.kind {
+                AttributeKind::Numeric { value, .. } => {
+                    self.expr(&value.expr, list);
+                }
+                AttributeKind::Dash {
+                    spacing: Some(spacing),
+                    ..
+                } => {
+                    self.expr(spacing, list);
+                }
+                AttributeKind::Color { value, .. } => {
+                    self.expr(value, list);
+                }
+                AttributeKind::Go { distance, .. }
+                | AttributeKind::EdgeHeading { distance, .. } => {
+                    if let Some(distance) = distance {
+                        self.expr(&distance.expr, list);
+                    }
+                }
+                AttributeKind::Heading {
+                    distance, heading, ..
+                } => {
+                    if let Some(distance) = distance {
+                        self.expr(&distance.expr, list);
+                    }
+                    self.expr(heading, list);
+                }
+                AttributeKind::Even { position, .. }
+                | AttributeKind::From(position)
+                | AttributeKind::To(position)
+                | AttributeKind::At(position)
+                | AttributeKind::With { position, .. } => self.position(position, list),
+                AttributeKind::Same(Some(object)) | AttributeKind::Behind(object) => {
+                    self.object_ref(object, list);
+                }
+                _ => {}
+            }
+        }
+        self.current = outer;
+        if let Some(label) = &statement.label {
+            self.model.objects[id.0].label = Some(label.clone());
+            self.reference(label.span, Target::Object(id), true);
+        }
+        id
+    }
+
+    fn expr(&mut self, expr: &Expr, list: &[ObjectId]) -> Option<f64> {
+        let value = match &expr.kind {
+            ExprKind::Number(value) => Some(*value),
+            ExprKind::Variable(name) => {
+                let (target, value) = match self.model.variables.get(&name.text) {
+                    Some(value) => (Target::Variable(name.text.clone()), Some(*value)),
+                    None => match (builtin(&name.text), color(&name.text)) {
+                        (Some(value), _) => (Target::Variable(name.text.clone()), Some(value)),
+                        (None, Some((color, value))) => (
+                            Target::Color(color.to_string(), value),
+                            Some(f64::from(value)),
+                        ),
+                        (None, None) => (Target::Unresolved, None),
+                    },
+                };
+                self.reference(name.span, target, false);
+                value
+            }
+            ExprKind::Color(name) => match color(&name.text) {
+                Some((color, value)) => {
+                    self.reference(name.span, Target::Color(color.to_string(), value), false);
+                    Some(f64::from(value))
+                }
+                None => {
+                    self.reference(name.span, Target::Unresolved, false);
+                    None
+                }
+            },
+            ExprKind::Unary { op, operand } => {
+                let operand = self.expr(operand, list);
+                match op {
+                    UnaryOp::Plus => operand,
+                    UnaryOp::Minus => operand.map(|v| -v),
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expr(lhs, list);
+                let rhs = self.expr(rhs, list);
+                match (lhs, rhs) {
+                    (Some(lhs), Some(rhs)) => match op {
+                        BinaryOp::Add => Some(lhs + rhs),
+                        BinaryOp::Subtract => Some(lhs - rhs),
+                        BinaryOp::Multiply => Some(lhs * rhs),
+                        BinaryOp::Divide if rhs == 0.0 => None,
+                        BinaryOp::Divide => Some(lhs / rhs),
+                    },
+                    _ => None,
+                }
+            }
+            ExprKind::Call { function, args } => {
+                let args: Vec<Option<f64>> = args.iter().map(|a| self.expr(a, list)).collect();
+                let x = args.first().copied().flatten();
+                let y = args.get(1).copied().flatten();
+                match function {
+                    // The C implementation's abs() always returns zero
+                    Function::Abs => x.map(|_| 0.0),
+                    Function::Cos => x.map(f64::cos),
+                    Function::Int => x.map(f64::round_ties_even),
+                    Function::Sin => x.map(f64::sin),
+                    Function::Sqrt => x.filter(|&x| x >= 0.0).map(f64::sqrt),
+                    Function::Max => x.zip(y).map(|(x, y)| if x > y { x } else { y }),
+                    Function::Min => x.zip(y).map(|(x, y)| if x < y { x } else { y }),
+                }
+            }
+            ExprKind::Distance(from, to) => {
+                self.position(from, list);
+                self.position(to, list);
+                None
+            }
+            ExprKind::Coordinate { place, .. } => {
+                self.place(place, list);
+                None
+            }
+            ExprKind::Property { object, .. } => {
+                self.object_ref(object, list);
+                None
+            }
+        };
+        if let Some(value) = value {
+            self.model.values.insert(expr.span, value);
+        }
+        value
+    }
+
+    fn position(&mut self, position: &Position, list: &[ObjectId]) {
+        match &position.kind {
+            PositionKind::Coordinates(x, y) => {
+                self.expr(x, list);
+                self.expr(y, list);
+            }
+            PositionKind::Place(place) => self.place(place, list),
+            PositionKind::Offset { place, x, y, .. } => {
+                self.place(place, list);
+                self.expr(x, list);
+                self.expr(y, list);
+            }
+            PositionKind::Combine(x, y) => {
+                self.position(x, list);
+                self.position(y, list);
+            }
+            PositionKind::Between { fraction, from, to } => {
+                self.expr(fraction, list);
+                self.position(from, list);
+                self.position(to, list);
+            }
+            PositionKind::Relative {
+                distance, position, ..
+            }
+            | PositionKind::EdgeHeading {
+                distance, position, ..
+            } => {
+                self.expr(distance, list);
+                self.position(position, list);
+            }
+            PositionKind::Heading {
+                distance,
+                heading,
+                position,
+            } => {
+                self.expr(distance, list);
+                self.expr(heading, list);
+                self.position(position, list);
+            }
+        }
+    }
+
+    fn place(&mut self, place: &Place, list: &[ObjectId]) {
+        match &place.kind {
+            PlaceKind::Object(object)
+            | PlaceKind::Edge { object, .. }
+            | PlaceKind::Vertex { object, .. } => {
+                self.object_ref(object, list);
+            }
+        }
+    }
+
+    /// Resolve an object reference, recording references for its parts
+    fn object_ref(&mut self, object: &ObjectRef, list: &[ObjectId]) -> Option<ObjectId> {
+        let found = match &object.kind {
+            ObjectRefKind::This => {
+                let target = self.current.map_or(Target::Unresolved, Target::Object);
+                self.reference(object.span, target, false);
+                self.current
+            }
+            ObjectRefKind::Named(names) => {
+                let mut basis: Option<ObjectId> = None;
+                for (idx, name) in names.iter().enumerate() {
+                    let search = match (idx, basis) {
+                        (0, _) => list.to_vec(),
+                        (_, Some(basis)) => self.model.objects[basis.0].children.clone(),
+                        (_, None) => Vec::new(),
+                    };
+                    basis = self.by_name(&search, &name.text);
+                    let target = basis.map_or(Target::Unresolved, Target::Object);
+                    self.reference(name.span, target, false);
+                }
+                basis
+            }
+            ObjectRefKind::Nth { nth, within } => {
+                let search = match within {
+                    Some(within) => match self.object_ref(within, list) {
+                        Some(basis) => self.model.objects[basis.0].children.clone(),
+                        None => Vec::new(),
+                    },
+                    None => list.to_vec(),
+                };
+                let mut matching = search.into_iter().filter(|id| {
+                    let class = self.model.objects[id.0].class.as_deref();
+                    match &nth.class {
+                        NthClass::Any => true,
+                        NthClass::Sublist => class == Some("[]"),
+                        NthClass::Class(name) => class == Some(name.text.as_str()),
+                    }
+                });
+                let count = nth.count.max(1) as usize;
+                let found = if nth.from_end {
+                    matching.rev().nth(count - 1)
+                } else {
+                    matching.nth(count - 1)
+                };
+                let span = match within {
+                    Some(within) => Span::new(object.span.start, within.span.start),
+                    None => object.span,
+                };
+                let target = found.map_or(Target::Unresolved, Target::Object);
+                self.reference(span, target, false);
+                found
+            }
+        };
+        found
+    }
+
+    /// Find an object by label, or failing that by its text, searching
+    /// from the most recent object backwards
+    fn by_name(&self, list: &[ObjectId], name: &str) -> Option<ObjectId> {
+        let objects = &self.model.objects;
+        list.iter()
+            .rev()
+            .find(|id| matches!(&objects[id.0].label, Some(label) if label.text == name))
+            .or_else(|| {
+                list.iter()
+                    .rev()
+                    .find(|id| objects[id.0].texts.iter().any(|t| t == name))
+            })
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+
+    fn model(source: &str) -> Model {
+        Model::new(&ast::parse(source).unwrap())
+    }
+
+    fn target_at<'a>(model: &'a Model, source: &str, needle: &str) -> &'a Target {
+        &model
+            .reference_at(source.find(needle).unwrap())
+            .unwrap()
+            .target
+    }
+
+    #[test]
+    fn resolves_nth_references() {
+        let source = "box\nP: (1, 1)\ncircle\nbox\narrow from 1st box.e to last.n\nline from previous box.s to 2nd last box.n";
+        let m = model(source);
+        let object = |needle| target_at(&m, source, needle).object().unwrap().index();
+        assert_eq!(object("1st box"), 0);
+        assert_eq!(object("last.n"), 3);
+        assert_eq!(object("previous"), 3);
+        assert_eq!(object("2nd last"), 0);
+        assert_eq!(m.objects()[1].class, None);
+    }
+
+    #[test]
+    fn resolves_names_in_scope() {
+        let source = "A: box\nOuter: [\n  A: circle\n  B: box at A.n\n]\nline from A.e to Outer.A.w\nX: box \"Hello\"\narrow from Hello.s";
+        let m = model(source);
+        let object = |offset: usize| m.reference_at(offset).unwrap().target.object().unwrap();
+        let circle = object(source.find("at A").unwrap() + 3);
+        assert_eq!(m.object(circle).class.as_deref(), Some("circle"));
+        let outer_a = object(source.find("Outer.A").unwrap() + 6);
+        assert_eq!(outer_a, circle);
+        let top_a = object(source.find("from A").unwrap() + 5);
+        assert_eq!(m.object(top_a).class.as_deref(), Some("box"));
+        assert_eq!(m.object(top_a).parent, None);
+        assert_eq!(m.object(circle).parent, Some(ObjectId(3)));
+        assert_eq!(m.object(ObjectId(3)).children, [ObjectId(1), ObjectId(2)]);
+        let hello = object(source.find("Hello.s").unwrap());
+        assert_eq!(m.object(hello).label.as_ref().unwrap().text, "X");
+        assert_eq!(m.top_level().count(), 5);
+    }
+
+    #[test]
+    fn references_must_already_exist() {
+        let source = "arrow from A.e\nA: box\nbox with .n at this.s";
+        let m = model(source);
+        assert_eq!(*target_at(&m, source, "A.e"), Target::Unresolved);
+        assert_eq!(*target_at(&m, source, "this"), Target::Object(ObjectId(2)));
+    }
+
+    #[test]
+    fn computes_values() {
+        let source = "v1 = 3\nv1 *= 2\nv2 = boxwid + v1 / 4\nv3 = 1 / 0\nv4 = max(v1, 10) - int(2.5)\nfill = Red\nv5 = A.x\nv6 = abs(-1)";
+        let m = model(source);
+        assert_eq!(m.variable("v1"), Some(6.0));
+        assert_eq!(m.variable("v2"), Some(2.25));
+        assert_eq!(m.variable("v3"), None);
+        assert_eq!(m.variable("v4"), Some(8.0));
+        assert_eq!(m.variable("fill"), Some(f64::from(0xff0000)));
+        assert_eq!(m.variable("v5"), None);
+        assert_eq!(m.variable("v6"), Some(0.0));
+        assert_eq!(m.variable("boxht"), Some(0.5));
+        assert_eq!(
+            *target_at(&m, source, "Red"),
+            Target::Color("Red".to_string(), 0xff0000)
+        );
+        assert_eq!(*target_at(&m, source, "A.x"), Target::Unresolved);
+    }
+
+    #[test]
+    fn finds_definitions_and_references() {
+        let source = "w1 = 1\nA: box wid w1\nw1 = 2\narrow from A.e right w1";
+        let m = model(source);
+        let var = Target::Variable("w1".to_string());
+        assert_eq!(m.definition(&var), Some(Span::new(0, 2)));
+        assert_eq!(m.references_to(&var).count(), 4);
+        assert_eq!(m.references_to(&var).filter(|r| r.definition).count(), 1);
+        let a = target_at(&m, source, "A.e").clone();
+        let spans: Vec<Span> = m.references_to(&a).map(|r| r.span).collect();
+        assert_eq!(spans, [Span::new(7, 8), Span::new(39, 40)]);
+    }
+}
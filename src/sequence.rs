@@ -0,0 +1,275 @@
+//! Sequence diagram helper
+//!
+//! Pikchr is perfectly capable of drawing sequence diagrams, but laying out
+//! the participants, lifelines and messages by hand is laborious.  The
+//! [`Sequence`] type lets you describe the diagram in terms of participants
+//! and the messages between them, and generates the pikchr source for you.
+//!
+//! ```
+//! use pikchr::PikchrFlags;
+//! use pikchr::sequence::Sequence;
+//!
+//! let pic = Sequence::new()
+//!     .participant("Client")
+//!     .participant("Server")
+//!     .message("Client", "Server", "GET /")
+//!     .reply("Server", "Client", "200 OK")
+//!     .render(None, PikchrFlags::default())
+//!     .unwrap();
+//! assert!(pic.contains("GET"));
+//! ```
+
+use crate::source::quote;
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::fmt::Write;
+
+/// Approximate width of a character, matching pikchr's `charwid`
+const CHAR_WIDTH: f64 = 0.08;
+/// Height of the participant boxes
+const BOX_HEIGHT: f64 = 0.4;
+/// Vertical distance between successive messages
+const MESSAGE_GAP: f64 = 0.4;
+/// Horizontal extent of a message from a participant to itself
+const SELF_WIDTH: f64 = 0.3;
+
+#[derive(Clone, Debug)]
+enum Step {
+    Message {
+        from: usize,
+        to: usize,
+        text: String,
+        reply: bool,
+    },
+}
+
+/// A sequence diagram under construction
+///
+/// Participants are laid out left to right in the order in which they are
+/// first mentioned, either by [`Sequence::participant`] or by a message.
+/// Messages are drawn top to bottom in the order they are added.
+#[derive(Clone, Debug, Default)]
+pub struct Sequence {
+    participants: Vec<String>,
+    steps: Vec<Step>,
+}
+
+impl Sequence {
+    /// Create a new, empty, sequence diagram
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn index_of(&mut self, name: &str) -> usize {
+        match self.participants.iter().position(|p| p == name) {
+            Some(idx) => idx,
+            None => {
+                self.participants.push(name.to_string());
+                self.participants.len() - 1
+            }
+        }
+    }
+
+    /// Add a participant to the diagram
+    ///
+    /// Adding a participant which is already present has no effect.
+    ///
+    /// ```
+    /// # use pikchr::sequence::Sequence;
+    /// let mut seq = Sequence::new();
+    /// seq.participant("Alice").participant("Bob").participant("Alice");
+    /// assert_eq!(seq.participants().len(), 2);
+    /// ```
+    pub fn participant(&mut self, name: &str) -> &mut Sequence {
+        self.index_of(name);
+        self
+    }
+
+    /// Add a message from one participant to another
+    ///
+    /// If either participant is not yet known, it is added.  A participant
+    /// may send a message to itself.
+    ///
+    /// ```
+    /// # use pikchr::sequence::Sequence;
+    /// let source = Sequence::new().message("A", "B", "hello").to_source();
+    /// assert!(source.contains("\"hello\""));
+    /// ```
+    pub fn message(&mut self, from: &str, to: &str, text: &str) -> &mut Sequence {
+        self.step(from, to, text, false)
+    }
+
+    /// Add a reply from one participant to another
+    ///
+    /// Replies are drawn with dashed lines, otherwise they behave exactly as
+    /// [`Sequence::message`] does.
+    pub fn reply(&mut self, from: &str, to: &str, text: &str) -> &mut Sequence {
+        self.step(from, to, text, true)
+    }
+
+    fn step(&mut self, from: &str, to: &str, text: &str, reply: bool) -> &mut Sequence {
+        let from = self.index_of(from);
+        let to = self.index_of(to);
+        self.steps.push(Step::Message {
+            from,
+            to,
+            text: text.to_string(),
+            reply,
+        });
+        self
+    }
+
+    /// The participants in the diagram, in left-to-right order
+    pub fn participants(&self) -> &[String] {
+        &self.participants
+    }
+
+    /// Compute the horizontal distance between neighbouring lifelines
+    ///
+    /// This must be wide enough for the participant boxes and for the
+    /// labels of any messages between neighbouring participants.
+    fn spacing(&self) -> f64 {
+        let text_width = |s: &str| s.chars().count() as f64 * CHAR_WIDTH;
+        let widest = self
+            .participants
+            .iter()
+            .map(|p| text_width(p) + 0.3)
+            .fold(0.75, f64::max);
+        self.steps
+            .iter()
+            .map(|Step::Message { from, to, text, .. }| {
+                let span = (*from as f64 - *to as f64).abs().max(1.0);
+                (text_width(text) + 0.3) / span
+            })
+            .fold(widest + 0.25, f64::max)
+    }
+
+    /// Generate the pikchr source for this sequence diagram
+    ///
+    /// ```
+    /// # use pikchr::sequence::Sequence;
+    /// let source = Sequence::new()
+    ///     .participant("A")
+    ///     .message("A", "A", "think")
+    ///     .to_source();
+    /// assert!(source.starts_with("P1: box"));
+    /// ```
+    pub fn to_source(&self) -> String {
+        let spacing = self.spacing();
+        let width = self
+            .participants
+            .iter()
+            .map(|p| p.chars().count() as f64 * CHAR_WIDTH + 0.3)
+            .fold(0.75, f64::max);
+        let mut out = String::new();
+
+        for (idx, name) in self.participants.iter().enumerate() {
+            writeln!(
+                out,
+                "P{}: box {} wid {:.3} ht {} at ({:.3}, 0)",
+                idx + 1,
+                quote(name),
+                width,
+                BOX_HEIGHT,
+                idx as f64 * spacing
+            )
+            .unwrap();
+        }
+
+        let mut y = -(BOX_HEIGHT / 2.0);
+        for step in &self.steps {
+            let Step::Message {
+                from,
+                to,
+                text,
+                reply,
+            } = step;
+            y -= MESSAGE_GAP;
+            let style = if *reply { " dashed" } else { "" };
+            let (from, to) = (from + 1, to + 1);
+            if from == to {
+                writeln!(
+                    out,
+                    "arrow from (P{p}.x, {y:.3}) right {w} then down {h} then left {w}{s}",
+                    p = from,
+                    y = y,
+                    w = SELF_WIDTH,
+                    h = MESSAGE_GAP / 2.0,
+                    s = style
+                )
+                .unwrap();
+                writeln!(
+                    out,
+                    "text {} ljust at (P{}.x + {:.3}, {:.3})",
+                    quote(text),
+                    from,
+                    SELF_WIDTH + 0.05,
+                    y - MESSAGE_GAP / 4.0
+                )
+                .unwrap();
+                y -= MESSAGE_GAP / 2.0;
+            } else {
+                writeln!(
+                    out,
+                    "arrow from (P{f}.x, {y:.3}) to (P{t}.x, {y:.3}){s} {l} above",
+                    f = from,
+                    t = to,
+                    y = y,
+                    s = style,
+                    l = quote(text)
+                )
+                .unwrap();
+            }
+        }
+
+        let bottom = y - MESSAGE_GAP;
+        for idx in 1..=self.participants.len() {
+            writeln!(
+                out,
+                "line dashed color gray from P{i}.s to (P{i}.x, {b:.3})",
+                i = idx,
+                b = bottom
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Render the sequence diagram
+    ///
+    /// This is a convenience wrapper which passes the result of
+    /// [`Sequence::to_source`] to [`Pikchr::render`].
+    pub fn render(&self, class: Option<&str>, flags: PikchrFlags) -> Result<Pikchr, PikchrError> {
+        Pikchr::render(&self.to_source(), class, flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_all_kinds_of_message() {
+        let mut seq = Sequence::new();
+        seq.participant("Browser")
+            .message("Browser", "Server", "POST \"/login\"")
+            .message("Server", "Database", "SELECT")
+            .reply("Database", "Server", "row")
+            .message("Server", "Server", "hash password")
+            .reply("Server", "Browser", "302 Found");
+        assert_eq!(seq.participants(), ["Browser", "Server", "Database"]);
+        let source = seq.to_source();
+        if let Err(e) = seq.render(None, PikchrFlags::default()) {
+            panic!("{}\n{}", source, e);
+        }
+        assert_eq!(source.matches("dashed").count(), 2 + 3);
+    }
+
+    #[test]
+    fn long_labels_widen_the_spacing() {
+        let mut narrow = Sequence::new();
+        narrow.message("A", "B", "x");
+        let mut wide = Sequence::new();
+        wide.message("A", "B", "a rather long message label");
+        assert!(wide.spacing() > narrow.spacing());
+    }
+}
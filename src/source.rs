@@ -0,0 +1,56 @@
+//! Helpers for generating pikchr source text
+
+/// Quote some text as a pikchr string literal
+pub(crate) fn quote(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len() + 2);
+    ret.push('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret.push('"');
+    ret
+}
+
+/// Generate the path of a connection between two objects in a single row
+///
+/// The objects are assumed to be laid out left-to-right and named by
+/// `prefix` followed by their (1-based) position in the row.  Neighbours
+/// are joined directly, anything else is routed around the row.  The `nth`
+/// parameter is the index of the connection being drawn, and is used to
+/// alternate routes above and below the row and to stagger them so that
+/// they do not overlap.  Connections from an object to itself are drawn as
+/// a small loop above the object.
+pub(crate) fn row_route(prefix: &str, from: usize, to: usize, nth: usize) -> String {
+    let (f, t) = (from + 1, to + 1);
+    if to == from + 1 {
+        format!("from {p}{f}.e to {p}{t}.w", p = prefix, f = f, t = t)
+    } else if from == to + 1 {
+        format!("from {p}{f}.w to {p}{t}.e", p = prefix, f = f, t = t)
+    } else if from == to {
+        format!(
+            "from ({p}{f}.x - 0.1, {p}{f}.n.y) up 0.2 then right 0.2 then down until even with {p}{f}.n",
+            p = prefix,
+            f = f
+        )
+    } else {
+        let (edge, vert) = match nth % 2 {
+            0 => ("n", "up"),
+            _ => ("s", "down"),
+        };
+        let horiz = if to > from { "right" } else { "left" };
+        let rise = 0.25 + 0.1 * (nth / 2) as f64;
+        format!(
+            "from {p}{f}.{e} {v} {r:.2} then {h} until even with {p}{t}.{e} then to {p}{t}.{e}",
+            p = prefix,
+            f = f,
+            t = t,
+            e = edge,
+            v = vert,
+            h = horiz,
+            r = rise
+        )
+    }
+}
@@ -0,0 +1,209 @@
+//! Which statements drew which parts of a diagram
+//!
+//! pikchr draws every object as elements directly inside the `<svg>`, in
+//! the order of the statements which made them.  A [`SourceMap`] records
+//! which of those elements each top-level statement drew, so that output
+//! can be changed statement by statement, such as by
+//! [`Pikchr::render_steps`](crate::Pikchr::render_steps).
+//!
+//! ```
+//! use pikchr::source_map::SourceMap;
+//!
+//! let source = "boxwid = 1\nbox \"a\"\narrow";
+//! let map = SourceMap::new(source).unwrap();
+//! let drawn: Vec<_> = map
+//!     .statements()
+//!     .iter()
+//!     .map(|s| (&source[s.span.start..s.span.end], s.elements.clone()))
+//!     .collect();
+//! assert_eq!(drawn, [("boxwid = 1", 0..0), ("box \"a\"", 0..2), ("arrow", 2..4)]);
+//! ```
+//!
+//! Neither backend reports where its output came from, so the map is made
+//! by rendering the source up to the end of each statement in turn and
+//! counting the elements.  Statements which come from a macro are part of
+//! the statement which called the macro.
+
+use crate::ast::{self, Span};
+use crate::svg::{Dom, Element, Node};
+use crate::{Pikchr, PikchrFlags};
+use std::ops::Range;
+
+/// The elements drawn by each top-level statement of a diagram
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    statements: Vec<Drawn>,
+}
+
+/// The elements drawn by a top-level statement
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Drawn {
+    /// Where the statement is in the source
+    pub span: Span,
+    /// The positions of the elements among those directly inside the
+    /// `<svg>`, which is empty for statements which draw nothing
+    pub elements: Range<usize>,
+}
+
+impl SourceMap {
+    /// Map the statements of some pikchr source to the elements they draw
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the source does not parse or render.
+    pub fn new(source: &str) -> Result<SourceMap, String> {
+        let flags = PikchrFlags::default();
+        let total = count(&Pikchr::render(source, None, flags)?)?;
+        let document = ast::parse(source).map_err(|e| e.to_string())?;
+
+        let mut spans: Vec<Span> = Vec::new();
+        for statement in &document.statements {
+            let span = statement.macro_call.unwrap_or(statement.span);
+            match spans.last_mut() {
+                // The rest of a macro's statements
+                Some(last) if last.end >= span.end => {}
+                _ => spans.push(span),
+            }
+        }
+
+        let mut statements = Vec::with_capacity(spans.len());
+        let mut drawn = 0;
+        for (i, span) in spans.iter().enumerate() {
+            let upto = if i + 1 == spans.len() {
+                total
+            } else {
+                // Source up to a statement which draws nothing may not
+                // render at all, for example when it has no objects yet
+                match Pikchr::render(&source[..span.end], None, flags) {
+                    Ok(pic) => count(&pic)?.clamp(drawn, total),
+                    Err(_) => drawn,
+                }
+            };
+            statements.push(Drawn {
+                span: *span,
+                elements: drawn..upto,
+            });
+            drawn = upto;
+        }
+        Ok(SourceMap { statements })
+    }
+
+    /// The top-level statements, in order
+    pub fn statements(&self) -> &[Drawn] {
+        &self.statements
+    }
+
+    /// The statement which drew an element, by its position among those
+    /// directly inside the `<svg>`
+    pub fn statement_of(&self, element: usize) -> Option<&Drawn> {
+        self.statements
+            .iter()
+            .find(|s| s.elements.contains(&element))
+    }
+
+    /// Wrap the elements drawn by each statement in a
+    /// `<g data-step="N">`, numbering the statements which draw something
+    /// from 1
+    ///
+    /// The `dom` should be the diagram which the map was made from.
+    pub fn group_steps(&self, dom: &mut Dom) {
+        let svg = match dom.svg_mut() {
+            Some(svg) => svg,
+            None => return,
+        };
+        let mut children = Vec::new();
+        let mut group: Option<(usize, Element)> = None;
+        let mut element = 0;
+        for node in std::mem::take(&mut svg.children) {
+            if let Node::Element(_) = node {
+                let step = self
+                    .statements
+                    .iter()
+                    .filter(|s| !s.elements.is_empty())
+                    .position(|s| s.elements.contains(&element))
+                    .map_or(0, |i| i + 1);
+                element += 1;
+                if group.as_ref().map(|(n, _)| *n) != Some(step) {
+                    if let Some((_, g)) = group.take() {
+                        children.push(Node::Element(g));
+                    }
+                    let mut g = Element::new("g");
+                    g.set_attr("data-step", &step.to_string());
+                    group = Some((step, g));
+                }
+            }
+            match &mut group {
+                Some((_, g)) => g.children.push(node),
+                None => children.push(node),
+            }
+        }
+        if let Some((_, g)) = group {
+            children.push(Node::Element(g));
+        }
+        svg.children = children;
+    }
+}
+
+/// The number of elements directly inside the `<svg>`
+fn count(pic: &Pikchr) -> Result<usize, String> {
+    Ok(pic.dom()?.svg().map_or(0, |svg| svg.elements().count()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elements(source: &str) -> Vec<(&str, Range<usize>)> {
+        SourceMap::new(source)
+            .unwrap()
+            .statements()
+            .iter()
+            .map(|s| (&source[s.span.start..s.span.end], s.elements.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn maps_statements() {
+        assert_eq!(
+            elements("print \"x\"\nA: circle\n[box; box]\nline from A.s down"),
+            [
+                ("print \"x\"", 0..0),
+                ("A: circle", 0..1),
+                ("[box; box]", 1..3),
+                ("line from A.s down", 3..4),
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_macro_calls() {
+        assert_eq!(
+            elements("define pair { box; arrow }\npair()\ncircle"),
+            [
+                ("define pair { box; arrow }", 0..0),
+                ("pair()", 0..3),
+                ("circle", 3..4),
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_steps() {
+        let source = "box \"a\"; $x = 1; arrow";
+        let pic = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+        let mut dom = pic.dom().unwrap();
+        SourceMap::new(source).unwrap().group_steps(&mut dom);
+        let svg = dom.svg().unwrap();
+        let groups: Vec<_> = svg
+            .elements()
+            .map(|g| {
+                let names: Vec<_> = g.elements().map(|e| e.name.as_str()).collect();
+                (g.attr("data-step").unwrap(), names)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [("1", vec!["path", "text"]), ("2", vec!["polygon", "path"])]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! State machine diagrams
+//!
+//! A [`StateMachine`] describes a set of states and the transitions between
+//! them, and can generate pikchr source which draws them as a state
+//! diagram.  States are drawn left to right in the order they are declared,
+//! with a filled dot marking the initial state.
+//!
+//! ```
+//! use pikchr::state::StateMachine;
+//!
+//! let source = StateMachine::new()
+//!     .initial("Idle")
+//!     .transition("Idle", "Running", Some("start"))
+//!     .transition("Running", "Idle", Some("stop"))
+//!     .to_source();
+//! assert!(source.contains("\"Running\""));
+//! ```
+//!
+//! If the `derive` feature is enabled then the `PikchrStateDiagram` derive
+//! macro can generate a [`StateDiagram`] implementation directly from an
+//! enum, so that the code and its diagram can never drift apart.
+
+use crate::source::{quote, row_route};
+use crate::{Pikchr, PikchrError, PikchrFlags};
+use std::fmt::Write;
+
+#[derive(Clone, Debug)]
+struct Transition {
+    from: usize,
+    to: usize,
+    label: Option<String>,
+}
+
+/// A state machine which can be drawn as a pikchr diagram
+#[derive(Clone, Debug, Default)]
+pub struct StateMachine {
+    states: Vec<String>,
+    initial: Option<usize>,
+    transitions: Vec<Transition>,
+}
+
+impl StateMachine {
+    /// Create a new state machine with no states
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn index_of(&mut self, name: &str) -> usize {
+        match self.states.iter().position(|s| s == name) {
+            Some(idx) => idx,
+            None => {
+                self.states.push(name.to_string());
+                self.states.len() - 1
+            }
+        }
+    }
+
+    /// Add a state
+    ///
+    /// States are also added implicitly when they are first mentioned by
+    /// [`StateMachine::initial`] or [`StateMachine::transition`], so this is
+    /// only needed to control the order of states, or for states which have
+    /// no transitions at all.
+    pub fn state(&mut self, name: &str) -> &mut StateMachine {
+        self.index_of(name);
+        self
+    }
+
+    /// Mark a state as the initial state
+    pub fn initial(&mut self, name: &str) -> &mut StateMachine {
+        self.initial = Some(self.index_of(name));
+        self
+    }
+
+    /// Add a transition between two states, with an optional label
+    pub fn transition(&mut self, from: &str, to: &str, label: Option<&str>) -> &mut StateMachine {
+        let from = self.index_of(from);
+        let to = self.index_of(to);
+        self.transitions.push(Transition {
+            from,
+            to,
+            label: label.map(str::to_string),
+        });
+        self
+    }
+
+    /// The states in the machine, in the order they will be drawn
+    ///
+    /// ```
+    /// # use pikchr::state::StateMachine;
+    /// let mut machine = StateMachine::new();
+    /// machine.state("A").transition("B", "A", None);
+    /// assert_eq!(machine.states(), ["A", "B"]);
+    /// ```
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+
+    /// Generate the pikchr source for this state machine
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        if let Some(initial) = self.initial {
+            if initial == 0 {
+                out.push_str("dot rad 0.05\narrow 0.3\n");
+            }
+        }
+        for (idx, state) in self.states.iter().enumerate() {
+            if idx > 0 {
+                out.push_str("move 0.6\n");
+            }
+            writeln!(out, "S{}: box rad 0.15 {} fit", idx + 1, quote(state)).unwrap();
+        }
+        if let Some(initial) = self.initial.filter(|&i| i > 0) {
+            writeln!(
+                out,
+                "dot rad 0.05 at S{i}.s + (0, -0.4)\narrow from last dot to S{i}.s",
+                i = initial + 1
+            )
+            .unwrap();
+        }
+        for (nth, transition) in self.transitions.iter().enumerate() {
+            write!(
+                out,
+                "arrow {}",
+                row_route("S", transition.from, transition.to, nth)
+            )
+            .unwrap();
+            if let Some(label) = &transition.label {
+                write!(out, " {} above", quote(label)).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Render the state machine
+    ///
+    /// This is a convenience wrapper which passes the result of
+    /// [`StateMachine::to_source`] to [`Pikchr::render`].
+    pub fn render(&self, class: Option<&str>, flags: PikchrFlags) -> Result<Pikchr, PikchrError> {
+        Pikchr::render(&self.to_source(), class, flags)
+    }
+}
+
+/// Types which describe a state machine
+///
+/// This is normally implemented with the `PikchrStateDiagram` derive macro
+/// (enabled by the `derive` feature) rather than by hand.
+pub trait StateDiagram {
+    /// Describe the states and transitions of this state machine
+    fn state_machine() -> StateMachine;
+
+    /// Generate pikchr source for this state machine
+    fn pikchr_source() -> String {
+        Self::state_machine().to_source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_all_kinds_of_transition() {
+        let mut machine = StateMachine::new();
+        machine
+            .state("Idle")
+            .initial("Loading")
+            .transition("Idle", "Loading", Some("fetch"))
+            .transition("Loading", "Ready", None)
+            .transition("Ready", "Idle", Some("reset"))
+            .transition("Loading", "Loading", Some("retry"))
+            .transition("Loading", "Idle", Some("cancel"));
+        let source = machine.to_source();
+        if let Err(e) = machine.render(None, PikchrFlags::default()) {
+            panic!("{}\n{}", source, e);
+        }
+        assert!(source.contains("arrow from last dot to S2.s"));
+    }
+
+    struct Light;
+
+    impl StateDiagram for Light {
+        fn state_machine() -> StateMachine {
+            let mut machine = StateMachine::new();
+            machine
+                .initial("Off")
+                .transition("Off", "On", Some("flick"));
+            machine
+        }
+    }
+
+    #[test]
+    fn trait_provides_source() {
+        assert!(Light::pikchr_source().starts_with("dot rad 0.05\narrow 0.3\nS1: box"));
+    }
+}
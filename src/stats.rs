@@ -0,0 +1,76 @@
+//! Statistics about renders
+//!
+//! A service which renders diagrams from its users needs to know how much
+//! memory rendering takes, to plan its capacity and to spot diagrams which
+//! use far more than others.  With the `rust-alloc` feature, the C library
+//! allocates through the Rust allocator, and
+//! [`Pikchr::render_with_stats`] reports what each render allocated.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let (pic, stats) = Pikchr::render_with_stats("box; arrow; circle", None, PikchrFlags::default())
+//!     .unwrap();
+//! assert!(pic.contains("<circle"));
+//! assert!(stats.peak_bytes() > 0);
+//! assert!(stats.total_bytes() >= stats.peak_bytes());
+//! ```
+//!
+//! Only the memory which the C library allocates is counted, not that of
+//! the Rust code around it.
+//!
+//! [`Pikchr::render_with_stats`]: crate::Pikchr::render_with_stats
+
+use crate::raw::alloc::AllocStats;
+
+/// What a render allocated
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    alloc: AllocStats,
+}
+
+impl RenderStats {
+    pub(crate) fn new(alloc: AllocStats) -> RenderStats {
+        RenderStats { alloc }
+    }
+
+    /// The most memory which was allocated at any one time, in bytes
+    pub fn peak_bytes(&self) -> usize {
+        self.alloc.peak
+    }
+
+    /// All the memory which was allocated, in bytes
+    ///
+    /// Growing an allocation counts only the bytes it grew by.
+    pub fn total_bytes(&self) -> usize {
+        self.alloc.total
+    }
+
+    /// The number of times memory was allocated or grown
+    pub fn allocations(&self) -> usize {
+        self.alloc.allocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn grows_with_the_diagram() {
+        let render = |source: &str| {
+            Pikchr::render_with_stats(source, None, PikchrFlags::default())
+                .unwrap()
+                .1
+        };
+        let small = render("box");
+        let large = render(&"box; arrow\n".repeat(200));
+        assert!(large.peak_bytes() > small.peak_bytes());
+        assert!(large.allocations() > small.allocations());
+        // The same source allocates the same each time
+        assert_eq!(render("box"), small);
+        // Errors are not counted, and leave nothing allocated
+        assert!(Pikchr::render_with_stats("box wid", None, PikchrFlags::default()).is_err());
+        assert_eq!(render("box"), small);
+    }
+}
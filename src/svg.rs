@@ -0,0 +1,589 @@
+//! A lightweight tree of rendered SVG
+//!
+//! [`Dom::parse`] reads the markup which pikchr produces into a tree of
+//! [`Element`]s which can be inspected and changed, and the [`Dom`]
+//! displays as markup again.  [`Pikchr::dom`](crate::Pikchr::dom) parses a
+//! rendered diagram.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box \"Hello\"", None, PikchrFlags::default()).unwrap();
+//! let mut dom = pic.dom().unwrap();
+//! let svg = dom.svg_mut().unwrap();
+//! svg.set_attr("class", "diagram");
+//! svg.for_each_mut(&mut |element| {
+//!     if element.name == "text" {
+//!         element.set_attr("font-family", "serif");
+//!     }
+//! });
+//! let out = dom.to_string();
+//! assert!(out.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+//! assert!(out.contains(" font-family=\"serif\">Hello</text>"));
+//! ```
+//!
+//! Text and attribute values are kept as they appear in the markup, with
+//! any entities left as they are, since pikchr passes HTML entities such
+//! as `&rarr;` in labels through unchanged.  Use [`escape`] for values
+//! which may contain `<`, `&` or `"`.  Displaying the tree reproduces the
+//! text between tags exactly, but normalizes the tags themselves:
+//! attributes are separated by single spaces and quoted with `"` unless
+//! the value contains one.
+
+use std::fmt;
+
+/// A parsed SVG document
+///
+/// The SVG is usually the only element, but any output of `print`
+/// statements comes before it as text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dom {
+    /// The nodes at the top level of the document
+    pub nodes: Vec<Node>,
+}
+
+/// A node in the tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    /// An element, such as `<path .../>`
+    Element(Element),
+    /// Text between tags, as it appears in the markup
+    Text(String),
+    /// A comment, without the `<!--` and `-->`
+    Comment(String),
+}
+
+/// An element and its contents
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Element {
+    /// The tag name, such as `path`
+    pub name: String,
+    /// The attributes in order, with values as they appear in the markup
+    pub attributes: Vec<(String, String)>,
+    /// The nodes inside the element
+    pub children: Vec<Node>,
+}
+
+/// A rectangle in the coordinates of the SVG
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    /// The left edge
+    pub x: f64,
+    /// The top edge
+    pub y: f64,
+    /// The width, which is zero for a vertical line or a point
+    pub width: f64,
+    /// The height, which is zero for a horizontal line or a point
+    pub height: f64,
+}
+
+impl Rect {
+    /// The smallest rectangle containing some points
+    pub(crate) fn around(points: &[(f64, f64)]) -> Option<Rect> {
+        let (first, rest) = points.split_first()?;
+        let (mut x0, mut y0, mut x1, mut y1) = (first.0, first.1, first.0, first.1);
+        for &(x, y) in rest {
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+        }
+        Some(Rect {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        })
+    }
+
+    /// The smallest rectangle containing both rectangles
+    pub(crate) fn union(self, other: Rect) -> Rect {
+        Rect::around(&[
+            (self.x, self.y),
+            (self.x + self.width, self.y + self.height),
+            (other.x, other.y),
+            (other.x + other.width, other.y + other.height),
+        ])
+        .unwrap()
+    }
+
+    /// Whether a point is inside the rectangle or on its edge
+    pub(crate) fn contains(&self, x: f64, y: f64) -> bool {
+        (self.x..=self.x + self.width).contains(&x) && (self.y..=self.y + self.height).contains(&y)
+    }
+
+    /// Whether two rectangles overlap or touch
+    pub(crate) fn intersects(&self, other: &Rect) -> bool {
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+}
+
+impl Dom {
+    /// Parse SVG markup
+    ///
+    /// This understands the subset of XML which pikchr produces: elements,
+    /// attributes, text and comments, along with the HTML `<br>` which
+    /// ends each line of `print` output.
+    ///
+    /// # Errors
+    ///
+    /// It is an error if the markup is not well formed or uses other XML,
+    /// such as a `<!DOCTYPE>` or `<?xml?>` declaration.
+    pub fn parse(markup: &str) -> Result<Dom, String> {
+        let mut parser = Parser { markup, pos: 0 };
+        let nodes = parser.nodes(None)?;
+        Ok(Dom { nodes })
+    }
+
+    /// The `<svg>` element
+    pub fn svg(&self) -> Option<&Element> {
+        self.nodes.iter().find_map(|node| match node {
+            Node::Element(element) if element.name == "svg" => Some(element),
+            _ => None,
+        })
+    }
+
+    /// The `<svg>` element, for changing
+    pub fn svg_mut(&mut self) -> Option<&mut Element> {
+        self.nodes.iter_mut().find_map(|node| match node {
+            Node::Element(element) if element.name == "svg" => Some(element),
+            _ => None,
+        })
+    }
+
+    /// The `viewBox` of the `<svg>` element
+    pub fn view_box(&self) -> Option<Rect> {
+        match numbers(self.svg()?.attr("viewBox")?)[..] {
+            [x, y, width, height] => Some(Rect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Element {
+    /// Make an element with no attributes or children
+    pub fn new(name: &str) -> Element {
+        Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// The value of an attribute
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set an attribute, replacing any existing value or adding it last
+    ///
+    /// The value is used as it is; see [`escape`].
+    pub fn set_attr(&mut self, name: &str, value: &str) -> &mut Element {
+        match self.attributes.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.attributes.push((name.to_string(), value.to_string())),
+        }
+        self
+    }
+
+    /// Remove an attribute, returning its value if it was set
+    pub fn remove_attr(&mut self, name: &str) -> Option<String> {
+        let idx = self.attributes.iter().position(|(n, _)| n == name)?;
+        Some(self.attributes.remove(idx).1)
+    }
+
+    /// The elements directly inside this one
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// The elements directly inside this one, for changing
+    pub fn elements_mut(&mut self) -> impl Iterator<Item = &mut Element> {
+        self.children.iter_mut().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// This element and every element inside it with a tag name, in
+    /// document order
+    ///
+    /// ```
+    /// # use pikchr::svg::Dom;
+    /// let dom = Dom::parse("<svg><g><path/></g><path/></svg>").unwrap();
+    /// assert_eq!(dom.svg().unwrap().find_all("path").len(), 2);
+    /// ```
+    pub fn find_all(&self, name: &str) -> Vec<&Element> {
+        let mut found = Vec::new();
+        self.collect(name, &mut found);
+        found
+    }
+
+    fn collect<'a>(&'a self, name: &str, found: &mut Vec<&'a Element>) {
+        if self.name == name {
+            found.push(self);
+        }
+        for element in self.elements() {
+            element.collect(name, found);
+        }
+    }
+
+    /// Call a function with this element and every element inside it, in
+    /// document order
+    pub fn for_each_mut(&mut self, f: &mut dyn FnMut(&mut Element)) {
+        f(self);
+        for element in self.elements_mut() {
+            element.for_each_mut(f);
+        }
+    }
+
+    /// The text inside this element and the elements inside it, as it
+    /// appears in the markup
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.children {
+            match node {
+                Node::Text(t) => text.push_str(t),
+                Node::Element(element) => text.push_str(&element.text()),
+                Node::Comment(_) => {}
+            }
+        }
+        text
+    }
+
+    /// The points of a `<path>`, `<polygon>` or `<polyline>`
+    ///
+    /// Paths are read as the absolute commands which pikchr uses, and only
+    /// the end point of each arc is included.
+    pub fn points(&self) -> Vec<(f64, f64)> {
+        match self.name.as_str() {
+            "path" => path_points(self.attr("d").unwrap_or("")),
+            "polygon" | "polyline" => numbers(self.attr("points").unwrap_or(""))
+                .chunks_exact(2)
+                .map(|p| (p[0], p[1]))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The bounds of the element, worked out from its coordinates
+    ///
+    /// Paths include the control points of their curves, and the bounds of
+    /// other elements with an `x` and `y`, such as `<text>`, are a single
+    /// point.
+    ///
+    /// ```
+    /// # use pikchr::svg::Dom;
+    /// let dom = Dom::parse("<svg><circle cx=\"10\" cy=\"20\" r=\"5\"/></svg>").unwrap();
+    /// let circle = dom.svg().unwrap().elements().next().unwrap();
+    /// assert_eq!(circle.bounds().map(|b| (b.x, b.y, b.width)), Some((5.0, 15.0, 10.0)));
+    /// ```
+    pub fn bounds(&self) -> Option<Rect> {
+        let number = |name| self.attr(name).and_then(|v| v.trim().parse::<f64>().ok());
+        match self.name.as_str() {
+            "circle" | "ellipse" => {
+                let (cx, cy) = (number("cx")?, number("cy")?);
+                let rx = number("rx").or_else(|| number("r"))?;
+                let ry = number("ry").or_else(|| number("r"))?;
+                Some(Rect {
+                    x: cx - rx,
+                    y: cy - ry,
+                    width: 2.0 * rx,
+                    height: 2.0 * ry,
+                })
+            }
+            "rect" => Some(Rect {
+                x: number("x")?,
+                y: number("y")?,
+                width: number("width")?,
+                height: number("height")?,
+            }),
+            "path" | "polygon" | "polyline" => Rect::around(&self.points()),
+            _ => Rect::around(&[(number("x")?, number("y")?)]),
+        }
+    }
+}
+
+/// Escape text for use as SVG text or as an attribute value
+///
+/// ```
+/// assert_eq!(pikchr::svg::escape("a < \"b\" & c"), "a &lt; &quot;b&quot; &amp; c");
+/// ```
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl fmt::Display for Dom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Element(element) => write!(f, "{}", element),
+            Node::Text(text) => f.write_str(text),
+            Node::Comment(text) => write!(f, "<!--{}-->", text),
+        }
+    }
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}", self.name)?;
+        for (name, value) in &self.attributes {
+            let quote = if value.contains('"') { '\'' } else { '"' };
+            write!(f, " {}={}{}{}", name, quote, value, quote)?;
+        }
+        if self.children.is_empty() {
+            return f.write_str("/>");
+        }
+        f.write_str(">")?;
+        for node in &self.children {
+            write!(f, "{}", node)?;
+        }
+        write!(f, "</{}>", self.name)
+    }
+}
+
+/// The numbers in an attribute, ignoring any letters and separators
+fn numbers(value: &str) -> Vec<f64> {
+    value
+        .split(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | 'e')))
+        .filter_map(|n| n.parse().ok())
+        .collect()
+}
+
+/// The points of the absolute path commands which pikchr uses
+fn path_points(d: &str) -> Vec<(f64, f64)> {
+    let mut points = Vec::new();
+    let mut rest = d;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic()) {
+        let command = rest[start..].chars().next().unwrap();
+        let args = &rest[start + 1..];
+        let end = args
+            .find(|c: char| c.is_ascii_alphabetic() && c != 'e')
+            .unwrap_or(args.len());
+        let args = numbers(&args[..end]);
+        if command == 'A' {
+            // Only the last two of each seven arguments are a point
+            for arc in args.chunks_exact(7) {
+                points.push((arc[5], arc[6]));
+            }
+        } else {
+            points.extend(args.chunks_exact(2).map(|p| (p[0], p[1])));
+        }
+        rest = &rest[start + 1 + end..];
+    }
+    points
+}
+
+struct Parser<'a> {
+    markup: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.markup[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.pos)
+    }
+
+    /// Parse nodes until the closing tag of an element, or the end
+    fn nodes(&mut self, closing: Option<&str>) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        loop {
+            let rest = self.rest();
+            if rest.is_empty() {
+                return match closing {
+                    Some(name) => Err(self.error(&format!("missing </{}>", name))),
+                    None => Ok(nodes),
+                };
+            }
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment
+                    .find("-->")
+                    .ok_or_else(|| self.error("unterminated comment"))?;
+                nodes.push(Node::Comment(comment[..end].to_string()));
+                self.pos += 4 + end + 3;
+            } else if let Some(close) = rest.strip_prefix("</") {
+                let end = close
+                    .find('>')
+                    .ok_or_else(|| self.error("unterminated tag"))?;
+                let name = close[..end].trim();
+                if closing != Some(name) {
+                    return Err(self.error(&format!("unexpected </{}>", name)));
+                }
+                self.pos += 2 + end + 1;
+                return Ok(nodes);
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                return Err(self.error("unsupported markup"));
+            } else if rest.starts_with('<') {
+                nodes.push(Node::Element(self.element()?));
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                nodes.push(Node::Text(rest[..end].to_string()));
+                self.pos += end;
+            }
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '/' | '>'))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.pos += end;
+        Ok(rest[..end].to_string())
+    }
+
+    fn element(&mut self) -> Result<Element, String> {
+        self.pos += 1;
+        let mut element = Element::new(&self.name()?);
+        loop {
+            self.skip_space();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            }
+            if rest.starts_with('>') {
+                self.pos += 1;
+                if element.name == "br" {
+                    // The HTML line break which follows `print` output
+                    return Ok(element);
+                }
+                element.children = self.nodes(Some(&element.name))?;
+                return Ok(element);
+            }
+            let name = self.name()?;
+            self.skip_space();
+            if !self.rest().starts_with('=') {
+                return Err(self.error("expected ="));
+            }
+            self.pos += 1;
+            self.skip_space();
+            let rest = self.rest();
+            let quote = match rest.chars().next() {
+                Some(q @ '"') | Some(q @ '\'') => q,
+                _ => return Err(self.error("expected a quoted value")),
+            };
+            let end = rest[1..]
+                .find(quote)
+                .ok_or_else(|| self.error("unterminated value"))?;
+            element
+                .attributes
+                .push((name, rest[1..1 + end].to_string()));
+            self.pos += end + 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn parses_rendered_diagrams() {
+        let source = "print \"hi\"\nA: box \"a &rarr; b\"\narrow\ncircle \"x<y\"";
+        let pic = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+        let dom = pic.dom().unwrap();
+        assert_eq!(dom.nodes[0], Node::Text("hi".to_string()));
+        assert_eq!(dom.nodes[1], Node::Element(Element::new("br")));
+        let svg = dom.svg().unwrap();
+        assert_eq!(svg.attr("xmlns"), Some("http://www.w3.org/2000/svg"));
+        let texts: Vec<_> = svg.find_all("text").iter().map(|t| t.text()).collect();
+        // Spaces in text become non-breaking spaces
+        assert_eq!(texts, ["a\u{a0}&rarr;\u{a0}b", "x&lt;y"]);
+        assert_eq!(svg.find_all("polygon").len(), 1);
+        // Displaying and parsing again gives the same tree
+        assert_eq!(Dom::parse(&dom.to_string()).unwrap(), dom);
+    }
+
+    #[test]
+    fn changes_and_displays() {
+        let mut dom = Dom::parse("<svg a='1' b=\"x'y\">\n<path d=\"M0,0\"  />\n</svg>\n").unwrap();
+        let svg = dom.svg_mut().unwrap();
+        svg.set_attr("a", "2").set_attr("c", &escape("\"q\""));
+        assert_eq!(svg.remove_attr("missing"), None);
+        let mut g = Element::new("g");
+        g.children.push(Node::Comment(" group ".to_string()));
+        svg.children.push(Node::Element(g));
+        assert_eq!(
+            dom.to_string(),
+            "<svg a=\"2\" b=\"x'y\" c=\"&quot;q&quot;\">\n<path d=\"M0,0\"/>\n<g><!-- group --></g></svg>\n"
+        );
+    }
+
+    #[test]
+    fn bounds_paths() {
+        let path = |d: &str| {
+            let mut element = Element::new("path");
+            element.set_attr("d", d);
+            element.bounds().unwrap()
+        };
+        let rect = path("M2,423L110,423L110,351L2,351Z");
+        assert_eq!(
+            (rect.x, rect.y, rect.width, rect.height),
+            (2.0, 351.0, 108.0, 72.0)
+        );
+        let rect = path("M488,218L488,268A54 10 0 0 0 596 268");
+        assert_eq!(
+            (rect.x, rect.y, rect.width, rect.height),
+            (488.0, 218.0, 108.0, 50.0)
+        );
+    }
+
+    #[test]
+    fn reports_malformed_markup() {
+        let error = |markup| Dom::parse(markup).unwrap_err();
+        assert_eq!(error("<svg><g></svg>"), "unexpected </svg> at byte 8");
+        assert_eq!(error("<svg>"), "missing </svg> at byte 5");
+        assert_eq!(error("<svg a=1/>"), "expected a quoted value at byte 7");
+        assert_eq!(
+            error("<?xml version=\"1.0\"?>"),
+            "unsupported markup at byte 0"
+        );
+        assert_eq!(error("<svg><!-- x"), "unterminated comment at byte 5");
+    }
+}
@@ -0,0 +1,296 @@
+//! Generating random valid pikchr source
+
+/// Shapes with an inside, and the attributes which size them
+const BLOCKS: &[(&str, &[&str])] = &[
+    ("box", &["wid", "ht", "rad"]),
+    ("circle", &["rad"]),
+    ("ellipse", &["wid", "ht"]),
+    ("oval", &["wid", "ht"]),
+    ("cylinder", &["wid", "ht", "rad"]),
+    ("file", &["wid", "ht", "rad"]),
+];
+const LINES: &[&str] = &["arrow", "line", "spline", "move"];
+const DIRECTIONS: &[&str] = &["right", "left", "up", "down"];
+const ARROWHEADS: &[&str] = &["->", "<-", "<->"];
+const STYLES: &[&str] = &["dashed", "dotted", "thick", "thin", "invis"];
+const COLORS: &[&str] = &["Red", "Blue", "lightgray", "0x3366cc", "none"];
+const TEXT_STYLES: &[&str] = &["above", "below", "ljust", "rjust", "bold", "italic"];
+const WORDS: &[&str] = &["alpha", "beta", "γ", "x < y", "&amp;", "Δt", ""];
+const EDGES: &[&str] = &["n", "ne", "e", "se", "s", "sw", "w", "nw", "c"];
+
+/// A source of random pikchr programs, for property tests and fuzzing
+///
+/// Each program is made of a bounded number of statements, each drawn
+/// from a small grammar which the C library always accepts: shapes with
+/// sizes, labels and styles, lines in one or more directions, arrows
+/// between labelled shapes, changes of direction, variables and nested
+/// `[ ]` blocks.  Every program draws at least one shape, and the same
+/// seed always gives the same programs.
+///
+/// ```
+/// use pikchr::testing::SourceGenerator;
+/// use pikchr::{Pikchr, PikchrFlags};
+///
+/// let mut generator = SourceGenerator::new(42);
+/// generator.max_statements(10);
+/// for _ in 0..20 {
+///     let source = generator.source();
+///     assert!(Pikchr::render(&source, None, PikchrFlags::default()).is_ok(), "{}", source);
+/// }
+/// ```
+///
+/// To drive it from a fuzzer's or a property testing library's input,
+/// make a seed from the input with [`SourceGenerator::from_bytes`].
+#[derive(Clone, Debug)]
+pub struct SourceGenerator {
+    state: u64,
+    max_statements: usize,
+    max_depth: usize,
+    labels_made: usize,
+}
+
+impl SourceGenerator {
+    /// Make a generator from a seed
+    pub fn new(seed: u64) -> Self {
+        SourceGenerator {
+            // xorshift needs a state which is not zero
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+            max_statements: 20,
+            max_depth: 2,
+            labels_made: 0,
+        }
+        .warmed()
+    }
+
+    /// Make a generator from arbitrary bytes, such as a fuzzer's input
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        // FNV-1a, which any bytes make a usable seed from
+        let seed = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+        Self::new(seed)
+    }
+
+    fn warmed(mut self) -> Self {
+        if self.state == 0 {
+            self.state = 1;
+        }
+        for _ in 0..4 {
+            self.next();
+        }
+        self
+    }
+
+    /// Set the most statements a program has at each level of nesting
+    pub fn max_statements(&mut self, max_statements: usize) -> &mut SourceGenerator {
+        self.max_statements = max_statements;
+        self
+    }
+
+    /// Set how deeply `[ ]` blocks may nest
+    pub fn max_depth(&mut self, max_depth: usize) -> &mut SourceGenerator {
+        self.max_depth = max_depth;
+        self
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number below `n`, which must not be zero
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+
+    /// A length between 0.1 and 2 inches, sometimes in other units
+    fn length(&mut self) -> String {
+        let tenths = 1 + self.below(20);
+        match self.below(4) {
+            0 => format!("{:.3}cm", tenths as f64 * 0.254),
+            1 => format!("{}%", tenths * 10),
+            _ => format!("{}", tenths as f64 / 10.0),
+        }
+    }
+
+    fn label_text(&mut self) -> String {
+        let mut text = format!("\"{}\"", self.pick(WORDS));
+        if self.chance(30) {
+            text.push(' ');
+            text.push_str(self.pick(TEXT_STYLES));
+        }
+        text
+    }
+
+    /// A shape, which may be placed relative to one of the labels
+    fn block(&mut self, out: &mut String, labels: &[String], may_hide: bool) {
+        let (name, sizes) = BLOCKS[self.below(BLOCKS.len())];
+        out.push_str(name);
+        // Each attribute may only be given once
+        for size in sizes {
+            if self.chance(40) {
+                out.push_str(&format!(" {} {}", size, self.length()));
+            }
+        }
+        if self.chance(50) {
+            out.push(' ');
+            out.push_str(&self.label_text());
+        }
+        if self.chance(30) {
+            out.push_str(&format!(" fill {}", self.pick(COLORS)));
+        }
+        if self.chance(20) {
+            out.push_str(&format!(" color {}", self.pick(COLORS)));
+        }
+        if self.chance(20) {
+            let style = self.pick(STYLES);
+            if may_hide || style != "invis" {
+                out.push(' ');
+                out.push_str(style);
+            }
+        }
+        if self.chance(20) {
+            out.push_str(" fit");
+        }
+        if !labels.is_empty() && self.chance(20) {
+            let other = labels[self.below(labels.len())].clone();
+            let edge = self.pick(EDGES);
+            out.push_str(&format!(" with .{} at {}.{}", edge, other, edge));
+        }
+    }
+
+    /// A line in one or more directions, or between two shapes
+    fn line(&mut self, out: &mut String, labels: &[String]) {
+        out.push_str(self.pick(LINES));
+        if labels.len() >= 2 && self.chance(30) {
+            let from = labels[self.below(labels.len())].clone();
+            let to = labels[self.below(labels.len())].clone();
+            let from_edge = self.pick(EDGES);
+            let to_edge = self.pick(EDGES);
+            out.push_str(&format!(
+                " from {}.{} to {}.{}",
+                from, from_edge, to, to_edge
+            ));
+        } else {
+            for segment in 0..1 + self.below(3) {
+                if segment > 0 {
+                    out.push_str(" then");
+                }
+                out.push(' ');
+                out.push_str(self.pick(DIRECTIONS));
+                if self.chance(60) {
+                    out.push(' ');
+                    out.push_str(&self.length());
+                }
+            }
+        }
+        if self.chance(40) {
+            out.push(' ');
+            out.push_str(self.pick(ARROWHEADS));
+        }
+        if self.chance(30) {
+            out.push(' ');
+            out.push_str(&self.label_text());
+        }
+        if self.chance(20) {
+            out.push(' ');
+            out.push_str(self.pick(STYLES));
+        }
+    }
+
+    fn statements(&mut self, out: &mut String, depth: usize, labels: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        for i in 0..self.below(self.max_statements.max(1)) + 1 {
+            out.push_str(&indent);
+            let mut kind = self.below(11);
+            // The C library reports a diagram with nothing drawn in it as
+            // an error, so every program starts with a visible shape
+            let first = depth == 0 && i == 0;
+            if first {
+                kind = self.below(4);
+            }
+            if kind == 8 && depth >= self.max_depth {
+                kind = 9;
+            }
+            // Labels are unique across the program, so that references
+            // to them are never ambiguous.  Only objects can be labelled.
+            self.labels_made += 1;
+            let label = format!("L{}", self.labels_made);
+            let labelled = kind < 9 && self.chance(40);
+            if labelled {
+                out.push_str(&label);
+                out.push_str(": ");
+            }
+            match kind {
+                0..=3 => self.block(out, labels, !first),
+                4..=6 => self.line(out, labels),
+                7 => {
+                    let text = self.label_text();
+                    out.push_str(&format!("text {}", text));
+                }
+                8 => {
+                    out.push_str("[\n");
+                    // Labels inside a block cannot be seen from outside it
+                    self.statements(out, depth + 1, &mut Vec::new());
+                    out.push_str(&indent);
+                    out.push(']');
+                }
+                9 => out.push_str(self.pick(DIRECTIONS)),
+                _ => {
+                    let tenths = 1 + self.below(20);
+                    out.push_str(&format!("boxwid = {}", tenths as f64 / 10.0));
+                }
+            }
+            out.push('\n');
+            if labelled {
+                labels.push(label);
+            }
+        }
+    }
+
+    /// Generate a program
+    pub fn source(&mut self) -> String {
+        let mut out = String::new();
+        self.statements(&mut out, 0, &mut Vec::new());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn repeats_for_a_seed() {
+        let mut one = SourceGenerator::new(7);
+        let mut two = SourceGenerator::new(7);
+        assert_eq!(one.source(), two.source());
+        assert_ne!(
+            SourceGenerator::from_bytes(b"a").source(),
+            SourceGenerator::from_bytes(b"b").source()
+        );
+    }
+
+    #[test]
+    fn generates_valid_programs() {
+        for seed in 0..300 {
+            let source = SourceGenerator::new(seed).source();
+            let pic = Pikchr::render(&source, None, PikchrFlags::default())
+                .unwrap_or_else(|err| panic!("seed {}:\n{}\n{}", seed, source, err));
+            assert!(pic.dom().is_ok(), "{}", source);
+            assert!(crate::ast::parse(&source).is_ok(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Snapshot testing of rendered diagrams
+//!
+//! A test suite can keep the SVG which its diagrams should render to in
+//! snapshot files, and [`assert_snapshot`] checks a diagram against one.
+//! Snapshots are stored in a canonical form, with one element to a line
+//! and without the comments and whitespace which pikchr does not always
+//! write the same way, so that they are easy to review and a failure shows
+//! the lines which changed.
+//!
+//! ```no_run
+//! pikchr::testing::assert_snapshot("tests/snapshots/flow.svg", "box; arrow; circle");
+//! ```
+//!
+//! To write the snapshots afresh, for new diagrams or after a deliberate
+//! change, run the tests with the `PIKCHR_UPDATE_SNAPSHOTS` environment
+//! variable set to `1`.
+
+mod generate;
+
+pub use generate::SourceGenerator;
+
+use crate::hash::canonicalize;
+use crate::svg::{Element, Node};
+use crate::{Pikchr, PikchrFlags};
+use std::fmt::Write;
+use std::path::Path;
+
+/// The environment variable which makes checks write snapshots rather than
+/// compare against them
+pub const UPDATE_VAR: &str = "PIKCHR_UPDATE_SNAPSHOTS";
+
+/// How many unchanged lines to show around each change
+const CONTEXT: usize = 2;
+
+fn write_open_tag(out: &mut String, element: &Element) {
+    out.push('<');
+    out.push_str(&element.name);
+    for (name, value) in &element.attributes {
+        let quote = if value.contains('"') { '\'' } else { '"' };
+        let _ = write!(out, " {}={}{}{}", name, quote, value, quote);
+    }
+}
+
+fn write_nodes(out: &mut String, nodes: &[Node], depth: usize) {
+    for node in nodes {
+        out.push_str(&"  ".repeat(depth));
+        match node {
+            Node::Element(element)
+                if element
+                    .children
+                    .iter()
+                    .any(|child| matches!(child, Node::Element(_))) =>
+            {
+                write_open_tag(out, element);
+                out.push_str(">\n");
+                write_nodes(out, &element.children, depth + 1);
+                let _ = writeln!(out, "{}</{}>", "  ".repeat(depth), element.name);
+            }
+            // Text, and elements holding only text, stay on one line
+            node => {
+                let _ = writeln!(out, "{}", node);
+            }
+        }
+    }
+}
+
+/// The canonical form of a rendered diagram, as stored in snapshots
+///
+/// # Errors
+///
+/// It is an error if the rendered pikchr cannot be parsed; see
+/// [`Pikchr::dom`].
+pub fn canonical(pic: &Pikchr) -> Result<String, String> {
+    let mut dom = pic.dom()?;
+    canonicalize(&mut dom.nodes);
+    let mut out = String::new();
+    write_nodes(&mut out, &dom.nodes, 0);
+    Ok(out)
+}
+
+/// The lines of two texts which differ, in the style of `diff -u`
+///
+/// ```
+/// let diff = pikchr::testing::line_diff("a\nb\nc\n", "a\nB\nc\n");
+/// assert_eq!(diff, "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+/// ```
+pub fn line_diff(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = actual.lines().collect();
+    // The length of the longest common subsequence of each pair of suffixes
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    // Each line of the diff, with the lines it has reached in each text
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push((' ', a[i], i, j));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', a[i], i, j));
+            i += 1;
+        } else {
+            lines.push(('+', b[j], i, j));
+            j += 1;
+        }
+    }
+    let changed: Vec<usize> = (0..lines.len()).filter(|&k| lines[k].0 != ' ').collect();
+    let mut out = String::new();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(CONTEXT);
+        let mut end = changed[k];
+        // Changes close enough to share their context make one hunk
+        while k < changed.len() && changed[k] <= end + 2 * CONTEXT + 1 {
+            end = changed[k];
+            k += 1;
+        }
+        let end = (end + CONTEXT + 1).min(lines.len());
+        let hunk = &lines[start..end];
+        let old = hunk.iter().filter(|line| line.0 != '+').count();
+        let new = hunk.iter().filter(|line| line.0 != '-').count();
+        let _ = writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            hunk[0].2 + 1,
+            old,
+            hunk[0].3 + 1,
+            new
+        );
+        for (sign, line, _, _) in hunk {
+            let _ = writeln!(out, "{}{}", sign, line);
+        }
+    }
+    out
+}
+
+/// Check a rendered diagram against a snapshot file
+///
+/// If [`UPDATE_VAR`] is set to `1`, the snapshot is written instead.
+///
+/// # Errors
+///
+/// It is an error if the snapshot is missing or differs, in which case the
+/// error shows the lines which differ, or if the snapshot cannot be read
+/// or written.
+pub fn check_snapshot(path: impl AsRef<Path>, pic: &Pikchr) -> Result<(), String> {
+    let path = path.as_ref();
+    let actual = canonical(pic)?;
+    if std::env::var(UPDATE_VAR).is_ok_and(|value| value == "1") {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| format!("cannot create {}: {}", dir.display(), err))?;
+        }
+        return std::fs::write(path, actual)
+            .map_err(|err| format!("cannot write {}: {}", path.display(), err));
+    }
+    let expected = match std::fs::read_to_string(path) {
+        Ok(expected) => expected,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(format!(
+                "there is no snapshot at {}; set {}=1 to write it",
+                path.display(),
+                UPDATE_VAR
+            ))
+        }
+        Err(err) => return Err(format!("cannot read {}: {}", path.display(), err)),
+    };
+    if expected == actual {
+        return Ok(());
+    }
+    Err(format!(
+        "the diagram differs from the snapshot at {}; set {}=1 to update it\n{}",
+        path.display(),
+        UPDATE_VAR,
+        line_diff(&expected, &actual)
+    ))
+}
+
+/// Render some pikchr source and check it against a snapshot file
+///
+/// # Panics
+///
+/// This panics if the source cannot be rendered, or if
+/// [`check_snapshot`] fails.
+#[track_caller]
+pub fn assert_snapshot(path: impl AsRef<Path>, source: &str) {
+    let pic = match Pikchr::render(source, None, PikchrFlags::default()) {
+        Ok(pic) => pic,
+        Err(err) => panic!("cannot render the diagram: {}", err),
+    };
+    if let Err(err) = check_snapshot(path, &pic) {
+        panic!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_one_element_to_a_line() {
+        let pic = Pikchr::render("box \"A\"", None, PikchrFlags::default()).unwrap();
+        let text = canonical(&pic).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("<svg "));
+        assert!(lines[1].starts_with("  <path "));
+        assert!(lines[2].starts_with("  <text ") && lines[2].ends_with(">A</text>"));
+        assert_eq!(lines[3], "</svg>");
+    }
+
+    #[test]
+    fn diffs_lines() {
+        assert_eq!(line_diff("a\nb\n", "a\nb\n"), "");
+        let expected: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+        let actual: String = (1..=20)
+            .filter(|&n| n != 18)
+            .map(|n| match n {
+                3 => "three\n".to_string(),
+                n => format!("{}\n", n),
+            })
+            .collect();
+        assert_eq!(
+            line_diff(&expected, &actual),
+            "@@ -1,5 +1,5 @@\n 1\n 2\n-3\n+three\n 4\n 5\n\
+             @@ -16,5 +16,4 @@\n 16\n 17\n-18\n 19\n 20\n"
+        );
+    }
+
+    #[test]
+    fn checks_snapshots() {
+        let dir = std::env::temp_dir().join(format!("pikchr-snapshots-{}", std::process::id()));
+        let path = dir.join("box.svg");
+        let pic = Pikchr::render("box", None, PikchrFlags::default()).unwrap();
+        let err = check_snapshot(&path, &pic).unwrap_err();
+        assert!(err.starts_with("there is no snapshot"), "{}", err);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, canonical(&pic).unwrap()).unwrap();
+        assert_eq!(check_snapshot(&path, &pic), Ok(()));
+        let circle = Pikchr::render("circle", None, PikchrFlags::default()).unwrap();
+        let err = check_snapshot(&path, &circle).unwrap_err();
+        assert!(
+            err.contains("\n-  <path ") && err.contains("\n+  <circle "),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
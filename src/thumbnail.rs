@@ -0,0 +1,116 @@
+//! Small previews of diagrams
+//!
+//! [`Pikchr::thumbnail`] shrinks a rendered diagram to fit within a square
+//! of some number of pixels, for galleries and lists of search results.
+//! Only the size at which the SVG is shown changes, so it stays sharp.
+//!
+//! Text which would be too small to read at that size only adds noise, so
+//! it can be left out.  Text is taken to be 16 pixels high at full size,
+//! the default size of text in browsers, scaled by its `font-size`.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let pic = Pikchr::render("box wid 4 \"big\" big; box \"small\" small", None, PikchrFlags::default())
+//!     .unwrap();
+//! let thumbnail = pic.thumbnail(240, true).unwrap();
+//! assert_eq!(thumbnail.width(), 240);
+//! assert!(thumbnail.contains(">big</text>"));
+//! assert!(!thumbnail.contains(">small</text>"));
+//! ```
+//!
+//! [`Pikchr::thumbnail`]: crate::Pikchr::thumbnail
+
+use crate::svg::{Dom, Node};
+
+/// The height of text at full size, in pixels
+const TEXT_PX: f64 = 16.0;
+
+/// The height in pixels below which text cannot be read
+const READABLE_PX: f64 = 6.0;
+
+/// The scale of a `font-size` attribute, as pikchr writes them
+fn font_scale(size: Option<&str>) -> f64 {
+    size.and_then(|size| size.strip_suffix('%'))
+        .and_then(|percent| percent.parse::<f64>().ok())
+        .map_or(1.0, |percent| percent / 100.0)
+}
+
+/// Remove text which is too small to read when the diagram is shown at a
+/// scale, from any depth
+fn elide_text(nodes: &mut Vec<Node>, scale: f64) {
+    nodes.retain(|node| match node {
+        Node::Element(element) if element.name == "text" => {
+            TEXT_PX * font_scale(element.attr("font-size")) * scale >= READABLE_PX
+        }
+        _ => true,
+    });
+    for node in nodes.iter_mut() {
+        if let Node::Element(element) = node {
+            elide_text(&mut element.children, scale);
+        }
+    }
+}
+
+/// Shrink a rendered diagram of a size to fit within a square, returning
+/// its new size
+pub(crate) fn shrink(
+    dom: &mut Dom,
+    (width, height): (isize, isize),
+    max_px: isize,
+    elide: bool,
+) -> (isize, isize) {
+    let largest = width.max(height);
+    let scale = if largest > max_px {
+        max_px as f64 / largest as f64
+    } else {
+        1.0
+    };
+    let size = (
+        ((width as f64 * scale).round() as isize).max(1),
+        ((height as f64 * scale).round() as isize).max(1),
+    );
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return size,
+    };
+    svg.set_attr("width", &size.0.to_string())
+        .set_attr("height", &size.1.to_string());
+    if elide {
+        elide_text(&mut svg.children, scale);
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pikchr, PikchrFlags};
+
+    #[test]
+    fn reads_font_sizes() {
+        assert_eq!(font_scale(None), 1.0);
+        assert_eq!(font_scale(Some("125%")), 1.25);
+        assert_eq!(font_scale(Some("large")), 1.0);
+    }
+
+    #[test]
+    fn shrinks_to_fit() {
+        let pic = Pikchr::render("box ht 2 \"a\"", None, PikchrFlags::default()).unwrap();
+        let small = pic.thumbnail(100, false).unwrap();
+        assert_eq!((small.width(), small.height()), (38, 100));
+        let svg = small.dom().unwrap();
+        let svg = svg.svg().unwrap();
+        assert_eq!(svg.attr("height"), Some("100"));
+        assert_eq!(
+            svg.attr("viewBox"),
+            pic.dom().unwrap().svg().unwrap().attr("viewBox")
+        );
+        assert_eq!(svg.find_all("text").len(), 1);
+        // Smaller diagrams are left as they are
+        let same = pic.thumbnail(1000, true).unwrap();
+        assert_eq!((same.width(), same.height()), (pic.width(), pic.height()));
+        assert_eq!(same.dom().unwrap().svg().unwrap().find_all("text").len(), 1);
+        assert!(pic.thumbnail(0, false).is_err());
+    }
+}
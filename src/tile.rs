@@ -0,0 +1,219 @@
+//! Cropping and tiling diagrams
+//!
+//! A very large diagram, such as the architecture of a whole system, is
+//! slow to show as one SVG.  [`Pikchr::tiles`] splits it into a grid of
+//! tiles of a fixed size in pixels, each an SVG whose `viewBox` is its part
+//! of the diagram, so that a map-style viewer can load only the tiles in
+//! view.  [`Pikchr::crop`] makes a single SVG of any part of a diagram.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let source = "box; arrow; box; arrow; box; arrow; box";
+//! let pic = Pikchr::render(source, None, PikchrFlags::default()).unwrap();
+//! let tiles = pic.tiles(256, 256).unwrap();
+//! assert_eq!(tiles.len(), 3);
+//! assert_eq!((tiles[2].row(), tiles[2].column()), (0, 2));
+//! assert!(tiles[2].pikchr().contains(" viewBox=\"512.251 0 140.069 76.32\""));
+//! assert_eq!(tiles[2].pikchr().width(), 140);
+//! ```
+//!
+//! Lines and shapes which are wholly outside a tile are left out of it.
+//! Text is always kept, as its extent is not known.
+//!
+//! [`Pikchr::tiles`]: crate::Pikchr::tiles
+//! [`Pikchr::crop`]: crate::Pikchr::crop
+
+use crate::svg::{Element, Rect};
+use crate::{Pikchr, PikchrError};
+
+/// How far the strokes and arrowheads of a shape can reach beyond its
+/// points, in the coordinates of the SVG
+const STROKE_REACH: f64 = 8.0;
+
+/// One tile of a diagram
+pub struct Tile {
+    row: usize,
+    column: usize,
+    pikchr: Pikchr,
+}
+
+impl Tile {
+    /// The row of the tile, counting from 0 at the top
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// The column of the tile, counting from 0 at the left
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The tile as a diagram of its own
+    pub fn pikchr(&self) -> &Pikchr {
+        &self.pikchr
+    }
+
+    /// Take the diagram of the tile
+    pub fn into_pikchr(self) -> Pikchr {
+        self.pikchr
+    }
+}
+
+/// Set the `viewBox` of an `<svg>`, and its size in pixels if it has one
+pub(crate) fn reframe(svg: &mut Element, view_box: Rect, (width, height): (isize, isize)) {
+    svg.set_attr(
+        "viewBox",
+        &format!(
+            "{} {} {} {}",
+            view_box.x, view_box.y, view_box.width, view_box.height
+        ),
+    );
+    if svg.attr("width").is_some() {
+        svg.set_attr("width", &width.to_string());
+    }
+    if svg.attr("height").is_some() {
+        svg.set_attr("height", &height.to_string());
+    }
+}
+
+/// Crop a rendered diagram to part of its `viewBox`, which is shown at
+/// the same scale as before
+pub(crate) fn crop(pic: &Pikchr, view_box: Rect) -> Result<Pikchr, PikchrError> {
+    if !(view_box.width > 0.0 && view_box.height > 0.0) {
+        return Err(PikchrError::svg("the area to crop to is empty"));
+    }
+    let mut dom = pic.dom()?;
+    let old = dom
+        .view_box()
+        .ok_or_else(|| PikchrError::svg("the diagram has no viewBox"))?;
+    let scale_x = pic.width as f64 / old.width;
+    let scale_y = pic.height as f64 / old.height;
+    let width = (view_box.width * scale_x).round() as isize;
+    let height = (view_box.height * scale_y).round() as isize;
+    let svg = dom
+        .svg_mut()
+        .ok_or_else(|| PikchrError::svg("the diagram has no <svg>"))?;
+    let reach = Rect {
+        x: view_box.x - STROKE_REACH,
+        y: view_box.y - STROKE_REACH,
+        width: view_box.width + 2.0 * STROKE_REACH,
+        height: view_box.height + 2.0 * STROKE_REACH,
+    };
+    svg.children.retain(|node| match node {
+        crate::svg::Node::Element(element) => match element.name.as_str() {
+            "path" | "polygon" | "polyline" | "circle" | "ellipse" | "rect" => element
+                .bounds()
+                .is_none_or(|bounds| bounds.intersects(&reach)),
+            _ => true,
+        },
+        _ => true,
+    });
+    reframe(svg, view_box, (width, height));
+    Ok(Pikchr {
+        rendered: dom.to_string(),
+        width,
+        height,
+    })
+}
+
+/// Split a rendered diagram into tiles of a size in pixels
+pub(crate) fn tiles(pic: &Pikchr, width: isize, height: isize) -> Result<Vec<Tile>, PikchrError> {
+    if width <= 0 || height <= 0 {
+        return Err(PikchrError::svg("tiles must be at least a pixel in size"));
+    }
+    let view_box = pic
+        .dom()?
+        .view_box()
+        .ok_or_else(|| PikchrError::svg("the diagram has no viewBox"))?;
+    // The size of a tile in the coordinates of the SVG
+    let step_x = width as f64 * view_box.width / pic.width as f64;
+    let step_y = height as f64 * view_box.height / pic.height as f64;
+    let columns = (view_box.width / step_x).ceil().max(1.0) as usize;
+    let rows = (view_box.height / step_y).ceil().max(1.0) as usize;
+    // Tiles meet at edges rounded to a thousandth, which keeps the
+    // `viewBox` of each short without leaving gaps between them
+    let edge = |start: f64, step: f64, end: f64, i: usize| {
+        let at = (start + i as f64 * step).min(end);
+        (at * 1000.0).round() / 1000.0
+    };
+    let mut tiles = Vec::with_capacity(rows * columns);
+    for row in 0..rows {
+        let top = view_box.y;
+        let bottom = top + view_box.height;
+        let (y0, y1) = (
+            edge(top, step_y, bottom, row),
+            edge(top, step_y, bottom, row + 1),
+        );
+        for column in 0..columns {
+            let left = view_box.x;
+            let right = left + view_box.width;
+            let (x0, x1) = (
+                edge(left, step_x, right, column),
+                edge(left, step_x, right, column + 1),
+            );
+            let area = Rect {
+                x: x0,
+                y: y0,
+                width: ((x1 - x0) * 1000.0).round() / 1000.0,
+                height: ((y1 - y0) * 1000.0).round() / 1000.0,
+            };
+            tiles.push(Tile {
+                row,
+                column,
+                pikchr: crop(pic, area)?,
+            });
+        }
+    }
+    Ok(tiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PikchrFlags;
+
+    #[test]
+    fn crops_to_an_area() {
+        let pic = Pikchr::render(
+            "scale = 2\nbox; arrow; circle",
+            None,
+            PikchrFlags::default(),
+        )
+        .unwrap();
+        let area = Rect {
+            x: 150.0,
+            y: 0.0,
+            width: 150.0,
+            height: 76.0,
+        };
+        let cropped = pic.crop(area).unwrap();
+        let dom = cropped.dom().unwrap();
+        let svg = dom.svg().unwrap();
+        assert_eq!(svg.attr("viewBox"), Some("150 0 150 76"));
+        assert_eq!((cropped.width(), cropped.height()), (300, 151));
+        assert_eq!(svg.attr("width"), Some("300"));
+        // The box is left out, but the arrow and circle are kept
+        let names: Vec<_> = svg.elements().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["polygon", "path", "circle"]);
+        assert!(pic.crop(Rect::default()).is_err());
+    }
+
+    #[test]
+    fn covers_the_diagram() {
+        let pic = Pikchr::render("box wid 3 ht 2", None, PikchrFlags::default()).unwrap();
+        let tiles = pic.tiles(200, 100).unwrap();
+        let view_box = pic.dom().unwrap().view_box().unwrap();
+        let total = tiles
+            .iter()
+            .map(|t| t.pikchr().dom().unwrap().view_box().unwrap())
+            .fold(Rect::default(), |all, tile| all.union(tile));
+        assert_eq!(total, view_box);
+        assert_eq!(tiles.len(), 3 * 3);
+        assert_eq!(
+            tiles.iter().map(|t| t.pikchr().width()).sum::<isize>(),
+            3 * pic.width()
+        );
+        assert!(pic.tiles(0, 10).is_err());
+    }
+}
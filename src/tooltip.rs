@@ -0,0 +1,158 @@
+//! Tooltips from comments
+//!
+//! A comment starting `tooltip:` gives the statement it belongs to a
+//! tooltip, which [`Pikchr::render_with_tooltips`] adds to the shape the
+//! statement draws as an SVG `<title>`.  Browsers show the title when the
+//! pointer is over the shape.
+//!
+//! A tooltip comment belongs to the statement before it on the same line,
+//! or else to the next statement.  A statement with more than one tooltip
+//! comment has a tooltip of several lines.
+//!
+//! ```
+//! use pikchr::{Pikchr, PikchrFlags};
+//!
+//! let source = "
+//! ## tooltip: Where requests arrive
+//! box \"Server\"
+//! arrow
+//! cylinder \"Store\" // tooltip: Kept for a week
+//! ";
+//! let pic = Pikchr::render_with_tooltips(source, None, PikchrFlags::default()).unwrap();
+//! assert!(pic.contains("><title>Where requests arrive</title></path>"));
+//! assert!(pic.contains("><title>Kept for a week</title></path>"));
+//! ```
+//!
+//! [`Pikchr::render_with_tooltips`]: crate::Pikchr::render_with_tooltips
+
+use crate::ast::{tokenize, TokenKind};
+use crate::source_map::SourceMap;
+use crate::svg::{escape, Dom, Element, Node};
+
+/// The tooltip in a comment, if it has one
+fn tooltip(comment: &str) -> Option<&str> {
+    let text = if let Some(text) = comment.strip_prefix("/*") {
+        text.strip_suffix("*/").unwrap_or(text)
+    } else if let Some(text) = comment.strip_prefix("//") {
+        text
+    } else {
+        comment.strip_prefix('#')?
+    };
+    Some(text.trim().strip_prefix("tooltip:")?.trim())
+}
+
+/// The tooltip of each statement in a source map, by the position of the
+/// statement
+fn tooltips(source: &str, map: &SourceMap) -> Vec<Option<String>> {
+    let statements = map.statements();
+    let mut tooltips: Vec<Option<String>> = vec![None; statements.len()];
+    for token in tokenize(source) {
+        if token.kind != TokenKind::Comment {
+            continue;
+        }
+        let text = match tooltip(token.text(source)) {
+            Some(text) => text,
+            None => continue,
+        };
+        let span = token.span;
+        let before = statements.iter().rposition(|s| {
+            s.span.end <= span.start && !source[s.span.end..span.start].contains('\n')
+        });
+        let owner = before.or_else(|| statements.iter().position(|s| s.span.start >= span.end));
+        if let Some(i) = owner {
+            match &mut tooltips[i] {
+                Some(tooltip) => {
+                    tooltip.push('\n');
+                    tooltip.push_str(text);
+                }
+                tooltip => *tooltip = Some(text.to_string()),
+            }
+        }
+    }
+    tooltips
+}
+
+/// Add a `<title>` to the main shape of each statement with a tooltip
+///
+/// The `dom` should be the diagram which the map was made from.
+pub(crate) fn add_tooltips(dom: &mut Dom, source: &str, map: &SourceMap) {
+    let svg = match dom.svg_mut() {
+        Some(svg) => svg,
+        None => return,
+    };
+    let mut titles = Vec::new();
+    for (statement, tooltip) in map.statements().iter().zip(tooltips(source, map)) {
+        let tooltip = match tooltip {
+            Some(tooltip) => tooltip,
+            None => continue,
+        };
+        let elements: Vec<_> = svg
+            .elements()
+            .enumerate()
+            .filter(|(i, _)| statement.elements.contains(i))
+            .collect();
+        // The shape rather than its text or arrowheads
+        let shape = elements
+            .iter()
+            .find(|(_, e)| e.name != "text" && e.name != "polygon")
+            .or_else(|| elements.first());
+        if let Some((i, _)) = shape {
+            titles.push((*i, tooltip));
+        }
+    }
+    for (i, element) in svg.elements_mut().enumerate() {
+        if let Some((_, tooltip)) = titles.iter().find(|(t, _)| *t == i) {
+            let mut title = Element::new("title");
+            title.children.push(Node::Text(escape(tooltip)));
+            element.children.insert(0, Node::Element(title));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tooltip_comments() {
+        assert_eq!(tooltip("# tooltip: a"), Some("a"));
+        assert_eq!(tooltip("//tooltip:b  "), Some("b"));
+        assert_eq!(tooltip("/* tooltip: c */"), Some("c"));
+        assert_eq!(tooltip("# a comment"), None);
+        assert_eq!(tooltip("# see the tooltip: here"), None);
+    }
+
+    #[test]
+    fn attaches_tooltips_to_statements() {
+        let source = "# tooltip: first\n# tooltip: <again>\nbox\n$x = 1 # tooltip: x\n\
+                      arrow /* tooltip: arrow */; circle\n# tooltip: nothing follows\n";
+        let map = SourceMap::new(source).unwrap();
+        assert_eq!(
+            tooltips(source, &map),
+            [
+                Some("first\n<again>".to_string()),
+                Some("x".to_string()),
+                Some("arrow".to_string()),
+                None,
+            ]
+        );
+        let mut dom = crate::Pikchr::render(source, None, Default::default())
+            .unwrap()
+            .dom()
+            .unwrap();
+        add_tooltips(&mut dom, source, &map);
+        let titles: Vec<_> = dom
+            .svg()
+            .unwrap()
+            .elements()
+            .filter_map(|e| e.elements().next().map(|t| (e.name.as_str(), t.text())))
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                ("path", "first\n&lt;again&gt;".to_string()),
+                ("path", "arrow".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Tracing how a diagram is laid out
+//!
+//! When an object ends up somewhere unexpected, it helps to see where each
+//! statement put things.  [`trace`] lays out some source with the
+//! pure-Rust [`layout`](crate::layout) backend, recording after each
+//! statement the object it made, with its position and size, the value of
+//! any variable it set, and the current direction.  A [`Trace`] displays
+//! as a table with a line for each statement.
+//!
+//! ```
+//! let trace = pikchr::trace::trace("A: box\ndown\narrow\n$x = A.wid * 2").unwrap();
+//! assert_eq!(
+//!     trace.to_string(),
+//!     "   1  A: box         box A at (0, 0) wid 0.75 ht 0.5, going right\n\
+//!      \x20  2  down           going down\n\
+//!      \x20  3  arrow          arrow at (0, -0.5) wid 0 ht 0.5 from (0, -0.25) to (0, -0.75), going down\n\
+//!      \x20  4  $x = A.wid * 2 $x = 1.5, going down\n"
+//! );
+//! ```
+//!
+//! Statements within a `[...]` sublist come before the sublist itself,
+//! and are indented.  Their objects are where they were before the
+//! sublist was moved into place.
+
+use crate::ast::{self, Direction, Span};
+use crate::layout::{format_g, traced, LayoutError, Object, Point};
+use std::fmt;
+
+/// The state of a layout after one statement
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub(crate) span: Span,
+    pub(crate) line: usize,
+    pub(crate) text: String,
+    pub(crate) depth: usize,
+    pub(crate) direction: Direction,
+    pub(crate) object: Option<Object>,
+    pub(crate) variable: Option<(String, f64)>,
+}
+
+impl Step {
+    /// Where the statement is in the source
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The line of the source which the statement starts on, from 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The source of the statement
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// How many sublists the statement is within
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The direction after the statement
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The object which the statement made, if it made one
+    pub fn object(&self) -> Option<&Object> {
+        self.object.as_ref()
+    }
+
+    /// The variable which the statement set and its new value, if it set
+    /// one
+    pub fn variable(&self) -> Option<(&str, f64)> {
+        self.variable
+            .as_ref()
+            .map(|(name, value)| (name.as_str(), *value))
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Up => "up",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Right => "right",
+    }
+}
+
+fn point(p: Point) -> String {
+    format!("({}, {})", format_g(p.x, 6), format_g(p.y, 6))
+}
+
+impl fmt::Display for Step {
+    /// A summary of the state after the statement
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(object) = &self.object {
+            match (object.class(), object.name()) {
+                (Some(class), Some(name)) => write!(f, "{} {} at ", class, name)?,
+                (Some(class), None) => write!(f, "{} at ", class)?,
+                (None, Some(name)) => write!(f, "place {} at ", name)?,
+                (None, None) => f.write_str("place at ")?,
+            }
+            f.write_str(&point(object.center()))?;
+            if object.class().is_some() {
+                write!(
+                    f,
+                    " wid {} ht {}",
+                    format_g(object.width(), 6),
+                    format_g(object.height(), 6)
+                )?;
+            }
+            if !object.path().is_empty() {
+                write!(
+                    f,
+                    " from {} to {}",
+                    point(object.start()),
+                    point(object.end())
+                )?;
+            }
+            f.write_str(", ")?;
+        } else if let Some((name, value)) = self.variable() {
+            write!(f, "{} = {}, ", name, format_g(value, 6))?;
+        }
+        write!(f, "going {}", direction_name(self.direction))
+    }
+}
+
+/// The steps of a layout, and the error which stopped it if there was one
+#[derive(Clone, Debug)]
+pub struct Trace {
+    steps: Vec<Step>,
+    error: Option<LayoutError>,
+}
+
+impl Trace {
+    /// The state after each statement, in the order the statements were
+    /// laid out
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// The error which stopped the layout, if there was one
+    ///
+    /// The steps are those before the error.
+    pub fn error(&self) -> Option<&LayoutError> {
+        self.error.as_ref()
+    }
+}
+
+/// The first line of a statement, indented by its depth
+fn heading(step: &Step) -> String {
+    let mut first = step.text.lines().next().unwrap_or("").to_string();
+    if first.chars().count() > 40 || step.text.contains('\n') {
+        first = first.chars().take(39).collect();
+        first.push('…');
+    }
+    format!("{}{}", "  ".repeat(step.depth), first)
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headings: Vec<_> = self.steps.iter().map(heading).collect();
+        let width = headings
+            .iter()
+            .map(|h| h.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (step, heading) in self.steps.iter().zip(headings) {
+            writeln!(
+                f,
+                "{:>4}  {:<width$} {}",
+                step.line,
+                heading,
+                step,
+                width = width
+            )?;
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lay out pikchr source, recording the state after each statement
+///
+/// Layout stops at the first error, which is kept in the trace with the
+/// steps before it.
+///
+/// ```
+/// let trace = pikchr::trace::trace("box; circle rad 0; box").unwrap();
+/// assert_eq!(trace.steps().len(), 3);
+/// let trace = pikchr::trace::trace("box; move to B.n").unwrap();
+/// assert_eq!(trace.steps().len(), 1);
+/// assert_eq!(trace.error().unwrap().message(), "no such object");
+/// ```
+///
+/// # Errors
+///
+/// It is an error if the source cannot be parsed.
+pub fn trace(source: &str) -> Result<Trace, LayoutError> {
+    let document = ast::parse(source)?;
+    let (steps, error) = traced(source, &document);
+    Ok(Trace { steps, error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traces_sublists() {
+        let trace = trace("[\n  box \"a\"\n  circle\n]\nP: last [].n").unwrap();
+        assert!(trace.error().is_none());
+        let summary: Vec<_> = trace
+            .steps()
+            .iter()
+            .map(|s| (s.line(), s.depth(), s.object().unwrap().class()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, 1, Some("box")),
+                (3, 1, Some("circle")),
+                (1, 0, Some("[]")),
+                (5, 0, None)
+            ]
+        );
+        assert_eq!(
+            trace.to_string(),
+            "   2    box \"a\"    box at (0, 0) wid 0.75 ht 0.5, going right\n\
+             \x20  3    circle     circle at (0.625, 0) wid 0.5 ht 0.5, going right\n\
+             \x20  1  [\u{2026}           [] at (0, 0) wid 1.25 ht 0.5, going right\n\
+             \x20  5  P: last [].n place P at (0, 0.25), going right\n"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+/**
+ * Tree-sitter grammar for pikchr
+ *
+ * The grammar is deliberately lenient: it knows the statement structure
+ * (labels, assignments, macro definitions and nested `[...]` sublists)
+ * and every token of the language, but treats the body of a statement as
+ * a sequence of items rather than checking it against the full grammar.
+ * This keeps highlighting and structural editing working while a diagram
+ * is being typed.  The keywords mirror `KEYWORDS` in `src/ast/lexer.rs`.
+ */
+
+const CLASSES = [
+  'arc', 'arrow', 'box', 'circle', 'cylinder', 'dot', 'ellipse', 'file',
+  'line', 'move', 'oval', 'spline', 'text',
+];
+
+const EDGES = [
+  'n', 'ne', 'e', 'se', 's', 'sw', 'w', 'nw', 'c', 't', 'bot', 'north',
+  'east', 'south', 'west', 'top', 'bottom', 'start', 'end', 'center',
+];
+
+const ATTRIBUTES = [
+  'above', 'aligned', 'below', 'big', 'bold', 'ccw', 'chop', 'close', 'cw',
+  'dashed', 'diameter', 'dotted', 'fit', 'ht', 'height', 'invis', 'invisible',
+  'italic', 'ljust', 'rad', 'radius', 'rjust', 'same', 'small', 'solid',
+  'thick', 'thin', 'wid', 'width',
+];
+
+const KEYWORDS = [
+  'and', 'as', 'at', 'behind', 'between', 'down', 'even', 'from', 'go',
+  'heading', 'in', 'left', 'of', 'right', 'the', 'then', 'to', 'until', 'up',
+  'vertex', 'way', 'with', 'x', 'y',
+];
+
+const FUNCTIONS = ['abs', 'cos', 'dist', 'int', 'max', 'min', 'sin', 'sqrt'];
+
+function sep(rule, separator) {
+  return seq(optional(rule), repeat(seq(separator, optional(rule))));
+}
+
+module.exports = grammar({
+  name: 'pikchr',
+
+  extras: $ => [/[ \t\r\f]/, /\\\r?\n/, $.comment],
+
+  word: $ => $.identifier,
+
+  rules: {
+    document: $ => sep($._statement, $._eol),
+
+    _eol: _ => choice('\n', ';'),
+
+    _statement: $ => choice(
+      $.labeled_statement,
+      $._unlabeled_statement,
+    ),
+
+    labeled_statement: $ => seq(
+      field('label', $.place_name),
+      ':',
+      $._unlabeled_statement,
+    ),
+
+    _unlabeled_statement: $ => choice(
+      $.assignment,
+      $.macro_definition,
+      $.statement,
+    ),
+
+    assignment: $ => seq(
+      field('variable', choice($.identifier, 'fill', 'color', 'thickness')),
+      field('operator', choice('=', '+=', '-=', '*=', '/=')),
+      field('value', repeat1($._item)),
+    ),
+
+    macro_definition: $ => seq(
+      'define',
+      field('name', choice($.identifier, $.place_name)),
+      field('body', $.code_block),
+    ),
+
+    statement: $ => repeat1($._item),
+
+    _item: $ => choice(
+      $.sublist,
+      $.group,
+      $.string,
+      $.number,
+      $.ordinal,
+      $.class_name,
+      $.edge,
+      $.attribute,
+      $.keyword,
+      $.function,
+      $.property,
+      $.place_name,
+      $.identifier,
+      $.parameter,
+      $.macro_arguments,
+      'print',
+      'assert',
+      'last',
+      'previous',
+      'first',
+      'this',
+      'fill',
+      'color',
+      'thickness',
+      '.',
+      ',',
+      '+',
+      '-',
+      '*',
+      '/',
+      '%',
+      '<',
+      '>',
+      '==',
+      '->',
+      '<-',
+      '<->',
+    ),
+
+    sublist: $ => seq('[', sep($._statement, $._eol), ']'),
+
+    group: $ => seq('(', repeat(choice($._item, $._eol)), ')'),
+
+    // The arguments of a macro call may contain any text, but are most
+    // often pikchr, so they are parsed as a group
+    macro_arguments: $ => prec(1, seq($.identifier, $.group)),
+
+    code_block: $ => seq('{', repeat(choice($.code_block, $.string, /[^{}"]+/)), '}'),
+
+    class_name: _ => choice(...CLASSES),
+
+    edge: _ => choice(...EDGES),
+
+    attribute: _ => choice(...ATTRIBUTES),
+
+    keyword: _ => choice(...KEYWORDS),
+
+    function: _ => choice(...FUNCTIONS),
+
+    // `.x`, `.wid` and the like; `.` before a label or edge is a separate
+    // token
+    property: _ => token(seq('.', choice(
+      'x', 'y', 'wid', 'width', 'ht', 'height', 'rad', 'radius', 'diameter',
+      'thickness', 'color', 'fill', 'dashed', 'dotted',
+    ))),
+
+    string: _ => /"([^"\\\n]|\\.)*"/,
+
+    number: _ => token(choice(
+      /0[xX][0-9a-fA-F]+/,
+      seq(
+        choice(/[0-9]+(\.[0-9]*)?/, /\.[0-9]+/),
+        optional(/[eE][+-]?[0-9]+/),
+        optional(choice('in', 'cm', 'mm', 'pt', 'px', 'pc')),
+      ),
+    )),
+
+    ordinal: _ => token(prec(1, /[0-9]+(st|nd|rd|th)/)),
+
+    parameter: _ => token(prec(1, /\$[1-9]/)),
+
+    place_name: _ => /[A-Z][A-Za-z0-9_]*/,
+
+    identifier: _ => /[a-z_$@][A-Za-z0-9_]*/,
+
+    comment: _ => token(choice(
+      /#[^\n]*/,
+      /\/\/[^\n]*/,
+      /\/\*[^*]*\*+([^/*][^*]*\*+)*\//,
+    )),
+  },
+});
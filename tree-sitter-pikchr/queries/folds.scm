@@ -0,0 +1,2 @@
+(sublist) @fold
+(code_block) @fold
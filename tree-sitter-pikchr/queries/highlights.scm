@@ -0,0 +1,75 @@
+(comment) @comment
+(string) @string
+(number) @number
+(ordinal) @number
+
+(class_name) @type.builtin
+(attribute) @attribute
+(edge) @constant.builtin
+(property) @property
+(function) @function.builtin
+(keyword) @keyword
+(parameter) @variable.parameter
+
+(labeled_statement label: (place_name) @label)
+(place_name) @constant
+
+(assignment variable: (identifier) @variable)
+(identifier) @variable
+
+(macro_definition name: (_) @function.macro)
+(macro_arguments (identifier) @function.macro)
+
+[
+  "define"
+  "print"
+  "assert"
+] @keyword
+
+[
+  "last"
+  "previous"
+  "first"
+  "this"
+] @variable.builtin
+
+[
+  "fill"
+  "color"
+  "thickness"
+] @attribute
+
+[
+  "="
+  "+="
+  "-="
+  "*="
+  "/="
+  "+"
+  "-"
+  "*"
+  "/"
+  "%"
+  "=="
+  "<"
+  ">"
+  "->"
+  "<-"
+  "<->"
+] @operator
+
+[
+  "["
+  "]"
+  "("
+  ")"
+  "{"
+  "}"
+] @punctuation.bracket
+
+[
+  ","
+  ";"
+  ":"
+  "."
+] @punctuation.delimiter